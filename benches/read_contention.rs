@@ -0,0 +1,36 @@
+// Reads served by a reader while its transfer keeps filling the buffer, which both contend for
+// the state of the reader. The server runs in-process on localhost, so the link is never the bottleneck.
+
+#[path = "../tests/mock_server/mod.rs"]
+mod mock_server;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use httpfs::reader_pool::ReaderPool;
+use httpfs::transport::Transport;
+
+use mock_server::{test_data, MockServer};
+
+const RESOURCE_SIZE: usize = 32 * 1024 * 1024;
+
+fn sequential_reads(c: &mut Criterion) {
+    let server = MockServer::new(test_data(RESOURCE_SIZE)).start();
+    let mut group = c.benchmark_group("sequential_reads");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(RESOURCE_SIZE as u64));
+    for read_size in [4 * 1024, 128 * 1024] {
+        group.bench_with_input(BenchmarkId::from_parameter(read_size), &read_size, |b, &read_size| {
+            b.iter(|| {
+                // the reader ends with its transfer once the whole resource is read
+                let pool = ReaderPool::new(server.url(), RESOURCE_SIZE, Transport::with_headers(vec![]));
+                for offset in (0..RESOURCE_SIZE).step_by(read_size) {
+                    assert_eq!(pool.read(offset, read_size).unwrap().len(), read_size);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sequential_reads);
+criterion_main!(benches);
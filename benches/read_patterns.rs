@@ -0,0 +1,75 @@
+// Typical access patterns against the mock server, with and without the latency of a remote origin:
+// streaming a file from the beginning, and reads scattered over it like those of a database or an archive tool.
+
+#[path = "../tests/mock_server/mod.rs"]
+mod mock_server;
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use httpfs::profile::ReadProfile;
+use httpfs::reader_pool::ReaderPool;
+use httpfs::transport::Transport;
+
+use mock_server::{test_data, MockServer};
+
+const RESOURCE_SIZE: usize = 16 * 1024 * 1024;
+const SEQUENTIAL_READ: usize = 128 * 1024;
+const RANDOM_READ: usize = 16 * 1024;
+const RANDOM_READS: usize = 64;
+const LATENCIES_MS: [u64; 2] = [0, 5];
+
+// Offsets spread over the resource in a fixed pseudo-random order.
+fn random_offsets() -> Vec<usize> {
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    (0..RANDOM_READS)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize % (RESOURCE_SIZE - RANDOM_READ)
+        })
+        .collect()
+}
+
+fn sequential(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequential");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(RESOURCE_SIZE as u64));
+    for latency in LATENCIES_MS {
+        let server = MockServer::new(test_data(RESOURCE_SIZE)).with_latency(Duration::from_millis(latency)).start();
+        group.bench_with_input(BenchmarkId::new("latency_ms", latency), &latency, |b, _| {
+            b.iter(|| {
+                let pool = ReaderPool::new(server.url(), RESOURCE_SIZE, Transport::with_headers(vec![]));
+                for offset in (0..RESOURCE_SIZE).step_by(SEQUENTIAL_READ) {
+                    assert_eq!(pool.read(offset, SEQUENTIAL_READ).unwrap().len(), SEQUENTIAL_READ);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn random(c: &mut Criterion) {
+    let offsets = random_offsets();
+    let mut group = c.benchmark_group("random");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes((RANDOM_READS * RANDOM_READ) as u64));
+    for latency in LATENCIES_MS {
+        let server = MockServer::new(test_data(RESOURCE_SIZE)).with_latency(Duration::from_millis(latency)).start();
+        for (name, profile) in [("default", ReadProfile::default()), ("columnar", ReadProfile::columnar())] {
+            group.bench_with_input(BenchmarkId::new(name, format!("latency_ms_{}", latency)), &latency, |b, _| {
+                b.iter(|| {
+                    let pool = ReaderPool::new(server.url(), RESOURCE_SIZE, Transport::with_headers(vec![]))
+                        .with_profile(profile);
+                    for &offset in &offsets {
+                        assert_eq!(pool.read(offset, RANDOM_READ).unwrap().len(), RANDOM_READ);
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sequential, random);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+#ifndef HTTPFS_H
+#define HTTPFS_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Functions returning int return 0 on success and a negative errno value on failure.
+ */
+
+typedef struct HttpfsConfig HttpfsConfig;
+typedef struct HttpfsMount HttpfsMount;
+
+/* Returns NULL if an argument is NULL or not valid UTF-8. */
+HttpfsConfig *httpfs_config_new(const char *url, const char *mountpoint);
+void httpfs_config_free(HttpfsConfig *config);
+
+/*
+ * Names the file in the root of the mount, "file" by default. Returns -EINVAL for names that are empty,
+ * "." or "..", contain a slash, or are reserved for the mount, ".httpfs".
+ */
+int httpfs_config_set_file_name(HttpfsConfig *config, const char *file_name);
+/* Adds a full header line, e.g. "Authorization: Bearer ...". */
+int httpfs_config_add_header(HttpfsConfig *config, const char *header);
+int httpfs_config_set_auto_unmount(HttpfsConfig *config, bool value);
+int httpfs_config_set_allow_root(HttpfsConfig *config, bool value);
+
+/* Mounts the resource in the background. The config may be freed right after the call. */
+int httpfs_mount(const HttpfsConfig *config, HttpfsMount **out);
+/* Returns 1 while mounted and 0 after the filesystem was unmounted from outside. */
+int httpfs_mount_is_alive(const HttpfsMount *mount);
+/* Unmounts the filesystem and frees the handle. */
+int httpfs_unmount(HttpfsMount *mount);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HTTPFS_H */
@@ -0,0 +1,288 @@
+// Range fetches on a tokio runtime with hyper, selected with `--backend hyper` instead of the IO thread of curl.
+// The transfers of all streaming readers of the process run as tasks on the few threads of the runtime, and the
+// one-shot range requests of FUSE reads block their FUSE thread on the runtime until the response is complete.
+// Requests get the headers and middlewares of the transport like those of curl, responses are checked by the same
+// code, and paused transfers, fair sharing of the bandwidth and the limit of connections per host work as with
+// curl. Metadata requests, revalidations and the warm-up of connections stay on curl.
+// Options negotiated by curl itself, i.e. the authentication schemes and TLS certificates, aren't available,
+// see `Transport::check_backend`, and a transfer counts as stalled once it receives nothing for the low speed time.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use bytes::Bytes;
+use curl::easy::WriteError;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Incoming;
+use hyper::header::{HeaderName, HeaderValue, LOCATION};
+use hyper::{Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use log::debug;
+use tokio::runtime::{self, Runtime};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, timeout};
+
+use crate::connections::origin_of;
+use crate::fetch_priority;
+use crate::transfers::{balance, Share};
+use crate::transport::{Transport, MAX_REDIRECTS};
+
+// Threads of the runtime, the transfers need little CPU besides copying the data
+const WORKER_THREADS: usize = 2;
+// How often paused transfers check whether they may continue without being woken, and shares are balanced
+const RECHECK: Duration = Duration::from_millis(50);
+// Idle connections kept per host for later requests
+const MAX_IDLE_CONNECTIONS: usize = 8;
+
+// What the response of a streamed request is handed to, like to the callbacks of a curl transfer.
+pub(crate) trait Sink: Send + Sync {
+    // The status and the headers, with lowercase names, of the final response.
+    fn on_response(&self, status: u32, headers: Vec<(String, String)>);
+    // Takes a chunk of the body: its length to go on, less to abort, or a pause to get it again once `resume`.
+    fn on_data(&self, chunk: &[u8]) -> Result<usize, WriteError>;
+    // Whether a paused transfer may continue, clearing the paused state.
+    fn resume(&self) -> bool;
+    fn cancelled(&self) -> bool;
+    fn contending_share(&self) -> Option<&Arc<Share>>;
+}
+
+type HttpClient = Client<HttpsConnector<HttpConnector>, Empty<Bytes>>;
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+static CLIENT: OnceLock<HttpClient> = OnceLock::new();
+// notified when a read frees buffer space or a reader stops, like the IO thread is woken
+static WOKEN: Notify = Notify::const_new();
+// the sinks of the transfers in progress, whose shares are balanced
+static STREAMING: Mutex<Vec<Arc<dyn Sink>>> = Mutex::new(vec![]);
+// connections per origin, while the transport limits them
+static CONNECTIONS: Mutex<BTreeMap<String, Arc<Semaphore>>> = Mutex::new(BTreeMap::new());
+
+fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(WORKER_THREADS)
+            .thread_name("httpfs-async")
+            .on_thread_start(fetch_priority::apply)
+            .enable_all()
+            .build()
+            .expect("the tokio runtime is created");
+        runtime.spawn(balance_shares());
+        runtime
+    })
+}
+
+// Runs `future` as a task of the runtime, e.g. the transfers of a reader.
+pub(crate) fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    runtime().spawn(future);
+}
+
+// Blocks the calling thread, e.g. of a FUSE request, until `future` has run on the runtime.
+// Must not be called from a task of the runtime.
+pub(crate) fn block_on<T>(future: impl Future<Output = T>) -> T {
+    runtime().block_on(future)
+}
+
+// Lets paused transfers check whether they may continue, e.g. after a read freed buffer space.
+pub(crate) fn wake() {
+    if RUNTIME.get().is_some() {
+        WOKEN.notify_waiters();
+    }
+}
+
+pub(crate) async fn pause(delay: Duration) {
+    sleep(delay).await
+}
+
+// The client of the first transport using the backend, whose socket options are those of the whole process.
+fn client(transport: &Transport) -> io::Result<&'static HttpClient> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let socket = transport.socket_options();
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_nodelay(socket.nodelay);
+    http.set_recv_buffer_size(socket.receive_buffer);
+    http.set_keepalive(socket.keepalive.map(|keepalive| keepalive.idle));
+    http.set_keepalive_interval(socket.keepalive.map(|keepalive| keepalive.interval));
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()?
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(http);
+    let client = Client::builder(TokioExecutor::new()).pool_max_idle_per_host(MAX_IDLE_CONNECTIONS).build(https);
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+// Waits for a free connection to the origin of `url` while the transport limits them.
+async fn acquire_connection(transport: &Transport, url: &str) -> Option<OwnedSemaphorePermit> {
+    let limit = transport.max_connections_per_host()?;
+    let connections = Arc::clone(CONNECTIONS.lock().unwrap()
+        .entry(origin_of(url))
+        .or_insert_with(|| Arc::new(Semaphore::new(limit))));
+    connections.acquire_owned().await.ok()
+}
+
+// Sends a GET request with `extra_headers` and follows redirects, returning the final response.
+// `aborted` is checked while waiting, e.g. for a request of a hedged read another request has won.
+pub(crate) async fn send(
+    transport: &Transport,
+    url: &str,
+    extra_headers: &[String],
+    aborted: &(dyn Fn() -> bool + Sync),
+) -> io::Result<(u32, Vec<(String, String)>, Incoming)> {
+    let client = client(transport)?;
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        // the middlewares see every request, e.g. to sign it for the host it is sent to
+        let prepared = transport.prepare_request("GET", &url, extra_headers)?;
+        let mut request = Request::get(prepared.url.as_str()).body(Empty::new()).map_err(io::Error::other)?;
+        for header in &prepared.headers {
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(io::Error::other)?;
+            let value = HeaderValue::from_str(value.trim()).map_err(io::Error::other)?;
+            request.headers_mut().append(name, value);
+        }
+        let response = abortable(client.request(request), aborted).await?.map_err(io::Error::other)?;
+        let status = u32::from(response.status().as_u16());
+        let location = response.headers().get(LOCATION).and_then(|location| location.to_str().ok());
+        if let Some(location) = location.filter(|_| response.status().is_redirection()) {
+            let location = resolve_location(&url, location)
+                .ok_or_else(|| io::Error::other(format!("Invalid redirect of {} to {:?}", url, location)))?;
+            debug!("Following the redirect of {} to {}", url, location);
+            url = location;
+            continue;
+        }
+        let headers = response.headers().iter()
+            .map(|(name, value)| (name.as_str().to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect();
+        return Ok((status, headers, response.into_body()));
+    }
+    Err(io::Error::other(format!("More than {} redirects", MAX_REDIRECTS)))
+}
+
+// The absolute URL of the `location` a response to `url` redirects to.
+fn resolve_location(url: &str, location: &str) -> Option<String> {
+    if location.contains("://") {
+        return Some(location.to_string());
+    }
+    let base: Uri = url.parse().ok()?;
+    let origin = format!("{}://{}", base.scheme_str()?, base.authority()?);
+    if location.starts_with('/') {
+        return Some(format!("{}{}", origin, location));
+    }
+    let path = base.path();
+    Some(format!("{}{}{}", origin, &path[..path.rfind('/').map_or(0, |i| i + 1)], location))
+}
+
+// The next chunk of `body`, None at its end. Fails with `TimedOut` if nothing arrives for the low speed time.
+pub(crate) async fn next_chunk(transport: &Transport, body: &mut Incoming) -> io::Result<Option<Bytes>> {
+    loop {
+        let frame = match transport.low_speed_limit() {
+            Some(limit) => timeout(limit.time, body.frame()).await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Transfer has stalled"))?,
+            None => body.frame().await,
+        };
+        let Some(frame) = frame.transpose().map_err(io::Error::other)? else {
+            return Ok(None);
+        };
+        // trailers are skipped
+        if let Ok(data) = frame.into_data() {
+            return Ok(Some(data));
+        }
+    }
+}
+
+// Streams the response to a GET request of `url` into `sink`, until the body ends or the sink aborts it.
+// A stalled transfer fails with `TimedOut`.
+pub(crate) async fn stream(
+    transport: &Transport,
+    url: &str,
+    headers: &[String],
+    sink: Arc<dyn Sink>,
+) -> io::Result<()> {
+    let _connection = acquire_connection(transport, url).await;
+    let (status, response_headers, mut body) = send(transport, url, headers, &|| sink.cancelled()).await?;
+    sink.on_response(status, response_headers);
+    STREAMING.lock().unwrap().push(Arc::clone(&sink));
+    let result = receive(transport, &mut body, &*sink).await;
+    STREAMING.lock().unwrap().retain(|streaming| !Arc::ptr_eq(streaming, &sink));
+    result
+}
+
+async fn receive(transport: &Transport, body: &mut Incoming, sink: &dyn Sink) -> io::Result<()> {
+    let aborted = || io::Error::new(io::ErrorKind::Interrupted, "Transfer was aborted");
+    while let Some(chunk) = next_chunk(transport, body).await? {
+        loop {
+            match sink.on_data(&chunk) {
+                Ok(len) if len == chunk.len() => break,
+                Ok(_) => return Err(aborted()),
+                // a pause, the same chunk is handed over again once the sink may take it
+                Err(_) => {
+                    while !sink.resume() {
+                        let _ = timeout(RECHECK, WOKEN.notified()).await;
+                    }
+                }
+            }
+        }
+        if sink.cancelled() {
+            return Err(aborted());
+        }
+    }
+    Ok(())
+}
+
+// Awaits `future` unless `aborted` returns true first, which is checked every RECHECK.
+async fn abortable<T>(future: impl Future<Output = T>, aborted: &(dyn Fn() -> bool + Sync)) -> io::Result<T> {
+    let mut future = pin!(future);
+    loop {
+        if aborted() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Request was aborted"));
+        }
+        if let Ok(output) = timeout(RECHECK, &mut future).await {
+            return Ok(output);
+        }
+    }
+}
+
+// Lets each file receive data in proportion to its weight, like the IO thread does for curl transfers.
+async fn balance_shares() {
+    let mut contending: Vec<Arc<Share>> = vec![];
+    loop {
+        sleep(RECHECK).await;
+        let streaming = STREAMING.lock().unwrap().clone();
+        let mut shares: Vec<&Arc<Share>> = vec![];
+        for share in streaming.iter().filter_map(|sink| sink.contending_share()) {
+            if !shares.iter().any(|known| Arc::ptr_eq(known, share)) {
+                shares.push(share);
+            }
+        }
+        if shares.is_empty() && contending.is_empty() {
+            continue;
+        }
+        contending = balance(&shares, &contending);
+        WOKEN.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_are_resolved_against_the_url() {
+        let url = "https://example.com/data/file.bin?x=1";
+        assert_eq!(resolve_location(url, "http://other.com/file").as_deref(), Some("http://other.com/file"));
+        assert_eq!(resolve_location(url, "/moved/file").as_deref(), Some("https://example.com/moved/file"));
+        assert_eq!(resolve_location(url, "next.bin").as_deref(), Some("https://example.com/data/next.bin"));
+    }
+}
@@ -0,0 +1,61 @@
+// Audit log of accesses to the mount, e.g. for compliance reviews of which local processes read a sensitive export.
+// Every open and read is appended as one line with the uid, gid and pid of the requesting process,
+// its command name and the byte range, e.g.
+// time=1760608800.123 uid=1000 gid=1000 pid=4242 comm="python3" op=read file="file" offset=0 size=131072 result=ok
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use crate::span::Span;
+
+// The process on whose behalf the kernel sent a request.
+#[derive(Clone, Copy, Debug)]
+pub struct Requester {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+}
+
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    // Appends to the log at `path`, which is created readable by the owner only.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).mode(0o600).open(path)?;
+        Ok(AuditLog { file: Mutex::new(file) })
+    }
+
+    // Records an operation on `file_name`, with the range read and the errno of a failure.
+    pub fn record(&self, requester: Requester, operation: &str, file_name: &str, span: Option<Span>, result: Result<(), i32>) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = format!(
+            "time={}.{:03} uid={} gid={} pid={} comm={:?} op={} file={:?}",
+            time.as_secs(), time.subsec_millis(), requester.uid, requester.gid, requester.pid,
+            command_name(requester.pid), operation, file_name,
+        );
+        if let Some(span) = span {
+            line += &format!(" offset={} size={}", span.start(), span.len());
+        }
+        match result {
+            Ok(()) => line += " result=ok\n",
+            Err(errno) => line += &format!(" result=error errno={}\n", errno),
+        }
+        // a single write of the whole line keeps lines of concurrent requests apart
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Unable to write the audit log: {}", e);
+        }
+    }
+}
+
+// The command name of a process, empty if it has exited or isn't visible, e.g. in another pid namespace.
+fn command_name(pid: u32) -> String {
+    fs::read_to_string(format!("/proc/{}/comm", pid)).map(|comm| comm.trim_end().to_string()).unwrap_or_default()
+}
@@ -0,0 +1,64 @@
+// Content-addressed store of verified blocks on local disk, shared by mounts of any URL. Blocks are stored
+// under their SHA-256, so identical data published under several URLs, e.g. mirrored artifacts or
+// re-uploaded datasets, is stored once, and a mount finds blocks another mount has downloaded.
+// The checksums of blocks must be known before they are read, which the checksum manifest provides.
+//
+//     <dir>/<first 2 hex digits>/<64 hex digits of the SHA-256>
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct BlockStore {
+    dir: PathBuf,
+}
+
+impl BlockStore {
+    // Uses `dir` as the store, creating it if it doesn't exist.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(BlockStore { dir: dir.to_path_buf() })
+    }
+
+    fn path(&self, checksum: &[u8; 32]) -> PathBuf {
+        let name = hex::encode(checksum);
+        self.dir.join(&name[..2]).join(name)
+    }
+
+    // Returns the block with `checksum`, None if it isn't stored. A corrupted block is removed.
+    pub fn get(&self, checksum: &[u8; 32]) -> Option<Vec<u8>> {
+        let path = self.path(checksum);
+        let data = fs::read(&path).ok()?;
+        if Sha256::digest(&data).as_slice() != checksum {
+            warn!("Stored block {} is corrupted, removing it", path.display());
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        debug!("Block {} is found in the store", hex::encode(checksum));
+        Some(data)
+    }
+
+    // Stores a verified block. It is written to a temporary file first, so that concurrent mounts
+    // never see a partially written block.
+    pub fn put(&self, checksum: &[u8; 32], data: &[u8]) {
+        let path = self.path(checksum);
+        if path.exists() {
+            return;
+        }
+        let temp = path.with_extension(format!("{}-{}.tmp", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::SeqCst)));
+        let result = fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| fs::File::create(&temp))
+            .and_then(|mut file| file.write_all(data))
+            .and_then(|_| fs::rename(&temp, &path));
+        if let Err(e) = result {
+            warn!("Unable to store block {}: {}", path.display(), e);
+            let _ = fs::remove_file(&temp);
+        }
+    }
+}
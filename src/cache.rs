@@ -0,0 +1,367 @@
+// Persistent cache of the downloaded parts of a resource on local disk, so that re-reads and later mounts
+// of the same URL don't download them again. The data is written at its offset into a sparse file,
+// and a bitmap records which blocks of the file hold data:
+//
+//     <dir>/<64 hex digits of the SHA-256 of the url>.data     sparse, as large as the resource
+//     <dir>/<...>.ranges                                       one bit per CACHE_BLOCK bytes of the resource
+//     <dir>/<...>.meta                                         size, ETag and Last-Modified of the cached version
+//
+// The data file is mapped into memory, so that reads of cached data are replied to from the mapping without
+// copying it first.
+//
+// The cache is emptied when the resource is found to have a different size, ETag or Last-Modified date, so
+// that a resource without ETag changing in place isn't served from the old data. A cache is used by one
+// process at a time, it is locked while open.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::ops::{Deref, Range};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+
+use libc::{LOCK_EX, LOCK_NB};
+use log::{debug, warn};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+
+use crate::span::Span;
+
+// Granularity of the bitmap. Reads of the kernel are aligned to pages, so it is the size of a page.
+pub const CACHE_BLOCK: usize = 4096;
+
+struct Blocks {
+    size: usize,
+    // bit `i % 8` of byte `i / 8` is set once block `i` has been written to the data file
+    present: Vec<u8>,
+    // of the data file as long as `size`, None if it is empty or couldn't be mapped
+    map: Option<Mmap>,
+}
+
+impl Blocks {
+    fn new(size: usize) -> Self {
+        Blocks { size, present: vec![0; size.div_ceil(CACHE_BLOCK).div_ceil(8)], map: None }
+    }
+
+    fn contains(&self, block: usize) -> bool {
+        self.present[block / 8] & (1 << (block % 8)) != 0
+    }
+
+    fn contains_span(&self, span: Span) -> bool {
+        (span.start() / CACHE_BLOCK..span.end().div_ceil(CACHE_BLOCK)).all(|i| self.contains(i))
+    }
+}
+
+// Cached data borrowed from the mapping of the data file. The cache isn't reset while it is borrowed.
+pub struct MappedSpan<'a> {
+    blocks: RwLockReadGuard<'a, Blocks>,
+    range: Range<usize>,
+}
+
+impl Deref for MappedSpan<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.blocks.map.as_ref().expect("the span is mapped")[self.range.clone()]
+    }
+}
+
+pub struct DiskCache {
+    data: File,
+    ranges: File,
+    meta_path: PathBuf,
+    // data is written under the write lock, so that a reset can't interleave with it
+    blocks: RwLock<Blocks>,
+}
+
+fn base_path(dir: &Path, url: &str) -> PathBuf {
+    dir.join(hex::encode(Sha256::digest(url.as_bytes())))
+}
+
+// Content of the meta file, identifying the version of the resource the data belongs to.
+fn identity(size: usize, etag: Option<&str>, last_modified: Option<&str>) -> String {
+    format!("size {}\netag {}\nlast_modified {}\n", size, etag.unwrap_or(""), last_modified.unwrap_or(""))
+}
+
+fn open_rw(path: &Path) -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+}
+
+// Size, ETag and Last-Modified date of the version of `url` cached in `dir`, e.g. to mount it without a request.
+pub fn cached_version(dir: &Path, url: &str) -> io::Result<(usize, Option<String>, Option<String>)> {
+    let meta_path = base_path(dir, url).with_extension("meta");
+    let content = fs::read_to_string(&meta_path)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("{} is invalid", meta_path.display()));
+    let mut lines = content.lines();
+    let size = lines.next().and_then(|line| line.strip_prefix("size ")).and_then(|size| size.parse().ok());
+    let etag = lines.next().and_then(|line| line.strip_prefix("etag"));
+    let last_modified = lines.next().and_then(|line| line.strip_prefix("last_modified"));
+    let value = |value: &str| Some(value.trim()).filter(|value| !value.is_empty()).map(String::from);
+    match (size, etag, last_modified) {
+        (Some(size), Some(etag), Some(last_modified)) => Ok((size, value(etag), value(last_modified))),
+        _ => Err(invalid()),
+    }
+}
+
+impl DiskCache {
+    // Opens the cache of `url` in `dir`, creating the directory if it doesn't exist. The cached data is
+    // dropped unless it belongs to the version of the resource with `size`, `etag` and `last_modified`.
+    pub fn open(
+        dir: &Path,
+        url: &str,
+        size: usize,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let base = base_path(dir, url);
+        let ranges = open_rw(&base.with_extension("ranges"))?;
+        // released when the file is closed, also if the process crashes
+        if unsafe { libc::flock(ranges.as_raw_fd(), LOCK_EX | LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(e.kind(), format!("{} is used by another process: {}", base.display(), e)));
+        }
+        let cache = DiskCache {
+            data: open_rw(&base.with_extension("data"))?,
+            ranges,
+            meta_path: base.with_extension("meta"),
+            blocks: RwLock::new(Blocks::new(0)),
+        };
+
+        if fs::read_to_string(&cache.meta_path).is_ok_and(|recorded| recorded == identity(size, etag, last_modified)) {
+            let mut blocks = Blocks::new(size);
+            let recorded = fs::read(base.with_extension("ranges"))?;
+            let len = recorded.len().min(blocks.present.len());
+            blocks.present[..len].copy_from_slice(&recorded[..len]);
+            let cached = blocks.present.iter().map(|byte| byte.count_ones() as usize).sum::<usize>();
+            debug!("{} blocks of {} are cached", cached, url);
+            blocks.map = cache.map_data(size);
+            *cache.blocks.write().unwrap() = blocks;
+        } else {
+            cache.clear(size, etag, last_modified)?;
+        }
+        Ok(cache)
+    }
+
+    // Drops the cached data, which belongs to another version of the resource than the one with `size`, `etag`
+    // and `last_modified`.
+    pub fn reset(&self, size: usize, etag: Option<&str>, last_modified: Option<&str>) {
+        if let Err(e) = self.clear(size, etag, last_modified) {
+            // the meta file is removed first, so the stale data isn't used by later mounts either
+            warn!("Unable to empty the cache {}: {}", self.meta_path.display(), e);
+        }
+    }
+
+    fn clear(&self, size: usize, etag: Option<&str>, last_modified: Option<&str>) -> io::Result<()> {
+        let mut blocks = self.blocks.write().unwrap();
+        *blocks = Blocks::new(size);
+        if let Err(e) = fs::remove_file(&self.meta_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e);
+            }
+        }
+        self.ranges.set_len(0)?;
+        self.ranges.set_len(blocks.present.len() as u64)?;
+        self.data.set_len(0)?;
+        self.data.set_len(size as u64)?;
+        blocks.map = self.map_data(size);
+        fs::write(&self.meta_path, identity(size, etag, last_modified))
+    }
+
+    // Maps the data file of `size` bytes, which is emptied and truncated with the lock on `blocks` held.
+    fn map_data(&self, size: usize) -> Option<Mmap> {
+        if size == 0 {
+            return None;
+        }
+        // the file is locked against other mounts, and no span is borrowed while it is truncated
+        unsafe { Mmap::map(&self.data) }
+            .inspect_err(|e| warn!("Unable to map the cache {}, reads copy the data: {}", self.meta_path.display(), e))
+            .ok()
+    }
+
+    // Forgets the blocks overlapping `span`, e.g. data which failed verification, so that it is downloaded again.
+    pub fn evict(&self, span: Span) {
+        let mut blocks = self.blocks.write().unwrap();
+        let span = span.clamp_end(blocks.size);
+        if span.is_empty() {
+            return;
+        }
+        let (first, end) = (span.start() / CACHE_BLOCK, span.end().div_ceil(CACHE_BLOCK));
+        for i in first..end {
+            blocks.present[i / 8] &= !(1 << (i % 8));
+        }
+        let changed = first / 8..(end - 1) / 8 + 1;
+        if let Err(e) = self.ranges.write_all_at(&blocks.present[changed.clone()], changed.start as u64) {
+            warn!("Unable to evict {:?} from the cache {}: {}", span, self.meta_path.display(), e);
+        }
+    }
+
+    // Returns the data of `span`, or of its part before the end of the resource, None unless all of it is cached.
+    pub fn read(&self, span: Span) -> Option<Vec<u8>> {
+        let blocks = self.blocks.read().unwrap();
+        let span = span.clamp_end(blocks.size);
+        if span.is_empty() || !blocks.contains_span(span) {
+            return None;
+        }
+        let mut data = vec![0; span.len()];
+        self.data.read_exact_at(&mut data, span.start() as u64)
+            .inspect_err(|e| warn!("Unable to read {:?} from the cache {}: {}", span, self.meta_path.display(), e))
+            .ok()?;
+        Some(data)
+    }
+
+    // Borrows the data of `span`, or of its part before the end of the resource, from the mapping of the data file
+    // without copying it. None unless all of it is cached and the file is mapped.
+    pub fn read_mapped(&self, span: Span) -> Option<MappedSpan<'_>> {
+        let blocks = self.blocks.read().unwrap();
+        let span = span.clamp_end(blocks.size);
+        if span.is_empty() || blocks.map.is_none() || !blocks.contains_span(span) {
+            return None;
+        }
+        Some(MappedSpan { blocks, range: span.as_range() })
+    }
+
+    // Keeps `data` of the resource starting at `offset`. Only whole blocks are recorded, and the last block
+    // of the resource, so parts of blocks at the edges of `data` are left out.
+    pub fn write(&self, offset: usize, data: &[u8]) {
+        let mut blocks = self.blocks.write().unwrap();
+        let span = Span::with_len(offset, data.len()).clamp_end(blocks.size);
+        let first = span.start().div_ceil(CACHE_BLOCK);
+        let end = if span.end() == blocks.size { span.end().div_ceil(CACHE_BLOCK) } else { span.end() / CACHE_BLOCK };
+        if first >= end || (first..end).all(|i| blocks.contains(i)) {
+            return;
+        }
+        let start = first * CACHE_BLOCK;
+        let stored = &data[start - offset..(end * CACHE_BLOCK).min(span.end()) - offset];
+        // the data is on disk before the bitmap says so
+        let result = self.data.write_all_at(stored, start as u64).and_then(|_| {
+            for i in first..end {
+                blocks.present[i / 8] |= 1 << (i % 8);
+            }
+            let changed = first / 8..(end - 1) / 8 + 1;
+            self.ranges.write_all_at(&blocks.present[changed.clone()], changed.start as u64)
+        });
+        if let Err(e) = result {
+            warn!("Unable to cache {} bytes at {}: {}", stored.len(), start, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    // three whole blocks and a short one
+    const SIZE: usize = 3 * CACHE_BLOCK + 100;
+    const URL: &str = "https://example.com/data.bin";
+
+    fn resource() -> Vec<u8> {
+        (0..SIZE).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("httpfs-cache-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn block(i: usize) -> Span {
+        Span::with_len(i * CACHE_BLOCK, CACHE_BLOCK).clamp_end(SIZE)
+    }
+
+    #[test]
+    fn partial_blocks_at_the_edges_are_left_out() {
+        let dir = cache_dir("edges");
+        let cache = DiskCache::open(&dir, URL, SIZE, None, None).unwrap();
+        let data = resource();
+        cache.write(100, &data[100..2 * CACHE_BLOCK + 50]);
+        assert_eq!(cache.read(block(1)).as_deref(), Some(&data[block(1).as_range()]));
+        assert_eq!(cache.read(block(0)), None);
+        assert_eq!(cache.read(Span::new(100, CACHE_BLOCK)), None);
+        assert_eq!(cache.read(Span::with_len(2 * CACHE_BLOCK, 10)), None);
+        // data within a single block isn't kept at all
+        cache.write(10, &data[10..CACHE_BLOCK - 10]);
+        assert_eq!(cache.read(Span::new(10, 20)), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn last_short_block_is_kept() {
+        let dir = cache_dir("last-block");
+        let cache = DiskCache::open(&dir, URL, SIZE, None, None).unwrap();
+        let data = resource();
+        // not from the start of the block
+        cache.write(3 * CACHE_BLOCK + 10, &data[3 * CACHE_BLOCK + 10..]);
+        assert_eq!(cache.read(block(3)), None);
+        cache.write(3 * CACHE_BLOCK, &data[3 * CACHE_BLOCK..]);
+        assert_eq!(cache.read(block(3)).as_deref(), Some(&data[block(3).as_range()]));
+        // reads past the end return the part before it
+        let past_end = Span::with_len(3 * CACHE_BLOCK + 50, CACHE_BLOCK);
+        assert_eq!(cache.read(past_end).as_deref(), Some(&data[3 * CACHE_BLOCK + 50..]));
+        assert_eq!(cache.read_mapped(past_end).as_deref(), Some(&data[3 * CACHE_BLOCK + 50..]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_at_or_past_the_end_keep_only_the_resource() {
+        let dir = cache_dir("past-end");
+        let cache = DiskCache::open(&dir, URL, SIZE, None, None).unwrap();
+        cache.write(SIZE, &[1; 10]);
+        cache.write(SIZE + CACHE_BLOCK, &[1; CACHE_BLOCK]);
+        assert_eq!(cache.read(Span::with_len(SIZE, 10)), None);
+        assert_eq!(cache.read(block(3)), None);
+
+        // e.g. a server answering with more data than the resource had at mount
+        let mut data = resource();
+        data.extend([1; CACHE_BLOCK]);
+        cache.write(2 * CACHE_BLOCK, &data[2 * CACHE_BLOCK..]);
+        assert_eq!(cache.read(Span::new(2 * CACHE_BLOCK, SIZE + 10)).as_deref(), Some(&data[2 * CACHE_BLOCK..SIZE]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicted_blocks_are_read_again() {
+        let dir = cache_dir("evict");
+        let data = resource();
+        let cache = DiskCache::open(&dir, URL, SIZE, None, None).unwrap();
+        cache.write(0, &data);
+        cache.evict(Span::with_len(CACHE_BLOCK + 1, 1));
+        assert_eq!(cache.read(block(1)), None);
+        assert!(cache.read(block(0)).is_some() && cache.read(block(2)).is_some());
+        assert!(cache.read_mapped(Span::new(0, SIZE)).is_none());
+
+        // the eviction is recorded for later mounts
+        drop(cache);
+        let cache = DiskCache::open(&dir, URL, SIZE, None, None).unwrap();
+        assert_eq!(cache.read(block(1)), None);
+        assert!(cache.read(block(0)).is_some());
+        cache.write(block(1).start(), &data[block(1).as_range()]);
+        assert_eq!(cache.read(Span::new(0, SIZE)).as_deref(), Some(&data[..]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_another_version_empties_the_cache() {
+        let dir = cache_dir("version");
+        let data = resource();
+        let cache = DiskCache::open(&dir, URL, SIZE, Some("\"a\""), None).unwrap();
+        cache.write(0, &data);
+        drop(cache);
+        let cache = DiskCache::open(&dir, URL, SIZE, Some("\"a\""), None).unwrap();
+        assert_eq!(cache.read(Span::new(0, SIZE)).as_deref(), Some(&data[..]));
+        drop(cache);
+
+        let cache = DiskCache::open(&dir, URL, SIZE, Some("\"b\""), None).unwrap();
+        assert_eq!(cache.read(block(0)), None);
+        assert_eq!(cached_version(&dir, URL).unwrap(), (SIZE, Some("\"b\"".to_string()), None));
+        drop(cache);
+        // the data of the previous version is gone for good
+        let cache = DiskCache::open(&dir, URL, SIZE, Some("\"a\""), None).unwrap();
+        assert_eq!(cache.read(block(0)), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+// Freshness of the resource as the origin declares it with `Cache-Control` and `Expires`, e.g. of dynamic
+// or frequently rotated resources, deciding how long buffered data is served before it is revalidated and
+// whether blocks may be written to disk at all.
+
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CachePolicy {
+    // `no-store`: the resource must not be stored on disk
+    pub no_store: bool,
+    // how long a response stays fresh, zero for `no-cache` and expired responses, None if not declared
+    pub max_age: Option<Duration>,
+}
+
+// Parses the headers of a response. `max-age` takes precedence over `Expires`, which is relative to
+// the `Date` of the response if it has one, and an invalid `Expires`, e.g. "0", means already expired.
+pub fn parse_cache_policy(cache_control: Option<&str>, expires: Option<&str>, date: Option<&str>) -> CachePolicy {
+    let mut policy = CachePolicy::default();
+    let mut no_cache = false;
+    for directive in cache_control.unwrap_or_default().split(',').map(str::trim) {
+        let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+        match name.trim().to_ascii_lowercase().as_str() {
+            "no-store" => policy.no_store = true,
+            "no-cache" => no_cache = true,
+            "max-age" => {
+                if let Ok(seconds) = value.trim().trim_matches('"').parse::<u64>() {
+                    policy.max_age = Some(Duration::from_secs(seconds));
+                }
+            }
+            _ => {}
+        }
+    }
+    if no_cache {
+        policy.max_age = Some(Duration::ZERO);
+    }
+    if policy.max_age.is_none() {
+        policy.max_age = expires.map(|expires| {
+            let now = date.and_then(|date| httpdate::parse_http_date(date).ok()).unwrap_or_else(SystemTime::now);
+            httpdate::parse_http_date(expires).ok()
+                .and_then(|expires| expires.duration_since(now).ok())
+                .unwrap_or_default()
+        });
+    }
+    policy
+}
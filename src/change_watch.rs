@@ -0,0 +1,77 @@
+// Polling of the resource for changes, for mounts of resources updated in place, e.g. a nightly export.
+// A change found by a conditional request on the ETag or Last-Modified is logged, the kernel caches of the
+// mount are invalidated, and an optional command is run with the new version in its environment:
+// HTTPFS_URL, HTTPFS_SIZE, HTTPFS_ETAG and HTTPFS_LAST_MODIFIED. The kernel doesn't generate inotify events
+// for changes of FUSE files it didn't write itself, so tools watching the file need the command.
+
+use std::process::Command;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::http_meta_reader::{HttpMetaReader, ResourceMeta};
+use crate::transport::Transport;
+
+pub struct ChangeWatch {
+    meta_reader: HttpMetaReader,
+    url: String,
+    interval: Duration,
+    command: Option<String>,
+}
+
+impl ChangeWatch {
+    // Checks the resource at `url` for changes every `interval`.
+    pub fn new(url: &str, transport: Transport, interval: Duration) -> Self {
+        ChangeWatch { meta_reader: HttpMetaReader::new(url, transport), url: url.to_string(), interval, command: None }
+    }
+
+    // Runs `command` with `sh -c` after every change.
+    pub fn with_command(mut self, command: &str) -> Self {
+        self.command = Some(command.to_string());
+        self
+    }
+
+    // Starts polling in the background, starting from the validators of `meta`. `on_change` is called
+    // after every change, e.g. to invalidate kernel caches.
+    pub fn start(self, meta: &ResourceMeta, on_change: impl Fn() + Send + 'static) {
+        let (mut etag, mut last_modified) = (meta.etag.clone(), meta.last_modified.clone());
+        if etag.is_none() && last_modified.is_none() {
+            warn!("{} has neither ETag nor Last-Modified, its changes can't be detected", self.url);
+            return;
+        }
+        thread::spawn(move || loop {
+            sleep(self.interval);
+            match self.meta_reader.revalidate(etag.as_deref(), last_modified.as_deref()) {
+                Ok(None) => debug!("{} has not changed", self.url),
+                Ok(Some(meta)) => {
+                    warn!("{} has changed: size {}, ETag {:?}, Last-Modified {:?}",
+                        self.url, meta.size, meta.etag, meta.last_modified);
+                    on_change();
+                    self.run_command(&meta);
+                    (etag, last_modified) = (meta.etag, meta.last_modified);
+                }
+                Err(e) => warn!("Unable to check {} for changes: {}", self.url, e),
+            }
+        });
+    }
+
+    fn run_command(&self, meta: &ResourceMeta) {
+        let Some(command) = &self.command else {
+            return;
+        };
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("HTTPFS_URL", &self.url)
+            .env("HTTPFS_SIZE", meta.size.to_string())
+            .env("HTTPFS_ETAG", meta.etag.as_deref().unwrap_or_default())
+            .env("HTTPFS_LAST_MODIFIED", meta.last_modified.as_deref().unwrap_or_default())
+            .status();
+        match status {
+            Ok(status) if status.success() => info!("Change command has finished"),
+            Ok(status) => warn!("Change command has failed with {}", status),
+            Err(e) => warn!("Unable to run the change command: {}", e),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+// Verification of the data flowing through readers against known SHA-256 checksums.
+//
+// The whole-file checksum can only be checked when the resource is read sequentially from the
+// beginning to the end; the read that completes the file fails if the checksum does not match.
+// A sidecar manifest with per-block checksums verifies every read regardless of access pattern:
+//
+//     block_size 1048576
+//     sha256 <checksum of the whole file, optional>
+//     <checksum of block 0>
+//     <checksum of block 1>
+//     ...
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use libc::EIO;
+use log::{debug, error};
+use sha2::{Digest, Sha256};
+
+use crate::block_store::BlockStore;
+
+// How many verified blocks are kept in memory, so small sequential reads don't refetch a block
+const VERIFIED_BLOCKS_CACHE: usize = 4;
+
+type Checksum = [u8; 32];
+
+pub struct ChecksumManifest {
+    block_size: usize,
+    file_checksum: Option<Checksum>,
+    blocks: Vec<Checksum>,
+}
+
+struct SequentialState {
+    hasher: Sha256,
+    // offset of the first byte which has not been hashed yet
+    position: usize,
+}
+
+pub struct Verifier {
+    file_size: usize,
+    file_checksum: Option<Checksum>,
+    manifest: Option<ChecksumManifest>,
+    sequential: Mutex<SequentialState>,
+    // set once a mismatch was detected, all further reads fail
+    corrupted: Mutex<bool>,
+    verified_blocks: Mutex<VecDeque<(usize, Arc<Vec<u8>>)>>,
+    // verified blocks are looked up in and added to it, if set
+    block_store: Option<BlockStore>,
+}
+
+pub fn parse_checksum(value: &str) -> Result<Checksum, String> {
+    let bytes = hex::decode(value.trim()).map_err(|e| format!("Invalid SHA-256 {:?}: {}", value, e))?;
+    bytes.try_into().map_err(|_| format!("Invalid SHA-256 {:?}: expected 64 hex digits", value))
+}
+
+impl ChecksumManifest {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut block_size = None;
+        let mut file_checksum = None;
+        let mut blocks = vec![];
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            if let Some(value) = line.strip_prefix("block_size") {
+                block_size = Some(value.trim().parse::<usize>().map_err(|e| format!("Invalid block_size: {}", e))?);
+            } else if let Some(value) = line.strip_prefix("sha256") {
+                file_checksum = Some(parse_checksum(value)?);
+            } else {
+                blocks.push(parse_checksum(line)?);
+            }
+        }
+        let block_size = block_size.filter(|&size| size > 0).ok_or("block_size is missing")?;
+        Ok(ChecksumManifest { block_size, file_checksum, blocks })
+    }
+}
+
+impl Verifier {
+    pub fn new(file_size: usize, file_checksum: Option<Checksum>, manifest: Option<ChecksumManifest>) -> io::Result<Self> {
+        if let Some(manifest) = &manifest {
+            let expected_blocks = file_size.div_ceil(manifest.block_size);
+            if manifest.blocks.len() != expected_blocks {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "Checksum manifest has {} blocks, but the resource of {} bytes consists of {}",
+                    manifest.blocks.len(), file_size, expected_blocks)));
+            }
+        }
+        let file_checksum = file_checksum.or_else(|| manifest.as_ref().and_then(|m| m.file_checksum));
+        Ok(Verifier {
+            file_size,
+            file_checksum,
+            manifest,
+            sequential: Mutex::new(SequentialState { hasher: Sha256::new(), position: 0 }),
+            corrupted: Mutex::new(false),
+            verified_blocks: Mutex::new(VecDeque::new()),
+            block_store: None,
+        })
+    }
+
+    // Shares the blocks of the manifest with other mounts through `block_store`.
+    pub fn with_block_store(mut self, block_store: BlockStore) -> Self {
+        self.block_store = Some(block_store);
+        self
+    }
+
+    // Whether the checksum of the whole file has not matched, all reads fail then.
+    pub fn is_corrupted(&self) -> bool {
+        *self.corrupted.lock().unwrap()
+    }
+
+    // Reads `offset..offset + size` using `read_range` to fetch data, verifying everything it returns.
+    pub fn read(
+        &self,
+        offset: usize,
+        size: usize,
+        read_range: impl Fn(usize, usize) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        if self.is_corrupted() {
+            return Err(io::Error::from_raw_os_error(EIO));
+        }
+        let data = match &self.manifest {
+            Some(manifest) => self.read_verified_blocks(manifest, offset, size, read_range)?,
+            None => read_range(offset, size)?,
+        };
+        self.hash_sequential(offset, &data)?;
+        Ok(data)
+    }
+
+    fn read_verified_blocks(
+        &self,
+        manifest: &ChecksumManifest,
+        offset: usize,
+        size: usize,
+        read_range: impl Fn(usize, usize) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        let end = self.file_size.min(offset.saturating_add(size));
+        let mut data = Vec::with_capacity(end.saturating_sub(offset));
+        let mut position = offset;
+        while position < end {
+            let index = position / manifest.block_size;
+            let block_start = index * manifest.block_size;
+            let block = self.get_verified_block(manifest, index, &read_range)?;
+            let from = position - block_start;
+            let to = (end - block_start).min(block.len());
+            data.extend_from_slice(&block[from..to]);
+            position = block_start + to;
+        }
+        Ok(data)
+    }
+
+    fn get_verified_block(
+        &self,
+        manifest: &ChecksumManifest,
+        index: usize,
+        read_range: impl Fn(usize, usize) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<Vec<u8>>> {
+        if let Some((_, block)) = self.verified_blocks.lock().unwrap().iter().find(|(i, _)| *i == index) {
+            return Ok(Arc::clone(block));
+        }
+        let block_start = index * manifest.block_size;
+        let block_size = manifest.block_size.min(self.file_size - block_start);
+        let stored = self.block_store.as_ref().and_then(|store| store.get(&manifest.blocks[index]));
+        let block = match stored {
+            Some(block) => block,
+            None => {
+                let block = read_range(block_start, block_size)?;
+                if block.len() != block_size || Sha256::digest(&block).as_slice() != manifest.blocks[index] {
+                    error!("Checksum mismatch in block {} ({}..{})", index, block_start, block_start + block_size);
+                    return Err(io::Error::from_raw_os_error(EIO));
+                }
+                debug!("Block {} has been verified", index);
+                if let Some(store) = &self.block_store {
+                    store.put(&manifest.blocks[index], &block);
+                }
+                block
+            }
+        };
+
+        let block = Arc::new(block);
+        let mut verified_blocks = self.verified_blocks.lock().unwrap();
+        verified_blocks.push_back((index, Arc::clone(&block)));
+        if verified_blocks.len() > VERIFIED_BLOCKS_CACHE {
+            verified_blocks.pop_front();
+        }
+        Ok(block)
+    }
+
+    // Feeds the part of `data` that continues the sequentially hashed prefix into the hasher.
+    fn hash_sequential(&self, offset: usize, data: &[u8]) -> io::Result<()> {
+        let Some(expected) = self.file_checksum else {
+            return Ok(());
+        };
+        let mut state = self.sequential.lock().unwrap();
+        let end = offset + data.len();
+        if offset > state.position || end <= state.position {
+            return Ok(());
+        }
+        let from = state.position - offset;
+        state.hasher.update(&data[from..]);
+        state.position = end;
+        if state.position < self.file_size {
+            return Ok(());
+        }
+
+        let actual = std::mem::take(&mut state.hasher).finalize();
+        if actual.as_slice() != expected {
+            error!("Checksum mismatch of the whole file: expected {}, got {}",
+                hex::encode(expected), hex::encode(actual));
+            *self.corrupted.lock().unwrap() = true;
+            return Err(io::Error::from_raw_os_error(EIO));
+        }
+        debug!("The whole file checksum has been verified");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn manifest_with_comments_and_file_checksum() {
+        let content = format!("# blocks of 1 MiB\nblock_size 1048576\n\nsha256 {}\n{}\n  {}  \n", EMPTY, EMPTY, EMPTY);
+        let manifest = ChecksumManifest::parse(&content).unwrap();
+        assert_eq!(manifest.block_size, 1048576);
+        assert_eq!(manifest.file_checksum, Some(parse_checksum(EMPTY).unwrap()));
+        assert_eq!(manifest.blocks.len(), 2);
+    }
+
+    #[test]
+    fn manifest_without_valid_block_size_is_rejected() {
+        assert!(ChecksumManifest::parse(EMPTY).is_err());
+        assert!(ChecksumManifest::parse(&format!("block_size 0\n{}", EMPTY)).is_err());
+        assert!(ChecksumManifest::parse(&format!("block_size 1M\n{}", EMPTY)).is_err());
+    }
+
+    #[test]
+    fn manifest_with_invalid_checksum_is_rejected() {
+        assert!(ChecksumManifest::parse("block_size 4096\nnot hex").is_err());
+        assert!(ChecksumManifest::parse(&format!("block_size 4096\n{}", &EMPTY[..62])).is_err());
+        assert!(ChecksumManifest::parse("block_size 4096\nsha256 abcd").is_err());
+    }
+
+    #[test]
+    fn manifest_must_cover_every_block() {
+        let manifest = || ChecksumManifest::parse(&format!("block_size 4096\n{}\n{}", EMPTY, EMPTY)).unwrap();
+        assert!(Verifier::new(8192, None, Some(manifest())).is_ok());
+        assert!(Verifier::new(4097, None, Some(manifest())).is_ok());
+        assert!(Verifier::new(4096, None, Some(manifest())).is_err());
+        assert!(Verifier::new(8193, None, Some(manifest())).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// Fails reads fast when a host keeps failing, instead of letting every read wait for data
+// which is not going to arrive. After FAILURE_THRESHOLD consecutive failures the circuit of the
+// host opens for COOLDOWN; afterwards requests are let through again and the first success closes it.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use libc::EIO;
+use log::{error, info};
+
+const FAILURE_THRESHOLD: u32 = 5;
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct HostState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct CircuitBreaker {
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+impl CircuitBreaker {
+    // Returns EIO while the circuit of the host of `url` is open.
+    pub fn check(&self, url: &str) -> io::Result<()> {
+        let hosts = self.hosts.lock().unwrap();
+        match hosts.get(host(url)).and_then(|state| state.opened_at) {
+            Some(opened_at) if opened_at.elapsed() < COOLDOWN => Err(io::Error::from_raw_os_error(EIO)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self, url: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(state) = hosts.remove(host(url)) {
+            if state.opened_at.is_some() {
+                info!("Host {} has recovered, closing the circuit", host(url));
+            }
+        }
+    }
+
+    pub fn record_failure(&self, url: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host(url).to_string()).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures < FAILURE_THRESHOLD {
+            return;
+        }
+        // a failed attempt after the cooldown opens the circuit again
+        if state.opened_at.is_none_or(|opened_at| opened_at.elapsed() >= COOLDOWN) {
+            error!("Host {} failed {} times in a row, failing reads for {:?}",
+                host(url), state.consecutive_failures, COOLDOWN);
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+// Returns `host[:port]` of the url, or the whole url if it can't be parsed.
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    authority.rsplit_once('@').map_or(authority, |(_, host)| host)
+}
@@ -0,0 +1,133 @@
+// Curl handles of the process grouped by the origin they connect to. A handle of a finished transfer
+// keeps its connection open for the next request to the same origin, and the number of open connections
+// per origin may be limited across all readers and mounts of the process, e.g. for small servers
+// or providers throttling by the number of connections.
+
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+use curl::easy::Easy;
+use log::debug;
+
+// How many handles with their open connections are kept per origin for later requests
+const MAX_IDLE_HANDLES: usize = 8;
+
+#[derive(Default)]
+struct Origin {
+    // handles in use and idle ones
+    open: usize,
+    idle: Vec<Easy>,
+}
+
+static ORIGINS: Mutex<BTreeMap<String, Origin>> = Mutex::new(BTreeMap::new());
+// signalled when a handle is released or closed
+static RELEASED: Condvar = Condvar::new();
+
+// A handle counted as an open connection of its origin until it is released or dropped.
+pub struct Connection {
+    // None while the handle is detached for a transfer of the IO thread
+    easy: Option<Easy>,
+    origin: String,
+    released: bool,
+}
+
+// Takes an idle handle connected to the origin of `url`, or creates a new one if fewer than `limit`
+// connections to it are open, waiting for one to be released otherwise.
+pub fn acquire(url: &str, limit: Option<usize>) -> Connection {
+    let origin = origin_of(url);
+    let mut origins = ORIGINS.lock().unwrap();
+    let mut waiting = false;
+    loop {
+        if let Some(connection) = take(&mut origins, &origin, limit) {
+            return connection;
+        }
+        if !waiting {
+            debug!("All connections to {} are in use, waiting for a free one", origin);
+            waiting = true;
+        }
+        origins = RELEASED.wait(origins).unwrap();
+    }
+}
+
+// Like `acquire`, but returns None instead of waiting, for the IO thread which must not block.
+pub fn try_acquire(url: &str, limit: Option<usize>) -> Option<Connection> {
+    let origin = origin_of(url);
+    take(&mut ORIGINS.lock().unwrap(), &origin, limit)
+}
+
+fn take(origins: &mut BTreeMap<String, Origin>, origin: &str, limit: Option<usize>) -> Option<Connection> {
+    let state = origins.entry(origin.to_string()).or_default();
+    let easy = match state.idle.pop() {
+        Some(easy) => easy,
+        None if limit.is_none_or(|limit| state.open < limit) => {
+            state.open += 1;
+            Easy::new()
+        }
+        None => return None,
+    };
+    Some(Connection { easy: Some(easy), origin: origin.to_string(), released: false })
+}
+
+// Keeps the handle after its transfer for later requests to the same origin.
+// Connections of transfers aborted in the middle of the body are closed by curl anyway.
+pub fn release(mut connection: Connection) {
+    let Some(mut easy) = connection.easy.take() else {
+        // a detached handle is gone, so dropping the connection counts it as closed
+        return;
+    };
+    connection.released = true;
+    easy.reset();
+    let mut origins = ORIGINS.lock().unwrap();
+    let state = origins.entry(connection.origin.clone()).or_default();
+    if state.idle.len() < MAX_IDLE_HANDLES {
+        state.idle.push(easy);
+    } else {
+        state.open -= 1;
+    }
+    RELEASED.notify_all();
+}
+
+impl Connection {
+    // Takes the handle out, e.g. to add it to a multi handle, while the connection stays counted.
+    pub(crate) fn detach(&mut self) -> Easy {
+        self.easy.take().expect("the handle is attached")
+    }
+
+    pub(crate) fn attach(&mut self, easy: Easy) {
+        self.easy = Some(easy);
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if !self.released {
+            if let Some(state) = ORIGINS.lock().unwrap().get_mut(&self.origin) {
+                state.open -= 1;
+            }
+            RELEASED.notify_all();
+        }
+    }
+}
+
+impl Deref for Connection {
+    type Target = Easy;
+
+    fn deref(&self) -> &Easy {
+        self.easy.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut Easy {
+        self.easy.as_mut().unwrap()
+    }
+}
+
+// The scheme, host and port of `url`, without credentials and path.
+pub(crate) fn origin_of(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    format!("{}://{}", scheme, host).to_ascii_lowercase()
+}
@@ -0,0 +1,535 @@
+// Locating the index of video containers, which players read before playing anything and which is
+// often stored at the end of the file: the `moov` box of MP4/MOV and the Cues element of Matroska/WebM.
+// Only the headers of top-level structures are read, each with a small ranged request.
+// The footers of Parquet and ORC files, which query engines read before any column, are located the same way.
+
+use std::cmp::min;
+use std::io;
+
+use log::debug;
+
+use crate::span::Span;
+
+// How many top-level boxes or elements are walked before giving up
+const MAX_WALKED: usize = 64;
+// Enough for the header of any box or element
+const HEADER_READ: usize = 16;
+// Larger SeekHead elements are not parsed
+const MAX_SEEK_HEAD: usize = 64 * 1024;
+// The Parquet footer ends with the length of the metadata and the magic
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const PARQUET_TAIL: usize = 8;
+const ORC_MAGIC: &[u8] = b"ORC";
+// The ORC postscript ends the file and is at most 255 bytes long, preceded by its length byte
+const ORC_TAIL: usize = 256;
+
+const EBML_ID: u32 = 0x1A45DFA3;
+const SEGMENT_ID: u32 = 0x18538067;
+const SEEK_HEAD_ID: u32 = 0x114D9B74;
+const SEEK_ID: u32 = 0x4DBB;
+const SEEK_ID_ID: u32 = 0x53AB;
+const SEEK_POSITION_ID: u32 = 0x53AC;
+const CUES_ID: u32 = 0x1C53BB6B;
+const CLUSTER_ID: u32 = 0x1F43B675;
+
+// Returns the span of the container index of a resource of `size` bytes, reading it with `read`.
+// None if the container is not recognized or has no index.
+pub fn find_index(size: usize, read: impl Fn(Span) -> io::Result<Vec<u8>>) -> io::Result<Option<Span>> {
+    let head = read(Span::new(0, min(size, HEADER_READ)))?;
+    if head.get(4..8) == Some(b"ftyp") {
+        return find_mp4_index(size, read);
+    }
+    if read_element_id(&head).map(|(id, _)| id) == Some(EBML_ID) {
+        return find_matroska_index(size, read);
+    }
+    if head.starts_with(PARQUET_MAGIC) {
+        return find_parquet_footer(size, read);
+    }
+    if head.starts_with(ORC_MAGIC) {
+        return find_orc_footer(size, read);
+    }
+    debug!("Container of the resource is not recognized");
+    Ok(None)
+}
+
+// Walks the top-level boxes up to `moov`.
+fn find_mp4_index(size: usize, read: impl Fn(Span) -> io::Result<Vec<u8>>) -> io::Result<Option<Span>> {
+    let mut offset = 0;
+    for _ in 0..MAX_WALKED {
+        let header = read(Span::with_len(offset, HEADER_READ).clamp_end(size))?;
+        let Some(kind) = header.get(4..8) else {
+            break;
+        };
+        let box_size = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            // the box extends to the end of the file
+            0 => size - offset,
+            // the size follows the type as a 64-bit number
+            1 => match header.get(8..16) {
+                Some(large) => usize::try_from(u64::from_be_bytes(large.try_into().unwrap())).unwrap_or(usize::MAX),
+                None => break,
+            },
+            box_size => box_size as usize,
+        };
+        if box_size < 8 {
+            debug!("Invalid MP4 box of {} bytes at offset {}", box_size, offset);
+            break;
+        }
+        if kind == b"moov" {
+            return Ok(Some(Span::with_len(offset, box_size).clamp_end(size)));
+        }
+        offset = offset.saturating_add(box_size);
+        if offset >= size {
+            break;
+        }
+    }
+    debug!("MP4 resource has no moov box");
+    Ok(None)
+}
+
+// Finds the Cues through the SeekHead at the start of the Segment.
+fn find_matroska_index(size: usize, read: impl Fn(Span) -> io::Result<Vec<u8>>) -> io::Result<Option<Span>> {
+    let read_header = |offset: usize| -> io::Result<Option<ElementHeader>> {
+        Ok(read_element_header(&read(Span::with_len(offset, HEADER_READ).clamp_end(size))?, offset))
+    };
+    let Some(ebml) = read_header(0)? else {
+        return Ok(None);
+    };
+    let Some(segment) = ebml.end().map(&read_header).transpose()?.flatten() else {
+        return Ok(None);
+    };
+    if segment.id != SEGMENT_ID {
+        debug!("Matroska resource has no Segment after the EBML header");
+        return Ok(None);
+    }
+
+    let segment_end = segment.end().map_or(size, |end| min(end, size));
+    let mut offset = segment.data_start;
+    for _ in 0..MAX_WALKED {
+        if offset >= segment_end {
+            break;
+        }
+        let Some(element) = read_header(offset)? else {
+            break;
+        };
+        match (element.id, element.end()) {
+            (CUES_ID, Some(end)) => return Ok(Some(Span::new(element.start, min(end, size)))),
+            (SEEK_HEAD_ID, Some(end)) if end - element.data_start <= MAX_SEEK_HEAD => {
+                let seek_head = read(Span::new(element.data_start, min(end, size)))?;
+                if let Some(position) = find_seek_position(&seek_head, CUES_ID) {
+                    let cues = read_header(segment.data_start.saturating_add(position))?;
+                    return Ok(cues
+                        .filter(|cues| cues.id == CUES_ID)
+                        .and_then(|cues| Some(Span::new(cues.start, min(cues.end()?, size)))));
+                }
+                offset = end;
+            }
+            // the index is not before the data, and walking the clusters would take a request per cluster
+            (CLUSTER_ID, _) | (_, None) => break,
+            (_, Some(end)) => offset = end,
+        }
+    }
+    debug!("Matroska resource has no Cues referenced before the first Cluster");
+    Ok(None)
+}
+
+// The footer is the file metadata followed by its length and the magic.
+fn find_parquet_footer(size: usize, read: impl Fn(Span) -> io::Result<Vec<u8>>) -> io::Result<Option<Span>> {
+    let tail = read(Span::new(size.saturating_sub(PARQUET_TAIL), size))?;
+    if tail.len() < PARQUET_TAIL || !tail.ends_with(PARQUET_MAGIC) {
+        debug!("Parquet resource has no footer");
+        return Ok(None);
+    }
+    let metadata_len = u32::from_le_bytes(tail[0..4].try_into().unwrap()) as usize;
+    // the file starts with the magic too
+    if PARQUET_MAGIC.len() + metadata_len + PARQUET_TAIL > size {
+        debug!("Parquet footer of {} bytes doesn't fit into the resource", metadata_len);
+        return Ok(None);
+    }
+    Ok(Some(Span::new(size - PARQUET_TAIL - metadata_len, size)))
+}
+
+// The file ends with the stripe statistics, the footer and the postscript describing their lengths.
+fn find_orc_footer(size: usize, read: impl Fn(Span) -> io::Result<Vec<u8>>) -> io::Result<Option<Span>> {
+    let tail = read(Span::new(size.saturating_sub(ORC_TAIL), size))?;
+    let Some((&postscript_len, rest)) = tail.split_last() else {
+        return Ok(None);
+    };
+    let Some(postscript) = rest.get(rest.len().saturating_sub(postscript_len as usize)..)
+        .filter(|postscript| postscript.len() == postscript_len as usize) else {
+        debug!("ORC resource has no postscript");
+        return Ok(None);
+    };
+    // footerLength, metadataLength and stripeStatisticsLength of the protobuf message
+    let mut len = 1 + postscript_len as u64;
+    let mut fields = postscript;
+    while let Some((field, value, rest)) = read_protobuf_field(fields) {
+        if matches!(field, 1 | 5 | 7) {
+            len = len.saturating_add(value);
+        }
+        fields = rest;
+    }
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    if !fields.is_empty() || ORC_MAGIC.len().saturating_add(len) > size {
+        debug!("ORC postscript is invalid or describes more than the resource");
+        return Ok(None);
+    }
+    Ok(Some(Span::new(size - len, size)))
+}
+
+// Reads a field of a protobuf message. Returns its number, its value if it is a varint (0 otherwise)
+// and the rest of the message.
+fn read_protobuf_field(message: &[u8]) -> Option<(u64, u64, &[u8])> {
+    let (key, rest) = read_protobuf_varint(message)?;
+    let (value, rest) = match key & 7 {
+        0 => read_protobuf_varint(rest)?,
+        1 => (0, rest.get(8..)?),
+        2 => {
+            let (len, rest) = read_protobuf_varint(rest)?;
+            (0, rest.get(usize::try_from(len).ok()?..)?)
+        }
+        5 => (0, rest.get(4..)?),
+        _ => return None,
+    };
+    Some((key >> 3, value, rest))
+}
+
+fn read_protobuf_varint(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[i + 1..]));
+        }
+    }
+    None
+}
+
+// Returns the position of the element with `target` id relative to the Segment data from the body of a SeekHead.
+fn find_seek_position(seek_head: &[u8], target: u32) -> Option<usize> {
+    let mut rest = seek_head;
+    while let Some(seek) = read_element_header(rest, 0) {
+        let body = rest.get(seek.data_start..seek.end()?)?;
+        rest = &rest[seek.end()?..];
+        if seek.id != SEEK_ID {
+            continue;
+        }
+        let mut id = None;
+        let mut position = None;
+        let mut fields = body;
+        while let Some(field) = read_element_header(fields, 0) {
+            let value = fields.get(field.data_start..field.end()?)?;
+            fields = &fields[field.end()?..];
+            match field.id {
+                SEEK_ID_ID => id = Some(value.iter().fold(0u32, |acc, b| acc << 8 | *b as u32)),
+                SEEK_POSITION_ID => position = Some(value.iter().fold(0u64, |acc, b| acc << 8 | *b as u64)),
+                _ => {}
+            }
+        }
+        if id == Some(target) {
+            return position.and_then(|position| usize::try_from(position).ok());
+        }
+    }
+    None
+}
+
+// The header of an EBML element at absolute offset `start`.
+struct ElementHeader {
+    id: u32,
+    start: usize,
+    data_start: usize,
+    // None if the size is unknown, e.g. for live streams
+    data_size: Option<usize>,
+}
+
+impl ElementHeader {
+    fn end(&self) -> Option<usize> {
+        self.data_start.checked_add(self.data_size?)
+    }
+}
+
+fn read_element_header(bytes: &[u8], start: usize) -> Option<ElementHeader> {
+    let (id, id_len) = read_element_id(bytes)?;
+    let (data_size, size_len) = read_vint(bytes.get(id_len..)?)?;
+    Some(ElementHeader { id, start, data_start: start + id_len + size_len, data_size })
+}
+
+// IDs are variable length integers with the length marker kept, at most 4 bytes long.
+fn read_element_id(bytes: &[u8]) -> Option<(u32, usize)> {
+    let len = vint_len(*bytes.first()?).filter(|len| *len <= 4)?;
+    let id = bytes.get(..len)?.iter().fold(0u32, |acc, b| acc << 8 | *b as u32);
+    Some((id, len))
+}
+
+// Reads a variable length integer of an element size. A value of all ones means an unknown size.
+fn read_vint(bytes: &[u8]) -> Option<(Option<usize>, usize)> {
+    let first = *bytes.first()?;
+    let len = vint_len(first)?;
+    let value = bytes.get(1..len)?.iter()
+        .fold(first as u64 & (0xFF >> len), |acc, b| acc << 8 | *b as u64);
+    let unknown = (1u64 << (7 * len)) - 1;
+    Some(((value != unknown).then(|| usize::try_from(value).unwrap_or(usize::MAX)), len))
+}
+
+// The length of a variable length integer is given by the number of leading zeros of its first byte.
+fn vint_len(first: u8) -> Option<usize> {
+    (first != 0).then_some(first.leading_zeros() as usize + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(data: &[u8]) -> Option<Span> {
+        find_index(data.len(), |span| Ok(data[span.clamp_end(data.len()).as_range()].to_vec())).unwrap()
+    }
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut mp4_box = ((8 + body.len()) as u32).to_be_bytes().to_vec();
+        mp4_box.extend_from_slice(kind);
+        mp4_box.extend_from_slice(body);
+        mp4_box
+    }
+
+    // An element with its size written as an 8 byte variable length integer.
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut element: Vec<u8> = id.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+        element.push(0x01);
+        element.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(body);
+        element
+    }
+
+    fn ebml_header() -> Vec<u8> {
+        element(EBML_ID, &element(0x4282, b"webm"))
+    }
+
+    // A SeekHead pointing at the Cues at `position`, of the same length whatever the position.
+    fn seek_head(position: u64) -> Vec<u8> {
+        let seek = [element(SEEK_ID_ID, &CUES_ID.to_be_bytes()), element(SEEK_POSITION_ID, &position.to_be_bytes())];
+        element(SEEK_HEAD_ID, &element(SEEK_ID, &seek.concat()))
+    }
+
+    // A Segment of `children`, with a SeekHead pointing at its Cues first if `indexed`.
+    fn matroska(children: &[Vec<u8>], indexed: bool) -> (Vec<u8>, Option<Span>) {
+        let head_len = if indexed { seek_head(0).len() } else { 0 };
+        let mut body = vec![];
+        let mut cues = None;
+        for child in children {
+            if child.starts_with(&CUES_ID.to_be_bytes()) {
+                cues = Some(head_len + body.len());
+            }
+            body.extend_from_slice(child);
+        }
+        if indexed {
+            body.splice(0..0, seek_head(cues.unwrap() as u64));
+        }
+        let data = [ebml_header(), element(SEGMENT_ID, &body)].concat();
+        let data_start = data.len() - body.len();
+        let cues = cues.map(|cues| Span::with_len(data_start + cues, element(CUES_ID, &[0; 20]).len()));
+        (data, cues)
+    }
+
+    #[test]
+    fn moov_at_end_of_mp4() {
+        let data = [mp4_box(b"ftyp", b"isom"), mp4_box(b"mdat", &[7; 1000]), mp4_box(b"moov", &[1; 100])].concat();
+        assert_eq!(find(&data), Some(Span::new(data.len() - 108, data.len())));
+    }
+
+    #[test]
+    fn mp4_box_with_64_bit_size_is_skipped() {
+        let mut mdat = 1u32.to_be_bytes().to_vec();
+        mdat.extend_from_slice(b"mdat");
+        mdat.extend_from_slice(&(16u64 + 500).to_be_bytes());
+        mdat.extend_from_slice(&[7; 500]);
+        let data = [mp4_box(b"ftyp", b"isom"), mdat, mp4_box(b"moov", &[1; 100])].concat();
+        assert_eq!(find(&data), Some(Span::new(data.len() - 108, data.len())));
+    }
+
+    #[test]
+    fn mp4_box_of_size_0_extends_to_end() {
+        let mut moov = mp4_box(b"moov", &[1; 100]);
+        moov[..4].copy_from_slice(&[0; 4]);
+        let data = [mp4_box(b"ftyp", b"isom"), moov].concat();
+        assert_eq!(find(&data), Some(Span::new(12, data.len())));
+
+        // nothing follows such a box
+        let mut mdat = mp4_box(b"mdat", &[7; 100]);
+        mdat[..4].copy_from_slice(&[0; 4]);
+        assert_eq!(find(&[mp4_box(b"ftyp", b"isom"), mdat, mp4_box(b"moov", &[1; 100])].concat()), None);
+    }
+
+    #[test]
+    fn truncated_or_invalid_mp4_boxes_end_the_walk() {
+        let ftyp = mp4_box(b"ftyp", b"isom");
+        // a box smaller than its header
+        assert_eq!(find(&[ftyp.clone(), vec![0, 0, 0, 4], b"moov".to_vec()].concat()), None);
+        // a 64-bit size cut off by the end of the file
+        assert_eq!(find(&[ftyp.clone(), vec![0, 0, 0, 1], b"moov".to_vec(), vec![0; 4]].concat()), None);
+        // a header cut off before its type
+        assert_eq!(find(&[ftyp.clone(), vec![0, 0, 1]].concat()), None);
+        // a box larger than the file
+        let moov = [u32::MAX.to_be_bytes().to_vec(), b"moov".to_vec()].concat();
+        assert_eq!(find(&[ftyp.clone(), moov].concat()), Some(Span::new(ftyp.len(), ftyp.len() + 8)));
+        let huge = [1u32.to_be_bytes().to_vec(), b"mdat".to_vec(), u64::MAX.to_be_bytes().to_vec()].concat();
+        assert_eq!(find(&[ftyp, huge].concat()), None);
+    }
+
+    #[test]
+    fn cues_before_first_cluster_are_found_by_walking() {
+        let children = [element(0x1549A966, &[0; 10]), element(CUES_ID, &[0; 20]), element(CLUSTER_ID, &[0; 50])];
+        let (data, cues) = matroska(&children, false);
+        assert_eq!(find(&data), cues);
+    }
+
+    #[test]
+    fn cues_after_cluster_are_found_through_seek_head() {
+        let children = [element(0x1549A966, &[0; 10]), element(CLUSTER_ID, &[0; 50]), element(CUES_ID, &[0; 20])];
+        let (data, cues) = matroska(&children, true);
+        assert!(cues.is_some());
+        assert_eq!(find(&data), cues);
+        // without the SeekHead the clusters aren't walked
+        assert_eq!(find(&matroska(&children, false).0), None);
+    }
+
+    #[test]
+    fn segment_of_unknown_size_is_walked_to_end() {
+        let (mut data, cues) = matroska(&[element(CUES_ID, &[0; 20]), element(CLUSTER_ID, &[0; 50])], false);
+        let segment = ebml_header().len();
+        data[segment + 4..segment + 12].copy_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(find(&data), cues);
+    }
+
+    #[test]
+    fn garbage_matroska_headers_have_no_index() {
+        let (cluster, cues) = (element(CLUSTER_ID, &[0; 50]), element(CUES_ID, &[0; 20]));
+        let (data, _) = matroska(&[cluster.clone(), cues.clone()], true);
+        // the SeekHead points beyond the end of the file, its position is the last field before the children
+        let position = data.len() - cues.len() - cluster.len() - 8;
+        let mut beyond = data.clone();
+        beyond[position..position + 8].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        assert_eq!(find(&beyond), None);
+        // a child of the Segment whose first byte is not a valid length marker
+        let mut invalid = data.clone();
+        invalid[ebml_header().len() + 12] = 0;
+        assert_eq!(find(&invalid), None);
+        // no Segment after the EBML header
+        assert_eq!(find(&[ebml_header(), element(CLUSTER_ID, &[0; 10])].concat()), None);
+        // cut off within the EBML header
+        assert_eq!(find(&ebml_header()[..6]), None);
+        assert_eq!(find(&[]), None);
+    }
+
+    fn parquet(metadata_len: usize, stored_len: u32) -> Vec<u8> {
+        let mut data = PARQUET_MAGIC.to_vec();
+        data.extend_from_slice(&[5; 300]);
+        data.extend_from_slice(&vec![9; metadata_len]);
+        data.extend_from_slice(&stored_len.to_le_bytes());
+        data.extend_from_slice(PARQUET_MAGIC);
+        data
+    }
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    // A postscript describing a footer and metadata of the given lengths.
+    fn postscript(footer_len: u64, metadata_len: u64) -> Vec<u8> {
+        [varint(1 << 3), varint(footer_len), varint(5 << 3), varint(metadata_len), varint(8000 << 3 | 2), varint(3),
+            ORC_MAGIC.to_vec()].concat()
+    }
+
+    // A file of stripes and `stored` bytes of footer and metadata, ended by `postscript` and its length.
+    fn orc(stored: usize, postscript: &[u8]) -> Vec<u8> {
+        let mut data = ORC_MAGIC.to_vec();
+        data.extend_from_slice(&[5; 300]);
+        data.extend_from_slice(&vec![9; stored]);
+        data.extend_from_slice(postscript);
+        data.push(postscript.len() as u8);
+        data
+    }
+
+    #[test]
+    fn parquet_footer_is_metadata_with_its_length() {
+        let data = parquet(100, 100);
+        assert_eq!(find(&data), Some(Span::new(data.len() - 108, data.len())));
+        // empty metadata
+        assert_eq!(find(&parquet(0, 0)), Some(Span::new(300 + 4, 300 + 12)));
+    }
+
+    #[test]
+    fn parquet_without_magic_at_end_has_no_footer() {
+        let mut data = parquet(100, 100);
+        let end = data.len();
+        data[end - 1] = b'0';
+        assert_eq!(find(&data), None);
+        assert_eq!(find(PARQUET_MAGIC), None);
+    }
+
+    #[test]
+    fn parquet_metadata_longer_than_file_has_no_footer() {
+        let data = parquet(100, 100);
+        let fits = (data.len() - 12) as u32;
+        assert_eq!(find(&parquet(100, fits)), Some(Span::new(4, data.len())));
+        assert_eq!(find(&parquet(100, fits + 1)), None);
+        assert_eq!(find(&parquet(100, u32::MAX)), None);
+    }
+
+    #[test]
+    fn orc_footer_is_described_by_postscript() {
+        let postscript = postscript(120, 30);
+        let data = orc(150, &postscript);
+        assert_eq!(find(&data), Some(Span::new(data.len() - 150 - postscript.len() - 1, data.len())));
+        // the whole file but its magic
+        let data = orc(150, &self::postscript(300 + 150, 0));
+        assert_eq!(find(&data), Some(Span::new(3, data.len())));
+    }
+
+    #[test]
+    fn orc_postscript_describing_more_than_file_has_no_footer() {
+        assert_eq!(find(&orc(150, &postscript(300 + 151, 0))), None);
+        assert_eq!(find(&orc(150, &postscript(1 << 40, 0))), None);
+        assert_eq!(find(&orc(150, &postscript(1, u64::MAX))), None);
+    }
+
+    #[test]
+    fn truncated_orc_postscript_has_no_footer() {
+        // the postscript is longer than the file
+        let mut data = ORC_MAGIC.to_vec();
+        data.extend_from_slice(&[0; 10]);
+        data.push(200);
+        assert_eq!(find(&data), None);
+        // a varint cut off by the end of the postscript
+        assert_eq!(find(&orc(0, &[varint(1 << 3), vec![0x80, 0x80]].concat())), None);
+        // a string longer than the rest of the postscript
+        assert_eq!(find(&orc(0, &[varint(8000 << 3 | 2), varint(10), b"ORC".to_vec()].concat())), None);
+        // an unknown wire type
+        assert_eq!(find(&orc(0, &varint(1 << 3 | 3))), None);
+    }
+
+    #[test]
+    fn protobuf_fields_of_each_wire_type() {
+        let message = [varint(1 << 3), varint(300), varint(2 << 3 | 1), vec![0; 8], varint(3 << 3 | 2), varint(2),
+            vec![1, 2], varint(4 << 3 | 5), vec![0; 4]].concat();
+        let (field, value, rest) = read_protobuf_field(&message).unwrap();
+        assert_eq!((field, value), (1, 300));
+        let (field, _, rest) = read_protobuf_field(rest).unwrap();
+        assert_eq!(field, 2);
+        let (field, _, rest) = read_protobuf_field(rest).unwrap();
+        assert_eq!(field, 3);
+        let (field, _, rest) = read_protobuf_field(rest).unwrap();
+        assert_eq!((field, rest.len()), (4, 0));
+        assert!(read_protobuf_field(rest).is_none());
+        // fixed-size values cut off
+        assert!(read_protobuf_field(&[varint(2 << 3 | 1), vec![0; 7]].concat()).is_none());
+        assert!(read_protobuf_field(&[varint(4 << 3 | 5), vec![0; 3]].concat()).is_none());
+        // a varint of more than 10 bytes
+        assert!(read_protobuf_varint(&[0xFF; 11]).is_none());
+        assert_eq!(read_protobuf_varint(&varint(u64::MAX)).map(|(value, _)| value), Some(u64::MAX));
+    }
+}
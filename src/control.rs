@@ -0,0 +1,158 @@
+// Control socket of a mount: a unix socket taking one request per connection, e.g. from `httpfs ctl`,
+// to change the files of the mount while it is mounted. A request is a command followed by its settings,
+// one per line, until the client shuts down its side of the connection:
+//
+//     add-file
+//     name=models/v2.bin
+//     url=https://example.com/models/v2.bin
+//     header=X-Api-Key: ...
+//
+// `add-file` takes the settings of a file of a tree manifest, `remove-file` only the `name`.
+// The reply is a single line, `ok` or `error: <reason>`.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+
+use crate::tree_manifest::TreeEntry;
+
+// Longest request accepted, a few settings with headers
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum ControlRequest {
+    // the file at the path of the entry, with its settings
+    AddFile(TreeEntry),
+    // the remote file at this path
+    RemoveFile(String),
+}
+
+impl ControlRequest {
+    pub fn parse(request: &str) -> Result<Self, String> {
+        let mut lines = request.lines().map(str::trim).filter(|line| !line.is_empty());
+        let command = lines.next().ok_or("The request is empty")?;
+        let mut entry = TreeEntry::default();
+        for line in lines {
+            let (key, value) = line.split_once('=').ok_or_else(|| format!("Expected key=value, found {:?}", line))?;
+            match (command, key.trim()) {
+                (_, "name") => entry.path = value.trim().to_string(),
+                ("add-file", key) => entry.set(key, value.trim())?,
+                (_, key) => return Err(format!("{} takes only a name, found {:?}", command, key)),
+            }
+        }
+        if entry.path.is_empty() {
+            return Err(format!("{} needs a name", command));
+        }
+        match command {
+            "add-file" if entry.url.is_empty() => Err(format!("File {} has no url", entry.path)),
+            "add-file" => Ok(ControlRequest::AddFile(entry)),
+            "remove-file" => Ok(ControlRequest::RemoveFile(entry.path)),
+            command => Err(format!("Unknown command {:?}", command)),
+        }
+    }
+}
+
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlServer {
+    // Listens at `path`, replacing the socket a previous mount may have left there, but not other files.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+            && UnixStream::connect(path).is_err()
+        {
+            debug!("Removing the stale control socket {}", path.display());
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        Ok(ControlServer { listener, path: path.to_path_buf() })
+    }
+
+    // Serves requests one at a time with `handle`, until the listener fails.
+    pub fn serve(&self, handle: impl Fn(ControlRequest) -> Result<(), String>) -> io::Result<()> {
+        info!("Taking control requests on {}", self.path.display());
+        for stream in self.listener.incoming() {
+            if let Err(e) = handle_connection(stream?, &handle) {
+                debug!("[control] Connection failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+type Handler<'a> = &'a dyn Fn(ControlRequest) -> Result<(), String>;
+
+fn handle_connection(mut stream: UnixStream, handle: Handler) -> io::Result<()> {
+    let mut request = String::new();
+    (&stream).take(MAX_REQUEST_LEN).read_to_string(&mut request)?;
+    let result = ControlRequest::parse(&request).and_then(|request| {
+        debug!("[control] {:?}", request);
+        handle(request)
+    });
+    match result {
+        Ok(()) => stream.write_all(b"ok\n"),
+        Err(e) => {
+            warn!("Control request failed: {}", e);
+            // the reply is a single line
+            stream.write_all(format!("error: {}\n", e.replace('\n', " ")).as_bytes())
+        }
+    }
+}
+
+// Sends `request` to the control socket at `path` and returns the error the mount replied with, if any.
+pub fn send(path: &Path, request: &str) -> io::Result<Result<(), String>> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(request.as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    match reply.trim_end() {
+        "ok" => Ok(Ok(())),
+        reply => match reply.strip_prefix("error: ") {
+            Some(e) => Ok(Err(e.to_string())),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected reply {:?}", reply))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_parsed() {
+        let request = ControlRequest::parse("add-file\nname=a/b.bin\nurl=https://example.com/b\nsize=10\n\
+            header=X-Api-Key: secret\nweight=2\n").unwrap();
+        let ControlRequest::AddFile(entry) = request else {
+            panic!("expected add-file, got {:?}", request);
+        };
+        assert_eq!((entry.path.as_str(), entry.url.as_str()), ("a/b.bin", "https://example.com/b"));
+        assert_eq!((entry.size, entry.weight), (Some(10), Some(2)));
+        assert_eq!(entry.headers, vec!["X-Api-Key: secret".to_string()]);
+
+        assert!(matches!(ControlRequest::parse("remove-file\nname=a/b.bin"),
+            Ok(ControlRequest::RemoveFile(path)) if path == "a/b.bin"));
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        for request in ["", "add-file\nurl=https://example.com/b", "add-file\nname=b", "remove-file\nname=b\nsize=1",
+            "add-file\nname=b\nurl=u\ncolor=red", "rename-file\nname=b", "add-file\nname=b\nurl"]
+        {
+            assert!(ControlRequest::parse(request).is_err(), "{:?} is accepted", request);
+        }
+    }
+}
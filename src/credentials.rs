@@ -0,0 +1,191 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+use crate::header_template::validate_header;
+
+// Rejections arriving within this time after a refresh, e.g. of the other readers whose requests were
+// in flight with the expired credentials, are retried with the refreshed ones instead of refreshing again
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Source of the authentication headers attached to every HTTP request.
+// Implement it to plug in vaults, STS flows or any other way of obtaining short-lived credentials.
+pub trait CredentialsProvider: Send + Sync {
+    // Called before each request. Returns full header lines, e.g. "Authorization: Bearer ...".
+    fn headers(&self) -> io::Result<Vec<String>>;
+
+    // Called when the server answered 401, before the request is repeated.
+    fn refresh(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Whether 403 responses are retried with refreshed credentials as well, e.g. for origins rejecting
+    // expired tokens or presigned URLs with 403.
+    fn refreshes_forbidden(&self) -> bool {
+        false
+    }
+}
+
+// Headers fixed at startup, e.g. passed via `--additional_header`.
+pub struct StaticHeaders {
+    headers: Vec<String>,
+}
+
+impl StaticHeaders {
+    pub fn new(headers: Vec<String>) -> Self {
+        StaticHeaders { headers }
+    }
+}
+
+impl CredentialsProvider for StaticHeaders {
+    fn headers(&self) -> io::Result<Vec<String>> {
+        Ok(self.headers.clone())
+    }
+}
+
+// Headers of a single resource sent along with those of another provider, e.g. the per-file headers of a tree
+// manifest next to the credentials of the mount. They replace headers of the same name of the provider.
+pub struct AddedHeaders {
+    headers: Vec<String>,
+    inner: Arc<dyn CredentialsProvider>,
+}
+
+impl AddedHeaders {
+    pub fn new(headers: Vec<String>, inner: Arc<dyn CredentialsProvider>) -> Self {
+        AddedHeaders { headers, inner }
+    }
+}
+
+impl CredentialsProvider for AddedHeaders {
+    fn headers(&self) -> io::Result<Vec<String>> {
+        let mut headers = self.inner.headers()?;
+        headers.retain(|header| !self.headers.iter().any(|added| header_name(added) == header_name(header)));
+        headers.extend(self.headers.iter().cloned());
+        Ok(headers)
+    }
+
+    fn refresh(&self) -> io::Result<()> {
+        self.inner.refresh()
+    }
+
+    fn refreshes_forbidden(&self) -> bool {
+        self.inner.refreshes_forbidden()
+    }
+}
+
+// Lowercase name of a header line, None if it has no colon.
+fn header_name(header: &str) -> Option<String> {
+    header.split_once(':').map(|(name, _)| name.trim().to_ascii_lowercase())
+}
+
+// Headers replaced by the output of a command whenever the server rejects the credentials, e.g. a script
+// fetching a short-lived token. The command is run with `sh -c` and prints header lines like
+// "Authorization: Bearer ..."; each replaces the header of the same name, others are added.
+// "Set-Cookie: ..." lines, e.g. the response headers of a login request, are sent back as one "Cookie" header.
+pub struct CommandCredentials {
+    command: String,
+    headers: Mutex<Vec<String>>,
+    refreshed_at: Mutex<Option<Instant>>,
+}
+
+impl CommandCredentials {
+    // Starts with `headers` until the first refresh.
+    pub fn new(command: &str, headers: Vec<String>) -> Self {
+        CommandCredentials {
+            command: command.to_string(),
+            headers: Mutex::new(headers),
+            refreshed_at: Mutex::new(None),
+        }
+    }
+
+    fn run_command(&self) -> io::Result<Vec<String>> {
+        let output = Command::new("sh").arg("-c").arg(&self.command).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!("{:?} failed with {}: {}", self.command, output.status, stderr.trim())));
+        }
+        let mut headers = Vec::new();
+        let mut cookies = Vec::new();
+        for line in String::from_utf8_lossy(&output.stdout).lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match line.split_once(':') {
+                // only the name=value pair, without attributes like Path or Expires
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("set-cookie") => {
+                    cookies.push(value.split(';').next().unwrap_or_default().trim().to_string());
+                }
+                _ => headers.push(validate_header(line).map_err(io::Error::other)?),
+            }
+        }
+        if !cookies.is_empty() {
+            headers.push(format!("Cookie: {}", cookies.join("; ")));
+        }
+        Ok(headers)
+    }
+
+    // Runs the command before the first request, e.g. to log into a session-based portal.
+    pub fn login(&self) -> io::Result<()> {
+        let fresh = self.run_command()?;
+        self.replace_headers(fresh);
+        *self.refreshed_at.lock().unwrap() = Some(Instant::now());
+        info!("Logged in with {:?}", self.command);
+        Ok(())
+    }
+
+    fn replace_headers(&self, fresh: Vec<String>) {
+        let mut headers = self.headers.lock().unwrap();
+        headers.retain(|header| !fresh.iter().any(|new| header_name(new) == header_name(header)));
+        headers.extend(fresh);
+    }
+}
+
+impl CredentialsProvider for CommandCredentials {
+    fn headers(&self) -> io::Result<Vec<String>> {
+        Ok(self.headers.lock().unwrap().clone())
+    }
+
+    fn refresh(&self) -> io::Result<()> {
+        // held while the command runs, so that concurrent rejections wait for its headers instead of running it again
+        let mut refreshed_at = self.refreshed_at.lock().unwrap();
+        if refreshed_at.is_some_and(|at| at.elapsed() < REFRESH_INTERVAL) {
+            debug!("Credentials have just been refreshed");
+            return Ok(());
+        }
+        let fresh = self.run_command()?;
+        self.replace_headers(fresh);
+        *refreshed_at = Some(Instant::now());
+        info!("Credentials have been refreshed");
+        Ok(())
+    }
+
+    fn refreshes_forbidden(&self) -> bool {
+        true
+    }
+}
+
+// Reads a password from the terminal without echoing it, e.g. of `--user` given without one.
+pub fn prompt_password(prompt: &str) -> io::Result<String> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    write!(tty, "{}", prompt)?;
+    tty.flush()?;
+    let fd = tty.as_raw_fd();
+    // SAFETY: termios is plain data filled by tcgetattr, `fd` is open for the whole function
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut hidden = original;
+    hidden.c_lflag &= !libc::ECHO;
+    hidden.c_lflag |= libc::ECHONL;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut password = String::new();
+    let read = BufReader::new(&tty).read_line(&mut password);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    read?;
+    Ok(password.trim_end_matches(['\n', '\r']).to_string())
+}
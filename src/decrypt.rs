@@ -0,0 +1,212 @@
+// Decryption of resources encrypted client-side in chunks with AES-256-GCM, so that encrypted blobs
+// in untrusted storage are exposed as their plaintext without downloading them as a whole.
+//
+// The resource starts with a random nonce prefix of 7 bytes, followed by the chunks. Every chunk of
+// the plaintext but the last one has the chunk size, and is stored encrypted along with its 16-byte tag.
+// The nonce of a chunk is the prefix, the big-endian 32-bit chunk index and a byte 1 for the last chunk,
+// 0 otherwise, so that chunks can't be reordered and truncation of the resource at a chunk boundary is detected.
+//
+//     prefix (7) | chunk 0 + tag (chunk_size + 16) | chunk 1 + tag | ... | last chunk + tag (1..=chunk_size + 16)
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use libc::EIO;
+use log::{debug, error};
+
+use crate::span::Span;
+
+pub const NONCE_PREFIX_LEN: usize = 7;
+pub const TAG_LEN: usize = 16;
+// How many decrypted chunks are kept in memory, so small sequential reads don't refetch a chunk
+const DECRYPTED_CHUNKS_CACHE: usize = 4;
+
+pub type EncryptionKey = [u8; 32];
+
+pub struct Decryption {
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    chunk_size: usize,
+    decrypted_chunks: Mutex<VecDeque<(usize, Arc<Vec<u8>>)>>,
+}
+
+// Loads a key file holding the 32 bytes of the key, either raw or as 64 hex digits.
+pub fn load_key(path: &Path) -> io::Result<EncryptionKey> {
+    let content = fs::read(path)?;
+    let key = match std::str::from_utf8(&content).ok().and_then(|text| hex::decode(text.trim()).ok()) {
+        Some(key) => key,
+        None => content,
+    };
+    key.try_into().map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+        format!("{}: expected a key of 32 bytes or 64 hex digits", path.display())))
+}
+
+impl Decryption {
+    // `nonce_prefix` is the beginning of the resource.
+    pub fn new(key: &EncryptionKey, nonce_prefix: &[u8], chunk_size: usize) -> io::Result<Self> {
+        let nonce_prefix = nonce_prefix.try_into().map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Encrypted resource is shorter than its nonce prefix of {} bytes", NONCE_PREFIX_LEN)))?;
+        if chunk_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Chunk size must not be zero"));
+        }
+        Ok(Decryption {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+            nonce_prefix,
+            chunk_size,
+            decrypted_chunks: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    // Size of the plaintext of a resource of `remote_size` bytes.
+    pub fn plain_size(&self, remote_size: usize) -> usize {
+        let encrypted = remote_size.saturating_sub(NONCE_PREFIX_LEN);
+        let chunks = encrypted.div_ceil(self.chunk_size + TAG_LEN);
+        encrypted.saturating_sub(chunks * TAG_LEN)
+    }
+
+    // Range of the resource holding the chunks of the plaintext `span`.
+    pub fn remote_span(&self, span: Span, remote_size: usize) -> Span {
+        let first = span.start() / self.chunk_size;
+        let last = span.end().div_ceil(self.chunk_size);
+        Span::new(self.chunk_start(first), self.chunk_start(last)).clamp_end(remote_size)
+    }
+
+    fn chunk_start(&self, index: usize) -> usize {
+        index.saturating_mul(self.chunk_size + TAG_LEN).saturating_add(NONCE_PREFIX_LEN)
+    }
+
+    // Reads `offset..offset + size` of the plaintext, fetching the chunks holding it with `read_range`.
+    pub fn read(
+        &self,
+        offset: usize,
+        size: usize,
+        remote_size: usize,
+        read_range: impl Fn(usize, usize) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        let end = self.plain_size(remote_size).min(offset.saturating_add(size));
+        let mut data = Vec::with_capacity(end.saturating_sub(offset));
+        let mut position = offset;
+        while position < end {
+            let index = position / self.chunk_size;
+            let chunk_start = index * self.chunk_size;
+            let chunk = self.get_decrypted_chunk(index, remote_size, &read_range)?;
+            let from = position - chunk_start;
+            let to = (end - chunk_start).min(chunk.len());
+            if from >= to {
+                break;
+            }
+            data.extend_from_slice(&chunk[from..to]);
+            position = chunk_start + to;
+        }
+        Ok(data)
+    }
+
+    fn get_decrypted_chunk(
+        &self,
+        index: usize,
+        remote_size: usize,
+        read_range: impl Fn(usize, usize) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<Vec<u8>>> {
+        if let Some((_, chunk)) = self.decrypted_chunks.lock().unwrap().iter().find(|(i, _)| *i == index) {
+            return Ok(Arc::clone(chunk));
+        }
+        let span = Span::new(self.chunk_start(index), self.chunk_start(index + 1)).clamp_end(remote_size);
+        let encrypted = read_range(span.start(), span.len())?;
+        let chunk_index = u32::try_from(index).map_err(|_| io::Error::from_raw_os_error(EIO))?;
+        let mut nonce = [0; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&chunk_index.to_be_bytes());
+        nonce[11] = (span.end() == remote_size) as u8;
+        let chunk = self.cipher.decrypt(Nonce::from_slice(&nonce), encrypted.as_slice()).map_err(|_| {
+            error!("Chunk {} ({:?}) can't be decrypted, the key is wrong or the data is corrupted", index, span);
+            io::Error::from_raw_os_error(EIO)
+        })?;
+        debug!("Chunk {} has been decrypted", index);
+
+        let chunk = Arc::new(chunk);
+        let mut decrypted_chunks = self.decrypted_chunks.lock().unwrap();
+        decrypted_chunks.push_back((index, Arc::clone(&chunk)));
+        if decrypted_chunks.len() > DECRYPTED_CHUNKS_CACHE {
+            decrypted_chunks.pop_front();
+        }
+        Ok(chunk)
+    }
+
+    // Forgets decrypted chunks, e.g. of a previous version of the resource.
+    pub fn clear(&self) {
+        self.decrypted_chunks.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: EncryptionKey = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+        16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    ];
+    const PLAINTEXT: &[u8] = b"The quick brown fox jumps";
+    const CHUNK_SIZE: usize = 8;
+
+    // PLAINTEXT encrypted with KEY, the nonce prefix "httpfs!" and chunks of CHUNK_SIZE by another implementation
+    fn encrypted() -> Vec<u8> {
+        hex::decode(concat!(
+            "687474706673216ec51e4b866790f4d9985512ae81745d5f91fce188292614a1",
+            "83bcd2cc3f48fe927c56b75bb20c64445bd140f01005b304b8db3ff2f20fc1f8",
+            "bcbbfccd21e8e51b06f59fbba79a275d2e1cc3fc827c6889020b345dcfade382",
+        )).unwrap()
+    }
+
+    fn read(key: &EncryptionKey, encrypted: &[u8], offset: usize, size: usize) -> io::Result<Vec<u8>> {
+        let decryption = Decryption::new(key, &encrypted[..NONCE_PREFIX_LEN], CHUNK_SIZE)?;
+        decryption.read(offset, size, encrypted.len(), |start, len| Ok(encrypted[start..start + len].to_vec()))
+    }
+
+    #[test]
+    fn known_resource_is_decrypted() {
+        let encrypted = encrypted();
+        let decryption = Decryption::new(&KEY, &encrypted[..NONCE_PREFIX_LEN], CHUNK_SIZE).unwrap();
+        assert_eq!(decryption.plain_size(encrypted.len()), PLAINTEXT.len());
+        assert_eq!(read(&KEY, &encrypted, 0, 1000).unwrap(), PLAINTEXT);
+        assert_eq!(read(&KEY, &encrypted, PLAINTEXT.len(), 10).unwrap(), b"");
+    }
+
+    #[test]
+    fn read_starting_mid_chunk_fetches_only_its_chunks() {
+        let encrypted = encrypted();
+        let decryption = Decryption::new(&KEY, &encrypted[..NONCE_PREFIX_LEN], CHUNK_SIZE).unwrap();
+        let fetched = Mutex::new(vec![]);
+        let data = decryption.read(11, 10, encrypted.len(), |start, len| {
+            fetched.lock().unwrap().push(Span::with_len(start, len));
+            Ok(encrypted[start..start + len].to_vec())
+        }).unwrap();
+        assert_eq!(data, &PLAINTEXT[11..21]);
+        // chunks 1 and 2, each stored with its tag after the nonce prefix
+        assert_eq!(fetched.into_inner().unwrap(), vec![Span::with_len(31, 24), Span::with_len(55, 24)]);
+        assert_eq!(decryption.remote_span(Span::new(11, 21), encrypted.len()), Span::new(31, 79));
+    }
+
+    #[test]
+    fn wrong_key_or_tampered_data_fails_with_eio() {
+        let encrypted = encrypted();
+        let mut wrong_key = KEY;
+        wrong_key[0] ^= 1;
+        assert_eq!(read(&wrong_key, &encrypted, 0, 8).unwrap_err().raw_os_error(), Some(EIO));
+
+        // the tag of the last chunk
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(read(&KEY, &tampered, 0, 1000).unwrap_err().raw_os_error(), Some(EIO));
+        assert_eq!(read(&KEY, &tampered, 0, 8).unwrap(), &PLAINTEXT[..8]);
+
+        // a resource cut after a chunk whose nonce doesn't mark it as the last one
+        let truncated = &encrypted[..NONCE_PREFIX_LEN + 3 * (CHUNK_SIZE + TAG_LEN)];
+        assert_eq!(read(&KEY, truncated, 16, 8).unwrap_err().raw_os_error(), Some(EIO));
+    }
+}
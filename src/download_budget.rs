@@ -0,0 +1,91 @@
+// Limit of the bytes a mount downloads, protecting against runaway egress bills caused by pathological
+// access patterns, e.g. a scan re-reading a large file in random order. Once the limit is exceeded no new
+// requests are sent and running transfers are aborted, so reads not served from buffered data fail with EIO.
+// The budget also counts the downloads of each mounted file, to report them at unmount and on `/metrics`.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use libc::EIO;
+use log::error;
+
+#[derive(Default)]
+pub struct DownloadBudget {
+    // None doesn't limit the downloads, which are still counted
+    limit: Option<u64>,
+    downloaded: AtomicU64,
+    exceeded: AtomicBool,
+    // bytes downloaded for each file, by its path in the mount
+    files: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+}
+
+impl DownloadBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        DownloadBudget { limit, ..DownloadBudget::default() }
+    }
+
+    // Counts received body bytes, returns false once they exceed the limit.
+    pub fn record(&self, bytes: usize) -> bool {
+        let downloaded = self.downloaded.fetch_add(bytes as u64, Ordering::SeqCst) + bytes as u64;
+        match self.limit {
+            Some(limit) if downloaded > limit => {
+                if !self.exceeded.swap(true, Ordering::SeqCst) {
+                    error!("Download limit of {} bytes is exceeded, reads needing new requests fail from now on", limit);
+                }
+                false
+            }
+            _ => true,
+        }
+    }
+
+    // Returns EIO once the limit is exceeded.
+    pub fn check(&self) -> io::Result<()> {
+        if self.exceeded.load(Ordering::SeqCst) {
+            return Err(io::Error::from_raw_os_error(EIO));
+        }
+        Ok(())
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::SeqCst)
+    }
+
+    // Returns the counter of the downloads of the file at `path`, the same one for all transports of the file.
+    pub fn file_counter(&self, path: &str) -> Arc<AtomicU64> {
+        Arc::clone(self.files.lock().unwrap().entry(path.to_string()).or_default())
+    }
+
+    // Reports the downloads counted for the file at `from` as those of `to` from now on, e.g. once the server
+    // has named the file. Its transports go on counting with the same counter.
+    pub fn rename_file(&self, from: &str, to: &str) {
+        let mut files = self.files.lock().unwrap();
+        if let Some(counter) = files.remove(from) {
+            files.insert(to.to_string(), counter);
+        }
+    }
+
+    // Returns the bytes downloaded for each file, ordered by path.
+    pub fn downloaded_by_file(&self) -> Vec<(String, u64)> {
+        self.files.lock().unwrap().iter()
+            .map(|(path, counter)| (path.clone(), counter.load(Ordering::SeqCst)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renamed_files_keep_counting() {
+        let budget = DownloadBudget::default();
+        let counter = budget.file_counter("file");
+        counter.fetch_add(5, Ordering::SeqCst);
+        budget.rename_file("file", "data.bin");
+        counter.fetch_add(2, Ordering::SeqCst);
+        budget.file_counter("other.bin").fetch_add(1, Ordering::SeqCst);
+        assert_eq!(budget.downloaded_by_file(), vec![("data.bin".to_string(), 7), ("other.bin".to_string(), 1)]);
+    }
+}
@@ -0,0 +1,115 @@
+// Copy of the resource to a local file through the readers, with everything they add: retries, mirrors,
+// verification, decryption etc. The copy is written to `<path>.part` and renamed to `<path>` once complete.
+// An interrupted copy is resumed from the end of the part file, as long as the resource still has the size
+// and ETag recorded next to it in `<path>.part.meta`; otherwise it starts over.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::reader_pool::ReaderPool;
+use crate::units::format_size;
+
+// How often the progress line is redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+// Content of the meta file, identifying the version of the resource the part file holds the beginning of.
+fn identity(pool: &ReaderPool, resource_etag: Option<&str>) -> String {
+    format!("size {}\netag {}\n", pool.file_size(), resource_etag.unwrap_or(""))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// Copies the resource of `pool`, whose ETag is `resource_etag`, into `path`, resuming an interrupted copy.
+pub fn export(pool: &ReaderPool, resource_etag: Option<&str>, path: &Path) -> io::Result<()> {
+    let part_path = with_suffix(path, ".part");
+    let meta_path = with_suffix(path, ".part.meta");
+    let identity = identity(pool, resource_etag);
+    let file_size = pool.file_size();
+
+    let resumable = fs::read_to_string(&meta_path).is_ok_and(|recorded| recorded == identity);
+    let start = match fs::metadata(&part_path) {
+        Ok(metadata) if resumable && metadata.len() as usize <= file_size => metadata.len() as usize,
+        Ok(_) => {
+            warn!("{} doesn't match the resource anymore, starting over", part_path.display());
+            0
+        }
+        Err(_) => 0,
+    };
+    if start > 0 {
+        info!("Resuming the copy at {} of {}", format_size(start as u64), format_size(file_size as u64));
+    }
+    fs::write(&meta_path, &identity)?;
+    let part = match start {
+        0 => File::create(&part_path)?,
+        _ => OpenOptions::new().append(true).open(&part_path)?,
+    };
+    let mut part = Progress::new(part, start, file_size);
+    let copied = pool.copy_range(start, file_size, &mut part);
+    part.finish();
+    copied?;
+    part.file.sync_all()?;
+
+    fs::rename(&part_path, path)?;
+    fs::remove_file(&meta_path)?;
+    Ok(())
+}
+
+// Counts the bytes written into the file, redrawing a progress line on stderr if it is a terminal.
+struct Progress {
+    file: File,
+    written: usize,
+    total: usize,
+    resumed_at: usize,
+    started: Instant,
+    drawn: Option<Instant>,
+    draw: bool,
+}
+
+impl Progress {
+    fn new(file: File, written: usize, total: usize) -> Self {
+        let draw = io::stderr().is_terminal();
+        Progress { file, written, total, resumed_at: written, started: Instant::now(), drawn: None, draw }
+    }
+
+    fn redraw(&mut self) {
+        if !self.draw {
+            return;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { (self.written - self.resumed_at) as f64 / elapsed } else { 0.0 };
+        let percent = if self.total > 0 { self.written as f64 * 100.0 / self.total as f64 } else { 100.0 };
+        eprint!("\r{} / {} ({:.1}%) {}/s    ", format_size(self.written as u64), format_size(self.total as u64),
+            percent, format_size(rate as u64));
+        self.drawn = Some(Instant::now());
+    }
+
+    fn finish(&mut self) {
+        self.redraw();
+        if self.draw {
+            eprintln!();
+        }
+    }
+}
+
+impl Write for Progress {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written;
+        if self.drawn.is_none_or(|drawn| drawn.elapsed() >= PROGRESS_INTERVAL) {
+            self.redraw();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,53 @@
+// Scheduling priority of the threads downloading ahead of reads: the IO thread driving the readers'
+// transfers and the worker of prefetch hints. On a busy host a lower priority keeps decrypting and
+// copying background data from competing with the latency-sensitive application for CPU time.
+// The priorities are set per thread, the threads serving FUSE requests keep the priority of the process.
+
+use std::io;
+use std::sync::OnceLock;
+
+use libc::{c_int, c_long, setpriority, syscall, SYS_ioprio_set, PRIO_PROCESS};
+use log::{debug, warn};
+
+// ioprio_set(2) constants, not exported by libc
+const IOPRIO_WHO_PROCESS: c_int = 1;
+const IOPRIO_CLASS_IDLE: c_long = 3;
+const IOPRIO_CLASS_SHIFT: c_long = 13;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchPriority {
+    // nice value, from -20 to 19, None keeps the one of the process
+    pub nice: Option<i32>,
+    // disk IO of the threads only happens when the whole system is otherwise idle,
+    // which the cgroup IO controller respects as well
+    pub idle_io: bool,
+}
+
+static PRIORITY: OnceLock<FetchPriority> = OnceLock::new();
+
+// Sets the priority of fetch threads started afterwards, only the first call has an effect.
+pub fn set_fetch_priority(priority: FetchPriority) {
+    if PRIORITY.set(priority).is_err() {
+        warn!("The priority of fetch threads is already set");
+    }
+}
+
+// Applies the configured priority to the calling thread, called by fetch threads as they start.
+pub(crate) fn apply() {
+    let Some(priority) = PRIORITY.get() else {
+        return;
+    };
+    if let Some(nice) = priority.nice {
+        // SAFETY: plain syscall, on Linux who 0 is the calling thread rather than the whole process
+        if unsafe { setpriority(PRIO_PROCESS, 0, nice) } != 0 {
+            warn!("Unable to set the nice value of a fetch thread to {}: {}", nice, io::Error::last_os_error());
+        }
+    }
+    if priority.idle_io {
+        // SAFETY: plain syscall, who 0 is the calling thread
+        if unsafe { syscall(SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT) } != 0 {
+            warn!("Unable to set the IO priority of a fetch thread: {}", io::Error::last_os_error());
+        }
+    }
+    debug!("Fetch thread runs with {:?}", priority);
+}
@@ -0,0 +1,167 @@
+// C interface, built into the `cdylib` target. See `include/httpfs.h` for the declarations.
+//
+// Functions returning `c_int` return 0 on success and a negative errno value on failure.
+// Safety: every pointer argument must be NULL or obtained from the matching `httpfs_*` function,
+// strings must be NUL-terminated, and a handle must not be used after it was freed.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_int, CStr};
+use std::io;
+use std::ptr;
+
+use libc::{EINVAL, EIO};
+use log::warn;
+
+use crate::file_system::{parse_file_name, HttpFs};
+use crate::http_meta_reader::HttpMetaReader;
+use crate::mount::{mount_options, Mount, MountHandle};
+use crate::reader_pool::ReaderPool;
+use crate::transport::Transport;
+
+pub struct HttpfsConfig {
+    url: String,
+    mountpoint: String,
+    file_name: String,
+    additional_headers: Vec<String>,
+    auto_unmount: bool,
+    allow_root: bool,
+}
+
+pub struct HttpfsMount {
+    handle: MountHandle,
+}
+
+unsafe fn to_string(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok().map(String::from)
+}
+
+fn to_errno(e: &io::Error) -> c_int {
+    -e.raw_os_error().unwrap_or(EIO)
+}
+
+// Creates a configuration for mounting `url` at `mountpoint`. Returns NULL if an argument is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_config_new(url: *const c_char, mountpoint: *const c_char) -> *mut HttpfsConfig {
+    let (Some(url), Some(mountpoint)) = (to_string(url), to_string(mountpoint)) else {
+        return ptr::null_mut();
+    };
+    Box::into_raw(Box::new(HttpfsConfig {
+        url,
+        mountpoint,
+        file_name: String::from("file"),
+        additional_headers: vec![],
+        auto_unmount: false,
+        allow_root: false,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_config_free(config: *mut HttpfsConfig) {
+    if !config.is_null() {
+        drop(Box::from_raw(config));
+    }
+}
+
+// Names the file in the root of the mount. Names `--file_name` rejects, e.g. empty ones or with a slash, are invalid.
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_config_set_file_name(config: *mut HttpfsConfig, file_name: *const c_char) -> c_int {
+    let file_name = to_string(file_name).and_then(|name| parse_file_name(&name).ok());
+    match (config.as_mut(), file_name) {
+        (Some(config), Some(file_name)) => {
+            config.file_name = file_name;
+            0
+        }
+        _ => -EINVAL,
+    }
+}
+
+// Adds a full header line, e.g. "Authorization: Bearer ...".
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_config_add_header(config: *mut HttpfsConfig, header: *const c_char) -> c_int {
+    match (config.as_mut(), to_string(header)) {
+        (Some(config), Some(header)) => {
+            config.additional_headers.push(header);
+            0
+        }
+        _ => -EINVAL,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_config_set_auto_unmount(config: *mut HttpfsConfig, value: bool) -> c_int {
+    match config.as_mut() {
+        Some(config) => {
+            config.auto_unmount = value;
+            0
+        }
+        None => -EINVAL,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_config_set_allow_root(config: *mut HttpfsConfig, value: bool) -> c_int {
+    match config.as_mut() {
+        Some(config) => {
+            config.allow_root = value;
+            0
+        }
+        None => -EINVAL,
+    }
+}
+
+// Mounts the resource in the background and stores the handle into `out`.
+// The configuration is not consumed and may be freed right after the call.
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_mount(config: *const HttpfsConfig, out: *mut *mut HttpfsMount) -> c_int {
+    let Some(config) = config.as_ref() else {
+        return -EINVAL;
+    };
+    if out.is_null() {
+        return -EINVAL;
+    }
+    let transport = Transport::with_headers(config.additional_headers.clone());
+    let file_size = match HttpMetaReader::new(&config.url, transport.clone()).fetch_file_size() {
+        Ok(size) => size,
+        Err(e) => {
+            warn!("Unable to fetch the size of {}: {}", config.url, e);
+            return to_errno(&e);
+        }
+    };
+    let fs = HttpFs::new(ReaderPool::new(&config.url, file_size, transport), &config.file_name);
+    let options = mount_options(config.auto_unmount, config.allow_root);
+    match Mount::spawn(fs, &config.mountpoint, &options) {
+        Ok(handle) => {
+            *out = Box::into_raw(Box::new(HttpfsMount { handle }));
+            0
+        }
+        Err(e) => {
+            warn!("Unable to mount {} at {}: {}", config.url, config.mountpoint, e);
+            to_errno(&e)
+        }
+    }
+}
+
+// Returns 1 while the filesystem is mounted and 0 after it was unmounted from outside.
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_mount_is_alive(mount: *const HttpfsMount) -> c_int {
+    match mount.as_ref() {
+        Some(mount) => mount.handle.is_alive() as c_int,
+        None => 0,
+    }
+}
+
+// Unmounts the filesystem and frees the handle.
+#[no_mangle]
+pub unsafe extern "C" fn httpfs_unmount(mount: *mut HttpfsMount) -> c_int {
+    if mount.is_null() {
+        return -EINVAL;
+    }
+    let mount = Box::from_raw(mount);
+    match mount.handle.unmount() {
+        Ok(()) => 0,
+        Err(e) => to_errno(&e),
+    }
+}
@@ -0,0 +1,57 @@
+// Name of the mounted file of a resource, so that tools keying off extensions, e.g. media players or unzip,
+// recognize it: the `filename` of `Content-Disposition` if the server sends one, like browsers save
+// downloads, or else the last segment of the path of the url.
+
+use crate::file_system::parse_file_name;
+use crate::percent_encoding::percent_decode;
+
+// Returns the name, None if neither the header nor the url gives a usable one.
+pub fn file_name_of(url: &str, content_disposition: Option<&str>) -> Option<String> {
+    // names which would be a path rather than a file in the root of the mount are ignored
+    content_disposition.and_then(disposition_file_name).and_then(|name| parse_file_name(&name).ok())
+        .or_else(|| url_file_name(url).and_then(|name| parse_file_name(&name).ok()))
+}
+
+// The name of e.g. `attachment; filename="report.pdf"`, preferring the UTF-8 `filename*=UTF-8''r%C3%A9sum%C3%A9.pdf`.
+fn disposition_file_name(value: &str) -> Option<String> {
+    let parameters: Vec<(String, &str)> = value.split(';').skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim()))
+        .collect();
+    let parameter = |name: &str| parameters.iter().find(|(n, _)| n == name).map(|(_, value)| *value);
+    let extended = parameter("filename*").and_then(|value| {
+        let (charset, rest) = value.split_once('\'')?;
+        let (_language, encoded) = rest.split_once('\'')?;
+        charset.eq_ignore_ascii_case("utf-8").then(|| percent_decode(encoded)).flatten()
+    });
+    extended.or_else(|| {
+        let value = parameter("filename")?;
+        match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            Some(quoted) => Some(quoted.replace("\\\"", "\"").replace("\\\\", "\\")),
+            None => Some(value.to_string()),
+        }
+    })
+}
+
+// The last segment of the path of `url`, None if the url has no path.
+fn url_file_name(url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest).split_once('/')?.1;
+    percent_decode(path.rsplit('/').next()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name_is_taken_from_content_disposition_or_url() {
+        let url = "https://example.com/data/train%20set.tar?sig=abc#part";
+        assert_eq!(file_name_of(url, None).as_deref(), Some("train set.tar"));
+        assert_eq!(file_name_of(url, Some("attachment; filename=\"report.pdf\"")).as_deref(), Some("report.pdf"));
+        let extended = "attachment; filename=\"resume.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf";
+        assert_eq!(file_name_of(url, Some(extended)).as_deref(), Some("résumé.pdf"));
+        assert_eq!(file_name_of(url, Some("attachment; filename=\"../etc/passwd\"")).as_deref(), Some("train set.tar"));
+        assert_eq!(file_name_of("https://example.com/", Some("inline")), None);
+    }
+}
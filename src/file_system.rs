@@ -1,217 +1,750 @@
+// The files and directories of a mount and the answers to requests about them, independent of the kernel
+// interface: `fuse` translates the requests of fuser, a binding of another platform would do the same.
+
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, SystemTime};
-
-use fuser::{
-    FileAttr, Filesystem, FileType, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    Request,
-};
-use libc::ENOENT;
+use std::fs::Metadata;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use libc::{EACCES, EINVAL, EIO, ENOENT, EROFS};
 use log::{debug, warn};
-use users::{get_current_gid, get_current_uid};
+use sha2::{Digest, Sha256};
+
+use crate::audit_log::{AuditLog, Requester};
+use crate::overlay::Overlay;
+use crate::reader_pool::ReaderPool;
+use crate::span::Span;
+use crate::units::parse_byte_range;
+
+// The size may shrink when the remote resource does, the kernel learns it after this time.
+// Cached pages are dropped on every open, since files are not opened with FOPEN_KEEP_CACHE.
+pub(crate) const FILE_INFO_CACHE_TTL: Duration = Duration::from_secs(60);
+
+pub(crate) const DIR_INO: u64 = 1;
+pub const FILE_INO: u64 = 2;
+const HEADERS_FILE_INO: u64 = 3;
+const CONTROL_DIR_INO: u64 = 4;
+const PREFETCH_HINTS_INO: u64 = 5;
+const REFRESH_INO: u64 = 6;
+
+const CONTROL_DIR_NAME: &str = ".httpfs";
+const PREFETCH_HINTS_NAME: &str = "prefetch";
+const REFRESH_NAME: &str = "refresh";
+
+// Inodes of files and directories added with `with_file` have this bit set, above the inodes of the overlay
+const HASHED_INO_BIT: u64 = 1 << 63;
+
+enum Content {
+    Remote(Arc<ReaderPool>),
+    // e.g. the headers sidecar of a remote file
+    Text(String),
+    Dir,
+}
+
+// How the platform layer shows a node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum NodeKind {
+    File,
+    // the files of `.httpfs`, which are written to and always empty
+    ControlFile,
+    Directory,
+}
+
+// Attributes of a node, translated by the platform layer for its kernel interface.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NodeAttr {
+    pub ino: u64,
+    pub kind: NodeKind,
+    pub size: u64,
+    pub accessed: SystemTime,
+    pub modified: SystemTime,
+    pub created: SystemTime,
+}
+
+// A file or directory of the tree below the root
+struct Node {
+    parent: u64,
+    name: String,
+    content: Content,
+}
+
+// Parses NAME=URL of `--url`. The name is a file name in the root of the mount, without slashes.
+pub fn parse_named_url(value: &str) -> Result<(String, String), String> {
+    let (name, url) = value.split_once('=').ok_or("Expected NAME=URL")?;
+    let name = parse_file_name(name)?;
+    if url.is_empty() {
+        return Err("The URL is missing".to_string());
+    }
+    Ok((name, url.to_string()))
+}
+
+// Parses the name of a file in the root of the mount, e.g. of `--file_name`.
+pub fn parse_file_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains(['/', '\0']) || name == "." || name == ".." || name == CONTROL_DIR_NAME {
+        return Err(format!("{:?} is not a valid file name", name));
+    }
+    Ok(name.to_string())
+}
+
+// Checks that the paths of the files of a mount, e.g. `images/train/0001.jpg`, are relative, without empty,
+// `.` or `..` components, unique, and that none of them is a directory of another one.
+pub fn check_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+    let mut files = BTreeSet::new();
+    let mut dirs = BTreeSet::new();
+    for path in paths {
+        let components: Vec<&str> = path.split('/').collect();
+        if components.iter().any(|component| component.is_empty() || *component == "." || *component == "..")
+            || components[0] == CONTROL_DIR_NAME
+        {
+            return Err(format!("{:?} is not a valid path", path));
+        }
+        if !files.insert(path) {
+            return Err(format!("More than one file is at {}", path));
+        }
+        let mut end = 0;
+        for component in &components[..components.len() - 1] {
+            end += component.len();
+            dirs.insert(&path[..end]);
+            end += 1;
+        }
+    }
+    match files.intersection(&dirs).next() {
+        Some(path) => Err(format!("{} is both a file and a directory", path)),
+        None => Ok(()),
+    }
+}
+
+// Local users allowed to open and read the files of a mount shared with `allow_other`. A denied uid is
+// refused even if it's allowed; with an empty allow list all other users are allowed.
+#[derive(Clone, Debug, Default)]
+pub struct UidAccess {
+    pub allowed: Vec<u32>,
+    pub denied: Vec<u32>,
+}
 
-use crate::http_reader::{DataAddr, HttpReader};
+impl UidAccess {
+    pub fn permits(&self, uid: u32) -> bool {
+        !self.denied.contains(&uid) && (self.allowed.is_empty() || self.allowed.contains(&uid))
+    }
+}
 
-const FILE_INFO_CACHE_TTL: Duration = Duration::from_secs(60);
-const MAX_READERS: usize = 5;
-const REREAD_ATTEMPTS: u8 = 5;
+// Files and directories below the root: the main file, its headers and the added ones
+#[derive(Default)]
+struct Tree {
+    // by their inodes
+    nodes: BTreeMap<u64, Node>,
+    // inodes of the nodes by their parent and name
+    children: BTreeMap<(u64, String), u64>,
+}
 
+// Adds and removes remote files of a file system while it is mounted, e.g. for the control socket.
+#[derive(Clone)]
+pub struct MountedFiles {
+    tree: Arc<Mutex<Tree>>,
+}
 
 pub struct HttpFs {
-    readers: Arc<Mutex<Vec<Arc<HttpReader>>>>,
-    file_size: usize,
-    file_name: String,
-    resource_url: String,
-    additional_headers: Vec<String>,
-    readers_counter: Arc<Mutex<usize>>, // just for logging
+    // shared with `MountedFiles`, locked while a request looks at the tree but not during remote reads
+    tree: Arc<Mutex<Tree>>,
+    // whether ranges to prefetch are accepted by writes to `.httpfs/prefetch`
+    prefetch_hints: bool,
+    // called on writes to `.httpfs/refresh`, if set
+    on_refresh: Option<Box<dyn Fn() + Send>>,
+    // when the file was last opened or read
+    last_access: Arc<Mutex<Instant>>,
+    audit_log: Option<AuditLog>,
+    uid_access: UidAccess,
+    // local files shown next to and shadowing the remote ones, if set
+    overlay: Option<Overlay>,
+    // timestamps of the directories and of files without a modification time, the same for all requests
+    created: SystemTime,
 }
 
 impl HttpFs {
-    pub fn new(url: &str, file_size: usize, file_name: &str, additional_headers: Vec<String>) -> Self {
+    // Serves the resource of `pool` as the file `file_name` in the root.
+    pub fn new(pool: ReaderPool, file_name: &str) -> Self {
+        let fs = Self::empty();
+        fs.tree().insert(FILE_INO, DIR_INO, file_name.to_string(), Content::Remote(Arc::new(pool)));
+        fs
+    }
+
+    // A file system without files, to add them with `with_file`.
+    pub fn empty() -> Self {
         HttpFs {
-            readers: Arc::new(Mutex::new(vec![])),
-            file_size,
-            file_name: String::from(file_name),
-            resource_url: String::from(url),
-            additional_headers,
-            readers_counter: Arc::new(Mutex::new(0)),
+            tree: Arc::new(Mutex::new(Tree::default())),
+            prefetch_hints: false,
+            on_refresh: None,
+            last_access: Arc::new(Mutex::new(Instant::now())),
+            audit_log: None,
+            uid_access: UidAccess::default(),
+            overlay: None,
+            created: SystemTime::now(),
         }
     }
 
-    pub fn drain_data_from_suitable_reader(&self, offset: usize, size: usize) -> Result<Vec<u8>, ()> {
-        let addr = DataAddr::new(offset, size);
-        let arc = Arc::clone(&self.readers);
-        let mut readers = arc.lock().unwrap();
+    // Adds the remote file at `path`, e.g. `images/train/0001.jpg`, read through `pool`, with its directories
+    // and the optional `headers` exposed as `<path>.headers`. Inodes are derived from the paths, so they stay
+    // the same across mounts. The paths of a mount must pass `check_paths`.
+    pub fn with_file(self, path: &str, pool: ReaderPool, headers: Option<String>) -> Self {
+        self.tree().add(path, pool, headers);
+        self
+    }
 
-        let mut res: Option<Vec<u8>> = None;
-        for reader in &*readers {
-            res = reader.try_drain_data(addr);
-            if res != None {
-                break;
-            }
+    // Exposes `headers`, e.g. the raw response headers of the metadata request, as `<file_name>.headers`.
+    pub fn with_headers_file(self, headers: String) -> Self {
+        let mut tree = self.tree();
+        if let Some(file) = tree.nodes.get(&FILE_INO) {
+            let name = format!("{}.headers", file.name);
+            tree.insert(HEADERS_FILE_INO, DIR_INO, name, Content::Text(headers));
         }
-        // no any suitable reader found, creating new
-        if res == None {
-            debug!("!------- Suitable reader not found, creating new...");
-
-            let reader = Arc::new(HttpReader::new(
-                &self.resource_url,
-                offset,
-                self.file_size,
-                self.additional_headers.clone(),
-                self.inc_and_get_readers_counter()
-            ));
-            let rc = Arc::clone(&reader);
-            thread::spawn(move || {
-                rc.fetching_loop();
-            });
-            debug!("HttpReader fetching loop has started");
-            res = reader.try_drain_data(addr);
-            readers.push(reader);
-
-            if readers.len() > MAX_READERS {
-                let stop_readers_to = readers.len() - MAX_READERS;
-                debug!("Readers 0..{} will be stopped", stop_readers_to);
-                for reader in &readers[0..stop_readers_to] {
-                    debug!("Call stop");
-                    reader.stop();
-                }
-                debug!("Readers {}..{} will work", stop_readers_to, readers.len());
-                *readers = readers[stop_readers_to..readers.len()].to_vec();
+        drop(tree);
+        self
+    }
+
+    // Exposes `.httpfs/prefetch`, where applications write ranges they are going to read, one `START-END`
+    // per line, e.g. `17M-80M`. The pool must accept hints, and the mount must not be read-only.
+    pub fn with_prefetch_hints(mut self) -> Self {
+        self.prefetch_hints = true;
+        self
+    }
+
+    // Exposes `.httpfs/refresh`, calling `on_refresh` on every write to it, e.g. to list a bucket again.
+    // The mount must not be read-only.
+    pub fn with_refresh(mut self, on_refresh: impl Fn() + Send + 'static) -> Self {
+        self.on_refresh = Some(Box::new(on_refresh));
+        self
+    }
+
+    // Records every open and read with the requesting process in `audit_log`.
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    // Refuses opens and reads of users `uid_access` doesn't permit with EACCES.
+    pub fn with_uid_access(mut self, uid_access: UidAccess) -> Self {
+        self.uid_access = uid_access;
+        self
+    }
+
+    // Shows the regular files of a local directory in the mount, shadowing remote files of the same name.
+    pub fn with_overlay(mut self, overlay: Overlay) -> Self {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    // Returns the handle adding and removing files, shared with the file system after it is mounted.
+    pub fn files(&self) -> MountedFiles {
+        MountedFiles { tree: Arc::clone(&self.tree) }
+    }
+
+    // Returns the time of the last open or read, shared with the file system after it is mounted.
+    pub fn last_access(&self) -> Arc<Mutex<Instant>> {
+        Arc::clone(&self.last_access)
+    }
+
+    fn touch(&self) {
+        *self.last_access.lock().unwrap() = Instant::now();
+    }
+
+    fn tree(&self) -> MutexGuard<'_, Tree> {
+        self.tree.lock().unwrap()
+    }
+
+    fn audit(&self, requester: Requester, operation: &str, ino: u64, span: Option<Span>, result: Result<(), i32>) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let file_name = match ino {
+            PREFETCH_HINTS_INO => format!("{}/{}", CONTROL_DIR_NAME, PREFETCH_HINTS_NAME),
+            REFRESH_INO => format!("{}/{}", CONTROL_DIR_NAME, REFRESH_NAME),
+            _ => match (self.overlay.as_ref().and_then(|overlay| overlay.name(ino)), self.tree().path(ino)) {
+                (Some(name), _) => name.to_string_lossy().into_owned(),
+                (None, Some(path)) => path,
+                (None, None) => ino.to_string(),
+            },
+        };
+        audit_log.record(requester, operation, &file_name, span, result);
+    }
+
+    // Pool of the main file or of a remote file added with `with_file`.
+    fn remote_pool(&self, ino: u64) -> Option<Arc<ReaderPool>> {
+        match self.tree().nodes.get(&ino).map(|node| &node.content) {
+            Some(Content::Remote(pool)) => Some(Arc::clone(pool)),
+            _ => None,
+        }
+    }
+
+    fn get_node_attr(&self, ino: u64, node: &Node) -> NodeAttr {
+        match &node.content {
+            Content::Remote(pool) => {
+                // the Last-Modified date, so that tools like rsync or make see the resource change only with it
+                let modified = pool.modified().unwrap_or(self.created);
+                let attr = self.get_regular_file_attr(ino, pool.file_size());
+                NodeAttr { accessed: modified, modified, created: modified, ..attr }
             }
-            debug!("Total readers now {}", readers.len());
+            Content::Text(text) => self.get_regular_file_attr(ino, text.len()),
+            Content::Dir => self.get_dir_attr(ino),
+        }
+    }
+
+    fn get_regular_file_attr(&self, ino: u64, size: usize) -> NodeAttr {
+        NodeAttr {
+            ino,
+            kind: NodeKind::File,
+            size: size as u64,
+            accessed: self.created,
+            modified: self.created,
+            created: self.created,
+        }
+    }
+
+    fn get_overlay_file_attr(&self, ino: u64, metadata: &Metadata) -> NodeAttr {
+        let modified = metadata.modified().unwrap_or(self.created);
+        NodeAttr { modified, ..self.get_regular_file_attr(ino, metadata.len() as usize) }
+    }
+
+    fn get_dir_attr(&self, ino: u64) -> NodeAttr {
+        NodeAttr { kind: NodeKind::Directory, ..self.get_regular_file_attr(ino, 0) }
+    }
+
+    // The files of `.httpfs` which are enabled, all of them written to and always empty.
+    fn control_files(&self) -> Vec<(u64, &'static str)> {
+        let mut files = vec![];
+        if self.prefetch_hints {
+            files.push((PREFETCH_HINTS_INO, PREFETCH_HINTS_NAME));
+        }
+        if self.on_refresh.is_some() {
+            files.push((REFRESH_INO, REFRESH_NAME));
+        }
+        files
+    }
+
+    fn is_control_file(&self, ino: u64) -> bool {
+        self.control_files().iter().any(|&(file, _)| file == ino)
+    }
+
+    fn get_control_file_attr(&self, ino: u64) -> NodeAttr {
+        NodeAttr { kind: NodeKind::ControlFile, ..self.get_regular_file_attr(ino, 0) }
+    }
+
+    // Hints the pool with the ranges written to `.httpfs/prefetch`, one per line.
+    fn hint_prefetch(&self, data: &[u8]) -> Result<(), String> {
+        let text = std::str::from_utf8(data).map_err(|_| "Prefetch hints are not UTF-8".to_string())?;
+        let ranges = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(parse_byte_range)
+            .collect::<Result<Vec<_>, _>>()?;
+        let pool = self.remote_pool(FILE_INO).ok_or("There is no file to prefetch")?;
+        for range in ranges {
+            pool.hint(Span::new(range.start, range.end.unwrap_or(pool.file_size())));
+        }
+        Ok(())
+    }
+}
+
+impl Tree {
+    // Adds the remote file at `path` with its directories and headers, see `HttpFs::with_file`.
+    fn add(&mut self, path: &str, pool: ReaderPool, headers: Option<String>) {
+        let (dirs, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut parent = DIR_INO;
+        let mut end = 0;
+        for component in dirs.split('/').filter(|component| !component.is_empty()) {
+            end += component.len();
+            parent = match self.children.get(&(parent, component.to_string())) {
+                Some(&ino) => ino,
+                None => self.insert_hashed(&dirs[..end], parent, component, Content::Dir),
+            };
+            end += 1;
+        }
+        self.insert_hashed(path, parent, name, Content::Remote(Arc::new(pool)));
+        if let Some(headers) = headers {
+            let headers_name = format!("{}.headers", name);
+            self.insert_hashed(&format!("{}.headers", path), parent, &headers_name, Content::Text(headers));
+        }
+    }
+
+    fn insert_hashed(&mut self, path: &str, parent: u64, name: &str, content: Content) -> u64 {
+        let digest = Sha256::digest(path.as_bytes());
+        let mut ino = HASHED_INO_BIT | u64::from_be_bytes(digest[..8].try_into().unwrap());
+        while self.nodes.contains_key(&ino) {
+            warn!("Inode of {} collides with another file, it is not stable across mounts", path);
+            ino = HASHED_INO_BIT | ino.wrapping_add(1);
+        }
+        self.insert(ino, parent, name.to_string(), content);
+        ino
+    }
+
+    fn insert(&mut self, ino: u64, parent: u64, name: String, content: Content) {
+        self.children.insert((parent, name.clone()), ino);
+        self.nodes.insert(ino, Node { parent, name, content });
+    }
+
+    // Path of a node relative to the root.
+    fn path(&self, ino: u64) -> Option<String> {
+        let mut node = self.nodes.get(&ino)?;
+        let mut path = node.name.clone();
+        while let Some(parent) = self.nodes.get(&node.parent) {
+            path = format!("{}/{}", parent.name, path);
+            node = parent;
         }
+        Some(path)
+    }
+
+    // Entries of a directory of the tree in the order of their names.
+    fn list(&self, dir: u64) -> impl Iterator<Item = (u64, NodeKind, String)> + '_ {
+        self.children.range((dir, String::new())..)
+            .take_while(move |((parent, _), _)| *parent == dir)
+            .map(|((_, name), &ino)| {
+                let kind = match self.nodes[&ino].content {
+                    Content::Dir => NodeKind::Directory,
+                    _ => NodeKind::File,
+                };
+                (ino, kind, name.clone())
+            })
+    }
+
+    // Inode of the node at `path`, e.g. `images/train`.
+    fn find(&self, path: &str) -> Option<u64> {
+        path.split('/').try_fold(DIR_INO, |parent, name| self.children.get(&(parent, name.to_string())).copied())
+    }
 
-        match res {
-            None => {
-                Err(())
+    // Checks that a file can be added at `path`: its directories are not files, and no node is there yet.
+    fn check_new(&self, path: &str) -> Result<(), String> {
+        check_paths([path])?;
+        let mut parent = DIR_INO;
+        for (end, _) in path.match_indices('/').chain([(path.len(), "")]) {
+            let name = path[..end].rsplit('/').next().unwrap_or_default();
+            let Some(&ino) = self.children.get(&(parent, name.to_string())) else {
+                return Ok(());
+            };
+            if end == path.len() {
+                return Err(format!("More than one file is at {}", path));
             }
-            Some(data) => {
-                Ok(data)
+            if !matches!(self.nodes[&ino].content, Content::Dir) {
+                return Err(format!("{} is both a file and a directory", &path[..end]));
             }
+            parent = ino;
         }
+        Ok(())
     }
 
-    fn get_file_attr(&self) -> FileAttr {
-        FileAttr {
-            ino: 2,
-            size: self.file_size as u64,
-            blocks: 1,
-            atime: SystemTime::now(),
-            mtime: SystemTime::now(),
-            ctime: SystemTime::now(),
-            crtime: SystemTime::now(),
-            kind: FileType::RegularFile,
-            perm: 0o644,
-            nlink: 1,
-            uid: get_current_uid(),
-            gid: get_current_gid(),
-            rdev: 0,
-            flags: 0,
-            blksize: 512,
-        }
-    }
-
-    fn get_dir_attr(&self) -> FileAttr {
-        FileAttr {
-            ino: 1,
-            size: 0,
-            blocks: 0,
-            atime: SystemTime::now(),
-            mtime: SystemTime::now(),
-            ctime: SystemTime::now(),
-            crtime: SystemTime::now(),
-            kind: FileType::Directory,
-            perm: 0o755,
-            nlink: 2,
-            uid: get_current_uid(),
-            gid: get_current_gid(),
-            rdev: 0,
-            flags: 0,
-            blksize: 512,
-        }
-    }
-    fn inc_and_get_readers_counter(&self) -> usize {
-        let arc = Arc::clone(&self.readers_counter);
-        let mut counter = arc.lock().unwrap();
-        *counter += 1;
-        *counter
-    }
-}
-
-impl Filesystem for HttpFs {
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        if parent == 1 && name.to_str() == Some(&self.file_name) {
-            reply.entry(&FILE_INFO_CACHE_TTL, &self.get_file_attr(), 0);
-        } else {
-            reply.error(ENOENT);
+    // Removes the remote file at `path`, its headers and the directories left empty, returning the parent inode
+    // and the name of each removed node.
+    fn remove(&mut self, path: &str) -> Result<Vec<(u64, String)>, String> {
+        let ino = self.find(path)
+            .filter(|ino| matches!(self.nodes[ino].content, Content::Remote(_)))
+            .ok_or_else(|| format!("There is no remote file at {}", path))?;
+        let headers = self.find(&format!("{}.headers", path))
+            .filter(|ino| matches!(self.nodes[ino].content, Content::Text(_)));
+        let mut removed = vec![];
+        for ino in [Some(ino), headers].into_iter().flatten() {
+            removed.push(self.remove_node(ino));
+        }
+        let mut dir = removed[0].0;
+        while dir != DIR_INO && self.list(dir).next().is_none() {
+            let (parent, name) = self.remove_node(dir);
+            removed.push((parent, name));
+            dir = parent;
+        }
+        Ok(removed)
+    }
+
+    fn remove_node(&mut self, ino: u64) -> (u64, String) {
+        let node = self.nodes.remove(&ino).expect("the node exists");
+        self.children.remove(&(node.parent, node.name.clone()));
+        (node.parent, node.name)
+    }
+}
+
+impl MountedFiles {
+    // Adds the remote file at `path` like `HttpFs::with_file`, unless it conflicts with the files of the mount.
+    pub fn add(&self, path: &str, pool: ReaderPool, headers: Option<String>) -> Result<(), String> {
+        let mut tree = self.tree.lock().unwrap();
+        tree.check_new(path)?;
+        if headers.is_some() {
+            tree.check_new(&format!("{}.headers", path))?;
+        }
+        tree.add(path, pool, headers);
+        Ok(())
+    }
+
+    // Removes the remote file at `path` with its headers and the directories left empty. Returns the parent
+    // inode and the name of each removed node, to invalidate them in the kernel. Reads of the file opened
+    // before fail with ENOENT.
+    pub fn remove(&self, path: &str) -> Result<Vec<(u64, String)>, String> {
+        self.tree.lock().unwrap().remove(path)
+    }
+}
+
+// Requests of the platform layer, failing with an errno.
+impl HttpFs {
+    pub(crate) fn lookup_entry(&mut self, parent: u64, name: &OsStr) -> Result<NodeAttr, i32> {
+        let control_file = self.control_files().into_iter()
+            .find(|&(_, file_name)| parent == CONTROL_DIR_INO && name.to_str() == Some(file_name));
+        if !self.control_files().is_empty() && parent == DIR_INO && name.to_str() == Some(CONTROL_DIR_NAME) {
+            return Ok(self.get_dir_attr(CONTROL_DIR_INO));
+        }
+        if let Some((ino, _)) = control_file {
+            return Ok(self.get_control_file_attr(ino));
+        }
+        if let Some((ino, metadata)) = self.overlay.as_mut()
+            .filter(|_| parent == DIR_INO)
+            .and_then(|overlay| overlay.lookup(name))
+        {
+            return Ok(self.get_overlay_file_attr(ino, &metadata));
+        }
+        let tree = self.tree();
+        match name.to_str().and_then(|name| tree.children.get(&(parent, name.to_string()))) {
+            Some(&ino) => Ok(self.get_node_attr(ino, &tree.nodes[&ino])),
+            None => Err(ENOENT),
         }
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+    pub(crate) fn attr(&self, ino: u64) -> Result<NodeAttr, i32> {
+        if let Some(overlay) = self.overlay.as_ref().filter(|overlay| overlay.name(ino).is_some()) {
+            return match overlay.metadata(ino) {
+                Ok(metadata) => Ok(self.get_overlay_file_attr(ino, &metadata)),
+                Err(e) => Err(e.raw_os_error().unwrap_or(ENOENT)),
+            };
+        }
+        if let Some(node) = self.tree().nodes.get(&ino) {
+            return Ok(self.get_node_attr(ino, node));
+        }
         match ino {
-            1 => reply.attr(&FILE_INFO_CACHE_TTL, &self.get_dir_attr()),
-            2 => reply.attr(&FILE_INFO_CACHE_TTL, &self.get_file_attr()),
-            _ => reply.error(ENOENT),
+            DIR_INO => Ok(self.get_dir_attr(DIR_INO)),
+            CONTROL_DIR_INO if !self.control_files().is_empty() => Ok(self.get_dir_attr(CONTROL_DIR_INO)),
+            ino if self.is_control_file(ino) => Ok(self.get_control_file_attr(ino)),
+            _ => Err(ENOENT),
         }
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
+    // Checks whether `requester` may open the file, for writing only the files of `.httpfs`.
+    pub(crate) fn open_file(&self, requester: Requester, ino: u64, write: bool) -> Result<(), i32> {
+        let result = if !self.uid_access.permits(requester.uid) {
+            debug!("Refusing to open {} for uid {}", ino, requester.uid);
+            Err(EACCES)
+        } else if write && !self.is_control_file(ino) {
+            read_only("open for writing", ino);
+            Err(EROFS)
+        } else {
+            self.touch();
+            Ok(())
+        };
+        self.audit(requester, "open", ino, None, result);
+        result
+    }
+
+    // Hands at most `size` bytes of the file from `offset` to `reply`, shorter only at the end of the file.
+    // `requester_alive` tells whether the process of the request still waits for the data.
+    pub(crate) fn read_file(
+        &self,
+        requester: Requester,
         ino: u64,
-        _fh: u64,
-        offset: i64,
-        _size: u32,
-        _flags: i32,
-        _lock: Option<u64>,
-        reply: ReplyData,
+        offset: u64,
+        size: usize,
+        requester_alive: impl Fn() -> bool,
+        reply: impl FnOnce(Result<&[u8], i32>),
     ) {
-        debug!("-------> Requested data block: offset={} size={}", offset, _size);
-        if ino == 2 {
-            for i in [0..REREAD_ATTEMPTS] {
-                match self.drain_data_from_suitable_reader(offset as usize, _size as usize) {
-                    Ok(data) => {
-                        debug!("-------> Replied data block: offset={} size={}", offset, data.len());
-                        reply.data(&data);
-                        break;
-                    }
-                    Err(_) => {
-                        warn!("Error read block in attempt {:?}", i)
-                    }
+        debug!("-------> Requested data block: offset={} size={}", offset, size);
+        let offset = offset as usize;
+        // a descriptor opened by an allowed user may be passed to a denied one, e.g. over a unix socket
+        if !self.uid_access.permits(requester.uid) {
+            self.audit(requester, "read", ino, Some(Span::with_len(offset, size)), Err(EACCES));
+            reply(Err(EACCES));
+            return;
+        }
+        self.touch();
+        let text = match self.tree().nodes.get(&ino).map(|node| &node.content) {
+            Some(Content::Text(text)) => Some(text.clone()),
+            _ => None,
+        };
+        if let Some(text) = text {
+            let text = text.as_bytes();
+            let start = min(offset, text.len());
+            let end = min(start + size, text.len());
+            self.audit(requester, "read", ino, Some(Span::new(start, end)), Ok(()));
+            reply(Ok(&text[start..end]));
+        } else if let Some(overlay) = self.overlay.as_ref().filter(|overlay| overlay.name(ino).is_some()) {
+            let span = Span::with_len(offset, size);
+            match overlay.read(ino, offset as u64, size) {
+                Ok(data) => {
+                    self.audit(requester, "read", ino, Some(Span::with_len(span.start(), data.len())), Ok(()));
+                    reply(Ok(&data));
+                }
+                Err(e) => {
+                    let errno = e.raw_os_error().unwrap_or(EIO);
+                    self.audit(requester, "read", ino, Some(span), Err(errno));
+                    reply(Err(errno));
+                }
+            }
+        } else if let Some(pool) = self.remote_pool(ino) {
+            if let Some(data) = pool.read_mapped(offset, size) {
+                debug!("-------> Replied cached data block: offset={} size={}", offset, data.len());
+                self.audit(requester, "read", ino, Some(Span::with_len(offset, data.len())), Ok(()));
+                reply(Ok(&data));
+                return;
+            }
+            // given up once the requesting process is gone, if it was found alive at first
+            let watched = requester_alive();
+            match pool.read_cancellable(offset, size, || watched && !requester_alive()) {
+                Ok(data) => {
+                    debug!("-------> Replied data block: offset={} size={}", offset, data.len());
+                    self.audit(requester, "read", ino, Some(Span::with_len(offset, data.len())), Ok(()));
+                    reply(Ok(&data));
+                }
+                Err(e) => {
+                    warn!("Unable to read block: offset={} size={}: {}", offset, size, e);
+                    let errno = e.raw_os_error().unwrap_or(EIO);
+                    self.audit(requester, "read", ino, Some(Span::with_len(offset, size)), Err(errno));
+                    reply(Err(errno));
                 }
             }
         } else {
-            reply.error(ENOENT);
+            reply(Err(ENOENT));
         }
     }
 
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        if ino != 1 {
-            reply.error(ENOENT);
-            return;
+    // Entries of a directory, starting with `.` and `..`.
+    pub(crate) fn dir_entries(&mut self, ino: u64) -> Result<Vec<(u64, NodeKind, String)>, i32> {
+        let dir_parent = self.tree().nodes.get(&ino)
+            .filter(|node| matches!(node.content, Content::Dir))
+            .map(|node| node.parent);
+        match (ino, dir_parent) {
+            (DIR_INO, _) => {
+                let mut entries = vec![
+                    (DIR_INO, NodeKind::Directory, ".".to_string()),
+                    (DIR_INO, NodeKind::Directory, "..".to_string()),
+                ];
+                entries.extend(self.tree().list(DIR_INO));
+                if let Some(overlay) = &mut self.overlay {
+                    // local files shadow remote ones of the same name
+                    let local = overlay.entries();
+                    entries.retain(|(_, _, name)| !local.iter().any(|(_, local_name)| local_name == name));
+                    entries.extend(local.into_iter().map(|(ino, name)| (ino, NodeKind::File, name)));
+                }
+                if !self.control_files().is_empty() {
+                    entries.push((CONTROL_DIR_INO, NodeKind::Directory, CONTROL_DIR_NAME.to_string()));
+                }
+                Ok(entries)
+            }
+            (_, Some(parent)) => {
+                let mut entries = vec![
+                    (ino, NodeKind::Directory, ".".to_string()),
+                    (parent, NodeKind::Directory, "..".to_string()),
+                ];
+                entries.extend(self.tree().list(ino));
+                Ok(entries)
+            }
+            (CONTROL_DIR_INO, _) if !self.control_files().is_empty() => {
+                let mut entries = vec![
+                    (CONTROL_DIR_INO, NodeKind::Directory, ".".to_string()),
+                    (DIR_INO, NodeKind::Directory, "..".to_string()),
+                ];
+                entries.extend(self.control_files().into_iter()
+                    .map(|(ino, name)| (ino, NodeKind::ControlFile, name.to_string())));
+                Ok(entries)
+            }
+            _ => Err(ENOENT),
         }
+    }
 
-        let entries = vec![
-            (1, FileType::Directory, "."),
-            (1, FileType::Directory, ".."),
-            (2, FileType::RegularFile, &self.file_name),
-        ];
+    // Everything below modifies the file system, which is read-only except for writes to the files of `.httpfs`.
 
-        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
-            // i + 1 means the index of the next entry
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
-                break;
-            }
+    // Takes the data written to a file of `.httpfs`, returning its length.
+    pub(crate) fn write_file(&self, ino: u64, data: &[u8]) -> Result<u32, i32> {
+        if self.prefetch_hints && ino == PREFETCH_HINTS_INO {
+            return match self.hint_prefetch(data) {
+                Ok(()) => Ok(data.len() as u32),
+                Err(e) => {
+                    warn!("Rejecting prefetch hints: {}", e);
+                    Err(EINVAL)
+                }
+            };
+        }
+        if let Some(on_refresh) = self.on_refresh.as_ref().filter(|_| ino == REFRESH_INO) {
+            on_refresh();
+            return Ok(data.len() as u32);
+        }
+        read_only("write", ino);
+        Err(EROFS)
+    }
+
+    // Changes the attributes of a file, only truncations of the files of `.httpfs` are accepted.
+    pub(crate) fn set_attr(&self, ino: u64) -> Result<NodeAttr, i32> {
+        if self.is_control_file(ino) {
+            // e.g. truncation by `echo 0-1M > .httpfs/prefetch`, the file is always empty anyway
+            return Ok(self.get_control_file_attr(ino));
         }
-        reply.ok();
+        read_only("setattr", ino);
+        Err(EROFS)
+    }
+}
+
+pub(crate) fn read_only(operation: &str, ino: u64) {
+    debug!("Rejecting {} of inode {}: the file system is read-only", operation, ino);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Transport;
+
+    fn pool() -> ReaderPool {
+        ReaderPool::new("http://localhost/file", 10, Transport::with_headers(vec![]))
+    }
+
+    #[test]
+    fn files_are_added_and_removed_while_mounted() {
+        let fs = HttpFs::empty().with_file("data/a.bin", pool(), None);
+        let files = fs.files();
+        files.add("data/sub/b.bin", pool(), Some("Content-Length: 10".to_string())).unwrap();
+        assert!(fs.tree().find("data/sub/b.bin.headers").is_some());
+
+        assert!(files.add("data/a.bin", pool(), None).is_err());
+        assert!(files.add("data/a.bin/c.bin", pool(), None).is_err());
+        assert!(files.add("data", pool(), None).is_err());
+        assert!(files.add(".httpfs/c.bin", pool(), None).is_err());
+
+        let sub = fs.tree().find("data/sub").unwrap();
+        let data = fs.tree().find("data").unwrap();
+        let removed = files.remove("data/sub/b.bin").unwrap();
+        assert_eq!(removed, vec![
+            (sub, "b.bin".to_string()),
+            (sub, "b.bin.headers".to_string()),
+            (data, "sub".to_string()),
+        ]);
+        assert!(fs.tree().find("data/sub").is_none());
+        assert!(fs.tree().find("data/a.bin").is_some());
+        assert!(files.remove("data/sub/b.bin").is_err());
+        assert!(files.remove("data").is_err());
+    }
+
+    #[test]
+    fn requests_are_answered_without_a_platform() {
+        let requester = Requester { uid: 1000, gid: 1000, pid: 0 };
+        let mut fs = HttpFs::new(pool(), "a.bin")
+            .with_headers_file("Content-Length: 10".to_string())
+            .with_prefetch_hints();
+        let headers = fs.lookup_entry(DIR_INO, OsStr::new("a.bin.headers")).unwrap();
+        assert_eq!((headers.kind, headers.size), (NodeKind::File, 18));
+        assert_eq!(fs.lookup_entry(DIR_INO, OsStr::new("b.bin")).unwrap_err(), ENOENT);
+        assert_eq!(fs.attr(FILE_INO).unwrap().size, 10);
+
+        let names: Vec<String> = fs.dir_entries(DIR_INO).unwrap().into_iter().map(|(_, _, name)| name).collect();
+        assert_eq!(names, [".", "..", "a.bin", "a.bin.headers", CONTROL_DIR_NAME]);
+        assert_eq!(fs.dir_entries(CONTROL_DIR_INO).unwrap()[2], (PREFETCH_HINTS_INO, NodeKind::ControlFile,
+            PREFETCH_HINTS_NAME.to_string()));
+
+        let mut read = None;
+        fs.read_file(requester, headers.ino, 8, 100, || false, |data| read = Some(data.map(<[u8]>::to_vec)));
+        assert_eq!(read, Some(Ok(b"Length: 10".to_vec())));
+        assert_eq!(fs.open_file(requester, headers.ino, false), Ok(()));
+        assert_eq!(fs.open_file(requester, headers.ino, true), Err(EROFS));
+        assert_eq!(fs.write_file(headers.ino, b"0-10"), Err(EROFS));
+        assert_eq!(fs.open_file(requester, PREFETCH_HINTS_INO, true), Ok(()));
     }
 }
@@ -0,0 +1,300 @@
+// FUSE binding of the file system on Unix, answering the requests of the kernel with fuser.
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::SystemTime;
+
+use fuser::{
+    FileAttr, Filesystem, FileType, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyOpen, ReplyWrite, Request, TimeOrNow,
+};
+use libc::{EROFS, O_ACCMODE, O_RDONLY};
+use users::{get_current_gid, get_current_uid};
+
+use crate::audit_log::Requester;
+use crate::file_system::{read_only, HttpFs, NodeAttr, NodeKind, FILE_INFO_CACHE_TTL};
+
+fn file_attr(attr: NodeAttr) -> FileAttr {
+    let (kind, perm, nlink, blocks) = match attr.kind {
+        NodeKind::File => (FileType::RegularFile, 0o644, 1, 1),
+        NodeKind::ControlFile => (FileType::RegularFile, 0o200, 1, 1),
+        NodeKind::Directory => (FileType::Directory, 0o755, 2, 0),
+    };
+    FileAttr {
+        ino: attr.ino,
+        size: attr.size,
+        blocks,
+        atime: attr.accessed,
+        mtime: attr.modified,
+        ctime: attr.modified,
+        crtime: attr.created,
+        kind,
+        perm,
+        nlink,
+        uid: get_current_uid(),
+        gid: get_current_gid(),
+        rdev: 0,
+        flags: 0,
+        blksize: 512,
+    }
+}
+
+fn file_type(kind: NodeKind) -> FileType {
+    match kind {
+        NodeKind::Directory => FileType::Directory,
+        NodeKind::File | NodeKind::ControlFile => FileType::RegularFile,
+    }
+}
+
+// Exited processes are gone from /proc or remain as zombies until reaped.
+#[cfg(target_os = "linux")]
+fn is_process_alive(pid: u32) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // the state follows the command name in parentheses, which may contain spaces
+        Ok(stat) => stat.rsplit_once(')').is_none_or(|(_, rest)| !rest.trim_start().starts_with(['Z', 'X'])),
+        Err(_) => false,
+    }
+}
+
+// Zombies count as alive here, their reads are given up once they are reaped.
+#[cfg(not(target_os = "linux"))]
+fn is_process_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+fn requester(req: &Request) -> Requester {
+    Requester { uid: req.uid(), gid: req.gid(), pid: req.pid() }
+}
+
+impl Filesystem for HttpFs {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_entry(parent, name) {
+            Ok(attr) => reply.entry(&FILE_INFO_CACHE_TTL, &file_attr(attr), 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&FILE_INFO_CACHE_TTL, &file_attr(attr)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        match self.open_file(requester(_req), ino, flags & O_ACCMODE != O_RDONLY) {
+            Ok(()) => reply.opened(0, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        _size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        // fuser answers interrupt requests itself, but an application aborted while waiting for the data,
+        // e.g. `cp` with Ctrl-C, exits, so the read is given up once its process is gone.
+        // Processes not visible from here, e.g. in another pid namespace, are not watched.
+        let pid = _req.pid();
+        let alive = || pid != 0 && is_process_alive(pid);
+        self.read_file(requester(_req), ino, offset as u64, _size as usize, alive, |data| match data {
+            Ok(data) => reply.data(data),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let entries = match self.dir_entries(ino) {
+            Ok(entries) => entries,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+            // i + 1 means the index of the next entry
+            if reply.add(entry.0, (i + 1) as i64, file_type(entry.1), &entry.2) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    // Everything below modifies the file system, which is read-only except for writes to the files of `.httpfs`.
+    // The mount is read-only too unless they are enabled, so the kernel rejects most of these itself,
+    // but all of them get the same answer anyway.
+
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        match self.set_attr(ino) {
+            Ok(attr) => reply.attr(&FILE_INFO_CACHE_TTL, &file_attr(attr)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        read_only("mknod", parent);
+        reply.error(EROFS);
+    }
+
+    fn mkdir(&mut self, _req: &Request, parent: u64, _name: &OsStr, _mode: u32, _umask: u32, reply: ReplyEntry) {
+        read_only("mkdir", parent);
+        reply.error(EROFS);
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        read_only("unlink", parent);
+        reply.error(EROFS);
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        read_only("rmdir", parent);
+        reply.error(EROFS);
+    }
+
+    fn symlink(&mut self, _req: &Request, parent: u64, _link_name: &OsStr, _target: &Path, reply: ReplyEntry) {
+        read_only("symlink", parent);
+        reply.error(EROFS);
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        read_only("rename", parent);
+        reply.error(EROFS);
+    }
+
+    fn link(&mut self, _req: &Request, ino: u64, _newparent: u64, _newname: &OsStr, reply: ReplyEntry) {
+        read_only("link", ino);
+        reply.error(EROFS);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.write_file(ino, _data) {
+            Ok(written) => reply.written(written),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        read_only("setxattr", ino);
+        reply.error(EROFS);
+    }
+
+    fn removexattr(&mut self, _req: &Request, ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        read_only("removexattr", ino);
+        reply.error(EROFS);
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        read_only("create", parent);
+        reply.error(EROFS);
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        read_only("fallocate", ino);
+        reply.error(EROFS);
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &Request,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        read_only("copy_file_range", ino_out);
+        reply.error(EROFS);
+    }
+}
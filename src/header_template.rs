@@ -0,0 +1,75 @@
+// Placeholders in header values evaluated for every request, for APIs requiring time-stamped
+// or per-request headers, e.g. `X-Request-Time: {epoch}`:
+//
+//     {date}   the current time as an HTTP date, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+//     {epoch}  the current time as seconds since the Unix epoch
+//     {range}  the value of the Range header of the request, e.g. "bytes=1024-", empty if none
+//
+// A literal brace is written as `{{` or `}}`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PLACEHOLDERS: [&str; 3] = ["date", "epoch", "range"];
+
+// Values of placeholders of a single request.
+pub struct RequestContext<'a> {
+    pub range: Option<&'a str>,
+}
+
+// Checks `Name: value` syntax of a header and placeholders in its value.
+pub fn validate_header(header: &str) -> Result<String, String> {
+    let Some((name, value)) = header.split_once(':') else {
+        return Err(format!("Invalid header {:?}: expected \"Name: value\"", header));
+    };
+    let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_token_char) {
+        return Err(format!("Invalid header name {:?}", name));
+    }
+    if value.contains(['\r', '\n']) {
+        return Err(format!("Header {:?} must be a single line", name));
+    }
+    expand_value(value, |placeholder| PLACEHOLDERS.contains(&placeholder).then(String::new))
+        .map_err(|e| format!("Invalid header {:?}: {}", name, e))?;
+    Ok(header.to_string())
+}
+
+// Evaluates placeholders of `header`. Headers with invalid templates are sent as is.
+pub fn expand_header(header: &str, context: &RequestContext) -> String {
+    if !header.contains(['{', '}']) {
+        return header.to_string();
+    }
+    let evaluate = |placeholder: &str| match placeholder {
+        "date" => Some(httpdate::fmt_http_date(SystemTime::now())),
+        "epoch" => Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().to_string()),
+        "range" => Some(context.range.unwrap_or_default().to_string()),
+        _ => None,
+    };
+    expand_value(header, evaluate).unwrap_or_else(|_| header.to_string())
+}
+
+fn expand_value(template: &str, evaluate: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        result.push_str(&rest[..i]);
+        let brace = rest.as_bytes()[i];
+        rest = &rest[i + 1..];
+        if rest.as_bytes().first() == Some(&brace) {
+            // escaped `{{` or `}}`
+            result.push(brace as char);
+            rest = &rest[1..];
+            continue;
+        }
+        if brace == b'}' {
+            return Err("unmatched '}', use '}}' for a literal brace".to_string());
+        }
+        let Some((placeholder, after)) = rest.split_once('}') else {
+            return Err("unclosed '{', use '{{' for a literal brace".to_string());
+        };
+        let value = evaluate(placeholder).ok_or_else(|| format!("unknown placeholder {{{}}}", placeholder))?;
+        result.push_str(&value);
+        rest = after;
+    }
+    result.push_str(rest);
+    Ok(result)
+}
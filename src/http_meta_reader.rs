@@ -1,34 +1,224 @@
-use curl::easy::{Easy, List};
-use log::debug;
+use std::io;
+
+use log::{debug, warn};
+
+use crate::cache_policy::{parse_cache_policy, CachePolicy};
+use crate::connections::Connection;
+use crate::rate_limit::RATE_LIMIT_RETRIES;
+use crate::transport::{
+    parse_content_range, parse_header, parse_status_line, Transport, AUTH_RETRIES, HTTP_FORBIDDEN,
+    HTTP_NOT_FOUND, HTTP_NOT_MODIFIED, HTTP_PARTIAL_CONTENT, HTTP_TOO_MANY_REQUESTS, HTTP_UNAUTHORIZED,
+};
+
+// Statuses of endpoints that don't support HEAD, e.g. presigned URLs signed for GET only
+const HEAD_REJECTED_STATUSES: [u32; 3] = [403, 405, 501];
+
+// The handle for querying the response info, the parsed and the raw headers of the final response
+struct MetaResponse {
+    easy: Connection,
+    headers: Vec<(String, String)>,
+    raw_headers: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum MetaRequest {
+    Head,
+    // GET of the first byte, for endpoints rejecting HEAD or not reporting the size in it
+    FirstByte,
+}
 
 pub struct HttpMetaReader {
     resource_url: String,
-    additional_headers: Vec<String>,
+    transport: Transport,
+}
+
+#[derive(Clone, Debug)]
+pub struct ResourceMeta {
+    pub size: usize,
+    // the url after following redirects
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // e.g. `attachment; filename="report.pdf"`
+    pub content_disposition: Option<String>,
+    // whether the response was partial or advertised `Accept-Ranges: bytes`, which some servers do
+    // and still ignore ranges
+    pub accepts_ranges: bool,
+    // from Cache-Control and Expires
+    pub cache_policy: CachePolicy,
+    // the status line and the headers of the final response as received
+    pub raw_headers: String,
 }
 
 impl HttpMetaReader {
 
-    pub fn new(url: &str, additional_headers: Vec<String>) -> Self {
+    pub fn new(url: &str, transport: Transport) -> Self {
         HttpMetaReader {
             resource_url: String::from(url),
-            additional_headers,
+            transport,
+        }
+    }
+
+    pub fn fetch_file_size(&self) -> io::Result<usize> {
+        Ok(self.fetch_meta()?.size)
+    }
+
+    pub fn fetch_meta(&self) -> io::Result<ResourceMeta> {
+        let mut attempt = 0;
+        let mut throttled = 0;
+        let mut request = MetaRequest::Head;
+        loop {
+            self.transport.rate_limiter().wait(|| false);
+            let MetaResponse { easy, headers, raw_headers } = self.perform(request, &[])?;
+            let status = easy.response_code()?;
+            if self.transport.rejects_credentials(status) && attempt < AUTH_RETRIES {
+                warn!("{:?} request was rejected with {}, retrying with refreshed credentials", request, status);
+                self.transport.refresh_credentials()?;
+                attempt += 1;
+                continue;
+            }
+            if status == HTTP_TOO_MANY_REQUESTS && throttled < RATE_LIMIT_RETRIES {
+                let retry_after = headers.iter().find(|(name, _)| name == "retry-after").map(|(_, v)| v.as_str());
+                self.transport.rate_limiter().throttled(retry_after);
+                throttled += 1;
+                continue;
+            }
+            if request == MetaRequest::Head && HEAD_REJECTED_STATUSES.contains(&status) {
+                warn!("HEAD request was rejected with {}, falling back to GET of the first byte", status);
+                request = MetaRequest::FirstByte;
+                continue;
+            }
+
+            if !(200..300).contains(&status) {
+                return Err(status_error(status));
+            }
+
+            let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+            let size = if status == HTTP_PARTIAL_CONTENT {
+                // the body is a single byte, the size is only known from Content-Range
+                header("content-range").and_then(parse_content_range).and_then(|range| range.total)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                        "Partial response does not specify the size of the resource"))?
+            } else {
+                let length = easy.content_length_download()?;
+                if length < 0.0 && request == MetaRequest::Head {
+                    // e.g. compressed or streamed responses, Content-Range of a partial response is authoritative
+                    debug!("HEAD response has no Content-Length, probing with GET of the first byte");
+                    request = MetaRequest::FirstByte;
+                    continue;
+                }
+                if length < 0.0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        "Server did not specify the size of the resource"));
+                }
+                length as usize
+            };
+            let url = easy.effective_url()?.unwrap_or(&self.resource_url).to_string();
+            let etag = header("etag").map(String::from);
+            let last_modified = header("last-modified").map(String::from);
+            let content_disposition = header("content-disposition").map(String::from);
+            let accepts_ranges = status == HTTP_PARTIAL_CONTENT || header("accept-ranges")
+                .is_some_and(|units| units.split(',').any(|unit| unit.trim().eq_ignore_ascii_case("bytes")));
+            debug!("Fetched the size of remote resource {}: {}, ETag: {:?}, Last-Modified: {:?}",
+                url, size, etag, last_modified);
+            let cache_policy = parse_cache_policy(header("cache-control"), header("expires"), header("date"));
+            return Ok(ResourceMeta {
+                size, url, etag, last_modified, content_disposition, accepts_ranges, cache_policy, raw_headers,
+            });
+        }
+    }
+
+    // Asks the server whether the resource still matches the validators with a conditional request.
+    // Returns None if it has not changed, or the metadata of the new version otherwise.
+    pub fn revalidate(&self, etag: Option<&str>, last_modified: Option<&str>) -> io::Result<Option<ResourceMeta>> {
+        let mut conditions = vec![];
+        if let Some(etag) = etag {
+            conditions.push(format!("If-None-Match: {}", etag));
+        }
+        if let Some(last_modified) = last_modified {
+            conditions.push(format!("If-Modified-Since: {}", last_modified));
+        }
+        let MetaResponse { easy, .. } = self.perform(MetaRequest::Head, &conditions)?;
+        let status = easy.response_code()?;
+        if status == HTTP_NOT_MODIFIED {
+            debug!("Remote resource has not been modified");
+            return Ok(None);
         }
+
+        // the server may ignore conditions or reject HEAD, so the validators are compared here
+        debug!("Conditional request returned {}, fetching metadata", status);
+        let meta = self.fetch_meta()?;
+        let unchanged = match etag {
+            Some(etag) => meta.etag.as_deref() == Some(etag),
+            None => meta.last_modified.as_deref() == last_modified,
+        };
+        Ok(if unchanged { None } else { Some(meta) })
     }
 
-    pub fn get_file_size(&self) -> usize {
-        let mut easy = Easy::new();
-        easy.nobody(true).unwrap();
-        let mut headers = List::new();
-        self.additional_headers.iter().for_each(|x| {
-            headers.append(&x).unwrap();
-        });
-        easy.http_headers(headers).unwrap();
-        easy
-            .url(&self.resource_url)
-            .unwrap();
-        easy.perform().unwrap();
-        let size = easy.content_length_download().unwrap() as usize;
-        debug!("Fetched the size of remote resource: {}", size);
-        size
+    // Checks that the server answers a range request with partial content rather than the whole resource.
+    // `Accept-Ranges` alone is not trusted, since some servers advertise it and still ignore ranges.
+    pub fn supports_ranges(&self) -> io::Result<bool> {
+        let mut attempt = 0;
+        loop {
+            let MetaResponse { easy, headers, .. } = self.perform(MetaRequest::FirstByte, &[])?;
+            let status = easy.response_code()?;
+            if self.transport.rejects_credentials(status) && attempt < AUTH_RETRIES {
+                self.transport.refresh_credentials()?;
+                attempt += 1;
+                continue;
+            }
+            if !(200..300).contains(&status) {
+                return Err(status_error(status));
+            }
+            let content_range = headers.iter().find(|(name, _)| name == "content-range").map(|(_, v)| v.as_str());
+            let honored = status == HTTP_PARTIAL_CONTENT && content_range.and_then(parse_content_range).is_some();
+            debug!("Range request returned {} with Content-Range {:?}", status, content_range);
+            return Ok(honored);
+        }
     }
+
+    fn perform(&self, request: MetaRequest, extra_headers: &[String]) -> io::Result<MetaResponse> {
+        let mut easy = match request {
+            MetaRequest::Head => self.transport.head(&self.resource_url, extra_headers)?,
+            MetaRequest::FirstByte => {
+                let mut headers = vec!["Range: bytes=0-0".to_string()];
+                headers.extend_from_slice(extra_headers);
+                self.transport.easy(&self.resource_url, &headers)?
+            }
+        };
+        let mut headers = vec![];
+        let mut raw_headers = String::new();
+        {
+            let mut transfer = easy.transfer();
+            transfer.header_function(|header| {
+                if parse_status_line(header).is_some() {
+                    // headers of a previous response, e.g. a redirect
+                    headers.clear();
+                    raw_headers.clear();
+                } else if let Some(header) = parse_header(header) {
+                    headers.push(header);
+                }
+                raw_headers.push_str(&String::from_utf8_lossy(header));
+                true
+            })?;
+            // only the headers are needed; aborting also avoids downloading the whole resource
+            // from servers that ignore the range
+            transfer.write_function(|_| Ok(0))?;
+            match transfer.perform() {
+                Err(e) if e.is_write_error() => {}
+                res => res?,
+            }
+        }
+        self.transport.on_response(&self.resource_url, easy.response_code()?, &headers);
+        Ok(MetaResponse { easy, headers, raw_headers })
+    }
+}
+
+fn status_error(status: u32) -> io::Error {
+    let kind = match status {
+        HTTP_NOT_FOUND => io::ErrorKind::NotFound,
+        HTTP_UNAUTHORIZED | HTTP_FORBIDDEN => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("Server responded with HTTP status {}", status))
 }
@@ -1,217 +1,759 @@
-use std::cmp::min;
-use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
-
-use curl::easy::{Easy, List};
-use log::{debug, warn};
-
-const MAX_BUFFER_SIZE: usize = 1024 * 1024;
-const MAX_RESPONSE_AWAIT_MS: u64 = 10000;
-// How to often check the buffer is filled
-const BUFFER_FILL_RECHECK_MS: u64 = 10;
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub struct DataAddr {
-    offset: usize,
-    size: usize,
-}
-
-impl DataAddr {
-    pub fn new(_offset: usize, _size: usize) -> Self {
-        Self {
-            offset: _offset,
-            size: _size,
-        }
-    }
-    fn get_data_end_position(&self) -> usize {
-        self.size + self.offset
-    }
-}
-
-#[derive()]
-pub struct HttpReader {
-    data: Arc<Mutex<Vec<u8>>>,
-    offset: Arc<Mutex<usize>>,
-    resource_size: usize,
-    resource_url: String,
-    should_stop: Arc<Mutex<bool>>,
-    additional_headers: Vec<String>,
-    ordinal_number: usize, // just for logging
-}
-
-impl HttpReader {
-    pub fn new(
-        url: &str,
-        start_offset: usize,
-        resource_size: usize,
-        additional_headers: Vec<String>,
-        ordinal_number: usize,
-    ) -> Self {
-        HttpReader {
-            data: Arc::new(Mutex::new(vec![])),
-            offset: Arc::new(Mutex::new(start_offset)),
-            resource_size,
-            resource_url: String::from(url),
-            should_stop: Arc::new(Mutex::new(false)),
-            additional_headers,
-            ordinal_number,
-        }
-    }
-
-    // Returns requested data from internal buffer or None if requested data isn't exists.
-    // Does left trim buffer if it required (leaning on MAX_BUFFER_PREPEND).
-    pub fn try_drain_data(&self, abs_addr: DataAddr) -> Option<Vec<u8>> {
-        debug!("[reader {}] Trying to drain data", self.ordinal_number);
-        let rel_addr = match self.abs_to_rel_addr(abs_addr) {
-            None => { return None; }
-            Some(data) => { data }
-        };
-
-        if !self.wait_for_data(abs_addr) {
-            return None;
-        }
-
-        let data_arc = Arc::clone(&self.data);
-        let mut data = data_arc.lock().unwrap();
-        let offset_arc = Arc::clone(&self.offset);
-        let mut offset = offset_arc.lock().unwrap();
-
-        let end = min(data.len(), rel_addr.get_data_end_position());
-        debug!("[reader {}] Preparing to write block {:?}", self.ordinal_number, rel_addr.offset..end);
-        let requested_data = data[rel_addr.offset..end]
-            .to_vec()
-            .clone();
-
-        debug!("[reader {}] Removing part of data {:?}", self.ordinal_number, 0..end);
-        *data = data[end..].to_vec().clone();
-        *offset += end;
-
-        debug!("[reader {}] End drain data. Current offset {}, length {}", self.ordinal_number, offset, data.len());
-        Some(requested_data)
-    }
-
-    // Returns true if you managed to get the necessary data.
-    fn wait_for_data(&self, abs_addr: DataAddr) -> bool {
-        // Really data downloading may be in progress, because we need to check data availability.
-        let end = min(abs_addr.get_data_end_position(), self.resource_size);
-        debug!("[reader {}] Waiting to read data block {:?} from http. Current data {:?}",
-            self.ordinal_number,[abs_addr.offset..end], [self.get_offset()..self.get_offset() + self.get_data_len()]);
-        let mut total_waited = 0;
-        while self.get_offset() + self.get_data_len() < end {
-            sleep(Duration::from_millis(BUFFER_FILL_RECHECK_MS));
-            total_waited += BUFFER_FILL_RECHECK_MS;
-            if total_waited > MAX_RESPONSE_AWAIT_MS {
-                warn!("[reader {}] The time to wait the data is over!", self.ordinal_number,);
-                return false;
-            }
-        }
-        return true;
-    }
-
-    fn get_offset(&self) -> usize {
-        let arc = Arc::clone(&self.offset);
-        let _offset = arc.lock().unwrap();
-        *_offset
-    }
-
-    // Validates requested data position in file and returns position of this data in local buffer.
-    // Returns None if requested data not in current buffer.
-    fn abs_to_rel_addr(&self, abs_addr: DataAddr) -> Option<DataAddr> {
-        let reader_offset = self.get_offset();
-        if abs_addr.offset < reader_offset {
-            debug!("[reader {}] Requested offset {} less than existing {}",
-                self.ordinal_number, abs_addr.offset, reader_offset);
-            return None;
-        }
-        let reader_possibly_data_reach = reader_offset + MAX_BUFFER_SIZE;
-        if abs_addr.get_data_end_position() > reader_possibly_data_reach {
-            debug!("[reader {}] Requested data {:?} can not be reached for reader {:?}",
-                self.ordinal_number,
-                [abs_addr.offset..abs_addr.get_data_end_position()],
-                [reader_offset..reader_possibly_data_reach]
-            );
-            return None;
-        }
-        let local_addr = DataAddr {
-            offset: abs_addr.offset - reader_offset,
-            size: abs_addr.size,
-        };
-        debug!("[reader {}] Translated absolute addr {:?} to local {:?}", self.ordinal_number, abs_addr, local_addr);
-        Some(local_addr)
-    }
-
-    pub fn fetching_loop(&self) {
-        debug!("[reader {}] Setup URL fetching", self.ordinal_number);
-        let mut easy = Easy::new();
-        easy.buffer_size(16384).unwrap();
-        easy.url(&self.resource_url).unwrap();
-
-        let mut headers = List::new();
-        let header = format!("Range: bytes={}-", self.get_offset());
-        headers.append(&header).unwrap();
-        self.additional_headers.iter().for_each(|x| {
-            headers.append(&x).unwrap();
-        });
-
-        debug!("[reader {}] CURL: Using headers {:?}", self.ordinal_number, headers);
-
-        easy.http_headers(headers).unwrap();
-
-        let mut transfer = easy.transfer();
-        transfer.write_function(|buf| {
-            let mut total_slept = 0;
-            while self.get_data_len() >= MAX_BUFFER_SIZE {
-                if total_slept == 0 {
-                    // Write log only the first iteration
-                    debug!("[reader {}] Sleeping because buffer is full. Current data range: {:?}",
-                        self.ordinal_number, [self.get_offset()..self.get_offset()+self.get_data_len()]);
-                }
-                sleep(Duration::from_millis(BUFFER_FILL_RECHECK_MS));
-                total_slept += BUFFER_FILL_RECHECK_MS;
-                if self.should_stop() {
-                    debug!("[reader {}] Stop fetching loop", self.ordinal_number);
-                    return Ok(0);
-                }
-            }
-            if total_slept > 0 {
-                debug!("[reader {}] Waked up from sleeping {} ms", self.ordinal_number, total_slept);
-            }
-            let data = Arc::clone(&self.data);
-            let mut _data = data.lock().unwrap();
-            _data.extend(buf);
-            debug!("[reader {}] Added {} bytes of data to buffer, new len is {}",
-                self.ordinal_number, buf.len(), _data.len());
-
-            Ok(buf.len())
-        }).unwrap();
-
-        debug!("[reader {}] Performing URL fetching", self.ordinal_number);
-        let res = transfer.perform();
-        debug!("[reader {}] Finished performing URL fetching", self.ordinal_number);
-
-        match res {
-            Ok(_) => {}
-            Err(e) => debug!("[reader {}] Write function returns error:  {}", self.ordinal_number, e)
-        }
-    }
-
-    fn get_data_len(&self) -> usize {
-        let arc = Arc::clone(&self.data);
-        let data = arc.lock().unwrap();
-        data.len()
-    }
-
-    fn should_stop(&self) -> bool {
-        let arc = Arc::clone(&self.should_stop);
-        let should_stop = arc.lock().unwrap();
-        *should_stop
-    }
-
-    pub fn stop(&self) {
-        debug!("[reader {}] Stopping reader", self.ordinal_number);
-        let arc = Arc::clone(&self.should_stop);
-        let mut should_stop = arc.lock().unwrap();
-        *should_stop = true
-    }
-}
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use curl::easy::WriteError;
+use libc::EIO;
+use log::{debug, warn};
+
+#[cfg(feature = "async-backend")]
+use crate::async_backend::{self, Sink};
+use crate::connections::Connection;
+use crate::profile::ReadProfile;
+use crate::rate_limit::RATE_LIMIT_RETRIES;
+use crate::resource_version::ResourceVersion;
+use crate::span::Span;
+use crate::throughput::TransferMeter;
+use crate::transfers::{self, Driver, Share, Step};
+use crate::transport::{
+    is_interim_status, parse_content_range, parse_header, parse_status_line, parse_unsatisfied_range, refusal_error,
+    Backend, Transport,
+    AUTH_RETRIES, HTTP_INTERNAL_SERVER_ERROR, HTTP_OK, HTTP_PARTIAL_CONTENT, HTTP_RANGE_NOT_SATISFIABLE,
+    HTTP_TOO_MANY_REQUESTS,
+};
+
+// Changes of the buffer and stops wake waiters at once, this is how often they check for what can't wake them:
+// a cancelled read or a resource found shorter by another reader
+const BUFFER_FILL_RECHECK_MS: u64 = 10;
+// How often a reader checks whether a connection to the host has been released
+const CONNECTION_RECHECK_MS: u64 = 50;
+
+// State of a single ranged request, shared with its callbacks.
+struct FetchState {
+    start: usize,
+    status: u32,
+    headers: Vec<(String, String)>,
+    // nothing is buffered until the headers of the final response are checked
+    accepted: bool,
+    // leading bytes of the body that precede the requested range
+    skip: usize,
+    // absolute offset of the next byte of the body
+    position: usize,
+    // absolute offset where the body is announced to end
+    expected_end: usize,
+    requested_at: Instant,
+    meter: TransferMeter,
+    // since when the transfer waits for the reads to free buffer space
+    paused_since: Option<Instant>,
+}
+
+// How many times the requests of a reader have been repeated.
+#[derive(Default)]
+struct Retries {
+    attempt: u8,
+    throttled: u8,
+    // resumptions without any progress
+    resumed: u8,
+    // where the last request started
+    fetched_from: usize,
+}
+
+#[derive()]
+pub struct HttpReader {
+    // The only lock of the hot path. The offsets are changed only while holding it, but are read without it,
+    // so that checks of the buffered span don't contend with the transfer adding data.
+    // A deque, since every read removes data from the front of a buffer of up to several megabytes.
+    data: Mutex<VecDeque<u8>>,
+    // signalled when data is added to the buffer or the reader stops
+    data_changed: Condvar,
+    // absolute offsets of the start and the end of the buffered data, both only grow
+    offset: AtomicUsize,
+    end: AtomicUsize,
+    // shared with the pool, so that a resource found to be shorter is clamped for all readers
+    resource_size: Arc<AtomicUsize>,
+    resource_url: String,
+    should_stop: AtomicBool,
+    // set when the server sent data that can't be buffered, the reader is useless afterwards
+    failed: AtomicBool,
+    // status of the response the reader failed with, 0 if it failed for another reason or hasn't failed
+    failed_status: AtomicU32,
+    // set by the write callback when the buffer is full or the file is ahead of the others, the IO thread resumes
+    // the transfer once reads free space and the file may receive data
+    paused: AtomicBool,
+    // of the bandwidth, with the other readers of the same file, if set
+    share: Option<Arc<Share>>,
+    // the transfer in progress, used only by the IO thread
+    fetch: Mutex<Option<FetchState>>,
+    retries: Mutex<Retries>,
+    // when the buffered data was last known to match the remote resource
+    validated_at: Mutex<Instant>,
+    // when the reader was created or last served a read
+    served_at: Mutex<Instant>,
+    transport: Transport,
+    version: Arc<ResourceVersion>,
+    profile: ReadProfile,
+    ordinal_number: usize, // just for logging
+}
+
+impl HttpReader {
+    pub fn new(
+        url: &str,
+        start_offset: usize,
+        resource_size: Arc<AtomicUsize>,
+        transport: Transport,
+        version: Arc<ResourceVersion>,
+        profile: ReadProfile,
+        ordinal_number: usize,
+    ) -> Self {
+        HttpReader {
+            data: Mutex::new(VecDeque::new()),
+            data_changed: Condvar::new(),
+            offset: AtomicUsize::new(start_offset),
+            end: AtomicUsize::new(start_offset),
+            resource_size,
+            resource_url: String::from(url),
+            should_stop: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            failed_status: AtomicU32::new(0),
+            paused: AtomicBool::new(false),
+            share: None,
+            fetch: Mutex::new(None),
+            retries: Mutex::new(Retries::default()),
+            validated_at: Mutex::new(Instant::now()),
+            served_at: Mutex::new(Instant::now()),
+            transport,
+            version,
+            profile,
+            ordinal_number,
+        }
+    }
+
+    // Receives data in turns with the readers of other files, in proportion to the weight of `share`.
+    pub fn with_share(mut self, share: Arc<Share>) -> Self {
+        self.share = Some(share);
+        self
+    }
+
+    // Starts fetching data with the backend of the transport.
+    pub fn start(self: &Arc<Self>) {
+        match self.transport.backend() {
+            Backend::Curl => transfers::spawn(Arc::clone(self) as Arc<dyn Driver>),
+            #[cfg(feature = "async-backend")]
+            Backend::Hyper => async_backend::spawn(Arc::clone(self).run_async()),
+        }
+    }
+
+    // Returns requested data from internal buffer or None if requested data isn't exists.
+    // The returned data is shorter than requested only at the end of the resource.
+    // Does left trim buffer up to the end of the requested data, except the rewind bytes of the profile.
+    // Gives up waiting for the data as soon as `cancelled` returns true.
+    pub fn try_drain_data(&self, requested: Span, cancelled: &dyn Fn() -> bool) -> Option<Vec<u8>> {
+        debug!("[reader {}] Trying to drain data", self.ordinal_number);
+        if !self.can_reach(requested) {
+            return None;
+        }
+
+        if !self.wait_for_data(requested, cancelled) {
+            return None;
+        }
+
+        let mut data = self.data.lock().unwrap();
+        let buffered = Span::with_len(self.get_offset(), data.len());
+        // the requested start is checked by `can_reach`, so only the end may go beyond the data
+        let local = requested.clamp_end(buffered.end()).relative_to(buffered.start())?;
+        debug!("[reader {}] Preparing to write block {:?}", self.ordinal_number, local);
+        let requested_data: Vec<u8> = data.range(local.as_range()).copied().collect();
+
+        let keep_from = local.end().saturating_sub(self.profile.rewind);
+        debug!("[reader {}] Removing part of data {:?}", self.ordinal_number, 0..keep_from);
+        data.drain(..keep_from);
+        let offset = self.offset.fetch_add(keep_from, Ordering::SeqCst) + keep_from;
+        self.resume_transfer();
+        *self.served_at.lock().unwrap() = Instant::now();
+
+        debug!("[reader {}] End drain data. Current offset {}, length {}", self.ordinal_number, offset, data.len());
+        Some(requested_data)
+    }
+
+    // Returns true if you managed to get the necessary data.
+    fn wait_for_data(&self, requested: Span, cancelled: &dyn Fn() -> bool) -> bool {
+        // Really data downloading may be in progress, because we need to check data availability.
+        // The end is rechecked on every iteration, since the resource may turn out to be shorter meanwhile.
+        let is_available = || self.get_end_position() >= requested.clamp_end(self.resource_size()).end();
+        debug!("[reader {}] Waiting to read data block {:?} from http. Current data {:?}",
+            self.ordinal_number, requested, self.get_buffered());
+        let mut total_waited = Duration::ZERO;
+        while !is_available() {
+            // data before the requested one is not needed, and it may not fit into the buffer after a seek ahead
+            self.discard_before(requested.start().saturating_sub(self.profile.rewind));
+            if self.should_stop() {
+                debug!("[reader {}] Reader has been stopped, the data will not arrive", self.ordinal_number);
+                // the reader may have stopped because it reached the new end of the resource
+                return is_available();
+            }
+            if cancelled() {
+                debug!("[reader {}] Waiting for data {:?} has been cancelled", self.ordinal_number, requested);
+                return false;
+            }
+            let waiting_since = Instant::now();
+            let data = self.data.lock().unwrap();
+            // the data may have arrived since the check, and then there would be no signal
+            if self.get_offset() + data.len() < requested.clamp_end(self.resource_size()).end() {
+                drop(self.data_changed.wait_timeout(data, Duration::from_millis(BUFFER_FILL_RECHECK_MS)).unwrap());
+            }
+            // the server asked to slow down, so the data is expected to come later
+            if !self.transport.rate_limiter().is_paused() {
+                total_waited += waiting_since.elapsed();
+            }
+            if total_waited > self.transport.read_timeout() {
+                warn!("[reader {}] The time to wait the data is over!", self.ordinal_number,);
+                return false;
+            }
+        }
+        true
+    }
+
+    // Drops buffered data before the absolute offset `position`.
+    fn discard_before(&self, position: usize) {
+        // usually there is nothing to discard, and the transfer shouldn't wait for the check
+        if position <= self.get_offset() {
+            return;
+        }
+        let mut data = self.data.lock().unwrap();
+        let discarded = min(position.saturating_sub(self.get_offset()), data.len());
+        if discarded > 0 {
+            data.drain(..discarded);
+            self.offset.fetch_add(discarded, Ordering::SeqCst);
+            self.resume_transfer();
+        }
+    }
+
+    // Lets the IO thread continue the transfer paused because the buffer was full.
+    fn resume_transfer(&self) {
+        if self.paused.load(Ordering::SeqCst) {
+            transfers::wake();
+        }
+    }
+
+    fn get_offset(&self) -> usize {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    // Returns the span of the resource currently held in the buffer.
+    fn get_buffered(&self) -> Span {
+        // the end is loaded after the start, so it can't be before it
+        let offset = self.get_offset();
+        Span::new(offset, self.get_end_position())
+    }
+
+    // Checks the requested data is at or after the buffer start and fits into the buffer reach,
+    // which extends further ahead for profiles seeking ahead.
+    fn can_reach(&self, requested: Span) -> bool {
+        let reach = Span::with_len(self.get_offset(), self.buffer_size() + self.profile.seek_ahead);
+        if !reach.contains(requested) {
+            debug!("[reader {}] Requested data {:?} can not be reached for reader {:?}",
+                self.ordinal_number, requested, reach);
+            return false;
+        }
+        true
+    }
+
+    // Whether a response with `status` is repeated rather than read, after refreshing credentials or a pause.
+    fn is_retried(&self, status: u32) -> bool {
+        self.transport.rejects_credentials(status) || status == HTTP_TOO_MANY_REQUESTS
+    }
+
+    // Counts a resumption of the transfer, returns false if the attempts without progress are used up.
+    fn may_resume(&self, retries: &mut Retries) -> bool {
+        if self.get_end_position() > retries.fetched_from || self.get_data_len() >= self.buffer_size() {
+            retries.resumed = 0;
+        }
+        if retries.resumed >= self.transport.retry_policy().attempts {
+            return false;
+        }
+        retries.resumed += 1;
+        true
+    }
+
+    // Decides what to do after a transfer or its setup ended with `result`: retry, resume or stop.
+    fn after_fetch(&self, result: io::Result<u32>) -> Step {
+        let mut retries = self.retries.lock().unwrap();
+        match result {
+            Ok(status) if self.transport.rejects_credentials(status) && retries.attempt < AUTH_RETRIES => {
+                warn!("[reader {}] Request was rejected with {}, retrying with refreshed credentials",
+                    self.ordinal_number, status);
+                // rare enough to block the other transfers for a while
+                if let Err(e) = self.transport.refresh_credentials() {
+                    warn!("[reader {}] Unable to refresh credentials: {}", self.ordinal_number, e);
+                    return Step::Done;
+                }
+                retries.attempt += 1;
+                Step::Wait(Duration::ZERO)
+            }
+            Ok(HTTP_TOO_MANY_REQUESTS) if retries.throttled < RATE_LIMIT_RETRIES => {
+                debug!("[reader {}] Request was rate limited, retrying after the pause", self.ordinal_number);
+                retries.throttled += 1;
+                Step::Wait(Duration::ZERO)
+            }
+            Ok(status) if self.is_retried(status) => {
+                warn!("[reader {}] Giving up after repeated {} responses", self.ordinal_number, status);
+                self.failed_status.store(status, Ordering::SeqCst);
+                self.fail();
+                Step::Done
+            }
+            Ok(_) | Err(_) if self.get_end_position() < self.resource_size() && self.may_resume(&mut retries) => {
+                let delay = self.transport.retry_policy().delay(retries.resumed - 1);
+                warn!("[reader {}] Transfer was interrupted at offset {}, resuming in {:?}",
+                    self.ordinal_number, self.get_end_position(), delay);
+                Step::Wait(delay)
+            }
+            Ok(_) => Step::Done,
+            Err(e) => {
+                debug!("[reader {}] Write function returns error:  {}", self.ordinal_number, e);
+                Step::Done
+            }
+        }
+    }
+
+    // What the IO thread does instead of the next request, None if it may be sent.
+    fn prepare_fetch(&self) -> Option<Step> {
+        if self.should_stop() {
+            return Some(Step::Done);
+        }
+        if let Some(pause) = self.transport.rate_limiter().remaining_pause() {
+            return Some(Step::Wait(pause));
+        }
+        if self.transport.circuit_breaker().check(&self.resource_url).is_err() {
+            warn!("[reader {}] Host is unavailable, not fetching", self.ordinal_number);
+            self.fail();
+            return Some(Step::Done);
+        }
+        if self.transport.download_budget().check().is_err() {
+            self.fail();
+            return Some(Step::Done);
+        }
+        self.retries.lock().unwrap().fetched_from = self.get_end_position();
+        None
+    }
+
+    // Headers of a request of the resource from the absolute offset `start` to its end.
+    fn range_headers(&self, start: usize) -> Vec<String> {
+        let mut headers = vec![format!("Range: bytes={}-", start)];
+        // a changed resource is sent whole, and its new validator fails the version check
+        headers.extend(self.version.if_range().map(|validator| format!("If-Range: {}", validator)));
+        headers
+    }
+
+    // Sets up a single ranged request from the current offset, None if the host has no free connection.
+    fn start_fetch(self: &Arc<Self>) -> io::Result<Option<Connection>> {
+        debug!("[reader {}] Setup URL fetching", self.ordinal_number);
+        let start = self.get_end_position();
+        let Some(mut easy) = self.transport.try_easy(&self.resource_url, &self.range_headers(start))? else {
+            return Ok(None);
+        };
+        easy.buffer_size(16384)?;
+        // the callbacks stay with the handle until they are replaced, so they must not keep the reader alive
+        let reader = Arc::downgrade(self);
+        easy.header_function(move |header| reader.upgrade().is_some_and(|reader| reader.on_header(header)))?;
+        let reader = Arc::downgrade(self);
+        easy.write_function(move |buf| reader.upgrade().map_or(Ok(0), |reader| reader.on_data(buf)))?;
+        self.begin_fetch(start);
+        debug!("[reader {}] Performing URL fetching", self.ordinal_number);
+        Ok(Some(easy))
+    }
+
+    // Resets the state of the transfer for a request from the absolute offset `start`.
+    fn begin_fetch(&self, start: usize) {
+        *self.fetch.lock().unwrap() = Some(FetchState {
+            start,
+            status: 0,
+            headers: vec![],
+            accepted: false,
+            skip: 0,
+            position: start,
+            expected_end: self.resource_size(),
+            requested_at: Instant::now(),
+            meter: TransferMeter::new(Arc::clone(self.transport.throughput())),
+            paused_since: None,
+        });
+    }
+
+    fn on_header(&self, header: &[u8]) -> bool {
+        let mut fetch = self.fetch.lock().unwrap();
+        let Some(fetch) = fetch.as_mut() else {
+            return false;
+        };
+        if let Some(code) = parse_status_line(header) {
+            self.on_status(fetch, code);
+        } else if let Some(header) = parse_header(header) {
+            fetch.headers.push(header);
+        } else if header == b"\r\n" && !is_interim_status(fetch.status) {
+            self.on_final_headers(fetch);
+        }
+        true
+    }
+
+    fn on_status(&self, fetch: &mut FetchState, status: u32) {
+        if fetch.status == 0 {
+            self.transport.throughput().record_latency(fetch.requested_at.elapsed());
+        }
+        fetch.status = status;
+        fetch.headers.clear();
+    }
+
+    // Decides whether the body of the final response is buffered, once all its headers have arrived.
+    fn on_final_headers(&self, fetch: &mut FetchState) {
+        self.transport.on_response(&self.resource_url, fetch.status, &fetch.headers);
+        let body = self.accept_response(fetch.status, &fetch.headers, fetch.start);
+        fetch.accepted = body.is_some();
+        if let Some(body) = body {
+            fetch.skip = fetch.start - body.start;
+            fetch.expected_end = body.end;
+        }
+    }
+
+    fn on_data(&self, buf: &[u8]) -> Result<usize, WriteError> {
+        let mut fetch = self.fetch.lock().unwrap();
+        let Some(fetch) = fetch.as_mut() else {
+            return Ok(0);
+        };
+        if self.is_retried(fetch.status) {
+            // the body of a request to be retried is not a part of the resource
+            return Ok(buf.len());
+        }
+        if !fetch.accepted {
+            debug!("[reader {}] Response has been rejected, stopping", self.ordinal_number);
+            return Ok(0);
+        }
+        if self.should_stop() {
+            debug!("[reader {}] Stop fetching", self.ordinal_number);
+            return Ok(0);
+        }
+        let mut data = self.data.lock().unwrap();
+        // the transfer is resumed as soon as a read frees some space in the buffer,
+        // and curl delivers the same data again then
+        if data.len() >= self.buffer_size() {
+            if fetch.paused_since.is_none() {
+                fetch.meter.finish_sample();
+                fetch.paused_since = Some(Instant::now());
+                debug!("[reader {}] Pausing because buffer is full. Current data range: {:?}",
+                    self.ordinal_number, Span::with_len(self.get_offset(), data.len()));
+            }
+            self.paused.store(true, Ordering::SeqCst);
+            return Err(WriteError::Pause);
+        }
+        if self.share.as_ref().is_some_and(|share| !share.may_receive()) {
+            if fetch.paused_since.is_none() {
+                fetch.meter.finish_sample();
+                fetch.paused_since = Some(Instant::now());
+                debug!("[reader {}] Pausing to let readers of other files receive data", self.ordinal_number);
+            }
+            self.paused.store(true, Ordering::SeqCst);
+            return Err(WriteError::Pause);
+        }
+        if let Some(paused_since) = fetch.paused_since.take() {
+            debug!("[reader {}] Resumed after pausing for {} ms",
+                self.ordinal_number, paused_since.elapsed().as_millis());
+        }
+        if !self.transport.record_download(buf.len()) {
+            self.fail();
+            return Ok(0);
+        }
+        fetch.meter.received(buf.len());
+        if let Some(share) = &self.share {
+            share.record(buf.len());
+        }
+        let to_skip = min(fetch.skip, buf.len());
+        fetch.skip -= to_skip;
+        let buf = &buf[to_skip..];
+        let buffer_end = self.get_offset() + data.len();
+        if buffer_end != fetch.position {
+            warn!("[reader {}] Received data for offset {}, but the buffer ends at {}",
+                self.ordinal_number, fetch.position, buffer_end);
+            self.fail();
+            return Ok(0);
+        }
+        data.extend(buf);
+        self.end.fetch_add(buf.len(), Ordering::SeqCst);
+        self.data_changed.notify_all();
+        fetch.position += buf.len();
+        debug!("[reader {}] Added {} bytes of data to buffer, new len is {}",
+            self.ordinal_number, buf.len(), data.len());
+
+        Ok(to_skip + buf.len())
+    }
+
+    // Checks the result of the transfer set up by `start_fetch` and returns its HTTP status.
+    fn finish_fetch(&self, mut easy: Connection, res: Result<(), curl::Error>) -> io::Result<u32> {
+        debug!("[reader {}] Finished performing URL fetching", self.ordinal_number);
+        // a later request of the handle must not reach the callbacks of this reader
+        let _ = easy.header_function(|_| true);
+        let _ = easy.write_function(|buf| Ok(buf.len()));
+        self.transport.release(easy);
+        let timed_out = res.as_ref().is_err_and(|e| e.is_operation_timedout());
+        self.end_fetch(res.map_err(io::Error::from), timed_out)
+    }
+
+    // Checks the result of a transfer from `begin_fetch`, which has stalled if `timed_out`, and returns its status.
+    fn end_fetch(&self, res: io::Result<()>, timed_out: bool) -> io::Result<u32> {
+        self.paused.store(false, Ordering::SeqCst);
+        let Some(mut fetch) = self.fetch.lock().unwrap().take() else {
+            return Err(io::Error::from_raw_os_error(EIO));
+        };
+        fetch.meter.finish_sample();
+        // a transfer waiting for free space in the buffer or for other files is slow because of the reader,
+        // not the server
+        let idle = self.get_data_len() >= self.buffer_size() || fetch.paused_since.is_some();
+        if timed_out {
+            if idle {
+                debug!("[reader {}] Idle transfer has been aborted by the low speed limit", self.ordinal_number);
+            } else {
+                warn!("[reader {}] Transfer has stalled at offset {}", self.ordinal_number, fetch.position);
+            }
+        }
+        // errors of stopped readers are caused by the stop itself
+        if fetch.status >= HTTP_INTERNAL_SERVER_ERROR || (res.is_err() && !idle && !self.should_stop()) {
+            self.transport.circuit_breaker().record_failure(&self.resource_url);
+        }
+        if !fetch.accepted && !self.should_stop() && !self.is_retried(fetch.status) {
+            // no data will arrive, so there is no sense to wait for it
+            self.failed_status.store(fetch.status, Ordering::SeqCst);
+            self.fail();
+        }
+        res?;
+        if fetch.accepted && !self.should_stop() && fetch.position < fetch.expected_end {
+            warn!("[reader {}] Transfer was truncated: received data up to offset {} of {}",
+                self.ordinal_number, fetch.position, fetch.expected_end);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated transfer"));
+        }
+        Ok(fetch.status)
+    }
+
+    // Decides whether the body of the response to `Range: bytes=<start>-` may be buffered.
+    // Returns the absolute range of the resource the body covers, or None if the body must be rejected.
+    fn accept_response(&self, status: u32, headers: &[(String, String)], start: usize) -> Option<Range<usize>> {
+        let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+        let body = match status {
+            HTTP_PARTIAL_CONTENT => {
+                let content_range = header("content-range");
+                match content_range.and_then(parse_content_range) {
+                    Some(range) if range.start == start => {
+                        if let Some(total) = range.total {
+                            self.shrink_resource(total);
+                        }
+                        range.start..range.end + 1
+                    }
+                    _ => {
+                        warn!("[reader {}] Requested data from offset {}, but the server returned range {:?}",
+                            self.ordinal_number, start, content_range);
+                        return None;
+                    }
+                }
+            }
+            HTTP_OK if start == 0 => 0..self.resource_size(),
+            HTTP_OK => {
+                // the server streams the whole resource, so the data before the offset is thrown away
+                warn!("[reader {}] Server ignored the requested range, discarding first {} bytes",
+                    self.ordinal_number, start);
+                0..self.resource_size()
+            }
+            HTTP_RANGE_NOT_SATISFIABLE => {
+                match header("content-range").and_then(parse_unsatisfied_range) {
+                    Some(size) if size <= start => self.shrink_resource(size),
+                    _ => warn!("[reader {}] Server can't satisfy the range from offset {}", self.ordinal_number, start),
+                }
+                return None;
+            }
+            status if self.transport.rejects_credentials(status) => return None,
+            HTTP_TOO_MANY_REQUESTS => {
+                self.transport.rate_limiter().throttled(header("retry-after"));
+                return None;
+            }
+            _ => {
+                warn!("[reader {}] Server responded with status {}", self.ordinal_number, status);
+                return None;
+            }
+        };
+        if !self.version.accept(header("etag"), header("last-modified")) {
+            return None;
+        }
+        self.transport.rate_limiter().succeeded();
+        self.transport.circuit_breaker().record_success(&self.resource_url);
+        Some(body)
+    }
+
+    fn resource_size(&self) -> usize {
+        self.resource_size.load(Ordering::SeqCst)
+    }
+
+    // Clamps the size of the resource after the server reported it ends at `size`.
+    // Waiters of data beyond it get the data available up to the new end.
+    fn shrink_resource(&self, size: usize) {
+        let previous = self.resource_size.fetch_min(size, Ordering::SeqCst);
+        if size < previous {
+            warn!("[reader {}] Remote resource has shrunk from {} to {} bytes", self.ordinal_number, previous, size);
+        }
+    }
+
+    // How much data is buffered ahead of the reads, adapted to the measured speed of the link.
+    fn buffer_size(&self) -> usize {
+        self.transport.throughput().buffer_size()
+    }
+
+    // Returns the absolute offset of the end of the buffered data, where fetching continues from.
+    fn get_end_position(&self) -> usize {
+        self.end.load(Ordering::SeqCst)
+    }
+
+    fn get_data_len(&self) -> usize {
+        self.get_buffered().len()
+    }
+
+    fn should_stop(&self) -> bool {
+        self.should_stop.load(Ordering::SeqCst)
+    }
+
+    // Returns how long ago the buffered data was fetched or revalidated.
+    pub fn age(&self) -> Duration {
+        self.validated_at.lock().unwrap().elapsed()
+    }
+
+    // Returns how long ago the reader served a read, or was created if it hasn't served any.
+    pub fn idle_time(&self) -> Duration {
+        self.served_at.lock().unwrap().elapsed()
+    }
+
+    // Returns the offset and a copy of the buffered data, e.g. to keep it after the reader is stopped.
+    pub fn buffered_data(&self) -> (usize, Vec<u8>) {
+        let data = self.data.lock().unwrap();
+        (self.get_offset(), data.iter().copied().collect())
+    }
+
+    pub fn mark_validated(&self) {
+        *self.validated_at.lock().unwrap() = Instant::now();
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    // The error of reads the reader has failed for because the server refuses them, e.g. ENOENT after a 404,
+    // None if another reader or a retry may still get the data.
+    pub fn refusal(&self) -> Option<io::Error> {
+        refusal_error(self.failed_status.load(Ordering::SeqCst))
+    }
+
+    fn fail(&self) {
+        warn!("[reader {}] Reader has failed", self.ordinal_number);
+        self.failed.store(true, Ordering::SeqCst);
+        self.stop();
+    }
+
+    pub fn stop(&self) {
+        debug!("[reader {}] Stopping reader", self.ordinal_number);
+        self.should_stop.store(true, Ordering::SeqCst);
+        self.data_changed.notify_all();
+        // the IO thread aborts the transfer
+        transfers::wake();
+    }
+}
+
+// The transfers of a reader on the runtime of the async backend, the same sequence the IO thread runs for curl.
+#[cfg(feature = "async-backend")]
+impl HttpReader {
+    async fn run_async(self: Arc<Self>) {
+        loop {
+            let step = match self.prepare_fetch() {
+                Some(step) => step,
+                None => {
+                    let start = self.get_end_position();
+                    self.begin_fetch(start);
+                    debug!("[reader {}] Performing URL fetching", self.ordinal_number);
+                    let headers = self.range_headers(start);
+                    let sink = Arc::clone(&self) as Arc<dyn Sink>;
+                    let res = async_backend::stream(&self.transport, &self.resource_url, &headers, sink).await;
+                    debug!("[reader {}] Finished performing URL fetching", self.ordinal_number);
+                    let timed_out = res.as_ref().is_err_and(|e| e.kind() == io::ErrorKind::TimedOut);
+                    let result = self.end_fetch(res, timed_out);
+                    if self.should_stop() {
+                        return;
+                    }
+                    self.after_fetch(result)
+                }
+            };
+            match step {
+                Step::Wait(delay) => async_backend::pause(delay).await,
+                // only the IO thread of curl transfers on connections
+                Step::Transfer(_) | Step::Done => return,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async-backend")]
+impl Sink for HttpReader {
+    fn on_response(&self, status: u32, headers: Vec<(String, String)>) {
+        let mut fetch = self.fetch.lock().unwrap();
+        if let Some(fetch) = fetch.as_mut() {
+            self.on_status(fetch, status);
+            fetch.headers = headers;
+            self.on_final_headers(fetch);
+        }
+    }
+
+    fn on_data(&self, chunk: &[u8]) -> Result<usize, WriteError> {
+        HttpReader::on_data(self, chunk)
+    }
+
+    fn resume(&self) -> bool {
+        Driver::resume(self)
+    }
+
+    fn cancelled(&self) -> bool {
+        self.should_stop()
+    }
+
+    fn contending_share(&self) -> Option<&Arc<Share>> {
+        Driver::contending_share(self)
+    }
+}
+
+impl Driver for HttpReader {
+    fn step(self: Arc<Self>) -> Step {
+        if let Some(step) = self.prepare_fetch() {
+            return step;
+        }
+        match self.start_fetch() {
+            Ok(Some(easy)) => Step::Transfer(easy),
+            Ok(None) => Step::Wait(Duration::from_millis(CONNECTION_RECHECK_MS)),
+            Err(e) => self.after_fetch(Err(e)),
+        }
+    }
+
+    fn finished(self: Arc<Self>, easy: Connection, result: Result<(), curl::Error>) -> Step {
+        let result = self.finish_fetch(easy, result);
+        if self.should_stop() {
+            return Step::Done;
+        }
+        self.after_fetch(result)
+    }
+
+    fn resume(&self) -> bool {
+        let has_space = self.get_data_len() < self.buffer_size();
+        let has_turn = self.share.as_ref().is_none_or(|share| share.may_receive());
+        // a stopped reader's transfer is resumed to be aborted by the write callback
+        ((has_space && has_turn) || self.should_stop()) && self.paused.swap(false, Ordering::SeqCst)
+    }
+
+    fn cancelled(&self) -> bool {
+        self.should_stop()
+    }
+
+    fn contending_share(&self) -> Option<&Arc<Share>> {
+        self.share.as_ref().filter(|_| self.get_data_len() < self.buffer_size())
+    }
+}
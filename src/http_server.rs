@@ -0,0 +1,221 @@
+// Re-exports the remote resource over local HTTP, for programs that can consume HTTP
+// but where mounting is not possible. Supports GET and HEAD with single byte ranges.
+
+use std::cmp::min;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, info, warn};
+
+use crate::reader_pool::ReaderPool;
+
+const MAX_HEADER_LINES: usize = 100;
+
+pub struct HttpServer {
+    pool: Arc<ReaderPool>,
+}
+
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    range: Option<String>,
+    keep_alive: bool,
+    has_body: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    Full,
+    // half-open interval of bytes
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+
+impl HttpServer {
+    pub fn new(pool: ReaderPool) -> Self {
+        HttpServer {
+            pool: Arc::new(pool),
+        }
+    }
+
+    // Accepts clients forever, serving each connection from its own thread.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        info!("Serving HTTP on {}", listener.local_addr()?);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr()?;
+            let pool = Arc::clone(&self.pool);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(&pool, stream) {
+                    debug!("[http {}] Connection failed: {}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(pool: &ReaderPool, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    while let Some(request) = read_request(&mut reader)? {
+        debug!("[http] {} {} range={:?}", request.method, request.path, request.range);
+        if request.method != "GET" && request.method != "HEAD" {
+            writer.write_all(b"HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\n\
+                Content-Length: 0\r\nConnection: close\r\n\r\n")?;
+            return Ok(());
+        }
+        if request.has_body {
+            writer.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+            return Ok(());
+        }
+
+        let size = pool.file_size();
+        let range = request.range.as_deref().map_or(RangeRequest::Full, |r| parse_range(r, size));
+        let connection = if request.keep_alive { "keep-alive" } else { "close" };
+        let (start, end) = match range {
+            RangeRequest::Full => {
+                write!(writer, "HTTP/1.1 200 OK\r\n")?;
+                (0, size)
+            }
+            RangeRequest::Partial(start, end) => {
+                write!(writer, "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+                    start, end - 1, size)?;
+                (start, end)
+            }
+            RangeRequest::Unsatisfiable => {
+                write!(writer, "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\n\
+                    Content-Length: 0\r\nConnection: {}\r\n\r\n", size, connection)?;
+                if !request.keep_alive {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        write!(writer, "Accept-Ranges: bytes\r\nContent-Type: application/octet-stream\r\n\
+            Content-Length: {}\r\nConnection: {}\r\n\r\n", end - start, connection)?;
+        if request.method == "GET" {
+            // the headers are already sent, so the only way to report the error is to drop the connection
+            pool.copy_range(start, end, &mut writer).map_err(|e| {
+                warn!("[http] Unable to send bytes {}..{}: {}", start, end, e);
+                e
+            })?;
+        }
+        writer.flush()?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// Returns None when the client closed the connection.
+pub(crate) fn read_request(reader: &mut impl BufRead) -> io::Result<Option<HttpRequest>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Bad request line {:?}", line)));
+    };
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        range: None,
+        keep_alive: version == "HTTP/1.1",
+        has_body: false,
+    };
+
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(Some(request));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "range" => request.range = Some(value.to_string()),
+            "connection" => request.keep_alive = !value.eq_ignore_ascii_case("close"),
+            "content-length" => request.has_body = value != "0",
+            "transfer-encoding" => request.has_body = true,
+            _ => {}
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Too many request headers"))
+}
+
+// Parses a `Range` header value of a resource of `size` bytes.
+// Multiple ranges and unknown units are not supported, such requests are served in full.
+fn parse_range(value: &str, size: usize) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((first, last)) = spec.split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+    let range = match (first.parse::<usize>(), last.parse::<usize>()) {
+        // bytes=-N, the last N bytes
+        (Err(_), Ok(suffix)) if first.is_empty() => (size.saturating_sub(suffix), size),
+        // bytes=N-
+        (Ok(start), Err(_)) if last.is_empty() => (start, size),
+        (Ok(start), Ok(end)) if start <= end => (start, min(end.saturating_add(1), size)),
+        _ => return RangeRequest::Full,
+    };
+    if range.0 >= size || range.0 >= range.1 {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(range.0, range.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffix_range_is_end_of_resource() {
+        assert_eq!(parse_range("bytes=-10", 100), RangeRequest::Partial(90, 100));
+        assert_eq!(parse_range("bytes=-1000", 100), RangeRequest::Partial(0, 100));
+        assert_eq!(parse_range("bytes=-0", 100), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_range_reaches_end() {
+        assert_eq!(parse_range("bytes=10-", 100), RangeRequest::Partial(10, 100));
+        assert_eq!(parse_range(" bytes=0- ", 100), RangeRequest::Partial(0, 100));
+    }
+
+    #[test]
+    fn closed_range_is_clamped_to_end() {
+        assert_eq!(parse_range("bytes=10-19", 100), RangeRequest::Partial(10, 20));
+        assert_eq!(parse_range("bytes=90-200", 100), RangeRequest::Partial(90, 100));
+        assert_eq!(parse_range(&format!("bytes=0-{}", usize::MAX), 100), RangeRequest::Partial(0, 100));
+    }
+
+    #[test]
+    fn inverted_or_unsupported_range_is_served_in_full() {
+        assert_eq!(parse_range("bytes=20-10", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-1", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-b", 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn range_beyond_end_is_unsatisfiable() {
+        assert_eq!(parse_range("bytes=100-", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=150-200", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+}
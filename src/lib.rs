@@ -0,0 +1,57 @@
+pub use mount::MountOption;
+
+#[cfg(feature = "async-backend")]
+pub mod async_backend;
+pub mod audit_log;
+pub mod aws_sigv4;
+pub mod block_store;
+pub mod cache;
+pub mod cache_policy;
+pub mod change_watch;
+pub mod checksum;
+pub mod circuit_breaker;
+pub mod connections;
+pub mod container_index;
+pub mod control;
+pub mod credentials;
+pub mod decrypt;
+pub mod download_budget;
+pub mod export;
+pub mod fetch_priority;
+pub mod ffi;
+pub mod file_name;
+pub mod file_system;
+mod fuse;
+pub mod header_template;
+pub mod http_meta_reader;
+pub mod http_reader;
+pub mod http_server;
+pub mod listing;
+pub mod metrics_server;
+pub mod middleware;
+pub mod mount;
+pub mod nbd;
+pub mod overlay;
+pub mod percent_encoding;
+pub mod prefetch_hints;
+pub mod privileges;
+pub mod profile;
+pub mod range_request;
+pub mod rate_limit;
+pub mod read_batch;
+pub mod reader_pool;
+pub mod remotes;
+pub mod resource_version;
+pub mod s3_listing;
+pub mod sandbox;
+pub mod span;
+pub mod spool;
+pub mod striping;
+pub mod throughput;
+pub mod transfers;
+pub mod transport;
+pub mod tree_manifest;
+pub mod units;
+pub mod warm_connections;
+#[cfg(feature = "python")]
+mod python;
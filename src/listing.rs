@@ -0,0 +1,71 @@
+// Listing of remote resources with their sizes and modification times, printed by `httpfs ls` as a table
+// for people or as JSON for scripts, to check what a mount would show before mounting it.
+
+use std::time::UNIX_EPOCH;
+
+use crate::http_meta_reader::ResourceMeta;
+use crate::units::format_size;
+
+pub struct Entry {
+    pub url: String,
+    pub size: usize,
+    // `Last-Modified` of the resource
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+}
+
+impl Entry {
+    pub fn new(url: &str, meta: &ResourceMeta) -> Self {
+        Entry { url: url.to_string(), size: meta.size, last_modified: meta.last_modified.clone(), etag: meta.etag.clone() }
+    }
+
+    // Modification time in seconds since the Unix epoch, None if the server didn't send a valid one.
+    fn mtime(&self) -> Option<u64> {
+        let last_modified = httpdate::parse_http_date(self.last_modified.as_deref()?).ok()?;
+        Some(last_modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+    }
+}
+
+// One line per entry: the size, exact or with a unit suffix if `human`, the modification time and the url.
+pub fn format_table(entries: &[Entry], human: bool) -> String {
+    let sizes: Vec<String> = entries.iter()
+        .map(|entry| if human { format_size(entry.size as u64) } else { entry.size.to_string() })
+        .collect();
+    let width = sizes.iter().map(String::len).max().unwrap_or(0);
+    entries.iter().zip(sizes)
+        .map(|(entry, size)| {
+            let modified = entry.last_modified.as_deref().unwrap_or("-");
+            format!("{:>width$}  {:<29}  {}\n", size, modified, entry.url, width = width)
+        })
+        .collect()
+}
+
+// A JSON array of objects with `url`, `size`, `mtime` (Unix seconds) and `etag`, the last two null if unknown.
+pub fn format_json(entries: &[Entry]) -> String {
+    let objects: Vec<String> = entries.iter()
+        .map(|entry| {
+            let mtime = entry.mtime().map_or("null".to_string(), |mtime| mtime.to_string());
+            let etag = entry.etag.as_deref().map_or("null".to_string(), json_string);
+            format!("{{\"url\":{},\"size\":{},\"mtime\":{},\"etag\":{}}}", json_string(&entry.url), entry.size, mtime, etag)
+        })
+        .collect();
+    format!("[{}]\n", objects.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -1,18 +1,73 @@
-use clap::{Arg, ArgAction, Command};
-use fuser::{MountOption};
-use log::debug;
+use std::cmp::min;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 
-use crate::file_system::HttpFs;
-use crate::http_meta_reader::HttpMetaReader;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use log::{debug, info, warn};
 
-mod file_system;
-mod http_reader;
-mod http_meta_reader;
+use httpfs::MountOption;
+use httpfs::audit_log::AuditLog;
+use httpfs::aws_sigv4::{parse_aws_scope, AwsCredentials, AwsSigV4};
+use httpfs::block_store::BlockStore;
+use httpfs::cache::{cached_version, DiskCache};
+use httpfs::cache_policy::CachePolicy;
+use httpfs::change_watch::ChangeWatch;
+use httpfs::checksum::{parse_checksum, ChecksumManifest, Verifier};
+use httpfs::control::{self, ControlRequest, ControlServer};
+use httpfs::credentials::{prompt_password, CommandCredentials};
+use httpfs::decrypt::{load_key, Decryption, NONCE_PREFIX_LEN};
+use httpfs::fetch_priority::{set_fetch_priority, FetchPriority};
+use httpfs::export::export;
+use httpfs::file_name::file_name_of;
+use httpfs::file_system::{check_paths, parse_file_name, parse_named_url, HttpFs, MountedFiles, UidAccess, FILE_INO};
+use httpfs::header_template::validate_header;
+use httpfs::http_meta_reader::{HttpMetaReader, ResourceMeta};
+use httpfs::http_server::HttpServer;
+use httpfs::listing::{format_json, format_table, Entry};
+use httpfs::metrics_server::MetricsServer;
+use httpfs::mount::{mount_options, Mount};
+use httpfs::nbd::NbdServer;
+use httpfs::overlay::Overlay;
+use httpfs::privileges::{drop_privileges, parse_account, Account};
+use httpfs::profile::{parse_profile, ReadProfile};
+use httpfs::range_request::fetch_range;
+use httpfs::reader_pool::{parse_buffer_size, ReaderPool};
+use httpfs::remotes::Remotes;
+use httpfs::resource_version::{parse_etag_policy, EtagPolicy, ResourceVersion};
+use httpfs::s3_listing::S3Location;
+use httpfs::sandbox::enable_seccomp;
+use httpfs::span::Span;
+use httpfs::tree_manifest::{TreeEntry, TreeManifest};
+use httpfs::transport::{
+    parse_backend, parse_credentials, parse_user, Backend, HttpAuth, Keepalive, LowSpeedLimit, RetryPolicy,
+    SocketOptions, TlsOptions, Transport,
+};
+use httpfs::units::{format_size, parse_byte_range, parse_duration, parse_size, ByteRange};
+use httpfs::warm_connections::warm_up;
+
+// How often the conditions of --idle_unmount and --unmount_after are checked
+const UNMOUNT_RECHECK: Duration = Duration::from_secs(1);
+// Name of the file of the resource given by URL
+const MAIN_FILE_NAME: &str = "file";
+// How many metadata requests of the files of a tree manifest are sent at once
+const TREE_META_FETCHERS: usize = 8;
 
 fn main() {
     env_logger::init();
 
     let matches = Command::new("hello")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(
             Arg::new("MOUNT_POINT")
                 .required(true)
@@ -21,7 +76,7 @@ fn main() {
         )
         .arg(
             Arg::new("URL")
-                .required(true)
+                .required_unless_present("manifest")
                 .index(2)
                 .help("Remote HTTP resource url"),
         )
@@ -31,11 +86,106 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Automatically unmount on process exit"),
         )
+        .arg(
+            Arg::new("idle_unmount")
+                .long("idle_unmount")
+                .value_parser(parse_duration)
+                .help("Unmount when the file has not been opened or read for this long, e.g. 30m"),
+        )
+        .arg(
+            Arg::new("unmount_after")
+                .long("unmount_after")
+                .value_parser(parse_duration)
+                .help("Unmount after this long since mounting, e.g. 6h"),
+        )
         .arg(
             Arg::new("additional_header")
                 .long("additional_header")
                 .action(ArgAction::Append)
-                .help("Additional header will be added to HTTP requests"),
+                .global(true)
+                .value_parser(validate_header)
+                .help("Additional header will be added to HTTP requests. \
+                    Values may contain {date}, {epoch} and {range} evaluated per request"),
+        )
+        .arg(
+            Arg::new("auth_refresh_cmd")
+                .long("auth_refresh_cmd")
+                .global(true)
+                .help("Shell command run when requests are rejected with 401 or 403; the header lines it prints, \
+                    e.g. Authorization: Bearer <token>, replace those of the same name and the requests are repeated"),
+        )
+        .arg(
+            Arg::new("login_cmd")
+                .long("login_cmd")
+                .global(true)
+                .conflicts_with("auth_refresh_cmd")
+                .help("Shell command logging into a session-based portal before the first request, run again \
+                    when requests are rejected; the header lines it prints are sent with all requests, \
+                    Set-Cookie lines as a Cookie header"),
+        )
+        .arg(
+            Arg::new("negotiate")
+                .long("negotiate")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Authenticate with SPNEGO (Negotiate) using the Kerberos ticket of the user, see kinit"),
+        )
+        .arg(
+            Arg::new("ntlm")
+                .long("ntlm")
+                .global(true)
+                .value_parser(parse_credentials)
+                .conflicts_with("negotiate")
+                .help("Authenticate with NTLM as DOMAIN\\user:password, e.g. for IIS or SharePoint"),
+        )
+        .arg(
+            Arg::new("user")
+                .long("user")
+                .global(true)
+                .value_parser(parse_user)
+                .conflicts_with_all(["negotiate", "ntlm"])
+                .help("Authenticate with Basic or Digest, whichever the server asks for, as USER:PASSWORD, \
+                    or as USER with the password read from the terminal"),
+        )
+        .arg(
+            Arg::new("aws_sigv4")
+                .long("aws_sigv4")
+                .global(true)
+                .value_parser(parse_aws_scope)
+                .conflicts_with_all(["negotiate", "ntlm", "user"])
+                .help("Sign every request with AWS Signature V4 for REGION[:SERVICE], e.g. eu-central-1 for private \
+                    S3 objects, with the keys of AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY or of the AWS_PROFILE \
+                    of ~/.aws/credentials"),
+        )
+        .arg(
+            Arg::new("cacert")
+                .long("cacert")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Trust the CA certificates of this PEM file instead of those of the system, \
+                    e.g. for servers with certificates of a private CA"),
+        )
+        .arg(
+            Arg::new("cert")
+                .long("cert")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Authenticate with the client certificate of this PEM file, for servers requiring mutual TLS"),
+        )
+        .arg(
+            Arg::new("key")
+                .long("key")
+                .global(true)
+                .requires("cert")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("PEM file of the private key of --cert, unless the certificate file contains it"),
+        )
+        .arg(
+            Arg::new("insecure")
+                .long("insecure")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Accept any server certificate and host name, only for self-signed test servers"),
         )
         .arg(
             Arg::new("allow_root")
@@ -43,29 +193,1583 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Allow root user to access filesystem"),
         )
+        .arg(
+            Arg::new("allow_other")
+                .long("allow_other")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("allow_root")
+                .help("Allow all users to access filesystem, narrowed by --allow_uid and --deny_uid"),
+        )
+        .arg(
+            Arg::new("allow_uid")
+                .long("allow_uid")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(u32))
+                .help("Allow only this uid, given once per user, to open and read the file"),
+        )
+        .arg(
+            Arg::new("deny_uid")
+                .long("deny_uid")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(u32))
+                .help("Refuse opens and reads of this uid, given once per user"),
+        )
+        .arg(
+            Arg::new("prefetch_hints")
+                .long("prefetch_hints")
+                .value_parser(parse_size)
+                .help("Download ranges written to .httpfs/prefetch ahead of reads, holding up to this much of them, \
+                    e.g. 256M. The mount is not read-only then, though only the hints can be written"),
+        )
+        .arg(
+            Arg::new("headers_file")
+                .long("headers_file")
+                .action(ArgAction::SetTrue)
+                .help("Expose the response headers of the resource as file.headers next to the file"),
+        )
+        .arg(
+            Arg::new("file_name")
+                .long("file_name")
+                .value_parser(parse_file_name)
+                .help("Name of the file of URL in the mount. By default it is the filename of Content-Disposition \
+                    or else the last segment of the URL path, `file` if neither gives one"),
+        )
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .action(ArgAction::Append)
+                .value_parser(parse_named_url)
+                .help("Mount another resource as the file NAME next to the main one, given as NAME=URL"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Mount the directory tree of remote files described in this file, next to the file of URL if given"),
+        )
+        .arg(
+            Arg::new("control_socket")
+                .long("control_socket")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Take requests adding and removing files of the mount on this unix socket, see the ctl command"),
+        )
+        .arg(
+            Arg::new("listing")
+                .long("listing")
+                .global(true)
+                .value_parser(["s3"])
+                .help("Mount the objects of the bucket of URL, e.g. https://bucket.s3.amazonaws.com/prefix/, \
+                    as a directory tree, listed with ListObjectsV2. s3://BUCKET/PREFIX URLs are listed without it"),
+        )
+        .arg(
+            Arg::new("relist_interval")
+                .long("relist_interval")
+                .value_parser(parse_duration)
+                .help("List a mounted bucket again this often, e.g. 5m, adding new objects, replacing changed ones \
+                    and removing deleted ones. Writes to .httpfs/refresh list it at once, 0 lists it only then. \
+                    The mount is not read-only then"),
+        )
+        .arg(
+            Arg::new("s3_endpoint")
+                .long("s3_endpoint")
+                .global(true)
+                .help("Endpoint of the store of s3:// URLs other than AWS, e.g. http://minio.local:9000, \
+                    whose buckets are addressed by path"),
+        )
+        .arg(
+            Arg::new("overlay")
+                .long("overlay")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Show the regular files of this local directory in the mount, \
+                    a local file shadows the remote file of the same name"),
+        )
+        .arg(
+            Arg::new("audit_log")
+                .long("audit_log")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Append every open and read with the uid, gid, pid and command of the requesting process \
+                    and the byte range to this file"),
+        )
+        .arg(
+            Arg::new("remotes_config")
+                .long("remotes_config")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Named remotes config, ~/.config/httpfs/remotes.conf by default. \
+                    Remote resources are referenced as NAME:PATH"),
+        )
+        .arg(
+            Arg::new("sha256")
+                .long("sha256")
+                .global(true)
+                .value_parser(parse_checksum)
+                .help("Expected SHA-256 of the whole resource, checked when it is read sequentially"),
+        )
+        .arg(
+            Arg::new("checksum_manifest")
+                .long("checksum_manifest")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Sidecar manifest with per-block SHA-256 checksums, every read is verified against it"),
+        )
+        .arg(
+            Arg::new("block_store")
+                .long("block_store")
+                .global(true)
+                .requires("checksum_manifest")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Directory storing the blocks of the checksum manifest by their SHA-256, shared by all mounts, \
+                    so identical blocks under different URLs are downloaded and stored once"),
+        )
+        .arg(
+            Arg::new("cache_dir")
+                .long("cache_dir")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Directory keeping the downloaded data of each URL in a sparse file, reads and later mounts \
+                    of the same version of the resource don't download it again"),
+        )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .global(true)
+                .requires("cache_dir")
+                .conflicts_with_all(["watch_interval", "revalidate_after"])
+                .action(ArgAction::SetTrue)
+                .help("Make no requests: the size is taken from --cache_dir and reads of data which isn't cached \
+                    fail with EIO"),
+        )
+        .arg(
+            Arg::new("decrypt_key")
+                .long("decrypt_key")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("File with the AES-256 key of a resource encrypted in chunks with AES-GCM, \
+                    32 bytes or 64 hex digits. The decrypted content is served instead"),
+        )
+        .arg(
+            Arg::new("decrypt_chunk_size")
+                .long("decrypt_chunk_size")
+                .global(true)
+                .value_parser(parse_size)
+                .default_value("64K")
+                .help("Plaintext size of the chunks the resource is encrypted in"),
+        )
+        .arg(
+            Arg::new("gunzip")
+                .long("gunzip")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Serve the decompressed content of a gzip resource, which is decompressed into a local spool \
+                    file from the beginning, reads wait for the spool to reach them"),
+        )
+        .arg(
+            Arg::new("spool_dir")
+                .long("spool_dir")
+                .alias("gunzip_spool_dir")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Directory of the spool file of --gunzip or of a server ignoring byte ranges, \
+                    the temporary directory by default"),
+        )
+        .arg(
+            Arg::new("spool_limit")
+                .long("spool_limit")
+                .alias("gunzip_spool_limit")
+                .global(true)
+                .value_parser(parse_size)
+                .help("Maximum size of the spool file, e.g. 20G. Reads beyond fail"),
+        )
+        .arg(
+            Arg::new("low_speed_limit")
+                .long("low_speed_limit")
+                .global(true)
+                .value_parser(parse_size)
+                .default_value("1K")
+                .help("Transfers slower than this many bytes per second for low_speed_time are retried"),
+        )
+        .arg(
+            Arg::new("low_speed_time")
+                .long("low_speed_time")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .help("Seconds a transfer may stay below low_speed_limit, 0 disables the check"),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .global(true)
+                .value_parser(clap::value_parser!(u8))
+                .default_value("5")
+                .help("How many times in a row an interrupted transfer is resumed without receiving any data \
+                    before its reads fail"),
+        )
+        .arg(
+            Arg::new("retry_delay")
+                .long("retry_delay")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("500ms")
+                .help("Delay before resuming an interrupted transfer, doubled with every further attempt \
+                    and shortened by a random part up to a half"),
+        )
+        .arg(
+            Arg::new("max_retry_delay")
+                .long("max_retry_delay")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("10s")
+                .help("Longest delay before resuming an interrupted transfer"),
+        )
+        .arg(
+            Arg::new("max_connections_per_host")
+                .long("max_connections_per_host")
+                .global(true)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Open at most this many connections to a host, requests wait for a free one"),
+        )
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .global(true)
+                .value_parser(parse_backend)
+                .default_value("curl")
+                .help("HTTP implementation of range fetches: curl, or hyper to multiplex them on a tokio runtime \
+                    with the async-backend feature"),
+        )
+        .arg(
+            Arg::new("reader_idle_timeout")
+                .long("reader_idle_timeout")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("60s")
+                .help("Stop readers which haven't served a read for this long, freeing their buffers \
+                    and connections, 0 to keep them until new readers displace them"),
+        )
+        .arg(
+            Arg::new("max_readers")
+                .long("max_readers")
+                .global(true)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("5")
+                .help("Streaming readers kept per file at once, each with a buffer and a connection"),
+        )
+        .arg(
+            Arg::new("buffer_size")
+                .long("buffer_size")
+                .global(true)
+                .value_parser(parse_buffer_size)
+                .help("Size of the buffer of every reader, e.g. 32M for a fast link with a high latency, \
+                    by default it is sized by the measured throughput between 512K and 8M"),
+        )
+        .arg(
+            Arg::new("read_timeout")
+                .long("read_timeout")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("10s")
+                .help("How long a read waits for a reader to receive its data before the read is retried"),
+        )
+        .arg(
+            Arg::new("mirror")
+                .long("mirror")
+                .action(ArgAction::Append)
+                .global(true)
+                .help("Another URL of the same resource, one-shot requests without a response within \
+                    hedge_delay are repeated on it"),
+        )
+        .arg(
+            Arg::new("hedge_delay")
+                .long("hedge_delay")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("250ms")
+                .help("How long a one-shot request waits for a response before it is repeated on the next mirror"),
+        )
+        .arg(
+            Arg::new("stripe_size")
+                .long("stripe_size")
+                .global(true)
+                .value_parser(parse_size)
+                .help("Download sequential reads ahead in stripes of this size, e.g. 4M, fetched in parallel \
+                    from the URL and every mirror to aggregate their bandwidth"),
+        )
+        .arg(
+            Arg::new("warm_connections")
+                .long("warm_connections")
+                .global(true)
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+                .help("Open this many connections to the host at start, so that the first seeks \
+                    don't wait for handshakes"),
+        )
+        .arg(
+            Arg::new("drop_privileges")
+                .long("drop_privileges")
+                .global(true)
+                .value_parser(parse_account)
+                .help("Switch to this USER[:GROUP] once mounted or listening, so that a process started \
+                    by root does all network IO unprivileged"),
+        )
+        .arg(
+            Arg::new("seccomp")
+                .long("seccomp")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Restrict the process to the system calls serving reads needs once mounted or listening; \
+                    unmounting is then left to fusermount -u"),
+        )
+        .arg(
+            Arg::new("max_download")
+                .long("max_download")
+                .global(true)
+                .value_parser(parse_size)
+                .help("Stop downloading once this much has been downloaded, e.g. 50G; reads not served \
+                    from buffered data fail with EIO then"),
+        )
+        .arg(
+            Arg::new("metrics_listen")
+                .long("metrics_listen")
+                .global(true)
+                .help("Serve /metrics on this address, e.g. 127.0.0.1:9100, and with the profiling feature \
+                    CPU profiles at /debug/pprof/profile and flame graphs at /debug/pprof/flamegraph"),
+        )
+        .arg(
+            Arg::new("egress_cost_per_gb")
+                .long("egress_cost_per_gb")
+                .value_parser(clap::value_parser!(f64))
+                .help("Price of downloading 1G (1024^3 bytes), e.g. 0.09, to estimate the egress cost \
+                    of the mount in the summary printed at unmount"),
+        )
+        .arg(
+            Arg::new("fetch_nice")
+                .long("fetch_nice")
+                .global(true)
+                .allow_negative_numbers(true)
+                .value_parser(clap::value_parser!(i32).range(-20..=19))
+                .help("Nice value of the threads downloading ahead of reads, e.g. 10, so that they don't \
+                    compete with the application for CPU time"),
+        )
+        .arg(
+            Arg::new("fetch_idle_io")
+                .long("fetch_idle_io")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Put the threads downloading ahead of reads in the idle IO scheduling class"),
+        )
+        .arg(
+            Arg::new("tcp_nodelay")
+                .long("tcp_nodelay")
+                .global(true)
+                .value_parser(clap::value_parser!(bool))
+                .default_value("true")
+                .help("Send requests without waiting to fill TCP segments"),
+        )
+        .arg(
+            Arg::new("tcp_receive_buffer")
+                .long("tcp_receive_buffer")
+                .global(true)
+                .value_parser(parse_size)
+                .help("Receive buffer of connections, e.g. 4M, for links with a large bandwidth-delay product \
+                    which the system default doesn't cover"),
+        )
+        .arg(
+            Arg::new("tcp_keepalive")
+                .long("tcp_keepalive")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("60s")
+                .help("Send keepalive probes on connections idle for this long, 0 to disable"),
+        )
+        .arg(
+            Arg::new("tcp_keepalive_interval")
+                .long("tcp_keepalive_interval")
+                .global(true)
+                .value_parser(parse_duration)
+                .default_value("15s")
+                .help("Interval between keepalive probes"),
+        )
+        .arg(
+            Arg::new("revalidate_after")
+                .long("revalidate_after")
+                .global(true)
+                .value_parser(parse_duration)
+                .help("Age after which buffered data is revalidated with the server before reuse, e.g. 5m, \
+                    it is downloaded again only if the resource has changed"),
+        )
+        .arg(
+            Arg::new("watch_interval")
+                .long("watch_interval")
+                .global(true)
+                .value_parser(parse_duration)
+                .help("Check the resource for changes this often, e.g. 5m, logging them and invalidating \
+                    the kernel caches of the mount"),
+        )
+        .arg(
+            Arg::new("on_change_cmd")
+                .long("on_change_cmd")
+                .global(true)
+                .requires("watch_interval")
+                .help("Shell command run after a change, with HTTPFS_URL, HTTPFS_SIZE, HTTPFS_ETAG \
+                    and HTTPFS_LAST_MODIFIED in its environment"),
+        )
+        .arg(
+            Arg::new("honor_cache_control")
+                .long("honor_cache_control")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Revalidate buffered data after the max-age of Cache-Control or at Expires of the resource, \
+                    unless --revalidate_after is given, and keep no-store resources off disk"),
+        )
+        .arg(
+            Arg::new("etag_policy")
+                .long("etag_policy")
+                .global(true)
+                .value_parser(parse_etag_policy)
+                .default_value("ignore")
+                .help("What to do when the ETag of the resource changes: fail reads with EIO, \
+                    refresh the size and continue, or ignore the change and log it"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .global(true)
+                .value_parser(parse_profile)
+                .default_value("default")
+                .help("Tune readers for an access pattern: media keeps data for short seeks in both \
+                    directions and prefetches the end of the file, where video containers often keep their index; \
+                    columnar prefetches the footer of Parquet or ORC files and reads column chunks without readahead"),
+        )
+        .arg(
+            Arg::new("prefetch_index")
+                .long("prefetch_index")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Download the index of MP4 or Matroska files or the footer of Parquet or ORC files at start, \
+                    as the media and columnar profiles do"),
+        )
+        .arg(
+            Arg::new("no_readahead")
+                .long("no_readahead")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Read just the requested range with one request per read instead of streaming ahead, \
+                    for random access like database probing"),
+        )
+        .arg(
+            Arg::new("read_batch_window")
+                .long("read_batch_window")
+                .global(true)
+                .value_parser(parse_duration)
+                .help("Merge scattered reads arriving within this window, e.g. 2ms, into fewer requests"),
+        )
+        .arg(
+            Arg::new("first_data_prefetch")
+                .long("first_data_prefetch")
+                .global(true)
+                .value_parser(parse_size)
+                .default_value("256K")
+                .help("Download this much of the beginning of the file along with its metadata, \
+                    so that the first read doesn't wait for a new request, 0 to disable"),
+        )
+        .arg(
+            Arg::new("require_ranges")
+                .long("require_ranges")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Fail at start if the server does not honor byte ranges, \
+                    instead of falling back to downloading the resource from the beginning"),
+        )
+        .arg(
+            Arg::new("file_size")
+                .long("file_size")
+                .global(true)
+                .value_parser(parse_size)
+                .conflicts_with("offline")
+                .help("Size of the resource of URL, e.g. 4G, for servers answering neither HEAD nor a range request \
+                    with it. The metadata request is skipped, so the file has no ETag, Last-Modified or \
+                    Content-Disposition name"),
+        )
+        .subcommand(
+            Command::new("nbd")
+                .about("Serve the resource as a read-only network block device instead of mounting it")
+                .arg(
+                    Arg::new("URL")
+                        .required(true)
+                        .index(1)
+                        .help("Remote HTTP resource url"),
+                )
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .default_value("127.0.0.1:10809")
+                        .help("Address to accept NBD clients on"),
+                )
+                .arg(
+                    Arg::new("export_name")
+                        .long("export_name")
+                        .default_value("httpfs")
+                        .help("Export name announced to NBD clients"),
+                ),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Re-export the resource over local HTTP with Range support instead of mounting it")
+                .arg(
+                    Arg::new("URL")
+                        .required(true)
+                        .index(1)
+                        .help("Remote HTTP resource url"),
+                )
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .default_value("127.0.0.1:8080")
+                        .help("Address to accept HTTP clients on"),
+                ),
+        )
+        .subcommand(
+            Command::new("cat")
+                .about("Write the resource or a range of it to stdout or a file without mounting")
+                .arg(
+                    Arg::new("URL")
+                        .required(true)
+                        .index(1)
+                        .help("Remote HTTP resource url"),
+                )
+                .arg(
+                    Arg::new("range")
+                        .long("range")
+                        .value_parser(parse_byte_range)
+                        .help("Byte range to read: START-END (END exclusive) or START-, e.g. 100M-101M"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Write to the file instead of stdout"),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Copy the resource to a local file with a progress line, resuming an interrupted copy")
+                .arg(
+                    Arg::new("URL")
+                        .required(true)
+                        .index(1)
+                        .help("Remote HTTP resource url"),
+                )
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .index(2)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Local file to create, the copy is kept in PATH.part until it is complete"),
+                ),
+        )
+        .subcommand(
+            Command::new("ctl")
+                .about("Add or remove files of a running mount through its control socket")
+                .arg(
+                    Arg::new("control_socket")
+                        .long("control_socket")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Control socket of the mount"),
+                )
+                .arg(
+                    Arg::new("COMMAND")
+                        .required(true)
+                        .index(1)
+                        .value_parser(["add-file", "remove-file"])
+                        .help("add-file or remove-file"),
+                )
+                .arg(
+                    Arg::new("SETTINGS")
+                        .index(2)
+                        .num_args(0..)
+                        .help("KEY=VALUE settings: name=PATH, and for add-file url=URL and optionally the size, \
+                            header and weight settings of a file of a tree manifest"),
+                ),
+        )
+        .subcommand(
+            Command::new("ls")
+                .about("Print the size and modification time of the resource without mounting")
+                .arg(
+                    Arg::new("URL")
+                        .required(true)
+                        .index(1)
+                        .help("Remote HTTP resource url"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print a JSON array with size in bytes and mtime in Unix seconds"),
+                )
+                .arg(
+                    Arg::new("human_readable")
+                        .long("human_readable")
+                        .short('H')
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("json")
+                        .help("Print sizes with K, M, G and T suffixes"),
+                ),
+        )
         .get_matches();
 
-    let mountpoint = matches.get_one::<String>("MOUNT_POINT").unwrap();
-    let resource_url = matches.get_one::<String>("URL").unwrap();
-    let mut options = vec![
-        MountOption::RO,
-        MountOption::FSName("httpfs".to_string()),
-    ];
-    if matches.get_flag("auto_unmount") {
-        options.push(MountOption::AutoUnmount);
-    }
-    if matches.get_flag("allow_root") {
-        options.push(MountOption::AllowRoot);
-    }
-    let additional_headers: Vec<String> = matches.get_many::<String>("additional_header")
-        .unwrap()
-        .map(|x| x.to_string())
-        .collect();
+    if let Some(("ctl", ctl_matches)) = matches.subcommand() {
+        control_mount(ctl_matches);
+        return;
+    }
 
-    let meta_reader = HttpMetaReader::new(resource_url, additional_headers.clone());
-    let fs = HttpFs::new(resource_url, meta_reader.get_file_size(), "file", additional_headers.clone());
+    let remotes_path = matches.get_one::<PathBuf>("remotes_config").cloned().or_else(Remotes::default_path);
+    let remotes = match remotes_path {
+        Some(path) => Remotes::load(&path).unwrap_or_else(|e| {
+            eprintln!("Unable to load remotes: {}", e);
+            exit(1);
+        }),
+        None => Remotes::default(),
+    };
 
-    fuser::mount2(fs, mountpoint, &options).unwrap();
+    let command_matches = matches.subcommand().map_or(&matches, |(_, m)| m);
+    // only a mount of a tree manifest has no URL
+    let remote = command_matches.get_one::<String>("URL").map(|url| remotes.resolve(url)).unwrap_or_default();
+    let login_cmd = matches.get_one::<String>("login_cmd").cloned().or(remote.login_cmd);
+    let mut additional_headers = remote.headers;
+    additional_headers.extend(matches.get_many::<String>("additional_header")
+        .unwrap_or_default()
+        .map(|x| x.to_string()));
+    let resource_url = remote.url.as_str();
+    let mut transport = match (login_cmd, matches.get_one::<String>("auth_refresh_cmd")) {
+        (Some(command), _) => {
+            let credentials = CommandCredentials::new(&command, additional_headers);
+            if let Err(e) = credentials.login() {
+                eprintln!("Unable to log in: {}", e);
+                exit(1);
+            }
+            Transport::new(Arc::new(credentials))
+        }
+        (None, Some(command)) => Transport::new(Arc::new(CommandCredentials::new(command, additional_headers))),
+        (None, None) => Transport::with_headers(additional_headers),
+    };
+    let low_speed_time = *matches.get_one::<u64>("low_speed_time").unwrap();
+    if low_speed_time > 0 {
+        let bytes_per_sec = *matches.get_one::<usize>("low_speed_limit").unwrap();
+        transport = transport.with_low_speed_limit(LowSpeedLimit {
+            bytes_per_sec: u32::try_from(bytes_per_sec).unwrap_or(u32::MAX),
+            time: Duration::from_secs(low_speed_time),
+        });
+    }
+    if let Some(&buffer_size) = matches.get_one::<usize>("buffer_size") {
+        transport = transport.with_buffer_size(buffer_size);
+    }
+    transport = transport.with_read_timeout(*matches.get_one::<Duration>("read_timeout").unwrap());
+    transport = transport.with_retry_policy(RetryPolicy {
+        attempts: *matches.get_one::<u8>("retries").unwrap(),
+        base_delay: *matches.get_one::<Duration>("retry_delay").unwrap(),
+        max_delay: *matches.get_one::<Duration>("max_retry_delay").unwrap(),
+    });
+    let ntlm = matches.get_one::<(String, String)>("ntlm");
+    let user = matches.get_one::<(String, Option<String>)>("user");
+    let auth = match (ntlm, user) {
+        (Some((username, password)), _) => Some(HttpAuth::Ntlm { username: username.clone(), password: password.clone() }),
+        (None, Some((username, password))) => {
+            let password = password.clone().unwrap_or_else(|| {
+                prompt_password(&format!("Password of {}: ", username)).unwrap_or_else(|e| {
+                    eprintln!("Unable to read the password: {}", e);
+                    exit(1);
+                })
+            });
+            Some(HttpAuth::Password { username: username.clone(), password })
+        }
+        (None, None) => matches.get_flag("negotiate").then_some(HttpAuth::Negotiate),
+    };
+    if let Some(auth) = auth {
+        if let Err(e) = auth.check_supported() {
+            eprintln!("Unable to authenticate with {}: {}", auth.name(), e);
+            exit(1);
+        }
+        transport = transport.with_http_auth(auth);
+    }
+    if let Some((region, service)) = matches.get_one::<(String, String)>("aws_sigv4") {
+        let credentials = AwsCredentials::load().unwrap_or_else(|e| {
+            eprintln!("Unable to load the AWS credentials: {}", e);
+            exit(1);
+        });
+        transport = transport.with_middleware(Arc::new(AwsSigV4::new(region, service, credentials)));
+    }
+    if let Some(&limit) = matches.get_one::<usize>("max_download") {
+        transport = transport.with_download_limit(limit as u64);
+    }
+    if let Some(&limit) = matches.get_one::<u64>("max_connections_per_host") {
+        transport = transport.with_max_connections_per_host(limit as usize);
+    }
+    transport = transport.with_socket_options(SocketOptions {
+        nodelay: *matches.get_one::<bool>("tcp_nodelay").unwrap(),
+        receive_buffer: matches.get_one::<usize>("tcp_receive_buffer").copied(),
+        keepalive: matches.get_one::<Duration>("tcp_keepalive")
+            .filter(|idle| !idle.is_zero())
+            .map(|&idle| Keepalive {
+                idle,
+                interval: *matches.get_one::<Duration>("tcp_keepalive_interval").unwrap(),
+            }),
+    });
+    let tls = TlsOptions {
+        ca_cert: matches.get_one::<PathBuf>("cacert").cloned(),
+        cert: matches.get_one::<PathBuf>("cert").cloned(),
+        key: matches.get_one::<PathBuf>("key").cloned(),
+        insecure: matches.get_flag("insecure"),
+    };
+    for path in [&tls.ca_cert, &tls.cert, &tls.key].into_iter().flatten() {
+        // a missing file would otherwise fail every request with a TLS error
+        if let Err(e) = File::open(path) {
+            eprintln!("Unable to read {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
+    if tls.insecure {
+        warn!("Server certificates are not verified, the connections are open to interception");
+    }
+    transport = transport.with_tls_options(tls);
+    transport = transport.with_backend(*matches.get_one::<Backend>("backend").unwrap());
+    if let Err(e) = transport.check_backend() {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    set_fetch_priority(FetchPriority {
+        nice: matches.get_one::<i32>("fetch_nice").copied(),
+        idle_io: matches.get_flag("fetch_idle_io"),
+    });
+
+    if let Some(listen) = matches.get_one::<String>("metrics_listen") {
+        // clones of the transport share its download counter
+        MetricsServer::new(transport.clone()).start(listen_on(listen));
+    }
+
+    let bucket = s3_location(&matches, resource_url);
+    if bucket.is_some() && !matches!(matches.subcommand_name(), None | Some("ls")) {
+        eprintln!("A bucket can only be mounted or listed with ls");
+        exit(1);
+    }
+    match (matches.subcommand(), bucket) {
+        (Some(("nbd", nbd_matches)), _) => serve_nbd(nbd_matches, resource_url, transport),
+        (Some(("serve", serve_matches)), _) => serve_http(serve_matches, resource_url, transport),
+        (Some(("cat", cat_matches)), _) => cat(cat_matches, resource_url, transport),
+        (Some(("export", export_matches)), _) => export_to(export_matches, resource_url, transport),
+        (Some(("ls", ls_matches)), Some(bucket)) => list_bucket(ls_matches, &bucket, transport),
+        (Some(("ls", ls_matches)), None) => list(ls_matches, resource_url, transport),
+        // the objects of a bucket are mounted as a tree, without a file of the URL itself
+        (_, Some(bucket)) => mount(&matches, None, Some(bucket), &remotes, transport),
+        (_, None) => mount(&matches, (!resource_url.is_empty()).then_some(resource_url), None, &remotes, transport),
+    }
 
     debug!("End work");
 }
+
+// Fetches the resource metadata and sets up readers with the options shared by all commands.
+fn open_pool(matches: &ArgMatches, resource_url: &str, transport: Transport) -> (ReaderPool, ResourceMeta) {
+    let offline = matches.get_flag("offline");
+    let warm_connections = *matches.get_one::<usize>("warm_connections").unwrap();
+    if warm_connections > 0 && !offline {
+        warm_up(&transport, resource_url, warm_connections);
+    }
+    let meta_reader = HttpMetaReader::new(resource_url, transport.clone());
+    // the beginning of the resource is requested at the same time as the metadata, its size isn't known yet
+    let first_data_len = if offline { 0 } else { *matches.get_one::<usize>("first_data_prefetch").unwrap() };
+    let first_data_version = ResourceVersion::new(None, EtagPolicy::Ignore);
+    let (meta, first_data) = thread::scope(|scope| {
+        let first_data = (first_data_len > 0).then(|| {
+            scope.spawn(|| fetch_range(&transport, resource_url, Span::new(0, first_data_len), &first_data_version))
+        });
+        let meta = match matches.get_one::<usize>("file_size") {
+            Some(&size) => Ok(known_meta(resource_url, size)),
+            None => fetch_meta(matches, resource_url, transport.clone()),
+        };
+        (meta, first_data.map(|first_data| first_data.join().unwrap()))
+    });
+    let meta = meta.unwrap_or_else(|e| {
+        eprintln!("Unable to fetch the size of {}: {}", resource_url, e);
+        exit(1);
+    });
+    // ranges of an empty resource can't be satisfied by any server. Without --require_ranges, they are checked
+    // only if the server doesn't advertise them
+    let require_ranges = matches.get_flag("require_ranges");
+    let ranges_supported = meta.size == 0 || offline || (meta.accepts_ranges && !require_ranges)
+        || match meta_reader.supports_ranges() {
+            Ok(supported) => supported,
+            Err(e) if require_ranges => {
+                eprintln!("Unable to check byte range support of {}: {}", resource_url, e);
+                exit(1);
+            }
+            Err(e) => {
+                warn!("Unable to check byte range support of {}: {}", resource_url, e);
+                true
+            }
+        };
+    if !ranges_supported && require_ranges {
+        eprintln!("{} does not support byte ranges, reads would download it from the beginning", resource_url);
+        exit(1);
+    }
+    let file_size = meta.size;
+    let mut pool = new_pool(matches, resource_url, transport, &meta);
+    let mirrors: Vec<String> = matches.get_many::<String>("mirror").unwrap_or_default().cloned().collect();
+    if !mirrors.is_empty() {
+        pool = pool.with_mirrors(mirrors, *matches.get_one::<Duration>("hedge_delay").unwrap());
+    }
+    if let Some(&stripe_size) = matches.get_one::<usize>("stripe_size") {
+        pool = pool.with_striping(stripe_size);
+    }
+    match (first_data, first_data_version.etag()) {
+        (Some(Ok(_)), Some(etag)) if meta.etag.as_ref().is_some_and(|meta_etag| *meta_etag != etag) => {
+            warn!("{} has changed between the requests at start, its beginning is not prefetched", resource_url);
+        }
+        (Some(Ok(data)), _) => pool.add_prefetched(0, data),
+        // e.g. the range of an empty resource can't be satisfied
+        (Some(Err(e)), _) => debug!("Unable to prefetch the beginning of {}: {}", resource_url, e),
+        (None, _) => {}
+    }
+    // the end of a resource without ranges is only reached by downloading all of it
+    if ranges_supported {
+        if let Err(e) = pool.prefetch_tail() {
+            warn!("Unable to prefetch the end of {}: {}", resource_url, e);
+        }
+        if let Err(e) = pool.prefetch_container_index() {
+            warn!("Unable to prefetch the container index of {}: {}", resource_url, e);
+        }
+    }
+    let cache_policy = if matches.get_flag("honor_cache_control") { meta.cache_policy } else { CachePolicy::default() };
+
+    let file_checksum = matches.get_one::<[u8; 32]>("sha256").copied();
+    let manifest = matches.get_one::<PathBuf>("checksum_manifest").map(|path| {
+        ChecksumManifest::load(path).unwrap_or_else(|e| {
+            eprintln!("Unable to load checksum manifest: {}", e);
+            exit(1);
+        })
+    });
+    if file_checksum.is_some() || manifest.is_some() {
+        let mut verifier = Verifier::new(file_size, file_checksum, manifest).unwrap_or_else(|e| {
+            eprintln!("Unable to verify {}: {}", resource_url, e);
+            exit(1);
+        });
+        let block_store = matches.get_one::<PathBuf>("block_store");
+        if block_store.is_some() && cache_policy.no_store {
+            warn!("{} is marked no-store, its blocks are not kept in the block store", resource_url);
+        }
+        if let Some(dir) = block_store.filter(|_| !cache_policy.no_store) {
+            let block_store = BlockStore::open(dir).unwrap_or_else(|e| {
+                eprintln!("Unable to open the block store {}: {}", dir.display(), e);
+                exit(1);
+            });
+            verifier = verifier.with_block_store(block_store);
+        }
+        pool = pool.with_verifier(verifier);
+    }
+
+    if let Some(path) = matches.get_one::<PathBuf>("decrypt_key") {
+        let key = load_key(path).unwrap_or_else(|e| {
+            eprintln!("Unable to load the decryption key: {}", e);
+            exit(1);
+        });
+        let chunk_size = *matches.get_one::<usize>("decrypt_chunk_size").unwrap();
+        let decryption = pool.read(0, NONCE_PREFIX_LEN)
+            .and_then(|nonce_prefix| Decryption::new(&key, &nonce_prefix, chunk_size))
+            .unwrap_or_else(|e| {
+                eprintln!("Unable to decrypt {}: {}", resource_url, e);
+                exit(1);
+            });
+        pool = pool.with_decryption(decryption);
+    }
+
+    // the spool holds the data as served, verified and decrypted
+    if !ranges_supported {
+        warn!("{} does not support byte ranges, it is downloaded once into a spool file which reads wait for",
+            resource_url);
+        pool = pool.with_spool(&spool_dir(matches), spool_limit(matches)).unwrap_or_else(|e| {
+            eprintln!("Unable to spool {}: {}", resource_url, e);
+            exit(1);
+        });
+    }
+    if matches.get_flag("gunzip") {
+        pool = pool.with_gunzip(&spool_dir(matches), spool_limit(matches)).unwrap_or_else(|e| {
+            eprintln!("Unable to decompress {}: {}", resource_url, e);
+            exit(1);
+        });
+    }
+    (pool, meta)
+}
+
+fn spool_dir(matches: &ArgMatches) -> PathBuf {
+    matches.get_one::<PathBuf>("spool_dir").cloned().unwrap_or_else(env::temp_dir)
+}
+
+fn spool_limit(matches: &ArgMatches) -> Option<usize> {
+    matches.get_one::<usize>("spool_limit").copied()
+}
+
+// Sets up readers of `resource_url` with the options that apply to any resource, as opposed to those
+// describing the resource of the command line, like its checksum or mirrors.
+fn new_pool(matches: &ArgMatches, resource_url: &str, transport: Transport, meta: &ResourceMeta) -> ReaderPool {
+    let etag_policy = *matches.get_one::<EtagPolicy>("etag_policy").unwrap();
+    let mut profile = *matches.get_one::<ReadProfile>("profile").unwrap();
+    profile.prefetch_index |= matches.get_flag("prefetch_index");
+    profile.one_shot_reads |= matches.get_flag("no_readahead");
+    let mut pool = ReaderPool::new(resource_url, meta.size, transport)
+        .with_etag_policy(meta.etag.clone(), meta.last_modified.clone(), etag_policy)
+        .with_profile(profile)
+        .with_max_readers(*matches.get_one::<u64>("max_readers").unwrap() as usize);
+    if let Some(&window) = matches.get_one::<Duration>("read_batch_window") {
+        pool = pool.with_read_batching(window);
+    }
+    let reader_idle_timeout = *matches.get_one::<Duration>("reader_idle_timeout").unwrap();
+    if !reader_idle_timeout.is_zero() {
+        pool = pool.with_reader_idle_timeout(reader_idle_timeout);
+    }
+    let cache_policy = if matches.get_flag("honor_cache_control") { meta.cache_policy } else { CachePolicy::default() };
+    let cache_dir = matches.get_one::<PathBuf>("cache_dir");
+    if cache_dir.is_some() && cache_policy.no_store {
+        warn!("{} is marked no-store, its data is not kept in the cache", resource_url);
+    }
+    if let Some(dir) = cache_dir.filter(|_| !cache_policy.no_store) {
+        let offline = matches.get_flag("offline");
+        match DiskCache::open(dir, resource_url, meta.size, meta.etag.as_deref(), meta.last_modified.as_deref()) {
+            Ok(cache) => pool = pool.with_cache(cache, offline),
+            Err(e) if offline => {
+                eprintln!("Unable to open the cache of {}: {}", resource_url, e);
+                exit(1);
+            }
+            Err(e) => warn!("Unable to open the cache of {}, reads are not cached: {}", resource_url, e),
+        }
+    }
+    let revalidate_after = matches.get_one::<Duration>("revalidate_after").copied();
+    if let Some(max_age) = revalidate_after.or(cache_policy.max_age) {
+        if meta.etag.is_none() && meta.last_modified.is_none() {
+            warn!("{} has neither ETag nor Last-Modified, buffered data can't be revalidated", resource_url);
+        } else {
+            debug!("Buffered data is revalidated after {:?}", max_age);
+            pool = pool.with_revalidation(max_age, meta.last_modified.clone());
+        }
+    }
+    pool
+}
+
+// Fetches the metadata of a resource mounted next to the main one, e.g. given with `--url`.
+fn open_file_pool(matches: &ArgMatches, resource_url: &str, transport: Transport) -> (ReaderPool, ResourceMeta) {
+    let meta = fetch_meta(matches, resource_url, transport.clone()).unwrap_or_else(|e| {
+        eprintln!("Unable to fetch the size of {}: {}", resource_url, e);
+        exit(1);
+    });
+    (new_pool(matches, resource_url, transport, &meta), meta)
+}
+
+// Fetches the metadata of a resource, or takes the size, ETag and Last-Modified of its cached version offline.
+fn fetch_meta(matches: &ArgMatches, resource_url: &str, transport: Transport) -> io::Result<ResourceMeta> {
+    if !matches.get_flag("offline") {
+        return HttpMetaReader::new(resource_url, transport).fetch_meta();
+    }
+    let cache_dir = matches.get_one::<PathBuf>("cache_dir").unwrap();
+    let (size, etag, last_modified) = cached_version(cache_dir, resource_url)
+        .map_err(|e| io::Error::new(e.kind(), format!("no cached version in {}: {}", cache_dir.display(), e)))?;
+    Ok(ResourceMeta { etag, last_modified, ..known_meta(resource_url, size) })
+}
+
+// Metadata of a resource whose size is known without a request, e.g. from a tree manifest.
+fn known_meta(resource_url: &str, size: usize) -> ResourceMeta {
+    ResourceMeta {
+        size,
+        url: resource_url.to_string(),
+        etag: None,
+        last_modified: None,
+        content_disposition: None,
+        accepts_ranges: false,
+        cache_policy: CachePolicy::default(),
+        raw_headers: String::new(),
+    }
+}
+
+// Loads a tree manifest, resolving urls of named remotes, whose headers are sent before those of the entry.
+fn load_tree(path: &Path, remotes: &Remotes) -> TreeManifest {
+    let mut tree = TreeManifest::load(path).unwrap_or_else(|e| {
+        eprintln!("Unable to load the manifest: {}", e);
+        exit(1);
+    });
+    for entry in &mut tree.entries {
+        let remote = remotes.resolve(&entry.url);
+        entry.url = remote.url;
+        entry.headers.splice(0..0, remote.headers);
+    }
+    tree
+}
+
+// Sets up readers of the files of a tree manifest, in the order of its entries. The metadata of files
+// without a size in the manifest is fetched by a few threads at once.
+fn open_tree_pools(matches: &ArgMatches, entries: &[TreeEntry], transport: &Transport) -> Vec<(ReaderPool, ResourceMeta)> {
+    let next = AtomicUsize::new(0);
+    let opened = Mutex::new(Vec::with_capacity(entries.len()));
+    thread::scope(|scope| {
+        for _ in 0..min(TREE_META_FETCHERS, entries.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(entry) = entries.get(i) else {
+                    break;
+                };
+                let (pool, meta) = open_entry_pool(matches, entry, transport).unwrap_or_else(|e| {
+                    eprintln!("Unable to fetch the size of {}: {}", entry.url, e);
+                    exit(1);
+                });
+                opened.lock().unwrap().push((i, pool, meta));
+            });
+        }
+    });
+    let mut opened = opened.into_inner().unwrap();
+    opened.sort_by_key(|(i, _, _)| *i);
+    opened.into_iter().map(|(_, pool, meta)| (pool, meta)).collect()
+}
+
+// Sets up readers of a file of a tree manifest or added through the control socket, with its headers and weight.
+fn open_entry_pool(
+    matches: &ArgMatches,
+    entry: &TreeEntry,
+    transport: &Transport,
+) -> io::Result<(ReaderPool, ResourceMeta)> {
+    let transport = transport.with_added_headers(entry.headers.clone()).with_counted_file(&entry.path);
+    let meta = match entry.size {
+        Some(size) => ResourceMeta {
+            etag: entry.etag.clone(),
+            last_modified: entry.last_modified.clone(),
+            ..known_meta(&entry.url, size)
+        },
+        None => fetch_meta(matches, &entry.url, transport.clone())?,
+    };
+    let pool = new_pool(matches, &entry.url, transport, &meta);
+    let pool = match entry.weight {
+        Some(weight) => pool.with_weight(weight),
+        None => pool,
+    };
+    Ok((pool, meta))
+}
+
+// Mounts the resource until it is unmounted, then prints what the session downloaded,
+// e.g. to attribute egress costs to workloads.
+fn mount(
+    matches: &ArgMatches,
+    resource_url: Option<&str>,
+    bucket: Option<S3Location>,
+    remotes: &Remotes,
+    transport: Transport,
+) {
+    serve_mount(matches, resource_url, bucket, remotes, transport.clone());
+    let budget = transport.download_budget();
+    let cost = |downloaded: u64| matches.get_one::<f64>("egress_cost_per_gb")
+        .map(|per_gb| format!(", estimated egress cost {:.2}", per_gb * downloaded as f64 / (1u64 << 30) as f64))
+        .unwrap_or_default();
+    let downloaded = budget.downloaded();
+    eprintln!("Downloaded {} of {}{}",
+        format_size(downloaded), resource_url.unwrap_or("the mounted files"), cost(downloaded));
+    let files = budget.downloaded_by_file();
+    if files.len() > 1 {
+        for (path, downloaded) in files {
+            eprintln!("  {}: {}{}", path, format_size(downloaded), cost(downloaded));
+        }
+    }
+}
+
+fn serve_mount(
+    matches: &ArgMatches,
+    resource_url: Option<&str>,
+    bucket: Option<S3Location>,
+    remotes: &Remotes,
+    transport: Transport,
+) {
+    let mountpoint = matches.get_one::<String>("MOUNT_POINT").unwrap();
+    let mut options = mount_options(matches.get_flag("auto_unmount"), matches.get_flag("allow_root"));
+    if matches.get_flag("allow_other") {
+        options.push(MountOption::AllowOther);
+    }
+
+    let headers_file = matches.get_flag("headers_file");
+    let named_urls: Vec<&(String, String)> = matches.get_many("url").unwrap_or_default().collect();
+    let mut tree = matches.get_one::<PathBuf>("manifest").map(|path| load_tree(path, remotes));
+    let relist_interval = matches.get_one::<Duration>("relist_interval").copied();
+    if relist_interval.is_some() && bucket.is_none() {
+        eprintln!("--relist_interval applies to a mounted bucket");
+        exit(1);
+    }
+    // the bucket with the objects mounted from it, if it is listed again
+    let mut relisted = None;
+    if let Some(bucket) = bucket {
+        let objects = bucket.list(&transport).unwrap_or_else(|e| {
+            eprintln!("Unable to list {}: {}", bucket.bucket_url, e);
+            exit(1);
+        });
+        let listed = bucket.tree(objects);
+        debug!("Mounting {} objects of {}", listed.entries.len(), bucket.bucket_url);
+        tree.get_or_insert_with(TreeManifest::default).entries.extend(listed.entries.iter().cloned());
+        if relist_interval.is_some() {
+            relisted = Some((bucket, listed));
+        }
+    }
+    let mut other_paths: Vec<String> = named_urls.iter().map(|(name, _)| name.clone()).collect();
+    other_paths.extend(tree.iter().flat_map(|tree| tree.entries.iter().map(|entry| entry.path.clone())));
+    let check_mount_paths = |main_name: Option<&str>| {
+        let mut paths: Vec<String> = main_name.map(String::from).into_iter().collect();
+        paths.extend(other_paths.iter().cloned());
+        if headers_file {
+            let headers_paths: Vec<String> = paths.iter().map(|path| format!("{}.headers", path)).collect();
+            paths.extend(headers_paths);
+        }
+        if let Err(e) = check_paths(paths.iter().map(String::as_str)) {
+            eprintln!("Unable to mount the files: {}", e);
+            exit(1);
+        }
+    };
+    // the name may still change with Content-Disposition, the paths are checked before any request all the same
+    let given_name = matches.get_one::<String>("file_name");
+    let provisional_name = resource_url.map(|url| {
+        given_name.cloned().or_else(|| file_name_of(url, None)).unwrap_or_else(|| MAIN_FILE_NAME.to_string())
+    });
+    check_mount_paths(provisional_name.as_deref());
+    let hints_budget = matches.get_one::<usize>("prefetch_hints").copied();
+    if hints_budget.is_some() && resource_url.is_none() {
+        eprintln!("--prefetch_hints applies to the file of URL");
+        exit(1);
+    }
+
+    let (mut fs, meta) = match resource_url {
+        Some(resource_url) => {
+            let provisional_name = provisional_name.as_deref().expect("the file of URL has a name");
+            let file_transport = transport.clone().with_counted_file(provisional_name);
+            let (mut pool, meta) = open_pool(matches, resource_url, file_transport);
+            if let Some(budget) = hints_budget {
+                pool = pool.with_prefetch_hints(budget);
+            }
+            let name = given_name.cloned()
+                .or_else(|| file_name_of(&meta.url, meta.content_disposition.as_deref()))
+                .or_else(|| file_name_of(resource_url, None))
+                .unwrap_or_else(|| MAIN_FILE_NAME.to_string());
+            if provisional_name != name {
+                debug!("The file of {} is named {}", resource_url, name);
+                check_mount_paths(Some(&name));
+                transport.download_budget().rename_file(provisional_name, &name);
+            }
+            let mut fs = HttpFs::new(pool, &name);
+            if headers_file {
+                fs = fs.with_headers_file(meta.raw_headers.clone());
+            }
+            (fs, Some(meta))
+        }
+        None => (HttpFs::empty(), None),
+    };
+    for (name, url) in named_urls {
+        let (pool, meta) = open_file_pool(matches, url, transport.clone().with_counted_file(name));
+        fs = fs.with_file(name, pool, headers_file.then_some(meta.raw_headers));
+    }
+    if let Some(tree) = tree {
+        for (entry, (pool, meta)) in tree.entries.iter().zip(open_tree_pools(matches, &tree.entries, &transport)) {
+            fs = fs.with_file(&entry.path, pool, headers_file.then_some(meta.raw_headers));
+        }
+    }
+    fs = fs.with_uid_access(UidAccess {
+        allowed: matches.get_many::<u32>("allow_uid").unwrap_or_default().copied().collect(),
+        denied: matches.get_many::<u32>("deny_uid").unwrap_or_default().copied().collect(),
+    });
+    if let Some(dir) = matches.get_one::<PathBuf>("overlay") {
+        let overlay = Overlay::new(dir).unwrap_or_else(|e| {
+            eprintln!("Unable to use the overlay {}: {}", dir.display(), e);
+            exit(1);
+        });
+        fs = fs.with_overlay(overlay);
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("audit_log") {
+        let audit_log = AuditLog::open(path).unwrap_or_else(|e| {
+            eprintln!("Unable to open the audit log {}: {}", path.display(), e);
+            exit(1);
+        });
+        fs = fs.with_audit_log(audit_log);
+    }
+    if hints_budget.is_some() {
+        // the kernel would reject writes of hints to a read-only mount, the file system rejects all other writes
+        options.retain(|option| *option != MountOption::RO);
+        fs = fs.with_prefetch_hints();
+    }
+    let mut relist = None;
+    if let Some((bucket, listed)) = relisted {
+        let (relist_now, requests) = mpsc::channel();
+        // like hints, writes to the refresh file need a writable mount
+        options.retain(|option| *option != MountOption::RO);
+        fs = fs.with_refresh(move || {
+            let _ = relist_now.send(());
+        });
+        relist = Some((bucket, listed, requests));
+    }
+
+    let idle_unmount = matches.get_one::<Duration>("idle_unmount").copied();
+    let unmount_after = matches.get_one::<Duration>("unmount_after").copied();
+    if matches.get_flag("seccomp") && (idle_unmount.is_some() || unmount_after.is_some()) {
+        // unmounting runs fusermount, which the filter doesn't allow
+        eprintln!("--seccomp can't be combined with --idle_unmount or --unmount_after");
+        exit(1);
+    }
+    let restricted = matches.contains_id("drop_privileges") || matches.get_flag("seccomp");
+    let watched = matches.contains_id("watch_interval");
+    let control = matches.get_one::<PathBuf>("control_socket").map(|path| {
+        ControlServer::bind(path).unwrap_or_else(|e| {
+            eprintln!("Unable to listen on {}: {}", path.display(), e);
+            exit(1);
+        })
+    });
+    if idle_unmount.is_none() && unmount_after.is_none() && !restricted && !watched && control.is_none()
+        && relist.is_none()
+    {
+        if let Err(e) = Mount::run(fs, mountpoint, &options) {
+            eprintln!("Unable to mount {}: {}", mountpoint, e);
+            exit(1);
+        }
+        return;
+    }
+
+    let mounted_at = Instant::now();
+    let last_access = fs.last_access();
+    let files = fs.files();
+    let handle = Mount::spawn(fs, mountpoint, &options).unwrap_or_else(|e| {
+        eprintln!("Unable to mount {}: {}", mountpoint, e);
+        exit(1);
+    });
+    if let (Some(resource_url), Some(meta)) = (resource_url, &meta) {
+        let notifier = handle.notifier();
+        watch_changes(matches, resource_url, transport.clone(), meta, move || {
+            // drops cached pages and attributes, so that open files see the new size and content
+            if let Err(e) = notifier.inval_inode(FILE_INO) {
+                warn!("Unable to invalidate the kernel cache of the file: {}", e);
+            }
+        });
+    }
+    if let Some((bucket, listed, requests)) = relist {
+        let notifier = handle.notifier();
+        let files = files.clone();
+        relist_bucket(matches, bucket, listed, requests, files, transport.clone(), move |parent, name| {
+            if let Err(e) = notifier.inval_entry(parent, OsStr::new(name)) {
+                warn!("Unable to invalidate the kernel entry of {}: {}", name, e);
+            }
+        });
+    }
+    if let Some(control) = control {
+        let notifier = handle.notifier();
+        serve_control(matches, control, files, remotes, transport, move |parent, name| {
+            // the kernel would go on finding removed files by their cached entries
+            if let Err(e) = notifier.inval_entry(parent, OsStr::new(name)) {
+                warn!("Unable to invalidate the kernel entry of {}: {}", name, e);
+            }
+        });
+    }
+    restrict_process(matches);
+    if idle_unmount.is_none() && unmount_after.is_none() {
+        if let Err(e) = handle.join() {
+            eprintln!("Mount of {} failed: {}", mountpoint, e);
+            exit(1);
+        }
+        return;
+    }
+    while handle.is_alive() {
+        sleep(UNMOUNT_RECHECK);
+        let idle = last_access.lock().unwrap().elapsed();
+        if idle_unmount.is_some_and(|limit| idle >= limit) {
+            info!("No reads for {} s, unmounting {}", idle.as_secs(), mountpoint);
+        } else if unmount_after.is_some_and(|limit| mounted_at.elapsed() >= limit) {
+            info!("Mounted for {} s, unmounting {}", mounted_at.elapsed().as_secs(), mountpoint);
+        } else {
+            continue;
+        }
+        if let Err(e) = handle.unmount() {
+            eprintln!("Unable to unmount {}: {}", mountpoint, e);
+            exit(1);
+        }
+        return;
+    }
+}
+
+// Serves requests of the control socket in the background, adding files with the options of the mount and
+// calling `on_removed` with the parent inode and the name of each node removed.
+fn serve_control(
+    matches: &ArgMatches,
+    control: ControlServer,
+    files: MountedFiles,
+    remotes: &Remotes,
+    transport: Transport,
+    on_removed: impl Fn(u64, &str) + Send + 'static,
+) {
+    let (matches, remotes) = (matches.clone(), remotes.clone());
+    let headers_file = matches.get_flag("headers_file");
+    thread::spawn(move || {
+        let result = control.serve(|request| match request {
+            ControlRequest::AddFile(mut entry) => {
+                let remote = remotes.resolve(&entry.url);
+                entry.url = remote.url;
+                entry.headers.splice(0..0, remote.headers);
+                let (pool, meta) = open_entry_pool(&matches, &entry, &transport)
+                    .map_err(|e| format!("Unable to fetch the size of {}: {}", entry.url, e))?;
+                files.add(&entry.path, pool, headers_file.then_some(meta.raw_headers))?;
+                info!("Added {} of {}", entry.path, entry.url);
+                Ok(())
+            }
+            ControlRequest::RemoveFile(path) => {
+                for (parent, name) in files.remove(&path)? {
+                    on_removed(parent, &name);
+                }
+                info!("Removed {}", path);
+                Ok(())
+            }
+        });
+        if let Err(e) = result {
+            warn!("Control socket has failed, files can't be added or removed anymore: {}", e);
+        }
+    });
+}
+
+// Lists the bucket again every `--relist_interval`, or only when asked through `requests` if it is zero, mounting the
+// objects which are new or changed since the previous listing and removing the ones which are gone. Changed
+// objects are replaced by new files, so that nothing mixes up their versions, and `on_removed` is called with
+// the parent inode and the name of each node removed. It stops once the file system is dropped.
+fn relist_bucket(
+    matches: &ArgMatches,
+    bucket: S3Location,
+    mut listed: TreeManifest,
+    requests: Receiver<()>,
+    files: MountedFiles,
+    transport: Transport,
+    on_removed: impl Fn(u64, &str) + Send + 'static,
+) {
+    let matches = matches.clone();
+    let headers_file = matches.get_flag("headers_file");
+    let interval = matches.get_one::<Duration>("relist_interval").copied().unwrap_or_default();
+    thread::spawn(move || loop {
+        let woken = match interval.is_zero() {
+            true => requests.recv().map_err(RecvTimeoutError::from),
+            false => requests.recv_timeout(interval),
+        };
+        if woken == Err(RecvTimeoutError::Disconnected) {
+            return;
+        }
+        // several writes to the refresh file while listing are answered by one listing
+        while requests.try_recv().is_ok() {}
+
+        let objects = match bucket.list(&transport) {
+            Ok(objects) => objects,
+            Err(e) => {
+                warn!("Unable to list {} again: {}", bucket.bucket_url, e);
+                continue;
+            }
+        };
+        let mut newer = bucket.tree(objects);
+        let (changed, removed) = listed.changes(&newer);
+        let replaced = changed.iter()
+            .filter(|entry| listed.entries.iter().any(|old| old.path == entry.path))
+            .map(|entry| &entry.path);
+        for path in removed.iter().chain(replaced) {
+            match files.remove(path) {
+                Ok(nodes) => nodes.iter().for_each(|(parent, name)| on_removed(*parent, name)),
+                Err(e) => warn!("Unable to remove {}: {}", path, e),
+            }
+        }
+        let mut failed = vec![];
+        for entry in &changed {
+            let added = open_entry_pool(&matches, entry, &transport)
+                .map_err(|e| e.to_string())
+                .and_then(|(pool, meta)| files.add(&entry.path, pool, headers_file.then_some(meta.raw_headers)));
+            if let Err(e) = added {
+                warn!("Unable to mount {} of {}: {}", entry.path, entry.url, e);
+                failed.push(entry.path.clone());
+            }
+        }
+        info!("Listed {} again: {} objects new or changed, {} gone", bucket.bucket_url, changed.len(), removed.len());
+        // the objects which couldn't be mounted count as new on the next listing
+        newer.entries.retain(|entry| !failed.contains(&entry.path));
+        listed = newer;
+    });
+}
+
+// Sends a request to the control socket of a mount, e.g. `httpfs ctl --control_socket S add-file name=… url=…`.
+fn control_mount(matches: &ArgMatches) {
+    let path = matches.get_one::<PathBuf>("control_socket").unwrap();
+    let command = matches.get_one::<String>("COMMAND").unwrap();
+    let settings: Vec<&String> = matches.get_many::<String>("SETTINGS").unwrap_or_default().collect();
+    if settings.iter().any(|setting| setting.contains('\n')) {
+        eprintln!("Settings can't span several lines");
+        exit(1);
+    }
+    let request: String = [command].into_iter().chain(settings).map(|line| format!("{}\n", line)).collect();
+    match control::send(path, &request) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("Unable to {}: {}", command, e);
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("Unable to reach the mount at {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+// Starts checking the resource for changes if asked, calling `on_change` after each of them.
+fn watch_changes(
+    matches: &ArgMatches,
+    resource_url: &str,
+    transport: Transport,
+    meta: &ResourceMeta,
+    on_change: impl Fn() + Send + 'static,
+) {
+    let Some(&interval) = matches.get_one::<Duration>("watch_interval") else {
+        return;
+    };
+    let mut watch = ChangeWatch::new(resource_url, transport, interval);
+    if let Some(command) = matches.get_one::<String>("on_change_cmd") {
+        if matches.get_flag("seccomp") {
+            // the filter doesn't allow running programs
+            eprintln!("--seccomp can't be combined with --on_change_cmd");
+            exit(1);
+        }
+        watch = watch.with_command(command);
+    }
+    watch.start(meta, on_change);
+}
+
+// Drops the privileges of the process and installs the seccomp filter once the mount or listener is set up,
+// exiting if it isn't possible, rather than going on with more rights than asked for.
+fn restrict_process(matches: &ArgMatches) {
+    if let Some(&account) = matches.get_one::<Account>("drop_privileges") {
+        if let Err(e) = drop_privileges(account) {
+            eprintln!("Unable to switch to uid {} gid {}: {}", account.uid, account.gid, e);
+            exit(1);
+        }
+    }
+    if matches.get_flag("seccomp") {
+        if let Err(e) = enable_seccomp() {
+            eprintln!("Unable to install the seccomp filter: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn serve_nbd(matches: &ArgMatches, resource_url: &str, transport: Transport) {
+    let listen = matches.get_one::<String>("listen").unwrap();
+    let export_name = matches.get_one::<String>("export_name").unwrap();
+
+    let (pool, meta) = open_pool(matches, resource_url, transport.clone());
+    watch_changes(matches, resource_url, transport, &meta, || {});
+    let listener = listen_on(listen);
+    restrict_process(matches);
+
+    if let Err(e) = NbdServer::new(pool, export_name).serve(listener) {
+        eprintln!("NBD server on {} failed: {}", listen, e);
+        exit(1);
+    }
+}
+
+fn serve_http(matches: &ArgMatches, resource_url: &str, transport: Transport) {
+    let listen = matches.get_one::<String>("listen").unwrap();
+
+    let (pool, meta) = open_pool(matches, resource_url, transport.clone());
+    watch_changes(matches, resource_url, transport, &meta, || {});
+    let listener = listen_on(listen);
+    restrict_process(matches);
+
+    if let Err(e) = HttpServer::new(pool).serve(listener) {
+        eprintln!("HTTP server on {} failed: {}", listen, e);
+        exit(1);
+    }
+}
+
+fn listen_on(listen: &str) -> TcpListener {
+    TcpListener::bind(listen).unwrap_or_else(|e| {
+        eprintln!("Unable to listen on {}: {}", listen, e);
+        exit(1);
+    })
+}
+
+fn cat(matches: &ArgMatches, resource_url: &str, transport: Transport) {
+    let range = matches.get_one::<ByteRange>("range").copied();
+
+    let (pool, _) = open_pool(matches, resource_url, transport);
+    let file_size = pool.file_size();
+    let start = range.map_or(0, |r| r.start);
+    let end = range.and_then(|r| r.end).map_or(file_size, |end| min(end, file_size));
+    if start > end {
+        eprintln!("Range starts at {} beyond the end of the resource ({} bytes)", start, file_size);
+        exit(1);
+    }
+
+    let output: Box<dyn Write> = match matches.get_one::<String>("output") {
+        Some(path) => Box::new(File::create(path).unwrap_or_else(|e| {
+            eprintln!("Unable to create {}: {}", path, e);
+            exit(1);
+        })),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut output = BufWriter::new(output);
+    if let Err(e) = pool.copy_range(start, end, &mut output).and_then(|_| output.flush()) {
+        eprintln!("Unable to read bytes {}..{}: {}", start, end, e);
+        exit(1);
+    }
+}
+
+fn export_to(matches: &ArgMatches, resource_url: &str, transport: Transport) {
+    let path = matches.get_one::<PathBuf>("PATH").unwrap();
+    let (pool, meta) = open_pool(matches, resource_url, transport);
+    if let Err(e) = export(&pool, meta.etag.as_deref(), path) {
+        eprintln!("Unable to copy {} to {}: {}", resource_url, path.display(), e);
+        exit(1);
+    }
+}
+
+// The bucket and prefix of `s3://BUCKET/PREFIX`, or of a bucket URL with `--listing s3`; None for the URL
+// of a single resource.
+fn s3_location(matches: &ArgMatches, resource_url: &str) -> Option<S3Location> {
+    if !resource_url.starts_with("s3://") {
+        return matches.get_one::<String>("listing").map(|_| S3Location::from_bucket_url(resource_url));
+    }
+    let region = matches.get_one::<(String, String)>("aws_sigv4").map(|(region, _)| region.as_str());
+    let endpoint = matches.get_one::<String>("s3_endpoint").map(String::as_str);
+    let location = S3Location::parse(resource_url, region, endpoint).unwrap_or_else(|e| {
+        eprintln!("Unable to use the bucket: {}", e);
+        exit(1);
+    });
+    Some(location)
+}
+
+// Prints the objects of a bucket like `list` prints a resource, from the listing without further requests.
+fn list_bucket(matches: &ArgMatches, bucket: &S3Location, transport: Transport) {
+    let objects = bucket.list(&transport).unwrap_or_else(|e| {
+        eprintln!("Unable to list {}: {}", bucket.bucket_url, e);
+        exit(1);
+    });
+    let entries: Vec<Entry> = objects.into_iter()
+        .map(|object| Entry {
+            url: bucket.object_url(&object.key),
+            size: object.size,
+            last_modified: object.last_modified,
+            etag: object.etag,
+        })
+        .collect();
+    let listing = if matches.get_flag("json") {
+        format_json(&entries)
+    } else {
+        format_table(&entries, matches.get_flag("human_readable"))
+    };
+    print!("{}", listing);
+}
+
+fn list(matches: &ArgMatches, resource_url: &str, transport: Transport) {
+    let meta = fetch_meta(matches, resource_url, transport).unwrap_or_else(|e| {
+        eprintln!("Unable to fetch the metadata of {}: {}", resource_url, e);
+        exit(1);
+    });
+    let entries = [Entry::new(resource_url, &meta)];
+    let listing = if matches.get_flag("json") {
+        format_json(&entries)
+    } else {
+        format_table(&entries, matches.get_flag("human_readable"))
+    };
+    print!("{}", listing);
+}
@@ -0,0 +1,213 @@
+// Metrics listener of a mount or server: `/metrics` reports the downloaded bytes in the Prometheus text
+// format, in total and with a `file` label for each mounted file. Built with the `profiling` feature it also
+// profiles the running process on demand, so that throughput problems of production mounts can be investigated
+// without rebuilding them with instrumentation:
+//
+//     /debug/pprof/profile?seconds=30     CPU profile in the pprof format, e.g. for `go tool pprof`
+//     /debug/pprof/flamegraph?seconds=30  the same as an SVG flame graph
+//
+// The profiler samples all threads of the process, so only one profile is taken at a time.
+
+use std::io::{self, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::http_server::read_request;
+use crate::transport::Transport;
+
+const DEFAULT_PROFILE_DURATION: Duration = Duration::from_secs(30);
+const MAX_PROFILE_DURATION: Duration = Duration::from_secs(600);
+
+pub struct MetricsServer {
+    transport: Transport,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Response { status, content_type: "text/plain; charset=utf-8", body: body.into().into_bytes() }
+    }
+}
+
+impl MetricsServer {
+    // Reports the downloads of `transport` and of all its clones.
+    pub fn new(transport: Transport) -> Self {
+        MetricsServer { transport }
+    }
+
+    // Accepts clients in the background, serving each connection from its own thread, as profiles take a while.
+    pub fn start(self, listener: TcpListener) {
+        let server = Arc::new(self);
+        thread::spawn(move || {
+            if let Ok(addr) = listener.local_addr() {
+                info!("Serving metrics on {}", addr);
+            }
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Unable to accept a metrics client: {}", e);
+                        continue;
+                    }
+                };
+                let server = Arc::clone(&server);
+                thread::spawn(move || {
+                    if let Err(e) = server.handle_connection(stream) {
+                        debug!("[metrics] Connection failed: {}", e);
+                    }
+                });
+            }
+        });
+    }
+
+    // Answers a single request and closes the connection.
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        let Some(request) = read_request(&mut BufReader::new(&stream))? else {
+            return Ok(());
+        };
+        debug!("[metrics] {} {}", request.method, request.path);
+        let response = match request.method.as_str() {
+            "GET" => self.respond(&request.path),
+            _ => Response::text("405 Method Not Allowed", "Only GET is supported\n"),
+        };
+        write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status, response.content_type, response.body.len())?;
+        stream.write_all(&response.body)
+    }
+
+    fn respond(&self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match path {
+            "/metrics" => Response {
+                // the version of the Prometheus text format
+                content_type: "text/plain; version=0.0.4",
+                ..Response::text("200 OK", self.metrics())
+            },
+            "/debug/pprof/profile" | "/debug/pprof/flamegraph" => match profile_duration(query) {
+                Ok(duration) => profile(duration, path.ends_with("flamegraph")),
+                Err(e) => Response::text("400 Bad Request", format!("{}\n", e)),
+            },
+            _ => Response::text("404 Not Found", "Not found\n"),
+        }
+    }
+
+    fn metrics(&self) -> String {
+        let budget = self.transport.download_budget();
+        let mut metrics = format!(
+            "# HELP httpfs_downloaded_bytes_total Body bytes received from the remote, in total and for each file.\n\
+            # TYPE httpfs_downloaded_bytes_total counter\n\
+            httpfs_downloaded_bytes_total {}\n",
+            budget.downloaded(),
+        );
+        for (path, downloaded) in budget.downloaded_by_file() {
+            metrics += &format!("httpfs_downloaded_bytes_total{{file=\"{}\"}} {}\n", label_value(&path), downloaded);
+        }
+        metrics
+    }
+}
+
+// Escapes a label value of the Prometheus text format.
+fn label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// The `seconds` parameter of a profile request, 30 by default.
+fn profile_duration(query: &str) -> Result<Duration, String> {
+    let Some(seconds) = query.split('&').find_map(|param| param.strip_prefix("seconds=")) else {
+        return Ok(DEFAULT_PROFILE_DURATION);
+    };
+    seconds.parse().ok()
+        .map(Duration::from_secs)
+        .filter(|duration| !duration.is_zero() && *duration <= MAX_PROFILE_DURATION)
+        .ok_or_else(|| format!("seconds must be between 1 and {}, found {:?}", MAX_PROFILE_DURATION.as_secs(), seconds))
+}
+
+#[cfg(feature = "profiling")]
+fn profile(duration: Duration, flamegraph: bool) -> Response {
+    use std::sync::Mutex;
+
+    static PROFILING: Mutex<()> = Mutex::new(());
+    let Ok(_profiling) = PROFILING.try_lock() else {
+        return Response::text("409 Conflict", "Another profile is being taken\n");
+    };
+    take_profile(duration, flamegraph).unwrap_or_else(|e| {
+        warn!("Unable to profile: {}", e);
+        Response::text("500 Internal Server Error", format!("Unable to profile: {}\n", e))
+    })
+}
+
+#[cfg(feature = "profiling")]
+fn take_profile(duration: Duration, flamegraph: bool) -> Result<Response, String> {
+    use pprof::protos::Message;
+    use pprof::ProfilerGuardBuilder;
+
+    // samples per second, off the beat of timers firing at round frequencies
+    const FREQUENCY: i32 = 99;
+
+    let guard = ProfilerGuardBuilder::default()
+        .frequency(FREQUENCY)
+        // unwinding through these may deadlock in the signal handler
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| e.to_string())?;
+    info!("Profiling for {} s", duration.as_secs());
+    thread::sleep(duration);
+    let report = guard.report().build().map_err(|e| e.to_string())?;
+    let mut body = vec![];
+    if flamegraph {
+        report.flamegraph(&mut body).map_err(|e| e.to_string())?;
+        return Ok(Response { status: "200 OK", content_type: "image/svg+xml", body });
+    }
+    report.pprof().map_err(|e| e.to_string())?.encode(&mut body).map_err(|e| e.to_string())?;
+    Ok(Response { status: "200 OK", content_type: "application/octet-stream", body })
+}
+
+#[cfg(not(feature = "profiling"))]
+fn profile(_duration: Duration, _flamegraph: bool) -> Response {
+    Response::text("501 Not Implemented", "httpfs is built without the profiling feature\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_duration_is_bounded() {
+        assert_eq!(profile_duration(""), Ok(DEFAULT_PROFILE_DURATION));
+        assert_eq!(profile_duration("debug=1&seconds=5"), Ok(Duration::from_secs(5)));
+        for seconds in ["0", "601", "-1", "5s", ""] {
+            assert!(profile_duration(&format!("seconds={}", seconds)).is_err(), "{:?} is accepted", seconds);
+        }
+    }
+
+    #[test]
+    fn metrics_report_the_downloads() {
+        let transport = Transport::with_headers(vec![]);
+        transport.download_budget().record(1234);
+        let response = MetricsServer::new(transport).respond("/metrics");
+        assert_eq!(response.status, "200 OK");
+        assert!(String::from_utf8(response.body).unwrap().contains("\nhttpfs_downloaded_bytes_total 1234\n"));
+        assert_eq!(MetricsServer::new(Transport::with_headers(vec![])).respond("/other").status, "404 Not Found");
+    }
+
+    #[test]
+    fn metrics_report_the_downloads_of_each_file() {
+        let transport = Transport::with_headers(vec![]);
+        transport.clone().with_counted_file("a/1.bin").record_download(100);
+        transport.clone().with_counted_file("a/1.bin").record_download(20);
+        transport.clone().with_counted_file("say \"hi\"\\n").record_download(3);
+        let metrics = String::from_utf8(MetricsServer::new(transport).respond("/metrics").body).unwrap();
+        assert!(metrics.contains("\nhttpfs_downloaded_bytes_total 123\n"));
+        assert!(metrics.contains("\nhttpfs_downloaded_bytes_total{file=\"a/1.bin\"} 120\n"));
+        assert!(metrics.contains("\nhttpfs_downloaded_bytes_total{file=\"say \\\"hi\\\"\\\\n\"} 3\n"));
+    }
+}
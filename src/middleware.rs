@@ -0,0 +1,34 @@
+// Hooks into the HTTP requests of a transport, e.g. custom signing, header rewriting or telemetry for bespoke
+// origins, without patching the transport for each of them. Middlewares are compiled in, added with
+// `Transport::with_middleware`, and run in the order they were added, on retries and refreshes as well.
+
+use std::io;
+
+// A request about to be sent.
+#[derive(Debug)]
+pub struct Request {
+    // "GET" or "HEAD"
+    pub method: &'static str,
+    pub url: String,
+    // full header lines, e.g. "Range: bytes=0-1023", with the credentials and placeholders already evaluated
+    pub headers: Vec<String>,
+}
+
+// The final response of a request, after redirects, once its headers have arrived.
+#[derive(Debug)]
+pub struct Response<'a> {
+    pub url: &'a str,
+    pub status: u32,
+    // lowercase names and values
+    pub headers: &'a [(String, String)],
+}
+
+pub trait Middleware: Send + Sync {
+    // Called before each request. May change its url and headers; an error fails the request.
+    fn on_request(&self, _request: &mut Request) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Called for each response, before it is checked, e.g. for its status.
+    fn on_response(&self, _response: &Response) {}
+}
@@ -0,0 +1,133 @@
+// Mounts of the file system. The options, handle and notifier are httpfs's own, so that users of the library
+// don't depend on fuser, which serves the mounts with the binding in `fuse`.
+
+use std::any::Any;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use fuser::BackgroundSession;
+use log::debug;
+
+use crate::file_system::HttpFs;
+
+// How often `join` checks whether the session thread has ended
+const JOIN_RECHECK: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MountOption {
+    RO,
+    FSName(String),
+    AllowOther,
+    AllowRoot,
+    AutoUnmount,
+}
+
+// Options every httpfs mount uses, plus the optional ones chosen by the user.
+pub fn mount_options(auto_unmount: bool, allow_root: bool) -> Vec<MountOption> {
+    let mut options = vec![
+        MountOption::RO,
+        MountOption::FSName("httpfs".to_string()),
+    ];
+    if auto_unmount {
+        options.push(MountOption::AutoUnmount);
+    }
+    if allow_root {
+        options.push(MountOption::AllowRoot);
+    }
+    options
+}
+
+fn fuse_options(opts: &[MountOption]) -> Vec<fuser::MountOption> {
+    opts.iter().map(|option| match option {
+        MountOption::RO => fuser::MountOption::RO,
+        MountOption::FSName(name) => fuser::MountOption::FSName(name.clone()),
+        MountOption::AllowOther => fuser::MountOption::AllowOther,
+        MountOption::AllowRoot => fuser::MountOption::AllowRoot,
+        MountOption::AutoUnmount => fuser::MountOption::AutoUnmount,
+    }).collect()
+}
+
+pub struct Mount;
+
+impl Mount {
+    // Mounts the filesystem at `path` and serves it from a background thread.
+    // The returned handle unmounts the filesystem when it is dropped.
+    pub fn spawn<P: AsRef<Path>>(fs: HttpFs, path: P, opts: &[MountOption]) -> io::Result<MountHandle> {
+        let session = fuser::spawn_mount2(fs, path, &fuse_options(opts))?;
+        debug!("Background session has started at {:?}", session.mountpoint);
+        Ok(MountHandle { session })
+    }
+
+    // Mounts the filesystem at `path` and serves it on the calling thread until it is unmounted from outside.
+    pub fn run<P: AsRef<Path>>(fs: HttpFs, path: P, opts: &[MountOption]) -> io::Result<()> {
+        fuser::mount2(fs, path, &fuse_options(opts))
+    }
+}
+
+pub struct MountHandle {
+    session: BackgroundSession,
+}
+
+impl MountHandle {
+    pub fn mountpoint(&self) -> &Path {
+        &self.session.mountpoint
+    }
+
+    // Returns the handle for invalidating kernel caches of the mounted files.
+    pub fn notifier(&self) -> Notifier {
+        Notifier { notifier: self.session.notifier() }
+    }
+
+    // Returns false once the session thread has exited, e.g. after an external `fusermount -u`.
+    pub fn is_alive(&self) -> bool {
+        !self.session.guard.is_finished()
+    }
+
+    // Unmounts the filesystem and waits for the session thread to finish.
+    pub fn unmount(self) -> io::Result<()> {
+        debug!("Unmounting {:?}", self.session.mountpoint);
+        let guard = {
+            let session = self.session;
+            let BackgroundSession { guard, .. } = session;
+            guard
+            // the rest of the session is dropped here, which unmounts the filesystem
+        };
+        guard.join().unwrap_or_else(|e| Err(panic_to_error(e)))
+    }
+
+    // Blocks until the filesystem is unmounted from outside the process. Dropping any part of the session
+    // unmounts the filesystem, so the whole session is kept until its thread has ended.
+    pub fn join(self) -> io::Result<()> {
+        while self.is_alive() {
+            thread::sleep(JOIN_RECHECK);
+        }
+        let BackgroundSession { guard, .. } = self.session;
+        guard.join().unwrap_or_else(|e| Err(panic_to_error(e)))
+    }
+}
+
+pub struct Notifier {
+    notifier: fuser::Notifier,
+}
+
+impl Notifier {
+    // Drops the cached pages and attributes of a file.
+    pub fn inval_inode(&self, ino: u64) -> io::Result<()> {
+        self.notifier.inval_inode(ino, 0, 0)
+    }
+
+    // Drops the cached entry of `name` in the directory `parent`.
+    pub fn inval_entry(&self, parent: u64, name: &OsStr) -> io::Result<()> {
+        self.notifier.inval_entry(parent, name)
+    }
+}
+
+fn panic_to_error(e: Box<dyn Any + Send>) -> io::Error {
+    let message = e.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| e.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("unknown panic"));
+    io::Error::other(format!("FUSE session panicked: {}", message))
+}
@@ -0,0 +1,269 @@
+// Serves the remote resource as a read-only network block device.
+// Implements the fixed newstyle handshake and the transmission phase of the NBD protocol:
+// https://github.com/NetworkBlockDevice/nbd/blob/master/doc/proto.md
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, info, warn};
+
+use crate::reader_pool::ReaderPool;
+
+const NBDMAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const OPTION_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const NBD_FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const NBD_FLAG_NO_ZEROES: u16 = 1 << 1;
+const NBD_FLAG_C_NO_ZEROES: u32 = 1 << 1;
+
+const NBD_FLAG_HAS_FLAGS: u16 = 1 << 0;
+const NBD_FLAG_READ_ONLY: u16 = 1 << 1;
+const NBD_FLAG_SEND_FLUSH: u16 = 1 << 2;
+const NBD_FLAG_CAN_MULTI_CONN: u16 = 1 << 8;
+const TRANSMISSION_FLAGS: u16 =
+    NBD_FLAG_HAS_FLAGS | NBD_FLAG_READ_ONLY | NBD_FLAG_SEND_FLUSH | NBD_FLAG_CAN_MULTI_CONN;
+
+const NBD_OPT_EXPORT_NAME: u32 = 1;
+const NBD_OPT_ABORT: u32 = 2;
+const NBD_OPT_LIST: u32 = 3;
+const NBD_OPT_INFO: u32 = 6;
+const NBD_OPT_GO: u32 = 7;
+
+const NBD_REP_ACK: u32 = 1;
+const NBD_REP_SERVER: u32 = 2;
+const NBD_REP_INFO: u32 = 3;
+const NBD_REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const NBD_INFO_EXPORT: u16 = 0;
+
+const NBD_CMD_READ: u16 = 0;
+const NBD_CMD_WRITE: u16 = 1;
+const NBD_CMD_DISC: u16 = 2;
+const NBD_CMD_FLUSH: u16 = 3;
+
+const NBD_EPERM: u32 = 1;
+const NBD_EIO: u32 = 5;
+const NBD_EINVAL: u32 = 22;
+
+// Options bigger than this are rejected instead of being read into memory
+const MAX_OPTION_LENGTH: u32 = 64 * 1024;
+// Reads and writes bigger than this are refused, the same limit as the reference server uses
+const MAX_REQUEST_LENGTH: u32 = 32 * 1024 * 1024;
+
+pub struct NbdServer {
+    pool: Arc<ReaderPool>,
+    export_name: String,
+}
+
+impl NbdServer {
+    pub fn new(pool: ReaderPool, export_name: &str) -> Self {
+        NbdServer {
+            pool: Arc::new(pool),
+            export_name: String::from(export_name),
+        }
+    }
+
+    // Accepts clients forever, serving each of them from its own thread.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        info!("Serving NBD export {:?} on {}", self.export_name, listener.local_addr()?);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            let peer = stream.peer_addr()?;
+            debug!("[nbd {}] Client connected", peer);
+            let client = NbdClient {
+                pool: Arc::clone(&self.pool),
+                export_name: self.export_name.clone(),
+                stream,
+            };
+            thread::spawn(move || {
+                match client.run() {
+                    Ok(_) => debug!("[nbd {}] Client disconnected", peer),
+                    Err(e) => warn!("[nbd {}] Client failed: {}", peer, e),
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+struct NbdClient {
+    pool: Arc<ReaderPool>,
+    export_name: String,
+    stream: TcpStream,
+}
+
+impl NbdClient {
+    fn run(mut self) -> io::Result<()> {
+        if self.handshake()? {
+            self.transmission()?;
+        }
+        Ok(())
+    }
+
+    // Returns false if the client aborted the negotiation.
+    fn handshake(&mut self) -> io::Result<bool> {
+        self.write_u64(NBDMAGIC)?;
+        self.write_u64(IHAVEOPT)?;
+        self.write_u16(NBD_FLAG_FIXED_NEWSTYLE | NBD_FLAG_NO_ZEROES)?;
+        self.stream.flush()?;
+        let client_flags = self.read_u32()?;
+        let no_zeroes = client_flags & NBD_FLAG_C_NO_ZEROES != 0;
+
+        loop {
+            if self.read_u64()? != IHAVEOPT {
+                return Err(invalid_data("Bad option magic"));
+            }
+            let option = self.read_u32()?;
+            let length = self.read_u32()?;
+            if length > MAX_OPTION_LENGTH {
+                return Err(invalid_data("Option is too long"));
+            }
+            let mut data = vec![0; length as usize];
+            self.stream.read_exact(&mut data)?;
+            debug!("[nbd] Received option {} with {} bytes of data", option, length);
+
+            match option {
+                NBD_OPT_EXPORT_NAME => {
+                    self.write_u64(self.pool.file_size() as u64)?;
+                    self.write_u16(TRANSMISSION_FLAGS)?;
+                    if !no_zeroes {
+                        self.stream.write_all(&[0; 124])?;
+                    }
+                    self.stream.flush()?;
+                    return Ok(true);
+                }
+                NBD_OPT_ABORT => {
+                    self.write_option_reply(option, NBD_REP_ACK, &[])?;
+                    return Ok(false);
+                }
+                NBD_OPT_LIST => {
+                    let mut reply = (self.export_name.len() as u32).to_be_bytes().to_vec();
+                    reply.extend(self.export_name.as_bytes());
+                    self.write_option_reply(option, NBD_REP_SERVER, &reply)?;
+                    self.write_option_reply(option, NBD_REP_ACK, &[])?;
+                }
+                NBD_OPT_INFO | NBD_OPT_GO => {
+                    // there is only one export, so the requested name is not checked
+                    let mut info = NBD_INFO_EXPORT.to_be_bytes().to_vec();
+                    info.extend((self.pool.file_size() as u64).to_be_bytes());
+                    info.extend(TRANSMISSION_FLAGS.to_be_bytes());
+                    self.write_option_reply(option, NBD_REP_INFO, &info)?;
+                    self.write_option_reply(option, NBD_REP_ACK, &[])?;
+                    if option == NBD_OPT_GO {
+                        return Ok(true);
+                    }
+                }
+                _ => {
+                    self.write_option_reply(option, NBD_REP_ERR_UNSUP, &[])?;
+                }
+            }
+        }
+    }
+
+    fn transmission(&mut self) -> io::Result<()> {
+        loop {
+            if self.read_u32()? != REQUEST_MAGIC {
+                return Err(invalid_data("Bad request magic"));
+            }
+            let _flags = self.read_u16()?;
+            let command = self.read_u16()?;
+            let handle = self.read_u64()?;
+            let offset = self.read_u64()?;
+            let length = self.read_u32()?;
+
+            match command {
+                NBD_CMD_READ => self.handle_read(handle, offset, length)?,
+                NBD_CMD_WRITE => {
+                    if length > MAX_REQUEST_LENGTH {
+                        return Err(invalid_data("Write request is too long"));
+                    }
+                    // the payload has to be consumed even though it is rejected
+                    io::copy(&mut (&self.stream).take(length as u64), &mut io::sink())?;
+                    self.write_simple_reply(handle, NBD_EPERM, &[])?;
+                }
+                NBD_CMD_DISC => return Ok(()),
+                NBD_CMD_FLUSH => self.write_simple_reply(handle, 0, &[])?,
+                _ => {
+                    debug!("[nbd] Unsupported command {}", command);
+                    self.write_simple_reply(handle, NBD_EINVAL, &[])?;
+                }
+            }
+        }
+    }
+
+    fn handle_read(&mut self, handle: u64, offset: u64, length: u32) -> io::Result<()> {
+        debug!("[nbd] Requested data block: offset={} size={}", offset, length);
+        let file_size = self.pool.file_size() as u64;
+        let in_bounds = offset.checked_add(length as u64).is_some_and(|end| end <= file_size);
+        if length > MAX_REQUEST_LENGTH || !in_bounds {
+            return self.write_simple_reply(handle, NBD_EINVAL, &[]);
+        }
+        match self.pool.read(offset as usize, length as usize) {
+            Ok(data) if data.len() == length as usize => self.write_simple_reply(handle, 0, &data),
+            Ok(data) => {
+                warn!("[nbd] Short read at offset {}: {} of {} bytes", offset, data.len(), length);
+                self.write_simple_reply(handle, NBD_EIO, &[])
+            }
+            Err(e) => {
+                warn!("[nbd] Unable to read block: offset={} size={}: {}", offset, length, e);
+                self.write_simple_reply(handle, NBD_EIO, &[])
+            }
+        }
+    }
+
+    fn write_option_reply(&mut self, option: u32, reply_type: u32, data: &[u8]) -> io::Result<()> {
+        self.write_u64(OPTION_REPLY_MAGIC)?;
+        self.write_u32(option)?;
+        self.write_u32(reply_type)?;
+        self.write_u32(data.len() as u32)?;
+        self.stream.write_all(data)?;
+        self.stream.flush()
+    }
+
+    fn write_simple_reply(&mut self, handle: u64, error: u32, data: &[u8]) -> io::Result<()> {
+        self.write_u32(SIMPLE_REPLY_MAGIC)?;
+        self.write_u32(error)?;
+        self.write_u64(handle)?;
+        self.stream.write_all(data)?;
+        self.stream.flush()
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        self.stream.read_exact(&mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.stream.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.stream.read_exact(&mut buf)?;
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    fn write_u16(&mut self, value: u16) -> io::Result<()> {
+        self.stream.write_all(&value.to_be_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.stream.write_all(&value.to_be_bytes())
+    }
+
+    fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.stream.write_all(&value.to_be_bytes())
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,85 @@
+// Read-only overlay of a local directory over the mount, e.g. to patch a few files on top of a large remote
+// dataset. Regular files at the top of the directory appear next to the remote file, and a local file
+// with the name of a remote one shadows it. The directory is looked up on every access, so files
+// added or removed locally show up in the mount right away.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+// Inodes of local files are assigned from here on, above the inodes of the remote tree
+const FIRST_OVERLAY_INO: u64 = 1 << 32;
+
+pub struct Overlay {
+    dir: PathBuf,
+    // inodes are assigned on the first lookup and kept for the lifetime of the mount
+    inodes: HashMap<OsString, u64>,
+    names: HashMap<u64, OsString>,
+}
+
+impl Overlay {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        if !fs::metadata(dir)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a directory", dir.display())));
+        }
+        Ok(Overlay { dir: dir.to_path_buf(), inodes: HashMap::new(), names: HashMap::new() })
+    }
+
+    // Returns the inode and the metadata of the local file `name`, None if there is no such regular file.
+    pub fn lookup(&mut self, name: &OsStr) -> Option<(u64, Metadata)> {
+        let metadata = fs::metadata(self.dir.join(name)).ok().filter(Metadata::is_file)?;
+        Some((self.inode(name), metadata))
+    }
+
+    // Local regular files, with UTF-8 names, in the order of their names.
+    pub fn entries(&mut self) -> Vec<(u64, String)> {
+        let mut names: Vec<String> = fs::read_dir(&self.dir).into_iter().flatten().flatten()
+            .filter(|entry| fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        names.into_iter().map(|name| (self.inode(OsStr::new(&name)), name)).collect()
+    }
+
+    fn inode(&mut self, name: &OsStr) -> u64 {
+        if let Some(&ino) = self.inodes.get(name) {
+            return ino;
+        }
+        let ino = FIRST_OVERLAY_INO + self.inodes.len() as u64;
+        self.inodes.insert(name.to_os_string(), ino);
+        self.names.insert(ino, name.to_os_string());
+        ino
+    }
+
+    // Name of the local file with inode `ino`, None if it isn't an inode of the overlay.
+    pub fn name(&self, ino: u64) -> Option<&OsStr> {
+        self.names.get(&ino).map(OsString::as_os_str)
+    }
+
+    pub fn metadata(&self, ino: u64) -> io::Result<Metadata> {
+        fs::metadata(self.path(ino)?)
+    }
+
+    // Reads `size` bytes of the local file starting from `offset`, or less if the file ends earlier.
+    pub fn read(&self, ino: u64, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+        let file = File::open(self.path(ino)?)?;
+        let mut data = vec![0; size];
+        let mut len = 0;
+        while len < size {
+            match file.read_at(&mut data[len..], offset + len as u64)? {
+                0 => break,
+                read => len += read,
+            }
+        }
+        data.truncate(len);
+        Ok(data)
+    }
+
+    fn path(&self, ino: u64) -> io::Result<PathBuf> {
+        let name = self.name(ino).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(self.dir.join(name))
+    }
+}
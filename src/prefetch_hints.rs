@@ -0,0 +1,149 @@
+// Ranges applications announce they are going to read, e.g. a pipeline knowing it reads chunks 17 to 80,
+// downloaded in the background so that the data is waiting when the reads arrive. Hinted ranges are
+// downloaded in aligned blocks held up to a budget: the download pauses while the budget is used up,
+// and a block is dropped once a read reaches its end.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use log::{debug, warn};
+
+use crate::fetch_priority;
+use crate::span::Span;
+
+// Hinted ranges are downloaded in blocks of this size, aligned to it, so that overlapping hints share blocks
+const HINT_BLOCK: usize = 1024 * 1024;
+
+#[derive(Default)]
+struct State {
+    // blocks to download, in the order of the hints
+    queued: VecDeque<Span>,
+    downloaded: Vec<(Span, Vec<u8>)>,
+    // bytes of the downloaded blocks
+    held: usize,
+    downloading: Option<Span>,
+    // changed by `clear`, so that a block downloaded meanwhile is dropped
+    generation: u64,
+    worker_running: bool,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // signalled when blocks are queued or dropped, or the hints are closed
+    changed: Condvar,
+    budget: usize,
+}
+
+pub struct PrefetchHints {
+    shared: Arc<Shared>,
+}
+
+impl PrefetchHints {
+    // Holds at most `budget` bytes of downloaded hinted data.
+    pub fn new(budget: usize) -> Self {
+        PrefetchHints {
+            shared: Arc::new(Shared { state: Mutex::new(State::default()), changed: Condvar::new(), budget }),
+        }
+    }
+
+    // Queues `span` for the download. The worker downloading the blocks with the function made by `fetcher`
+    // is started if it isn't running.
+    pub fn hint<F>(&self, span: Span, fetcher: impl FnOnce() -> F)
+    where
+        F: Fn(Span) -> io::Result<Vec<u8>> + Send + 'static,
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        let mut block_start = span.start() - span.start() % HINT_BLOCK;
+        while block_start < span.end() {
+            let block = Span::with_len(block_start, HINT_BLOCK);
+            let known = state.downloading == Some(block) || state.queued.contains(&block)
+                || state.downloaded.iter().any(|(downloaded, _)| *downloaded == block);
+            if !known {
+                state.queued.push_back(block);
+            }
+            block_start += HINT_BLOCK;
+        }
+        debug!("Hinted {:?}, {} blocks are queued", span, state.queued.len());
+        if !state.worker_running && !state.queued.is_empty() {
+            state.worker_running = true;
+            let (shared, fetch) = (Arc::clone(&self.shared), fetcher());
+            thread::spawn(move || shared.download(fetch));
+        }
+        self.shared.changed.notify_all();
+    }
+
+    // Returns the hinted data from the start of `span` up to its end or the end of the downloaded block,
+    // None if the block isn't downloaded. The block is dropped once the read reaches its end.
+    pub fn read(&self, span: Span) -> Option<Vec<u8>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let index = state.downloaded.iter()
+            .position(|(block, data)| block.start() <= span.start() && span.start() < block.start() + data.len())?;
+        let (block, data) = &state.downloaded[index];
+        let received = Span::with_len(block.start(), data.len());
+        let local = span.clamp_end(received.end()).relative_to(block.start())?;
+        let result = data[local.as_range()].to_vec();
+        if span.end() >= received.end() {
+            let (_, data) = state.downloaded.swap_remove(index);
+            state.held -= data.len();
+            self.shared.changed.notify_all();
+        }
+        Some(result)
+    }
+
+    // Drops all hinted data, e.g. after the resource has changed.
+    pub fn clear(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queued.clear();
+        state.downloaded.clear();
+        state.held = 0;
+        state.generation += 1;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Drop for PrefetchHints {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Shared {
+    // Downloads queued blocks while the budget allows, until the queue is empty or the hints are closed.
+    fn download(&self, fetch: impl Fn(Span) -> io::Result<Vec<u8>>) {
+        fetch_priority::apply();
+        loop {
+            let (block, generation) = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if state.closed || state.queued.is_empty() {
+                        state.worker_running = false;
+                        return;
+                    }
+                    if state.held < self.budget {
+                        break;
+                    }
+                    state = self.changed.wait(state).unwrap();
+                }
+                let block = state.queued.pop_front().unwrap();
+                state.downloading = Some(block);
+                (block, state.generation)
+            };
+            let result = fetch(block);
+            let mut state = self.state.lock().unwrap();
+            state.downloading = None;
+            match result {
+                Ok(data) if !data.is_empty() && state.generation == generation => {
+                    debug!("Downloaded hinted block {:?}", block);
+                    state.held += data.len();
+                    state.downloaded.push((block, data));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Unable to download hinted block {:?}: {}", block, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+// Switching to an unprivileged account once the privileged setup is done, e.g. after root has mounted
+// on a restricted path or bound a low port, so that all network IO runs without root rights.
+
+use std::io;
+
+use libc::{gid_t, setgid, setgroups, setuid, uid_t};
+use log::info;
+use users::{get_group_by_name, get_user_by_name, get_user_by_uid};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Account {
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+// Parses USER or USER:GROUP, given by name or id. Without a group the primary group of the user is used.
+pub fn parse_account(value: &str) -> Result<Account, String> {
+    let (user, group) = match value.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (value, None),
+    };
+    let user = user.parse().ok().and_then(get_user_by_uid).or_else(|| get_user_by_name(user))
+        .ok_or_else(|| format!("Unknown user {:?}", user))?;
+    let gid = match group {
+        Some(group) => match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => get_group_by_name(group).ok_or_else(|| format!("Unknown group {:?}", group))?.gid(),
+        },
+        None => user.primary_group_id(),
+    };
+    Ok(Account { uid: user.uid(), gid })
+}
+
+// Switches all threads of the process to `account`, irrevocably. Supplementary groups are dropped first,
+// and the group before the user, which would lose the right to change it.
+pub fn drop_privileges(account: Account) -> io::Result<()> {
+    // SAFETY: plain syscalls, the glibc wrappers apply them to all threads of the process
+    unsafe {
+        if setgroups(1, &account.gid) != 0 || setgid(account.gid) != 0 || setuid(account.uid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    info!("Running as uid {} gid {}", account.uid, account.gid);
+    Ok(())
+}
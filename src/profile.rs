@@ -0,0 +1,52 @@
+// Tunings of readers for typical access patterns, chosen with `--profile`.
+
+// `media` suits video players: they read the container index, often at the end of the file,
+// then play sequentially with frequent short seeks in both directions.
+// `columnar` suits query engines reading Parquet or ORC files: they read the footer with the metadata,
+// then scattered column chunks, for which streaming ahead mostly downloads unneeded data.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReadProfile {
+    // How far beyond its buffer a reader is still used for a forward seek, skipping the data in between,
+    // instead of starting a new request
+    pub seek_ahead: usize,
+    // How many already read bytes are kept in a reader buffer for backward seeks
+    pub rewind: usize,
+    // How many bytes at the end of the resource are downloaded at start
+    pub tail_prefetch: usize,
+    // Whether the index of MP4 or Matroska containers, or the footer of Parquet or ORC files,
+    // is located and downloaded at start
+    pub prefetch_index: bool,
+    // Whether reads which no reader can serve are made with one-shot requests of just the read data,
+    // instead of starting a reader streaming ahead
+    pub one_shot_reads: bool,
+}
+
+impl ReadProfile {
+    // The default profile doesn't seek ahead, keep read data or prefetch anything.
+    pub fn media() -> Self {
+        ReadProfile {
+            seek_ahead: 8 * 1024 * 1024,
+            rewind: 256 * 1024,
+            tail_prefetch: 2 * 1024 * 1024,
+            prefetch_index: true,
+            one_shot_reads: false,
+        }
+    }
+
+    pub fn columnar() -> Self {
+        ReadProfile {
+            prefetch_index: true,
+            one_shot_reads: true,
+            ..ReadProfile::default()
+        }
+    }
+}
+
+pub fn parse_profile(value: &str) -> Result<ReadProfile, String> {
+    match value {
+        "default" => Ok(ReadProfile::default()),
+        "media" => Ok(ReadProfile::media()),
+        "columnar" => Ok(ReadProfile::columnar()),
+        _ => Err(format!("Unknown profile {:?}, expected default, media or columnar", value)),
+    }
+}
@@ -0,0 +1,77 @@
+// Python module, built with `maturin build --features python`.
+//
+//     import httpfs
+//     with httpfs.mount("https://example.com/data.bin", "/mnt/http", headers=["X-Token: 1"]):
+//         ...
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use crate::file_system::HttpFs;
+use crate::http_meta_reader::HttpMetaReader;
+use crate::mount::{mount_options, Mount as SpawnedMount, MountHandle};
+use crate::reader_pool::ReaderPool;
+use crate::transport::Transport;
+
+#[pyclass(name = "Mount", unsendable)]
+struct PyMount {
+    handle: Option<MountHandle>,
+    #[pyo3(get)]
+    mountpoint: String,
+}
+
+#[pymethods]
+impl PyMount {
+    #[getter]
+    fn is_alive(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| handle.is_alive())
+    }
+
+    // Unmounts the filesystem. Calling it again is a no-op.
+    fn unmount(&mut self) -> PyResult<()> {
+        match self.handle.take() {
+            Some(handle) => Ok(handle.unmount()?),
+            None => Ok(()),
+        }
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&mut self, _args: &Bound<'_, PyTuple>) -> PyResult<bool> {
+        self.unmount()?;
+        // do not suppress exceptions raised inside the `with` block
+        Ok(false)
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (url, path, *, file_name = "file", headers = vec![], auto_unmount = false, allow_root = false))]
+fn mount(
+    url: &str,
+    path: &str,
+    file_name: &str,
+    headers: Vec<String>,
+    auto_unmount: bool,
+    allow_root: bool,
+) -> PyResult<PyMount> {
+    let transport = Transport::with_headers(headers);
+    let file_size = HttpMetaReader::new(url, transport.clone()).fetch_file_size()?;
+    let fs = HttpFs::new(ReaderPool::new(url, file_size, transport), file_name);
+    let handle = SpawnedMount::spawn(fs, path, &mount_options(auto_unmount, allow_root))
+        .map_err(|e| PyRuntimeError::new_err(format!("Unable to mount {} at {}: {}", url, path, e)))?;
+    Ok(PyMount {
+        handle: Some(handle),
+        mountpoint: String::from(path),
+    })
+}
+
+#[pymodule]
+fn httpfs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMount>()?;
+    m.add_function(wrap_pyfunction!(mount, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,278 @@
+// One-shot requests of a single byte range, for reads that are not worth a streaming reader.
+// With mirrors, a request that hasn't got a response within the hedging delay is repeated on the next mirror
+// and the first complete response wins, smoothing over slow or overloaded edges.
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::iter::once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use libc::EIO;
+use log::{debug, warn};
+
+#[cfg(feature = "async-backend")]
+use crate::async_backend;
+use crate::resource_version::ResourceVersion;
+use crate::span::Span;
+use crate::transport::{
+    parse_content_range, parse_header, parse_status_line, refusal_error, Backend, Transport, AUTH_RETRIES, HTTP_OK,
+    HTTP_PARTIAL_CONTENT,
+};
+
+struct RangeResponse {
+    status: u32,
+    // headers of the final response
+    headers: Vec<(String, String)>,
+    // the body up to the end of the requested span
+    body: Vec<u8>,
+}
+
+// One of the requests of a hedged read.
+struct Race<'a> {
+    // called when the response starts to arrive
+    responded: &'a dyn Fn(),
+    // set once another request has won, aborting this one
+    lost: &'a AtomicBool,
+}
+
+enum RaceEvent {
+    Responded,
+    // the index of the URL and the result of its request
+    Fetched(usize, io::Result<Vec<u8>>),
+}
+
+// Downloads `span` of the resource with a single `Range: bytes=<start>-<end>` request.
+pub fn fetch_range(transport: &Transport, url: &str, span: Span, version: &ResourceVersion) -> io::Result<Vec<u8>> {
+    fetch(transport, url, span, Some(version), None)
+}
+
+// Downloads `span` of a mirror of the resource, whose ETag isn't checked against the version.
+pub fn fetch_mirror_range(transport: &Transport, url: &str, span: Span) -> io::Result<Vec<u8>> {
+    fetch(transport, url, span, None, None)
+}
+
+// Downloads `span` like `fetch_range`, requesting it also from the next of `mirrors` whenever no response
+// has arrived within `delay` or all started requests have failed. The first complete response is returned.
+// Mirrors are expected to serve the same content, but their ETags are not checked against `version`.
+pub fn fetch_range_hedged(
+    transport: &Transport,
+    url: &str,
+    mirrors: &[String],
+    span: Span,
+    version: &Arc<ResourceVersion>,
+    delay: Duration,
+) -> io::Result<Vec<u8>> {
+    let urls: Vec<&str> = once(url).chain(mirrors.iter().map(String::as_str)).collect();
+    let (sender, receiver) = mpsc::channel();
+    let lost = Arc::new(AtomicBool::new(false));
+    // the requests run on their own threads, so that the losing ones are left behind to abort
+    let start = |index: usize| {
+        let (transport, url, sender, lost) = (transport.clone(), urls[index].to_string(), sender.clone(), Arc::clone(&lost));
+        let version = (index == 0).then(|| Arc::clone(version));
+        thread::spawn(move || {
+            let responded = || {
+                let _ = sender.send(RaceEvent::Responded);
+            };
+            let race = Race { responded: &responded, lost: &lost };
+            let result = fetch(&transport, &url, span, version.as_deref(), Some(&race));
+            let _ = sender.send(RaceEvent::Fetched(index, result));
+        });
+    };
+
+    start(0);
+    let (mut started, mut pending) = (1, 1);
+    // until the first response arrives
+    let mut hedging = true;
+    loop {
+        let event = if hedging && started < urls.len() {
+            receiver.recv_timeout(delay)
+        } else {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match event {
+            Ok(RaceEvent::Responded) => hedging = false,
+            Ok(RaceEvent::Fetched(index, Ok(data))) => {
+                lost.store(true, Ordering::SeqCst);
+                debug!("Range request {:?} was served by {}", span, urls[index]);
+                return Ok(data);
+            }
+            Ok(RaceEvent::Fetched(index, Err(e))) => {
+                warn!("Range request {:?} to {} failed: {}", span, urls[index], e);
+                pending -= 1;
+                if pending > 0 {
+                    continue;
+                }
+                if started == urls.len() {
+                    return Err(e);
+                }
+                start(started);
+                (started, pending, hedging) = (started + 1, 1, true);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                debug!("No response to range request {:?} within {:?}, requesting it from {}", span, delay, urls[started]);
+                start(started);
+                (started, pending) = (started + 1, pending + 1);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(io::Error::from_raw_os_error(EIO)),
+        }
+    }
+}
+
+fn fetch(
+    transport: &Transport,
+    url: &str,
+    span: Span,
+    version: Option<&ResourceVersion>,
+    race: Option<&Race>,
+) -> io::Result<Vec<u8>> {
+    if span.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut attempt = 0;
+    loop {
+        let RangeResponse { status, headers, body } = perform(transport, url, span, version, race)?;
+        if transport.rejects_credentials(status) && attempt < AUTH_RETRIES {
+            warn!("Range request was rejected with {}, retrying with refreshed credentials", status);
+            transport.refresh_credentials()?;
+            attempt += 1;
+            continue;
+        }
+
+        let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+        // absolute offset of the first byte of the body
+        let body_start = match status {
+            HTTP_PARTIAL_CONTENT => match header("content-range").and_then(parse_content_range) {
+                Some(range) if range.start == span.start() => range.start,
+                range => {
+                    warn!("Requested {:?}, but the server returned range {:?}", span, range);
+                    return Err(io::Error::from_raw_os_error(EIO));
+                }
+            },
+            // the server ignored the range and sent the resource from the beginning
+            HTTP_OK => 0,
+            _ => {
+                warn!("Range request {:?} failed with status {}", span, status);
+                return Err(refusal_error(status).unwrap_or_else(|| io::Error::from_raw_os_error(EIO)));
+            }
+        };
+        if version.is_some_and(|version| !version.accept(header("etag"), header("last-modified"))) {
+            return Err(io::Error::from_raw_os_error(EIO));
+        }
+        let received = Span::with_len(body_start, body.len());
+        let local = span.intersect(received).and_then(|common| common.relative_to(body_start));
+        debug!("Range request {:?} received {:?}", span, received);
+        return Ok(local.map_or(vec![], |local| body[local.as_range()].to_vec()));
+    }
+}
+
+fn perform(
+    transport: &Transport,
+    url: &str,
+    span: Span,
+    version: Option<&ResourceVersion>,
+    race: Option<&Race>,
+) -> io::Result<RangeResponse> {
+    let mut headers = vec![format!("Range: bytes={}-{}", span.start(), span.end() - 1)];
+    headers.extend(version.and_then(ResourceVersion::if_range).map(|validator| format!("If-Range: {}", validator)));
+    match transport.backend() {
+        Backend::Curl => perform_curl(transport, url, span, &headers, race),
+        #[cfg(feature = "async-backend")]
+        Backend::Hyper => async_backend::block_on(perform_async(transport, url, span, &headers, race)),
+    }
+}
+
+fn perform_curl(
+    transport: &Transport,
+    url: &str,
+    span: Span,
+    headers: &[String],
+    race: Option<&Race>,
+) -> io::Result<RangeResponse> {
+    let mut easy = transport.easy(url, headers)?;
+    let lost = || race.is_some_and(|race| race.lost.load(Ordering::SeqCst));
+    // the progress callback aborts a lost request even while it waits for the response
+    easy.progress(race.is_some())?;
+    let status = Cell::new(0);
+    let headers = RefCell::new(vec![]);
+    let mut body = vec![];
+    let over_budget = Cell::new(false);
+    {
+        let mut transfer = easy.transfer();
+        transfer.header_function(|header| {
+            if let Some(code) = parse_status_line(header) {
+                if let Some(race) = race {
+                    (race.responded)();
+                }
+                status.set(code);
+                headers.borrow_mut().clear();
+            } else if let Some(header) = parse_header(header) {
+                headers.borrow_mut().push(header);
+            }
+            true
+        })?;
+        transfer.progress_function(|_, _, _, _| !lost())?;
+        transfer.write_function(|buf| {
+            if lost() {
+                return Ok(0);
+            }
+            // a server ignoring the range streams the whole resource, the rest of it isn't needed
+            if status.get() == HTTP_OK && body.len() >= span.end() {
+                return Ok(0);
+            }
+            if !transport.record_download(buf.len()) {
+                over_budget.set(true);
+                return Ok(0);
+            }
+            body.extend_from_slice(buf);
+            Ok(buf.len())
+        })?;
+        match transfer.perform() {
+            Err(e) if e.is_write_error() => {}
+            res => res?,
+        }
+    }
+    transport.release(easy);
+    if over_budget.get() {
+        return Err(io::Error::from_raw_os_error(EIO));
+    }
+    let headers = headers.into_inner();
+    transport.on_response(url, status.get(), &headers);
+    Ok(RangeResponse { status: status.get(), headers, body })
+}
+
+// The same request sent with the async backend.
+#[cfg(feature = "async-backend")]
+async fn perform_async(
+    transport: &Transport,
+    url: &str,
+    span: Span,
+    headers: &[String],
+    race: Option<&Race<'_>>,
+) -> io::Result<RangeResponse> {
+    let lost = race.map(|race| race.lost);
+    let lost = || lost.is_some_and(|lost| lost.load(Ordering::SeqCst));
+    let (status, headers, mut response) = async_backend::send(transport, url, headers, &lost).await?;
+    if let Some(race) = race {
+        (race.responded)();
+    }
+    let mut body = vec![];
+    while let Some(chunk) = async_backend::next_chunk(transport, &mut response).await? {
+        if lost() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Request was aborted"));
+        }
+        // a server ignoring the range streams the whole resource, the rest of it isn't needed
+        if status == HTTP_OK && body.len() >= span.end() {
+            break;
+        }
+        if !transport.record_download(chunk.len()) {
+            return Err(io::Error::from_raw_os_error(EIO));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    transport.on_response(url, status, &headers);
+    Ok(RangeResponse { status, headers, body })
+}
@@ -0,0 +1,96 @@
+// Backoff shared by all requests to one origin when it rate-limits with 429 Too Many Requests.
+// Every request waits while the origin asked to pause, and the number of parallel readers
+// is halved on each rejection and restored one step per successful response.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, warn};
+
+// How many times a request is repeated after 429 before giving up
+pub const RATE_LIMIT_RETRIES: u8 = 10;
+// Pause when the server didn't send Retry-After, doubled on every subsequent rejection
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// How to often check whether the pause is over
+const PAUSE_RECHECK: Duration = Duration::from_millis(100);
+
+struct State {
+    paused_until: Option<Instant>,
+    backoff: Duration,
+    // how many times the allowed concurrency is halved
+    halvings: u32,
+}
+
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter {
+            state: Mutex::new(State { paused_until: None, backoff: INITIAL_BACKOFF, halvings: 0 }),
+        }
+    }
+}
+
+impl RateLimiter {
+    // Records a 429 response with the value of its `Retry-After` header.
+    pub fn throttled(&self, retry_after: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        let pause = retry_after.and_then(parse_retry_after).unwrap_or(state.backoff);
+        state.backoff = (state.backoff * 2).min(MAX_BACKOFF);
+        state.halvings = state.halvings.saturating_add(1).min(usize::BITS - 1);
+        let until = Instant::now() + pause;
+        state.paused_until = Some(state.paused_until.map_or(until, |paused_until| paused_until.max(until)));
+        warn!("Server is rate limiting requests, pausing for {:?}", pause);
+    }
+
+    // Records a successful response, gradually restoring the concurrency.
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.halvings > 0 && !state.is_paused() {
+            state.halvings -= 1;
+            state.backoff = INITIAL_BACKOFF;
+            debug!("Rate limiting eased, concurrency is halved {} times", state.halvings);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().is_paused()
+    }
+
+    // Blocks until the pause is over or `should_stop` returns true.
+    pub fn wait(&self, should_stop: impl Fn() -> bool) {
+        while self.is_paused() && !should_stop() {
+            std::thread::sleep(PAUSE_RECHECK);
+        }
+    }
+
+    // How long the pause asked by the server still lasts, for callers that can't block.
+    pub fn remaining_pause(&self) -> Option<Duration> {
+        let paused_until = self.state.lock().unwrap().paused_until?;
+        Some(paused_until.saturating_duration_since(Instant::now())).filter(|pause| !pause.is_zero())
+    }
+
+    // Reduces `max` parallel requests according to the recent rejections, but never below one.
+    pub fn allowed_concurrency(&self, max: usize) -> usize {
+        (max >> self.state.lock().unwrap().halvings).max(1)
+    }
+}
+
+impl State {
+    fn is_paused(&self) -> bool {
+        self.paused_until.is_some_and(|until| Instant::now() < until)
+    }
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds).min(MAX_BACKOFF));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default().min(MAX_BACKOFF))
+}
@@ -0,0 +1,119 @@
+// Merging of small scattered reads arriving at about the same time, e.g. from SQLite or zip listings
+// served to several clients, into fewer and larger ranged requests. The first read of a batch waits
+// for the batching window, collecting the reads arriving meanwhile, then downloads them with one request
+// per group of nearby reads and hands every waiting read its part. Origins charging per request
+// get far fewer of them at the cost of the window added to the latency.
+
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+use libc::EINTR;
+use log::debug;
+
+use crate::span::Span;
+
+// Reads closer than this are downloaded with a single request, including the data between them
+const MAX_MERGE_GAP: usize = 128 * 1024;
+// Groups of reads are not merged beyond this size
+const MAX_MERGED_READ: usize = 4 * 1024 * 1024;
+// How often reads waiting for their batch check whether they are cancelled
+const CANCEL_RECHECK: Duration = Duration::from_millis(10);
+
+// Nearby reads downloaded with one request, with the data or the error of the request.
+struct Group {
+    span: Span,
+    data: Result<Vec<u8>, (io::ErrorKind, Option<i32>, String)>,
+}
+
+#[derive(Default)]
+struct BatchState {
+    requested: Vec<Span>,
+    // set once the batch is downloaded
+    groups: Option<Vec<Group>>,
+}
+
+#[derive(Default)]
+struct Batch {
+    state: Mutex<BatchState>,
+    fetched: Condvar,
+}
+
+pub struct ReadBatcher {
+    window: Duration,
+    // the batch collecting reads, None while no read waits for the window
+    collecting: Mutex<Option<Arc<Batch>>>,
+}
+
+impl ReadBatcher {
+    pub fn new(window: Duration) -> Self {
+        ReadBatcher { window, collecting: Mutex::new(None) }
+    }
+
+    // Reads `span` along with the other reads of the current batch, downloading groups of them with `fetch`.
+    // Gives up waiting for the batch with EINTR as soon as `cancelled` returns true.
+    pub fn read(
+        &self,
+        span: Span,
+        fetch: impl Fn(Span) -> io::Result<Vec<u8>>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> io::Result<Vec<u8>> {
+        let (batch, first) = {
+            let mut collecting = self.collecting.lock().unwrap();
+            let first = collecting.is_none();
+            let batch = Arc::clone(collecting.get_or_insert_with(Default::default));
+            batch.state.lock().unwrap().requested.push(span);
+            (batch, first)
+        };
+        if first {
+            sleep(self.window);
+            // reads arriving from now on start the next batch
+            self.collecting.lock().unwrap().take();
+            let requested = batch.state.lock().unwrap().requested.clone();
+            let spans = merge(requested);
+            debug!("Batch of reads is downloaded with {} requests", spans.len());
+            let groups = spans.into_iter()
+                .map(|span| Group { span, data: fetch(span).map_err(|e| (e.kind(), e.raw_os_error(), e.to_string())) })
+                .collect();
+            batch.state.lock().unwrap().groups = Some(groups);
+            batch.fetched.notify_all();
+        }
+
+        let mut state = batch.state.lock().unwrap();
+        while state.groups.is_none() {
+            if cancelled() {
+                return Err(io::Error::from_raw_os_error(EINTR));
+            }
+            state = batch.fetched.wait_timeout(state, CANCEL_RECHECK).unwrap().0;
+        }
+        let group = state.groups.iter().flatten()
+            .find(|group| group.span.contains(span))
+            .expect("every read of the batch is in a group");
+        let data = group.data.as_ref().map_err(|(kind, errno, message)| match errno {
+            Some(errno) => io::Error::from_raw_os_error(*errno),
+            None => io::Error::new(*kind, message.clone()),
+        })?;
+        // the group may end earlier than requested at the end of the resource
+        let received = Span::with_len(group.span.start(), data.len());
+        Ok(span.intersect(received)
+            .and_then(|common| common.relative_to(group.span.start()))
+            .map_or(vec![], |local| data[local.as_range()].to_vec()))
+    }
+}
+
+// Groups nearby spans into larger ones covering them.
+fn merge(mut spans: Vec<Span>) -> Vec<Span> {
+    spans.sort_by_key(|span| span.start());
+    let mut groups: Vec<Span> = vec![];
+    for span in spans {
+        match groups.last_mut() {
+            Some(group) if span.start() <= group.end().saturating_add(MAX_MERGE_GAP)
+                && span.end().max(group.end()) - group.start() <= MAX_MERGED_READ => {
+                *group = Span::new(group.start(), span.end().max(group.end()));
+            }
+            _ => groups.push(span),
+        }
+    }
+    groups
+}
@@ -0,0 +1,750 @@
+use std::cell::Cell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime};
+
+use libc::{EINTR, EIO};
+use log::{debug, warn};
+
+use crate::cache::{DiskCache, MappedSpan};
+use crate::checksum::Verifier;
+use crate::container_index::find_index;
+use crate::decrypt::Decryption;
+use crate::http_meta_reader::HttpMetaReader;
+use crate::http_reader::HttpReader;
+use crate::prefetch_hints::PrefetchHints;
+use crate::profile::ReadProfile;
+use crate::range_request::{fetch_mirror_range, fetch_range, fetch_range_hedged};
+use crate::read_batch::ReadBatcher;
+use crate::resource_version::{EtagPolicy, ResourceVersion};
+use crate::span::Span;
+use crate::spool::Spool;
+use crate::striping::Striping;
+use crate::transfers::Share;
+use crate::transport::{is_refusal, Transport};
+use crate::units::parse_size;
+
+const DEFAULT_MAX_READERS: usize = 5;
+const REREAD_ATTEMPTS: u8 = 5;
+// Larger reads are split into blocks of this size, so that each of them fits into a reader buffer
+const MAX_READ_BLOCK: usize = 128 * 1024;
+// Bulk copies are read from the readers and written out in blocks of this size
+const COPY_BLOCK_SIZE: usize = 1024 * 1024;
+// At most this many readers are created per READER_CREATION_WINDOW, further reads which no reader
+// can serve are made with one-shot ranged requests instead of starting and evicting readers in a loop
+const MAX_READER_CREATIONS: usize = 8;
+const READER_CREATION_WINDOW: Duration = Duration::from_secs(1);
+// How often idle readers are looked for at most
+const REAPER_INTERVAL: Duration = Duration::from_secs(1);
+// Larger container indexes are not prefetched
+const MAX_INDEX_PREFETCH: usize = 32 * 1024 * 1024;
+
+// Parses the size of reader buffers, which must fit a block of a read.
+pub fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    let size = parse_size(value)?;
+    if size < MAX_READ_BLOCK {
+        return Err(format!("Buffers of {} bytes are too small, at least {} are needed", size, MAX_READ_BLOCK));
+    }
+    Ok(size)
+}
+
+struct Revalidation {
+    max_age: Duration,
+    // the validator used along with the ETag of `version`
+    last_modified: Mutex<Option<String>>,
+}
+
+// Set of parallel HTTP readers of one remote resource.
+pub struct ReaderPool {
+    readers: Arc<Mutex<Vec<Arc<HttpReader>>>>,
+    file_size: Arc<AtomicUsize>,
+    resource_url: String,
+    transport: Transport,
+    version: Arc<ResourceVersion>,
+    verifier: Option<Verifier>,
+    // exposes the plaintext of a resource encrypted client-side, if set
+    decryption: Option<Decryption>,
+    // serves the decompressed content of a gzip resource, if set
+    spool: Option<Spool>,
+    revalidation: Option<Revalidation>,
+    profile: ReadProfile,
+    // regions of the resource downloaded in advance, e.g. its end
+    prefetched: Mutex<Vec<(Span, Vec<u8>)>>,
+    // merges one-shot reads arriving within a short window, if enabled
+    batcher: Option<ReadBatcher>,
+    // other URLs of the resource one-shot requests are hedged to after the delay
+    mirrors: Vec<String>,
+    hedge_delay: Duration,
+    // sequential reads are downloaded in stripes across the URL and the mirrors, if enabled
+    striping: Option<Striping>,
+    // ranges applications announced to read, if enabled
+    hints: Option<PrefetchHints>,
+    // readers which haven't served a read for this long are stopped, if enabled
+    reader_idle_timeout: Option<Duration>,
+    // streaming readers kept at once, fewer while the server is rate limiting
+    max_readers: usize,
+    // of the bandwidth, which the readers of this file get while readers of other files receive data too
+    share: Arc<Share>,
+    // keeps downloaded data on disk across mounts, if set
+    cache: Option<Arc<DiskCache>>,
+    // reads not found in the cache fail instead of making requests
+    offline: bool,
+    reaper_started: AtomicBool,
+    reader_creations: Mutex<VecDeque<Instant>>,
+    readers_counter: AtomicUsize, // just for logging
+}
+
+impl ReaderPool {
+    pub fn new(url: &str, file_size: usize, transport: Transport) -> Self {
+        ReaderPool {
+            readers: Arc::new(Mutex::new(vec![])),
+            file_size: Arc::new(AtomicUsize::new(file_size)),
+            resource_url: String::from(url),
+            transport,
+            version: Arc::new(ResourceVersion::new(None, EtagPolicy::Ignore)),
+            verifier: None,
+            decryption: None,
+            spool: None,
+            revalidation: None,
+            profile: ReadProfile::default(),
+            prefetched: Mutex::new(vec![]),
+            batcher: None,
+            mirrors: vec![],
+            hedge_delay: Duration::ZERO,
+            striping: None,
+            hints: None,
+            reader_idle_timeout: None,
+            max_readers: DEFAULT_MAX_READERS,
+            share: Arc::new(Share::new(1)),
+            cache: None,
+            offline: false,
+            reaper_started: AtomicBool::new(false),
+            reader_creations: Mutex::new(VecDeque::new()),
+            readers_counter: AtomicUsize::new(0),
+        }
+    }
+
+    // Tracks the version of the resource, starting from `etag` and `last_modified` of the metadata probe.
+    pub fn with_etag_policy(mut self, etag: Option<String>, last_modified: Option<String>, policy: EtagPolicy) -> Self {
+        self.version = Arc::new(ResourceVersion::new(etag, policy).with_last_modified(last_modified));
+        self
+    }
+
+    // Verifies all data served by `read` and `copy_range` against known checksums.
+    pub fn with_verifier(mut self, verifier: Verifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    // Serves the plaintext of the resource instead of its encrypted chunks, offsets and the file size
+    // refer to the plaintext from now on. Checksums still verify the resource as it is stored.
+    pub fn with_decryption(mut self, decryption: Decryption) -> Self {
+        self.decryption = Some(decryption);
+        self
+    }
+
+    // Turns the pool of a gzip resource into a pool serving its decompressed content, which is spooled
+    // into a file in `spool_dir` up to `max_size` bytes, see Spool.
+    pub fn with_gunzip(self, spool_dir: &Path, max_size: Option<usize>) -> io::Result<Self> {
+        let pool = ReaderPool::new(&self.resource_url, 0, self.transport.clone());
+        let spool = Spool::gunzip(self, spool_dir, max_size)?;
+        Ok(ReaderPool { spool: Some(spool), ..pool })
+    }
+
+    // Turns the pool of a resource whose server ignores byte ranges into a pool serving a copy of it in a file
+    // in `spool_dir`, downloaded once from the beginning, rather than every reader downloading the resource
+    // from the beginning up to its offset.
+    pub fn with_spool(self, spool_dir: &Path, max_size: Option<usize>) -> io::Result<Self> {
+        let pool = ReaderPool::new(&self.resource_url, 0, self.transport.clone());
+        let spool = Spool::copy(self, spool_dir, max_size)?;
+        Ok(ReaderPool { spool: Some(spool), ..pool })
+    }
+
+    // Revalidates buffered data older than `max_age` with a conditional request before serving it,
+    // using the ETag of the version and `last_modified` as validators.
+    pub fn with_revalidation(mut self, max_age: Duration, last_modified: Option<String>) -> Self {
+        self.revalidation = Some(Revalidation { max_age, last_modified: Mutex::new(last_modified) });
+        self
+    }
+
+    // Tunes readers for an access pattern, e.g. of video players.
+    pub fn with_profile(mut self, profile: ReadProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    // Merges one-shot reads arriving within `window` of each other into fewer requests.
+    pub fn with_read_batching(mut self, window: Duration) -> Self {
+        self.batcher = Some(ReadBatcher::new(window));
+        self
+    }
+
+    // Repeats one-shot requests on the next of `mirrors` when no response arrives within `hedge_delay`.
+    pub fn with_mirrors(mut self, mirrors: Vec<String>, hedge_delay: Duration) -> Self {
+        self.mirrors = mirrors;
+        self.hedge_delay = hedge_delay;
+        self
+    }
+
+    // Downloads the resource ahead of sequential reads in stripes of `stripe_size` bytes, fetched in parallel
+    // from the URL and the mirrors, to aggregate their bandwidth.
+    pub fn with_striping(mut self, stripe_size: usize) -> Self {
+        self.striping = Some(Striping::new(stripe_size));
+        self
+    }
+
+    // Accepts hints of ranges to be read soon, downloading them ahead and holding up to `budget` bytes of them.
+    pub fn with_prefetch_hints(mut self, budget: usize) -> Self {
+        self.hints = Some(PrefetchHints::new(budget));
+        self
+    }
+
+    // Stops readers which haven't served a read for `timeout`, freeing their buffers and connections
+    // instead of keeping them until new readers displace them.
+    pub fn with_reader_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.reader_idle_timeout = Some(timeout);
+        self
+    }
+
+    // Keeps up to `max_readers` streaming readers, e.g. more for many sequential streams over a high-latency link.
+    pub fn with_max_readers(mut self, max_readers: usize) -> Self {
+        self.max_readers = max_readers.max(1);
+        self
+    }
+
+    // Gets `weight` times the bandwidth of a file of weight 1 while readers of other files receive data too.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.share = Arc::new(Share::new(weight.max(1)));
+        self
+    }
+
+    // Serves reads from `cache` and keeps the downloaded data in it. Reads not found in the cache fail with EIO
+    // if `offline`, no requests are made then.
+    pub fn with_cache(mut self, cache: DiskCache, offline: bool) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self.offline = offline;
+        self
+    }
+
+    // Downloads `span` in the background, so that reads of it find it ready. Ignored without `with_prefetch_hints`.
+    pub fn hint(&self, span: Span) {
+        let (Some(hints), None, false) = (&self.hints, &self.spool, self.offline) else {
+            return;
+        };
+        let span = match &self.decryption {
+            Some(decryption) => decryption.remote_span(span, self.remote_size()),
+            None => span.clamp_end(self.remote_size()),
+        };
+        hints.hint(span, || {
+            let (transport, url, mirrors) = (self.transport.clone(), self.resource_url.clone(), self.mirrors.clone());
+            let (version, hedge_delay, file_size) = (Arc::clone(&self.version), self.hedge_delay, Arc::clone(&self.file_size));
+            move |span: Span| {
+                let span = span.clamp_end(file_size.load(Ordering::SeqCst));
+                fetch_span(&transport, &url, &mirrors, span, &version, hedge_delay)
+            }
+        });
+    }
+
+    // Downloads the end of the resource as long as the profile asks, e.g. for container indexes
+    // stored at the end of video files, so that reads of it don't wait for a new request.
+    pub fn prefetch_tail(&self) -> io::Result<()> {
+        if self.profile.tail_prefetch == 0 || self.offline {
+            return Ok(());
+        }
+        let file_size = self.remote_size();
+        self.prefetch(Span::new(file_size.saturating_sub(self.profile.tail_prefetch), file_size))
+    }
+
+    // Locates the index of MP4 or Matroska containers, or the footer of Parquet or ORC files,
+    // and downloads it as long as the profile asks, so that readers opening the file don't stall on it.
+    pub fn prefetch_container_index(&self) -> io::Result<()> {
+        if !self.profile.prefetch_index || self.offline {
+            return Ok(());
+        }
+        let read = |span: Span| match self.read_prefetched(span) {
+            Some(data) if data.len() == span.len() => Ok(data),
+            _ => self.fetch_range(span),
+        };
+        let Some(index) = find_index(self.remote_size(), read)? else {
+            return Ok(());
+        };
+        if index.len() > MAX_INDEX_PREFETCH {
+            warn!("Index {:?} is too large to be prefetched", index);
+            return Ok(());
+        }
+        if self.read_prefetched(index).is_some_and(|data| data.len() == index.len()) {
+            debug!("Index {:?} is already prefetched", index);
+            return Ok(());
+        }
+        self.prefetch(index)
+    }
+
+    fn prefetch(&self, span: Span) -> io::Result<()> {
+        let data = self.fetch_range(span)?;
+        debug!("Prefetched {} bytes of {:?}", data.len(), span);
+        self.add_prefetched(span.start(), data);
+        Ok(())
+    }
+
+    fn fetch_range(&self, span: Span) -> io::Result<Vec<u8>> {
+        fetch_span(&self.transport, &self.resource_url, &self.mirrors, span, &self.version, self.hedge_delay)
+    }
+
+    // Keeps `data` of the resource starting at `start` for reads, e.g. downloaded along with the metadata
+    // before the pool was created. Data beyond the end of the resource is dropped.
+    pub fn add_prefetched(&self, start: usize, mut data: Vec<u8>) {
+        data.truncate(self.remote_size().saturating_sub(start));
+        if data.is_empty() {
+            return;
+        }
+        self.prefetched.lock().unwrap().push((Span::with_len(start, data.len()), data));
+    }
+
+    // Size of the file served by reads, i.e. of the plaintext if the resource is decrypted.
+    pub fn file_size(&self) -> usize {
+        if let Some(spool) = &self.spool {
+            return spool.file_size();
+        }
+        match &self.decryption {
+            Some(decryption) => decryption.plain_size(self.remote_size()),
+            None => self.remote_size(),
+        }
+    }
+
+    // Last-Modified date of the current version of the resource, if the server sent one.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.version.modified()
+    }
+
+    fn remote_size(&self) -> usize {
+        self.file_size.load(Ordering::SeqCst)
+    }
+
+    // Reads `size` bytes starting from `offset`, or less if the resource ends earlier.
+    pub fn read(&self, offset: usize, size: usize) -> io::Result<Vec<u8>> {
+        self.read_cancellable(offset, size, || false)
+    }
+
+    // Like `read`, but gives up waiting for data with EINTR as soon as `cancelled` returns true,
+    // e.g. when the process which asked for the data has exited.
+    pub fn read_cancellable(&self, offset: usize, size: usize, cancelled: impl Fn() -> bool) -> io::Result<Vec<u8>> {
+        if let Some(spool) = &self.spool {
+            return spool.read(offset, size, &cancelled);
+        }
+        self.check_version()?;
+        match &self.decryption {
+            Some(decryption) => decryption.read(offset, size, self.remote_size(), |offset, size| {
+                self.read_verified(offset, size, &cancelled)
+            }),
+            None => self.read_verified(offset, size, &cancelled),
+        }
+    }
+
+    // Borrows cached data of `size` bytes starting from `offset` from the mapped cache, so that it is replied to
+    // without copying. None if it isn't all cached, or the data of the file isn't the remote data as is.
+    // Cached data has been verified before it was written, so it isn't verified again.
+    pub fn read_mapped(&self, offset: usize, size: usize) -> Option<MappedSpan<'_>> {
+        if self.spool.is_some() || self.decryption.is_some() {
+            return None;
+        }
+        let cache = self.cache.as_ref()?;
+        self.check_version().ok()?;
+        cache.read_mapped(Span::with_len(offset, size).clamp_end(self.remote_size()))
+    }
+
+    // Reads through the verifier, if any. Data is written to the cache only once verified, and cached data
+    // failing verification is evicted, so that a corrupted block isn't served from the cache again.
+    fn read_verified(&self, offset: usize, size: usize, cancelled: &dyn Fn() -> bool) -> io::Result<Vec<u8>> {
+        let Some(verifier) = &self.verifier else {
+            let data = self.read_unverified(offset, size, cancelled)?;
+            if let Some(cache) = &self.cache {
+                cache.write(offset, &data);
+            }
+            return Ok(data);
+        };
+        // the span read last, unless reading it failed, so that an error after it is a failed verification
+        let fetched = Cell::new(None);
+        let result = verifier.read(offset, size, |offset, size| {
+            fetched.set(None);
+            let data = self.read_unverified(offset, size, cancelled)?;
+            fetched.set(Some(Span::with_len(offset, data.len())));
+            Ok(data)
+        });
+        match (&result, &self.cache) {
+            (Ok(data), Some(cache)) => cache.write(offset, data),
+            (Err(_), Some(cache)) if verifier.is_corrupted() => {
+                warn!("Evicting the cached data of {} failing its checksum", self.resource_url);
+                cache.evict(Span::new(0, self.remote_size()));
+            }
+            (Err(_), Some(cache)) => {
+                if let Some(span) = fetched.get() {
+                    warn!("Evicting {:?} of {} failing its checksum from the cache", span, self.resource_url);
+                    cache.evict(span);
+                }
+            }
+            _ => {}
+        }
+        result
+    }
+
+    // Handles a change of the resource detected by readers according to the ETag policy.
+    fn check_version(&self) -> io::Result<()> {
+        if !self.version.is_changed() {
+            return Ok(());
+        }
+        if self.version.policy() != EtagPolicy::Refresh {
+            return Err(io::Error::from_raw_os_error(EIO));
+        }
+
+        let mut readers = self.readers.lock().unwrap();
+        // another read may have refreshed the resource while this one waited for the lock
+        if !self.version.is_changed() {
+            return Ok(());
+        }
+        let meta = HttpMetaReader::new(&self.resource_url, self.transport.clone()).fetch_meta()?;
+        warn!("Refreshed remote resource: size {} -> {}, ETag {:?}", self.remote_size(), meta.size, meta.etag);
+        for reader in readers.iter() {
+            reader.stop();
+        }
+        readers.clear();
+        self.drop_prefetched();
+        if let Some(cache) = &self.cache {
+            cache.reset(meta.size, meta.etag.as_deref(), meta.last_modified.as_deref());
+        }
+        if let Some(decryption) = &self.decryption {
+            decryption.clear();
+        }
+        self.file_size.store(meta.size, Ordering::SeqCst);
+        self.version.reset(meta.etag, meta.last_modified);
+        Ok(())
+    }
+
+    fn read_unverified(&self, offset: usize, size: usize, cancelled: &dyn Fn() -> bool) -> io::Result<Vec<u8>> {
+        let end = min(offset.saturating_add(size), self.remote_size());
+        let mut data = Vec::with_capacity(end.saturating_sub(offset));
+        while offset + data.len() < end {
+            let position = offset + data.len();
+            let block = self.read_block(position, min(MAX_READ_BLOCK, end - position), cancelled)?;
+            if block.is_empty() {
+                if position >= self.remote_size() {
+                    // the resource has shrunk during the read
+                    break;
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                    format!("No data received at offset {}", position)));
+            }
+            data.extend(block);
+        }
+        Ok(data)
+    }
+
+    // Writes bytes `start..end` of the resource into `writer` block by block.
+    pub fn copy_range(&self, start: usize, end: usize, writer: &mut impl Write) -> io::Result<()> {
+        let mut position = start;
+        while position < end {
+            let data = self.read(position, min(COPY_BLOCK_SIZE, end - position))?;
+            if data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                    format!("Resource ended at offset {} before expected", position)));
+            }
+            writer.write_all(&data)?;
+            position += data.len();
+        }
+        Ok(())
+    }
+
+    fn read_block(&self, offset: usize, size: usize, cancelled: &dyn Fn() -> bool) -> io::Result<Vec<u8>> {
+        if let Some(cache) = &self.cache {
+            if let Some(data) = cache.read(Span::with_len(offset, size).clamp_end(self.remote_size())) {
+                return Ok(data);
+            }
+            if self.offline {
+                warn!("{} bytes at offset {} are not cached, failing the read offline", size, offset);
+                return Err(io::Error::from_raw_os_error(EIO));
+            }
+        }
+        for i in 0..REREAD_ATTEMPTS {
+            if cancelled() {
+                debug!("Read at offset {} has been cancelled", offset);
+                return Err(io::Error::from_raw_os_error(EINTR));
+            }
+            self.check_version()?;
+            self.transport.circuit_breaker().check(&self.resource_url).inspect_err(|_| {
+                warn!("Host of {} is unavailable, failing read at offset {}", self.resource_url, offset);
+            })?;
+            match self.drain_data_from_suitable_reader(offset, size, cancelled)? {
+                Some(data) => return Ok(data),
+                None => warn!("Error read block in attempt {:?}", i),
+            }
+        }
+        Err(io::Error::from_raw_os_error(EIO))
+    }
+
+    // Returns None if the data couldn't be read this time, and an error if the server refuses to serve it.
+    pub fn drain_data_from_suitable_reader(
+        &self,
+        offset: usize,
+        size: usize,
+        cancelled: &dyn Fn() -> bool,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let addr = Span::with_len(offset, size);
+        if let Some(data) = self.read_prefetched(addr) {
+            return Ok(Some(data));
+        }
+        if let Some(data) = self.hints.as_ref().and_then(|hints| hints.read(addr)) {
+            return Ok(Some(data));
+        }
+        if let Some(data) = self.read_striped(addr, cancelled) {
+            return Ok(Some(data));
+        }
+        let arc = Arc::clone(&self.readers);
+        let mut readers = arc.lock().unwrap();
+        readers.retain(|reader| {
+            if reader.is_failed() {
+                // the data buffered before the failure is still good
+                retire(self.retire_cache(), reader);
+            }
+            !reader.is_failed()
+        });
+        self.revalidate_stale_readers(&mut readers);
+
+        for reader in &*readers {
+            if let Some(data) = reader.try_drain_data(addr, cancelled) {
+                return Ok(Some(data));
+            }
+            if cancelled() {
+                return Ok(None);
+            }
+        }
+
+        if self.profile.one_shot_reads || !self.admit_reader() {
+            // scattered reads, e.g. column chunks or a binary search, would evict readers before they are of any use
+            if let Some(limit) = self.transport.max_connections_per_host() {
+                // a reader with a full buffer keeps its connection, which the request would wait for forever
+                self.stop_oldest_readers(&mut readers, limit.saturating_sub(1));
+            }
+            drop(readers);
+            debug!("Reading {:?} with a one-shot request", addr);
+            let addr = addr.clamp_end(self.remote_size());
+            let fetch = |span| self.fetch_range(span);
+            let result = match &self.batcher {
+                Some(batcher) if !addr.is_empty() => batcher.read(addr, fetch, cancelled),
+                _ => fetch(addr),
+            };
+            return match result {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if is_refusal(&e) => Err(e),
+                Err(e) => {
+                    warn!("One-shot read of {:?} failed: {}", addr, e);
+                    Ok(None)
+                }
+            };
+        }
+
+        // no any suitable reader found, creating new
+        debug!("!------- Suitable reader not found, creating new...");
+
+        // fewer parallel requests while the server is rate limiting, and no more than connections allowed,
+        // since a reader keeps its connection even while its buffer is full
+        let max_readers = self.transport.rate_limiter().allowed_concurrency(self.max_readers)
+            .min(self.transport.max_connections_per_host().unwrap_or(self.max_readers))
+            .max(1);
+        self.stop_oldest_readers(&mut readers, max_readers - 1);
+
+        let reader = Arc::new(HttpReader::new(
+            &self.resource_url,
+            offset,
+            Arc::clone(&self.file_size),
+            self.transport.clone(),
+            Arc::clone(&self.version),
+            self.profile,
+            self.inc_and_get_readers_counter()
+        ).with_share(Arc::clone(&self.share)));
+        reader.start();
+        debug!("HttpReader transfer has started");
+        let res = reader.try_drain_data(addr, cancelled);
+        if res.is_none() && cancelled() {
+            // the reader was started for this read only
+            reader.stop();
+            return Ok(None);
+        }
+        if let (None, Some(refusal)) = (&res, reader.refusal()) {
+            warn!("Server refuses to serve {:?}: {}", addr, refusal);
+            return Err(refusal);
+        }
+        readers.push(reader);
+        debug!("Total readers now {}", readers.len());
+        self.start_reaper();
+        Ok(res)
+    }
+
+    fn read_striped(&self, addr: Span, cancelled: &dyn Fn() -> bool) -> Option<Vec<u8>> {
+        let striping = self.striping.as_ref()?;
+        let (addr, sources) = (addr.clamp_end(self.remote_size()), self.mirrors.len() + 1);
+        striping.read(addr, self.remote_size(), sources, cancelled, || {
+            let (transport, url, mirrors) = (self.transport.clone(), self.resource_url.clone(), self.mirrors.clone());
+            let version = Arc::clone(&self.version);
+            move |source: usize, span: Span| match source {
+                0 => fetch_range(&transport, &url, span, &version),
+                _ => fetch_mirror_range(&transport, &mirrors[source - 1], span),
+            }
+        })
+    }
+
+    // Starts the thread stopping idle readers with the first reader, if a timeout is set.
+    // The thread ends along with the pool.
+    fn start_reaper(&self) {
+        let Some(timeout) = self.reader_idle_timeout else {
+            return;
+        };
+        if self.reaper_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let readers = Arc::downgrade(&self.readers);
+        let cache = self.retire_cache().cloned();
+        thread::spawn(move || {
+            while let Some(readers) = readers.upgrade() {
+                readers.lock().unwrap().retain(|reader| {
+                    let idle = reader.idle_time() >= timeout;
+                    if idle {
+                        debug!("Reader has been idle for {:?}, stopping it", reader.idle_time());
+                        retire(cache.as_ref(), reader);
+                    }
+                    !idle
+                });
+                drop(readers);
+                sleep(min(timeout, REAPER_INTERVAL));
+            }
+        });
+    }
+
+    // Stops the oldest readers so that at most `keep` of them remain.
+    fn stop_oldest_readers(&self, readers: &mut Vec<Arc<HttpReader>>, keep: usize) {
+        if readers.len() > keep {
+            let stop_readers_to = readers.len() - keep;
+            debug!("Readers 0..{} will be stopped", stop_readers_to);
+            for reader in readers.drain(0..stop_readers_to) {
+                retire(self.retire_cache(), &reader);
+            }
+        }
+    }
+
+    // Returns the requested data, or its beginning, if it starts in a prefetched region.
+    fn read_prefetched(&self, addr: Span) -> Option<Vec<u8>> {
+        let prefetched = self.prefetched.lock().unwrap();
+        let (span, data) = prefetched.iter().find(|(span, _)| span.contains(Span::with_len(addr.start(), 1)))?;
+        let local = addr.clamp_end(span.end()).relative_to(span.start())?;
+        Some(data[local.as_range()].to_vec())
+    }
+
+    // Returns true if a new streaming reader may be created now, recording its creation.
+    fn admit_reader(&self) -> bool {
+        let mut creations = self.reader_creations.lock().unwrap();
+        while creations.front().is_some_and(|created| created.elapsed() > READER_CREATION_WINDOW) {
+            creations.pop_front();
+        }
+        if creations.len() >= MAX_READER_CREATIONS {
+            return false;
+        }
+        creations.push_back(Instant::now());
+        true
+    }
+
+    // Drops buffered data older than the revalidation age if the remote resource has changed.
+    fn revalidate_stale_readers(&self, readers: &mut Vec<Arc<HttpReader>>) {
+        let Some(revalidation) = &self.revalidation else {
+            return;
+        };
+        if !readers.iter().any(|reader| reader.age() > revalidation.max_age) {
+            return;
+        }
+        let mut last_modified = revalidation.last_modified.lock().unwrap();
+        let etag = self.version.etag();
+        let meta_reader = HttpMetaReader::new(&self.resource_url, self.transport.clone());
+        match meta_reader.revalidate(etag.as_deref(), last_modified.as_deref()) {
+            Ok(None) => {
+                debug!("Buffered data has been revalidated");
+                for reader in readers.iter() {
+                    reader.mark_validated();
+                }
+                return;
+            }
+            Ok(Some(meta)) => {
+                warn!("Remote resource has changed, dropping buffered data");
+                if let Some(cache) = &self.cache {
+                    cache.reset(meta.size, meta.etag.as_deref(), meta.last_modified.as_deref());
+                }
+                self.shrink_to(meta.size);
+                // applies the ETag policy, e.g. a refresh of the size on the next read
+                self.version.accept(meta.etag.as_deref(), meta.last_modified.as_deref());
+                *last_modified = meta.last_modified;
+            }
+            Err(e) => warn!("Unable to revalidate buffered data, dropping it: {}", e),
+        }
+        for reader in readers.iter() {
+            reader.stop();
+        }
+        readers.clear();
+        self.drop_prefetched();
+    }
+
+    // Clamps the exposed size when the remote resource has become shorter, so that reads beyond
+    // the new end return no data instead of waiting for bytes which will never arrive.
+    fn shrink_to(&self, size: usize) {
+        let previous = self.file_size.fetch_min(size, Ordering::SeqCst);
+        if size < previous {
+            warn!("Remote resource has shrunk from {} to {} bytes", previous, size);
+            self.drop_prefetched();
+        }
+    }
+
+    // Drops data downloaded ahead of reads, which may not match the remote resource anymore.
+    fn drop_prefetched(&self) {
+        self.prefetched.lock().unwrap().clear();
+        if let Some(striping) = &self.striping {
+            striping.clear();
+        }
+        if let Some(hints) = &self.hints {
+            hints.clear();
+        }
+    }
+
+    // The cache the buffers of stopped readers are kept in. None with a verifier, as the buffers aren't verified.
+    fn retire_cache(&self) -> Option<&Arc<DiskCache>> {
+        self.cache.as_ref().filter(|_| self.verifier.is_none())
+    }
+
+    fn inc_and_get_readers_counter(&self) -> usize {
+        self.readers_counter.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+// Stops a reader which isn't needed anymore, keeping the data it has buffered in the cache, if any,
+// so that it isn't downloaded again.
+fn retire(cache: Option<&Arc<DiskCache>>, reader: &HttpReader) {
+    reader.stop();
+    if let Some(cache) = cache {
+        let (offset, data) = reader.buffered_data();
+        cache.write(offset, &data);
+    }
+}
+
+// Downloads `span` with a one-shot request, hedged across the mirrors if there are any.
+fn fetch_span(
+    transport: &Transport,
+    url: &str,
+    mirrors: &[String],
+    span: Span,
+    version: &Arc<ResourceVersion>,
+    hedge_delay: Duration,
+) -> io::Result<Vec<u8>> {
+    if mirrors.is_empty() {
+        return fetch_range(transport, url, span, version);
+    }
+    fetch_range_hedged(transport, url, mirrors, span, version, hedge_delay)
+}
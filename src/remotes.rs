@@ -0,0 +1,123 @@
+// Named remotes shared across many mounts, configured in a git-config like file:
+//
+//     [remote "artifacts"]
+//     url = https://artifacts.example.com/releases
+//     header = Authorization: Bearer ...
+//     login_cmd = portal-login.sh
+//
+// A resource can then be referenced as `artifacts:path/to/file`.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::header_template::validate_header;
+
+#[derive(Clone, Debug, Default)]
+pub struct Remote {
+    pub url: String,
+    pub headers: Vec<String>,
+    // command logging into the remote before the first request, see `CommandCredentials::login`
+    pub login_cmd: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Remotes {
+    remotes: HashMap<String, Remote>,
+}
+
+impl Remotes {
+    // `$XDG_CONFIG_HOME/httpfs/remotes.conf`, falling back to `~/.config/httpfs/remotes.conf`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_dir.join("httpfs").join("remotes.conf"))
+    }
+
+    // Loads remotes from `path`. A missing file means there are no remotes.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("Remotes config {:?} does not exist", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e),
+        };
+        Self::parse(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut remotes = HashMap::new();
+        let mut current: Option<(String, Remote)> = None;
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = section.trim().strip_prefix("remote")
+                    .map(|name| name.trim().trim_matches('"'))
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| format!("line {}: expected [remote \"name\"], found {:?}", i + 1, line))?;
+                if let Some((name, remote)) = current.take() {
+                    remotes.insert(name, remote);
+                }
+                current = Some((name.to_string(), Remote::default()));
+                continue;
+            }
+
+            let Some((_, remote)) = current.as_mut() else {
+                return Err(format!("line {}: setting outside of a [remote] section", i + 1));
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value, found {:?}", i + 1, line));
+            };
+            match key.trim() {
+                "url" => remote.url = value.trim().to_string(),
+                "login_cmd" => remote.login_cmd = Some(value.trim().to_string()),
+                "header" => {
+                    let header = validate_header(value.trim()).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                    remote.headers.push(header);
+                }
+                key => return Err(format!("line {}: unknown setting {:?}", i + 1, key)),
+            }
+        }
+        if let Some((name, remote)) = current.take() {
+            remotes.insert(name, remote);
+        }
+
+        if let Some((name, _)) = remotes.iter().find(|(_, remote)| remote.url.is_empty()) {
+            return Err(format!("remote {:?} has no url", name));
+        }
+        Ok(Remotes { remotes })
+    }
+
+    // Resolves `name:path` of a configured remote into the full url and the remote headers.
+    // Anything else, e.g. a plain url, is returned as is.
+    pub fn resolve(&self, spec: &str) -> Remote {
+        let remote = spec.split_once(':')
+            .filter(|(_, path)| !path.starts_with("//"))
+            .and_then(|(name, path)| Some((self.remotes.get(name)?, path)));
+        match remote {
+            Some((remote, path)) => {
+                let url = if path.is_empty() {
+                    remote.url.clone()
+                } else {
+                    format!("{}/{}", remote.url.trim_end_matches('/'), path.trim_start_matches('/'))
+                };
+                debug!("Resolved {} to {}", spec, url);
+                Remote { url, headers: remote.headers.clone(), login_cmd: remote.login_cmd.clone() }
+            }
+            None => Remote { url: spec.to_string(), headers: vec![], login_cmd: None },
+        }
+    }
+}
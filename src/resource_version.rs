@@ -0,0 +1,135 @@
+// Detection of changes of the remote resource during an active mount.
+// The ETag of the first response is remembered and compared with the ETag of every later response,
+// or the Last-Modified date for resources without an ETag. Unless changes are ignored, ranged requests
+// carry the validator in `If-Range`, so that a server answers with the whole new version instead of
+// a range of it, which is then rejected by the comparison.
+
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::{error, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EtagPolicy {
+    // Fail reads with EIO, the mount has to be restarted to read the new version
+    Fail,
+    // Refetch the size, drop buffered data and continue with the new version
+    Refresh,
+    // Log the change and keep using responses of both versions
+    Ignore,
+}
+
+pub struct ResourceVersion {
+    policy: EtagPolicy,
+    etag: Mutex<Option<String>>,
+    // compared only while there is no ETag, and the modification time of the files of the resource
+    last_modified: Mutex<Option<String>>,
+    // set when a response of another version was rejected
+    changed: Mutex<bool>,
+}
+
+pub fn parse_etag_policy(value: &str) -> Result<EtagPolicy, String> {
+    match value {
+        "fail" => Ok(EtagPolicy::Fail),
+        "refresh" => Ok(EtagPolicy::Refresh),
+        "ignore" => Ok(EtagPolicy::Ignore),
+        _ => Err(format!("Unknown ETag policy {:?}, expected fail, refresh or ignore", value)),
+    }
+}
+
+impl ResourceVersion {
+    pub fn new(etag: Option<String>, policy: EtagPolicy) -> Self {
+        ResourceVersion {
+            policy,
+            etag: Mutex::new(etag),
+            last_modified: Mutex::new(None),
+            changed: Mutex::new(false),
+        }
+    }
+
+    // Also compares the Last-Modified date of responses while the resource has no ETag.
+    pub fn with_last_modified(self, last_modified: Option<String>) -> Self {
+        *self.last_modified.lock().unwrap() = last_modified;
+        self
+    }
+
+    pub fn policy(&self) -> EtagPolicy {
+        self.policy
+    }
+
+    // Compares the ETag of a response with the known one, or its Last-Modified date if neither has an ETag.
+    // Returns false if the body of the response must not be used.
+    pub fn accept(&self, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+        let mut expected_etag = self.etag.lock().unwrap();
+        let mut expected_last_modified = self.last_modified.lock().unwrap();
+        let (validator, expected, actual) =
+            match (expected_etag.as_mut(), etag, expected_last_modified.as_mut(), last_modified) {
+                (Some(expected), Some(etag), _, _) => ("ETag", expected, etag),
+                (None, None, Some(expected), Some(last_modified)) => ("Last-Modified", expected, last_modified),
+                _ => {
+                    // the first response with a validator tells it
+                    if expected_etag.is_none() {
+                        *expected_etag = etag.map(String::from);
+                    }
+                    if expected_last_modified.is_none() {
+                        *expected_last_modified = last_modified.map(String::from);
+                    }
+                    return true;
+                }
+            };
+        if expected == actual {
+            return true;
+        }
+
+        match self.policy {
+            EtagPolicy::Ignore => {
+                warn!("Remote resource has changed: {} {} is now {}", validator, expected, actual);
+                *expected = String::from(actual);
+                if let Some(last_modified) = last_modified {
+                    *expected_last_modified = Some(last_modified.to_string());
+                }
+                true
+            }
+            EtagPolicy::Fail => {
+                error!("Remote resource has changed: {} {} is now {}, reads will fail", validator, expected, actual);
+                *self.changed.lock().unwrap() = true;
+                false
+            }
+            EtagPolicy::Refresh => {
+                warn!("Remote resource has changed: {} {} is now {}, refreshing", validator, expected, actual);
+                *self.changed.lock().unwrap() = true;
+                false
+            }
+        }
+    }
+
+    // Value of the `If-Range` header of ranged requests: the ETag if it is strong, as weak ones can't be
+    // used there, or else the Last-Modified date. None if changes are ignored.
+    pub fn if_range(&self) -> Option<String> {
+        if self.policy == EtagPolicy::Ignore {
+            return None;
+        }
+        let etag = self.etag.lock().unwrap().clone();
+        etag.filter(|etag| !etag.starts_with("W/")).or_else(|| self.last_modified.lock().unwrap().clone())
+    }
+
+    // Time of the Last-Modified date of the version, None if the server sent none or an invalid one.
+    pub fn modified(&self) -> Option<SystemTime> {
+        httpdate::parse_http_date(self.last_modified.lock().unwrap().as_deref()?).ok()
+    }
+
+    pub fn etag(&self) -> Option<String> {
+        self.etag.lock().unwrap().clone()
+    }
+
+    pub fn is_changed(&self) -> bool {
+        *self.changed.lock().unwrap()
+    }
+
+    // Starts tracking the new version after the resource was refreshed.
+    pub fn reset(&self, etag: Option<String>, last_modified: Option<String>) {
+        *self.etag.lock().unwrap() = etag;
+        *self.last_modified.lock().unwrap() = last_modified;
+        *self.changed.lock().unwrap() = false;
+    }
+}
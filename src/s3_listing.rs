@@ -0,0 +1,297 @@
+// Listing of the objects of an S3 bucket, or of a bucket of a compatible store like MinIO, to mount them as
+// a directory tree. `s3://bucket/prefix/` lists the objects under the prefix with ListObjectsV2, and each
+// object becomes a file at its key relative to the prefix, read from the URL of the object like any other
+// file of a tree manifest. Keys are percent-encoded in the URLs and shown decoded in the mount.
+
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+use log::{debug, warn};
+
+use crate::file_system::check_paths;
+use crate::percent_encoding::percent_encode;
+use crate::transport::{parse_header, parse_status_line, Transport, AUTH_RETRIES};
+use crate::tree_manifest::{TreeEntry, TreeManifest};
+
+#[derive(Clone, Debug)]
+pub struct S3Location {
+    // e.g. https://bucket.s3.eu-central-1.amazonaws.com, without a trailing slash
+    pub bucket_url: String,
+    // keys of the listed objects start with it
+    pub prefix: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct S3Object {
+    pub key: String,
+    pub size: usize,
+    // as an HTTP date, like the Last-Modified header of the object
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+}
+
+impl S3Location {
+    // Parses `s3://BUCKET[/PREFIX]`. The bucket is addressed as https://BUCKET.s3.REGION.amazonaws.com,
+    // or as ENDPOINT/BUCKET with the `endpoint` of another store, e.g. http://minio.local:9000.
+    pub fn parse(url: &str, region: Option<&str>, endpoint: Option<&str>) -> Result<Self, String> {
+        let path = url.strip_prefix("s3://").ok_or_else(|| format!("{} is not an s3:// URL", url))?;
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            return Err(format!("{} has no bucket", url));
+        }
+        let bucket_url = match (endpoint, region) {
+            (Some(endpoint), _) => format!("{}/{}", endpoint.trim_end_matches('/'), bucket),
+            (None, Some(region)) => format!("https://{}.s3.{}.amazonaws.com", bucket, region),
+            (None, None) => format!("https://{}.s3.amazonaws.com", bucket),
+        };
+        Ok(S3Location { bucket_url, prefix: prefix.to_string() })
+    }
+
+    // Takes the URL of a bucket addressed by its host name, e.g. https://bucket.s3.amazonaws.com/prefix/,
+    // whose path is the prefix.
+    pub fn from_bucket_url(url: &str) -> Self {
+        let scheme_end = url.find("://").map_or(0, |i| i + 3);
+        let (bucket_url, prefix) = match url[scheme_end..].find('/') {
+            Some(i) => (&url[..scheme_end + i], &url[scheme_end + i + 1..]),
+            None => (url, ""),
+        };
+        S3Location { bucket_url: bucket_url.to_string(), prefix: prefix.to_string() }
+    }
+
+    pub fn object_url(&self, key: &str) -> String {
+        format!("{}/{}", self.bucket_url, percent_encode(key, true))
+    }
+
+    // Lists all objects under the prefix, following the continuation tokens of truncated pages.
+    pub fn list(&self, transport: &Transport) -> io::Result<Vec<S3Object>> {
+        let mut objects = vec![];
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut url = format!("{}/?list-type=2&prefix={}", self.bucket_url, percent_encode(&self.prefix, false));
+            if let Some(token) = &continuation {
+                url.push_str(&format!("&continuation-token={}", percent_encode(token, false)));
+            }
+            let (page, next) = parse_listing(&get(transport, &url)?);
+            debug!("Listed {} objects of {}", page.len(), self.bucket_url);
+            objects.extend(page);
+            match next {
+                Some(token) => continuation = Some(token),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    // The objects as a tree manifest, at their keys relative to the prefix up to its last slash. Keys
+    // of directory markers and keys that can't be paths of the mount, e.g. with empty segments, are left out.
+    pub fn tree(&self, objects: Vec<S3Object>) -> TreeManifest {
+        let base = self.prefix.rfind('/').map_or(0, |i| i + 1);
+        let entries = objects.into_iter()
+            .filter(|object| !object.key.ends_with('/'))
+            .filter_map(|object| {
+                let path = object.key.get(base..)?.to_string();
+                if let Err(e) = check_paths([path.as_str()]) {
+                    warn!("Object {} is not mounted: {}", object.key, e);
+                    return None;
+                }
+                Some(TreeEntry {
+                    path,
+                    url: self.object_url(&object.key),
+                    size: Some(object.size),
+                    etag: object.etag,
+                    last_modified: object.last_modified,
+                    headers: vec![],
+                    weight: None,
+                })
+            })
+            .collect();
+        TreeManifest { entries }
+    }
+}
+
+// The objects of a page of a ListObjectsV2 response, and the continuation token of the next page if it is
+// truncated.
+pub fn parse_listing(xml: &str) -> (Vec<S3Object>, Option<String>) {
+    let objects = elements(xml, "Contents").into_iter()
+        .filter_map(|contents| {
+            Some(S3Object {
+                key: unescape(elements(contents, "Key").first()?),
+                size: elements(contents, "Size").first()?.trim().parse().ok()?,
+                last_modified: elements(contents, "LastModified").first().and_then(|value| http_date(value.trim())),
+                etag: elements(contents, "ETag").first().map(|etag| unescape(etag)),
+            })
+        })
+        .collect();
+    let truncated = elements(xml, "IsTruncated").first().is_some_and(|value| value.trim() == "true");
+    let next = elements(xml, "NextContinuationToken").first().map(|token| unescape(token)).filter(|_| truncated);
+    (objects, next)
+}
+
+// Contents of the elements named `tag` in `xml`, in order. Enough for the flat responses of S3, whose
+// elements of one name don't nest.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        found.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    found
+}
+
+// Replaces the entity and character references of XML text.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let reference = &rest[1..end];
+        let c = match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => reference.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| reference.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// `2009-10-12T17:50:30.000Z` of a listing as an HTTP date.
+fn http_date(iso: &str) -> Option<String> {
+    let (date, time) = iso.split_once('T')?;
+    let mut date = date.split('-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.trim_end_matches('Z').split(':');
+    let (hour, minute) = (time.next()?.parse::<u64>().ok()?, time.next()?.parse::<u64>().ok()?);
+    let second = time.next()?.split('.').next()?.parse::<u64>().ok()?;
+    // days since the epoch of the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year.div_euclid(400), year.rem_euclid(400));
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(seconds)))
+}
+
+// Downloads a page of the listing with the credentials and middlewares of `transport`, e.g. SigV4 signing.
+fn get(transport: &Transport, url: &str) -> io::Result<String> {
+    let mut attempt = 0;
+    loop {
+        let mut easy = transport.easy(url, &[])?;
+        let mut status = 0;
+        let mut headers = vec![];
+        let mut body = vec![];
+        {
+            let mut transfer = easy.transfer();
+            transfer.header_function(|header| {
+                if let Some(code) = parse_status_line(header) {
+                    status = code;
+                    headers.clear();
+                } else if let Some(header) = parse_header(header) {
+                    headers.push(header);
+                }
+                true
+            })?;
+            transfer.write_function(|data| {
+                body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.perform()?;
+        }
+        transport.release(easy);
+        transport.on_response(url, status, &headers);
+        if transport.rejects_credentials(status) && attempt < AUTH_RETRIES {
+            warn!("Listing request was rejected with {}, retrying with refreshed credentials", status);
+            transport.refresh_credentials()?;
+            attempt += 1;
+            continue;
+        }
+        let body = String::from_utf8_lossy(&body).into_owned();
+        if !(200..300).contains(&status) {
+            // S3 tells the reason in the body, e.g. <Code>AccessDenied</Code>
+            let code = elements(&body, "Code").first().map(|code| format!(": {}", code)).unwrap_or_default();
+            return Err(io::Error::other(format!("Listing failed with HTTP status {}{}", status, code)));
+        }
+        return Ok(body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_listing_becomes_tree_of_objects() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+              <Name>bucket</Name>
+              <Prefix>photos/</Prefix>
+              <KeyCount>3</KeyCount>
+              <IsTruncated>true</IsTruncated>
+              <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+              <Contents>
+                <Key>photos/2006/</Key>
+                <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+                <ETag>&quot;d41d8cd98f00b204e9800998ecf8427e&quot;</ETag>
+                <Size>0</Size>
+              </Contents>
+              <Contents>
+                <Key>photos/2006/Tom &amp; Jerry.jpg</Key>
+                <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+                <ETag>&quot;fba9dede5f27731c9771645a39863328&quot;</ETag>
+                <Size>434234</Size>
+              </Contents>
+              <Contents>
+                <Key>photos/2006//empty-segment.jpg</Key>
+                <Size>1</Size>
+              </Contents>
+            </ListBucketResult>"#;
+        let (objects, next) = parse_listing(xml);
+        assert_eq!(next.as_deref(), Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM="));
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects[1].key, "photos/2006/Tom & Jerry.jpg");
+        assert_eq!(objects[1].size, 434234);
+        assert_eq!(objects[1].etag.as_deref(), Some("\"fba9dede5f27731c9771645a39863328\""));
+        assert_eq!(objects[1].last_modified.as_deref(), Some("Mon, 12 Oct 2009 17:50:30 GMT"));
+
+        // the directory marker and the key that isn't a path are left out, paths are relative to the prefix
+        let location = S3Location::parse("s3://bucket/photos/", Some("eu-central-1"), None).unwrap();
+        let tree = location.tree(objects);
+        assert_eq!(tree.entries.len(), 1);
+        assert_eq!(tree.entries[0].path, "2006/Tom & Jerry.jpg");
+        assert_eq!(tree.entries[0].url,
+            "https://bucket.s3.eu-central-1.amazonaws.com/photos/2006/Tom%20%26%20Jerry.jpg");
+        assert_eq!(tree.entries[0].size, Some(434234));
+
+        let location = S3Location::parse("s3://bucket/photos/20", None, Some("http://localhost:9000/")).unwrap();
+        assert_eq!(location.bucket_url, "http://localhost:9000/bucket");
+        assert_eq!(location.prefix, "photos/20");
+        let (objects, next) = parse_listing("<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>");
+        assert!(objects.is_empty() && next.is_none());
+    }
+}
@@ -0,0 +1,114 @@
+// Seccomp filter restricting the process to the system calls serving reads needs: FUSE or client sockets,
+// connections to the origin, threads and memory. httpfs parses headers and bodies of remote servers,
+// and the filter keeps a bug there from executing programs, opening files for writing, ptracing etc.
+// It is installed once the mount or listener is set up and privileges are dropped, which need more calls.
+// Denied calls fail with EPERM instead of killing the process, so that an overlooked call of a library
+// surfaces as an error of the operation rather than as a lost mount.
+// Only the system call numbers of x86_64 and aarch64 are listed, other architectures can't enable the filter.
+#![cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), allow(dead_code, unused_imports))]
+
+use std::io;
+
+use libc::{
+    c_long, c_uint, c_ulong, prctl, sock_filter, sock_fprog, syscall, EPERM, PR_SET_NO_NEW_PRIVS, SECCOMP_FILTER_FLAG_TSYNC,
+    SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS, SECCOMP_SET_MODE_FILTER, SYS_seccomp,
+};
+use log::info;
+
+// BPF instructions, see linux/filter.h
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_RET_K: u16 = 0x06;
+// offsets in struct seccomp_data
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED: &[c_long] = &[
+    // files: /dev/fuse, resolv.conf, CA certificates, /proc of the process
+    libc::SYS_read, libc::SYS_write, libc::SYS_readv, libc::SYS_writev, libc::SYS_pread64, libc::SYS_pwrite64,
+    libc::SYS_openat, libc::SYS_close, libc::SYS_fstat, libc::SYS_newfstatat, libc::SYS_statx, libc::SYS_lseek,
+    libc::SYS_fcntl, libc::SYS_ioctl, libc::SYS_dup, libc::SYS_dup3, libc::SYS_pipe2, libc::SYS_getdents64,
+    libc::SYS_readlinkat, libc::SYS_faccessat, libc::SYS_fstatfs, libc::SYS_getcwd,
+    // sockets
+    libc::SYS_socket, libc::SYS_socketpair, libc::SYS_connect, libc::SYS_bind, libc::SYS_listen, libc::SYS_accept,
+    libc::SYS_accept4, libc::SYS_sendto, libc::SYS_recvfrom, libc::SYS_sendmsg, libc::SYS_recvmsg,
+    libc::SYS_sendmmsg, libc::SYS_recvmmsg, libc::SYS_shutdown, libc::SYS_getsockname, libc::SYS_getpeername,
+    libc::SYS_setsockopt, libc::SYS_getsockopt,
+    // waiting
+    libc::SYS_ppoll, libc::SYS_pselect6, libc::SYS_epoll_create1, libc::SYS_epoll_ctl, libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2, libc::SYS_futex, libc::SYS_nanosleep, libc::SYS_clock_nanosleep, libc::SYS_clock_gettime,
+    libc::SYS_sched_yield, libc::SYS_restart_syscall,
+    // memory
+    libc::SYS_mmap, libc::SYS_munmap, libc::SYS_mprotect, libc::SYS_mremap, libc::SYS_madvise, libc::SYS_brk,
+    // threads and signals, fetch threads set their priority as they start
+    libc::SYS_clone, libc::SYS_clone3, libc::SYS_set_robust_list, libc::SYS_rseq, libc::SYS_exit,
+    libc::SYS_exit_group, libc::SYS_rt_sigaction, libc::SYS_rt_sigprocmask, libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack, libc::SYS_tgkill, libc::SYS_sched_getaffinity, libc::SYS_setpriority,
+    libc::SYS_ioprio_set, libc::SYS_prlimit64,
+    // identity and randomness
+    libc::SYS_getpid, libc::SYS_gettid, libc::SYS_getuid, libc::SYS_geteuid, libc::SYS_getgid, libc::SYS_getegid,
+    libc::SYS_getrandom, libc::SYS_uname, libc::SYS_getrusage, libc::SYS_sysinfo,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_pipe,
+];
+
+// Installs the filter for all threads of the process, irrevocably.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn enable_seccomp() -> io::Result<()> {
+    let mut program = vec![
+        statement(BPF_LD_W_ABS, ARCH_OFFSET),
+        // syscall numbers of other ABIs, e.g. 32-bit calls on x86_64, mean different calls
+        jump_if_equal(AUDIT_ARCH, 1, 0),
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, NR_OFFSET),
+    ];
+    for &nr in ALLOWED {
+        program.push(jump_if_equal(nr as u32, 0, 1));
+        program.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+    }
+    program.push(statement(BPF_RET_K, SECCOMP_RET_ERRNO | EPERM as c_uint));
+    let program = sock_fprog { len: program.len() as u16, filter: program.as_mut_ptr() };
+
+    // SAFETY: plain syscalls, the program outlives the call, which copies it
+    unsafe {
+        // required to install a filter without CAP_SYS_ADMIN, and keeps setuid binaries from regaining rights
+        if prctl(PR_SET_NO_NEW_PRIVS, 1 as c_ulong, 0 as c_ulong, 0 as c_ulong, 0 as c_ulong) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // TSYNC applies the filter to the threads already running, e.g. the FUSE session and IO threads
+        if syscall(SYS_seccomp, SECCOMP_SET_MODE_FILTER, SECCOMP_FILTER_FLAG_TSYNC, &program as *const sock_fprog) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    info!("Seccomp filter allowing {} system calls is installed", ALLOWED.len());
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn enable_seccomp() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the seccomp filter supports only x86_64 and aarch64"))
+}
+
+fn statement(code: u16, k: u32) -> sock_filter {
+    sock_filter { code, jt: 0, jf: 0, k }
+}
+
+fn jump_if_equal(k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code: BPF_JMP_JEQ_K, jt, jf, k }
+}
@@ -0,0 +1,140 @@
+// Half-open intervals of bytes used for all offset math of readers, so that buffer edges
+// and the end of the resource are handled in one place.
+
+use std::ops::Range;
+
+// Bytes `start..end` of the resource or of a buffer. Never inverted: `start <= end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    // An inverted interval becomes empty at `start`.
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end: end.max(start) }
+    }
+
+    // `len` bytes from `start`, cut at `usize::MAX`.
+    pub fn with_len(start: usize, len: usize) -> Self {
+        Span { start, end: start.saturating_add(len) }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // True if all bytes of `other` are in this span. An empty span is contained if it lies within the bounds.
+    pub fn contains(&self, other: Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    // Common bytes of both spans, None if they don't overlap.
+    pub fn intersect(&self, other: Span) -> Option<Span> {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end);
+        (start < end).then_some(Span { start, end })
+    }
+
+    // Cuts the span at `limit`, e.g. the end of the resource. Spans beyond it become empty at `limit`.
+    pub fn clamp_end(&self, limit: usize) -> Span {
+        Span { start: self.start.min(limit), end: self.end.min(limit) }
+    }
+
+    // Translates the span into coordinates starting at `origin`, e.g. the offset of a buffer.
+    // None if the span starts before `origin`.
+    pub fn relative_to(&self, origin: usize) -> Option<Span> {
+        let start = self.start.checked_sub(origin)?;
+        Some(Span { start, end: self.end - origin })
+    }
+
+    pub fn as_range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::quickcheck;
+
+    use super::*;
+
+    #[test]
+    fn new_never_inverts() {
+        assert_eq!(Span::new(10, 5), Span::new(10, 10));
+        assert!(Span::new(10, 5).is_empty());
+    }
+
+    #[test]
+    fn with_len_saturates() {
+        assert_eq!(Span::with_len(usize::MAX - 1, 10).end(), usize::MAX);
+    }
+
+    #[test]
+    fn intersect_at_edges() {
+        let buffer = Span::new(100, 200);
+        assert_eq!(buffer.intersect(Span::new(50, 100)), None);
+        assert_eq!(buffer.intersect(Span::new(200, 300)), None);
+        assert_eq!(buffer.intersect(Span::new(199, 300)), Some(Span::new(199, 200)));
+        assert_eq!(buffer.intersect(Span::new(50, 101)), Some(Span::new(100, 101)));
+    }
+
+    #[test]
+    fn clamp_end_beyond_eof() {
+        assert_eq!(Span::new(90, 110).clamp_end(100), Span::new(90, 100));
+        assert_eq!(Span::new(120, 130).clamp_end(100), Span::new(100, 100));
+    }
+
+    #[test]
+    fn relative_to_buffer() {
+        assert_eq!(Span::new(150, 160).relative_to(100), Some(Span::new(50, 60)));
+        assert_eq!(Span::new(100, 100).relative_to(100), Some(Span::new(0, 0)));
+        assert_eq!(Span::new(99, 160).relative_to(100), None);
+    }
+
+    quickcheck! {
+        fn prop_len_matches_range(start: usize, len: usize) -> bool {
+            let span = Span::with_len(start, len);
+            span.len() == span.as_range().len() && span.start() <= span.end()
+        }
+
+        fn prop_intersection_has_exactly_common_bytes(a: (u8, u8), b: (u8, u8)) -> bool {
+            let (a, b) = (Span::new(a.0 as usize, a.1 as usize), Span::new(b.0 as usize, b.1 as usize));
+            let common = a.intersect(b);
+            (0..256).all(|i| {
+                let in_both = a.as_range().contains(&i) && b.as_range().contains(&i);
+                in_both == common.is_some_and(|common| common.as_range().contains(&i))
+            })
+        }
+
+        fn prop_intersect_is_commutative(a: (usize, usize), b: (usize, usize)) -> bool {
+            let (a, b) = (Span::new(a.0, a.1), Span::new(b.0, b.1));
+            a.intersect(b) == b.intersect(a)
+        }
+
+        fn prop_clamp_end_never_exceeds_limit(start: usize, len: u16, limit: usize) -> bool {
+            let clamped = Span::with_len(start, len as usize).clamp_end(limit);
+            clamped.end() <= limit && clamped.start() <= clamped.end()
+        }
+
+        fn prop_relative_to_round_trips(origin: u32, offset: u16, len: u16) -> bool {
+            let origin = origin as usize;
+            let span = Span::with_len(origin + offset as usize, len as usize);
+            let relative = span.relative_to(origin).unwrap();
+            relative.start() == offset as usize && relative.len() == span.len()
+        }
+    }
+}
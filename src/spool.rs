@@ -0,0 +1,211 @@
+// Local spool file of content which can't be read from an arbitrary offset of the resource: the decompressed
+// content of gzip resources, or the whole resource of servers ignoring byte ranges. A background thread
+// reads the resource from the beginning into the spool as soon as it is opened, and reads wait until
+// the spool has reached their range. Transfer errors don't restart the spooling: it resumes from
+// the offset of the resource it has reached.
+//
+// The uncompressed size of gzip content is taken from the gzip trailer, which holds it modulo 4 GiB, until
+// the whole resource is decompressed. The size of larger content grows as the spool passes the size of the trailer.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use flate2::read::MultiGzDecoder;
+use libc::{EINTR, EIO};
+use log::{debug, error, info, warn};
+
+use crate::fetch_priority;
+use crate::reader_pool::ReaderPool;
+
+// Header and trailer of an empty gzip member
+const MIN_GZIP_SIZE: usize = 18;
+const TRAILER_SIZE_LEN: usize = 4;
+// Data is written into the spool in blocks of this size
+const SPOOL_BLOCK: usize = 256 * 1024;
+// A failed read of the resource is repeated this many times before the spool fails
+const RESUME_ATTEMPTS: u32 = 10;
+const RESUME_DELAY: Duration = Duration::from_secs(1);
+// How often waiting reads check whether they are cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+struct State {
+    // bytes of content in the spool
+    spooled: usize,
+    // set once the spooling has ended, with the errno of a failure
+    finished: Option<Result<(), i32>>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // signalled when data is spooled or the spooling ends
+    changed: Condvar,
+    file: File,
+    // the size of the resource or of the trailer, used until the spooling has ended
+    expected_size: usize,
+}
+
+pub struct Spool {
+    shared: Arc<Shared>,
+}
+
+impl Spool {
+    // Starts decompressing the resource of `compressed` into a spool file in `dir`, which is removed right away
+    // and freed when the spool is dropped. More than `max_size` bytes of content, if set, fail the decompression.
+    pub fn gunzip(compressed: ReaderPool, dir: &Path, max_size: Option<usize>) -> io::Result<Self> {
+        let compressed_size = compressed.file_size();
+        if compressed_size < MIN_GZIP_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Resource is too short to be gzip compressed"));
+        }
+        let trailer = compressed.read(compressed_size - TRAILER_SIZE_LEN, TRAILER_SIZE_LEN)?;
+        let trailer_size = u32::from_le_bytes(trailer.try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "Unable to read the gzip trailer")
+        })?) as usize;
+        Self::start(compressed, dir, max_size, trailer_size, true)
+    }
+
+    // Starts copying the resource of `pool` into a spool file in `dir`, like `gunzip` without the decompression.
+    pub fn copy(pool: ReaderPool, dir: &Path, max_size: Option<usize>) -> io::Result<Self> {
+        let size = pool.file_size();
+        Self::start(pool, dir, max_size, size, false)
+    }
+
+    fn start(source: ReaderPool, dir: &Path, max_size: Option<usize>, expected_size: usize, gunzip: bool)
+        -> io::Result<Self> {
+        let path = dir.join(format!("httpfs-spool-{}-{}", std::process::id(), SPOOL_COUNTER.fetch_add(1, Ordering::SeqCst)));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).mode(0o600).open(&path)?;
+        fs::remove_file(&path)?;
+        debug!("Spooling content into {}", path.display());
+
+        let shared = Arc::new(Shared { state: Mutex::new(State::default()), changed: Condvar::new(), file, expected_size });
+        let worker = Arc::clone(&shared);
+        thread::spawn(move || worker.fill(source, max_size, gunzip));
+        Ok(Spool { shared })
+    }
+
+    // Size of the content, exact once the spooling has ended.
+    pub fn file_size(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        match state.finished {
+            Some(Ok(())) => state.spooled,
+            _ => state.spooled.max(self.shared.expected_size),
+        }
+    }
+
+    // Reads `size` bytes of the content starting from `offset`, waiting until they are spooled.
+    // Gives up with EINTR as soon as `cancelled` returns true.
+    pub fn read(&self, offset: usize, size: usize, cancelled: &dyn Fn() -> bool) -> io::Result<Vec<u8>> {
+        let end = offset.saturating_add(size);
+        let mut state = self.shared.state.lock().unwrap();
+        while state.spooled < end && state.finished.is_none() {
+            if cancelled() {
+                debug!("Read at offset {} has been cancelled", offset);
+                return Err(io::Error::from_raw_os_error(EINTR));
+            }
+            state = self.shared.changed.wait_timeout(state, CANCEL_CHECK_INTERVAL).unwrap().0;
+        }
+        if let Some(Err(errno)) = state.finished {
+            if state.spooled < end {
+                return Err(io::Error::from_raw_os_error(errno));
+            }
+        }
+        let end = end.min(state.spooled);
+        drop(state);
+
+        let mut data = vec![0; end.saturating_sub(offset)];
+        self.shared.file.read_exact_at(&mut data, offset as u64)?;
+        Ok(data)
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+    }
+}
+
+impl Shared {
+    fn fill(&self, source: ReaderPool, max_size: Option<usize>, gunzip: bool) {
+        fetch_priority::apply();
+        let reader = ResumingReader { pool: source, position: 0 };
+        let result = match gunzip {
+            true => self.spool(MultiGzDecoder::new(reader), max_size),
+            false => self.spool(reader, max_size),
+        };
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(()) => info!("Spooled {} bytes of content", state.spooled),
+            Err(errno) => error!("Spooling failed with errno {} after {} bytes of content", errno, state.spooled),
+        }
+        state.finished = Some(result);
+        self.changed.notify_all();
+    }
+
+    fn spool(&self, mut content: impl Read, max_size: Option<usize>) -> Result<(), i32> {
+        let mut block = vec![0; SPOOL_BLOCK];
+        let mut spooled = 0;
+        loop {
+            if self.state.lock().unwrap().closed {
+                debug!("Spool is closed, spooling stops");
+                return Err(EIO);
+            }
+            let len = match content.read(&mut block) {
+                Ok(0) => return Ok(()),
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("Unable to spool the resource: {}", e);
+                    return Err(e.raw_os_error().unwrap_or(EIO));
+                }
+            };
+            if max_size.is_some_and(|max_size| spooled + len > max_size) {
+                error!("Content exceeds the spool limit of {} bytes", max_size.unwrap());
+                return Err(EIO);
+            }
+            if let Err(e) = self.file.write_all_at(&block[..len], spooled as u64) {
+                error!("Unable to write the spool: {}", e);
+                return Err(e.raw_os_error().unwrap_or(EIO));
+            }
+            spooled += len;
+            self.state.lock().unwrap().spooled = spooled;
+            self.changed.notify_all();
+        }
+    }
+}
+
+// Sequential reader of the resource, repeating failed reads at the same offset,
+// so that the spooling continues where it stopped.
+struct ResumingReader {
+    pool: ReaderPool,
+    position: usize,
+}
+
+impl Read for ResumingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut attempt = 1;
+        loop {
+            match self.pool.read(self.position, buf.len()) {
+                Ok(data) => {
+                    buf[..data.len()].copy_from_slice(&data);
+                    self.position += data.len();
+                    return Ok(data.len());
+                }
+                Err(e) if attempt < RESUME_ATTEMPTS => {
+                    warn!("Read at offset {} failed, resuming in {:?}: {}", self.position, RESUME_DELAY, e);
+                    sleep(RESUME_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// Striping of sequential reads across the origin and its mirrors, aggregating their bandwidth beyond
+// what a single connection provides. Once reads are sequential, the resource ahead of them is split into
+// stripes downloaded in parallel, stripe `i` from source `i % sources`, and reads are served from the stripes
+// in order. A stripe is dropped once reads have passed it, a failed stripe leaves the read to the readers.
+
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::fetch_priority;
+use crate::span::Span;
+
+// How often waiting reads check whether they are cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Stripe {
+    span: Span,
+    // None while it is being downloaded
+    data: Option<Arc<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct State {
+    // stripes ahead of the reads
+    stripes: Vec<Stripe>,
+    // end of the last read served, where the next sequential read starts
+    next_offset: usize,
+    // changed by `clear`, so that a stripe downloaded meanwhile is dropped
+    generation: u64,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // signalled when a stripe is downloaded or fails
+    changed: Condvar,
+}
+
+pub struct Striping {
+    shared: Arc<Shared>,
+    stripe_size: usize,
+}
+
+impl Striping {
+    pub fn new(stripe_size: usize) -> Self {
+        Striping {
+            shared: Arc::new(Shared { state: Mutex::new(State::default()), changed: Condvar::new() }),
+            stripe_size: stripe_size.max(1),
+        }
+    }
+
+    // Returns the data from the start of `span` up to its end or the end of its stripe, None if the read
+    // isn't sequential or its stripe has failed. A stripe ahead of the read per source, up to the end of
+    // the resource at `file_size`, is started with the function made by `fetcher`, which downloads a span
+    // from a source. Gives up waiting for the stripe as soon as `cancelled` returns true.
+    pub fn read<F>(
+        &self,
+        span: Span,
+        file_size: usize,
+        sources: usize,
+        cancelled: &dyn Fn() -> bool,
+        fetcher: impl FnOnce() -> F,
+    ) -> Option<Vec<u8>>
+    where
+        F: Fn(usize, Span) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        let striped = state.stripes.iter().any(|stripe| stripe.span.contains(Span::with_len(span.start(), 1)));
+        if span.is_empty() || (!striped && span.start() != state.next_offset) {
+            state.next_offset = span.end();
+            return None;
+        }
+        state.stripes.retain(|stripe| stripe.span.end() > span.start());
+        self.start_stripes(&mut state, span.start(), file_size, sources.max(1), fetcher);
+
+        loop {
+            let Some(stripe) = state.stripes.iter().find(|stripe| stripe.span.contains(Span::with_len(span.start(), 1))) else {
+                // the stripe has failed, the read is left to the readers
+                state.next_offset = span.end();
+                return None;
+            };
+            if let Some(data) = &stripe.data {
+                let local = span.clamp_end(stripe.span.end()).relative_to(stripe.span.start())?;
+                let result = data[local.as_range()].to_vec();
+                state.next_offset = span.start() + result.len();
+                return Some(result);
+            }
+            if cancelled() {
+                return None;
+            }
+            state = self.shared.changed.wait_timeout(state, CANCEL_CHECK_INTERVAL).unwrap().0;
+        }
+    }
+
+    // Starts downloads of the stripes from the one holding `offset` on, as many as there are sources.
+    fn start_stripes<F>(&self, state: &mut State, offset: usize, file_size: usize, sources: usize, fetcher: impl FnOnce() -> F)
+    where
+        F: Fn(usize, Span) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        let first = offset / self.stripe_size;
+        let missing: Vec<(usize, Span)> = (first..first + sources)
+            .map(|index| (index, Span::with_len(index * self.stripe_size, self.stripe_size).clamp_end(file_size)))
+            .filter(|(_, span)| !span.is_empty() && !state.stripes.iter().any(|stripe| stripe.span == *span))
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        let fetch = Arc::new(fetcher());
+        for (index, span) in missing {
+            let fetch = Arc::clone(&fetch);
+            let (shared, generation, source) = (Arc::clone(&self.shared), state.generation, index % sources);
+            state.stripes.push(Stripe { span, data: None });
+            debug!("Striping {:?} to source {}", span, source);
+            thread::spawn(move || {
+                fetch_priority::apply();
+                let result = fetch(source, span);
+                let mut state = shared.state.lock().unwrap();
+                if state.generation != generation {
+                    return;
+                }
+                let position = state.stripes.iter().position(|stripe| stripe.span == span && stripe.data.is_none());
+                match (result, position) {
+                    (Ok(data), Some(position)) if data.len() == span.len() => {
+                        state.stripes[position].data = Some(Arc::new(data));
+                    }
+                    (result, Some(position)) => {
+                        if let Err(e) = result {
+                            warn!("Stripe {:?} from source {} failed: {}", span, source, e);
+                        }
+                        state.stripes.remove(position);
+                    }
+                    (_, None) => {}
+                }
+                shared.changed.notify_all();
+            });
+        }
+    }
+
+    // Drops all stripes, e.g. after the resource has changed.
+    pub fn clear(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.stripes.clear();
+        state.generation += 1;
+        self.shared.changed.notify_all();
+    }
+}
@@ -0,0 +1,110 @@
+// Estimates of the bandwidth and the latency of the origin shared by all readers, from which the size
+// of reader buffers is derived: a buffer holds about what arrives within the latency plus BUFFERED_TIME.
+// Small buffers waste less on slow links when reads jump elsewhere, large ones keep fast links busy.
+// A fixed size may be set instead, e.g. for a link whose measurements mislead the estimate.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+const MIN_BUFFER_SIZE: usize = 512 * 1024;
+const MAX_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+// Used until the first transfer is measured
+const INITIAL_BUFFER_SIZE: usize = 1024 * 1024;
+const BUFFERED_TIME: Duration = Duration::from_secs(1);
+// Shorter transfers are dominated by the latency and don't tell much about the bandwidth
+const MIN_MEASURED_BYTES: usize = 64 * 1024;
+const MIN_MEASURED_TIME: Duration = Duration::from_millis(1);
+// How often a long transfer is sampled
+const SAMPLE_TIME: Duration = Duration::from_secs(1);
+// Weight of a new sample in the moving averages
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Default)]
+struct Estimates {
+    bytes_per_sec: Option<f64>,
+    latency_secs: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct Throughput {
+    estimates: Mutex<Estimates>,
+    fixed_buffer_size: Option<usize>,
+}
+
+impl Throughput {
+    // Buffers of `buffer_size` bytes whatever the measurements are.
+    pub fn with_fixed_buffer_size(buffer_size: usize) -> Self {
+        Throughput { fixed_buffer_size: Some(buffer_size), ..Throughput::default() }
+    }
+
+    // Records the time from sending a request to receiving its first response.
+    pub fn record_latency(&self, latency: Duration) {
+        let mut estimates = self.estimates.lock().unwrap();
+        estimates.latency_secs = Some(smooth(estimates.latency_secs, latency.as_secs_f64()));
+    }
+
+    // Records `bytes` received in `time` during which the transfer wasn't waiting for the reader.
+    pub fn record_transfer(&self, bytes: usize, time: Duration) {
+        if bytes < MIN_MEASURED_BYTES || time < MIN_MEASURED_TIME {
+            return;
+        }
+        let mut estimates = self.estimates.lock().unwrap();
+        estimates.bytes_per_sec = Some(smooth(estimates.bytes_per_sec, bytes as f64 / time.as_secs_f64()));
+        debug!("Measured {:.0} bytes/s, estimated {:.0} bytes/s with latency {:.3} s",
+            bytes as f64 / time.as_secs_f64(), estimates.bytes_per_sec.unwrap_or_default(),
+            estimates.latency_secs.unwrap_or_default());
+    }
+
+    // How many bytes a reader buffers ahead of the reads.
+    pub fn buffer_size(&self) -> usize {
+        if let Some(buffer_size) = self.fixed_buffer_size {
+            return buffer_size;
+        }
+        let estimates = self.estimates.lock().unwrap();
+        let Some(bytes_per_sec) = estimates.bytes_per_sec else {
+            return INITIAL_BUFFER_SIZE;
+        };
+        let time = estimates.latency_secs.unwrap_or_default() + BUFFERED_TIME.as_secs_f64();
+        ((bytes_per_sec * time) as usize).clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE)
+    }
+}
+
+// Measures a transfer in samples, excluding the time it waits for the reader to free buffer space.
+pub struct TransferMeter {
+    throughput: Arc<Throughput>,
+    sample_start: Option<Instant>,
+    sample_bytes: usize,
+}
+
+impl TransferMeter {
+    pub fn new(throughput: Arc<Throughput>) -> Self {
+        TransferMeter { throughput, sample_start: None, sample_bytes: 0 }
+    }
+
+    // Counts received bytes, recording a sample every SAMPLE_TIME.
+    pub fn received(&mut self, bytes: usize) {
+        let Some(sample_start) = self.sample_start else {
+            // the bytes arrived before the sample started
+            self.sample_start = Some(Instant::now());
+            return;
+        };
+        self.sample_bytes += bytes;
+        if sample_start.elapsed() >= SAMPLE_TIME {
+            self.finish_sample();
+        }
+    }
+
+    // Records the current sample, e.g. before the transfer waits for the reader or at its end.
+    pub fn finish_sample(&mut self) {
+        if let Some(sample_start) = self.sample_start.take() {
+            self.throughput.record_transfer(self.sample_bytes, sample_start.elapsed());
+        }
+        self.sample_bytes = 0;
+    }
+}
+
+fn smooth(average: Option<f64>, sample: f64) -> f64 {
+    average.map_or(sample, |average| average + SMOOTHING * (sample - average))
+}
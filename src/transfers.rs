@@ -0,0 +1,368 @@
+// Transfers of all readers of the process, driven by a single IO thread with a curl multi handle
+// instead of a thread blocked in every transfer. A transfer whose reader has a full buffer is paused
+// and resumed by the thread once reads free some space, so idle readers cost no threads, and scheduling,
+// limiting and cancelling of transfers happen in one place.
+// Connections of finished transfers stay in the cache of the multi handle for later transfers to the same host.
+//
+// Transfers of several files of a mount share the bandwidth fairly: each file has a share with a weight, and while
+// the transfers of several files are receiving data, those of a file getting ahead of the others by more than
+// FAIR_QUANTUM bytes per unit of weight are paused until the others catch up. A bulk copy of one file, which keeps
+// several readers streaming, can't starve reads of the other files then, and files with a higher weight get more.
+
+use std::cmp::Reverse;
+use std::mem;
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use curl::multi::{EasyHandle, Multi, MultiWaker};
+use curl_sys::CURLE_ABORTED_BY_CALLBACK;
+use log::{debug, warn};
+
+use crate::connections::Connection;
+use crate::fetch_priority;
+
+// How long the thread sleeps at most without being woken or any transfer needing it
+const MAX_POLL_TIME: Duration = Duration::from_secs(1);
+// Idle connections kept for later transfers, curl keeps only 4 per transfer in progress by default
+const MAX_CACHED_CONNECTIONS: usize = 32;
+// Delay before a transfer that couldn't be added to the multi handle is set up again
+const RESTART_DELAY: Duration = Duration::from_millis(100);
+// How many bytes per unit of weight a file may receive ahead of the file with the fewest
+const FAIR_QUANTUM: f64 = 1024.0 * 1024.0;
+// A file whose transfers have received nothing for this long, e.g. from a stalled server, isn't waited for
+const FAIR_STALL: Duration = Duration::from_millis(250);
+
+// What a driver wants the IO thread to do next.
+pub enum Step {
+    // perform the request set up on the connection, then report it to `finished`
+    Transfer(Connection),
+    // call `step` again after the delay, e.g. while the server asks to slow down
+    Wait(Duration),
+    Done,
+}
+
+// A sequence of transfers, e.g. of a reader resuming its interrupted transfers.
+// All methods are called on the IO thread and must not block.
+pub trait Driver: Send + Sync {
+    // Sets up the next transfer.
+    fn step(self: Arc<Self>) -> Step;
+    // Handles the result of the transfer set up by `step`, taking back its connection.
+    fn finished(self: Arc<Self>, connection: Connection, result: Result<(), curl::Error>) -> Step;
+    // Whether a transfer paused by its write callback may continue, clearing the paused state.
+    fn resume(&self) -> bool;
+    // Whether the driver doesn't need its transfers anymore.
+    fn cancelled(&self) -> bool;
+    // The share of the file the transfers belong to while they want data, e.g. have free buffer space.
+    fn contending_share(&self) -> Option<&Arc<Share>> {
+        None
+    }
+}
+
+// Share of the bandwidth of the transfers of one file.
+pub struct Share {
+    weight: u32,
+    // bytes counted as received, advanced when the file starts receiving again so that it doesn't make up
+    // for the time it was idle
+    received: AtomicU64,
+    // bytes the transfers may have received before they pause for the other files, set by the IO thread
+    allowed: AtomicU64,
+    received_at: Mutex<Instant>,
+}
+
+impl Share {
+    pub fn new(weight: u32) -> Self {
+        assert!(weight > 0, "the weight of a share must be positive");
+        Share {
+            weight,
+            received: AtomicU64::new(0),
+            allowed: AtomicU64::new(u64::MAX),
+            received_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Whether the transfers may receive more data, rather than pause for the other files.
+    pub fn may_receive(&self) -> bool {
+        self.received.load(Ordering::SeqCst) < self.allowed.load(Ordering::SeqCst)
+    }
+
+    // Counts `len` bytes received by a transfer.
+    pub fn record(&self, len: usize) {
+        self.received.fetch_add(len as u64, Ordering::SeqCst);
+        *self.received_at.lock().unwrap() = Instant::now();
+    }
+
+    // Bytes received per unit of weight.
+    fn progress(&self) -> f64 {
+        self.received.load(Ordering::SeqCst) as f64 / self.weight as f64
+    }
+
+    fn catch_up(&self, progress: f64) {
+        self.received.fetch_max((progress * self.weight as f64) as u64, Ordering::SeqCst);
+    }
+
+    fn allow(&self, progress: f64) {
+        self.allowed.store((progress * self.weight as f64) as u64, Ordering::SeqCst);
+    }
+
+    // Whether the transfers are receiving data or held back for the other files.
+    fn is_contending(&self) -> bool {
+        !self.may_receive() || self.received_at.lock().unwrap().elapsed() < FAIR_STALL
+    }
+}
+
+struct Active {
+    driver: Arc<dyn Driver>,
+    handle: EasyHandle,
+    // counts the connection while its handle is in the multi handle
+    connection: Connection,
+}
+
+struct Waiting {
+    until: Instant,
+    driver: Arc<dyn Driver>,
+}
+
+struct IoThread {
+    multi: Multi,
+    active: Vec<Active>,
+    waiting: Vec<Waiting>,
+    // shares contending for the bandwidth when the transfers were last balanced
+    contending: Vec<Arc<Share>>,
+}
+
+// drivers handed over to the IO thread
+static STARTING: Mutex<Vec<Arc<dyn Driver>>> = Mutex::new(vec![]);
+static WAKER: OnceLock<MultiWaker> = OnceLock::new();
+
+// Runs the transfers of `driver` on the IO thread, which is started with the first driver.
+pub fn spawn(driver: Arc<dyn Driver>) {
+    STARTING.lock().unwrap().push(driver);
+    wakeup(waker());
+}
+
+// Makes the IO thread check its transfers, e.g. after a read freed space in the buffer of a paused one.
+pub fn wake() {
+    if let Some(waker) = WAKER.get() {
+        wakeup(waker);
+    }
+    #[cfg(feature = "async-backend")]
+    crate::async_backend::wake();
+}
+
+fn wakeup(waker: &MultiWaker) {
+    if let Err(e) = waker.wakeup() {
+        warn!("Unable to wake the IO thread: {}", e);
+    }
+}
+
+fn waker() -> &'static MultiWaker {
+    WAKER.get_or_init(|| {
+        // the multi handle can't be sent to another thread, so the thread creates it
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            fetch_priority::apply();
+            let mut multi = Multi::new();
+            if let Err(e) = multi.set_max_connects(MAX_CACHED_CONNECTIONS) {
+                warn!("Unable to set the size of the connection cache: {}", e);
+            }
+            sender.send(multi.waker()).unwrap();
+            IoThread { multi, active: vec![], waiting: vec![], contending: vec![] }.run();
+        });
+        receiver.recv().unwrap()
+    })
+}
+
+impl IoThread {
+    fn run(mut self) {
+        debug!("IO thread has started");
+        loop {
+            self.start_waiting();
+            self.check_active();
+            if let Err(e) = self.multi.perform() {
+                warn!("Transfers failed: {}", e);
+            }
+            self.collect_finished();
+            if let Err(e) = self.multi.poll(&mut [], self.poll_time()) {
+                warn!("Waiting for transfers failed: {}", e);
+                thread::sleep(RESTART_DELAY);
+            }
+        }
+    }
+
+    // Takes over new drivers and calls those whose wait is over.
+    fn start_waiting(&mut self) {
+        let now = Instant::now();
+        let starting = mem::take(&mut *STARTING.lock().unwrap());
+        self.waiting.extend(starting.into_iter().map(|driver| Waiting { until: now, driver }));
+        let (due, waiting) = mem::take(&mut self.waiting).into_iter()
+            .filter(|waiting| !waiting.driver.cancelled())
+            .partition(|waiting| waiting.until <= now);
+        self.waiting = waiting;
+        for Waiting { driver, .. } in due {
+            let step = Arc::clone(&driver).step();
+            self.schedule(driver, step);
+        }
+    }
+
+    fn schedule(&mut self, driver: Arc<dyn Driver>, step: Step) {
+        match step {
+            Step::Transfer(mut connection) => match self.multi.add(connection.detach()) {
+                Ok(handle) => self.active.push(Active { driver, handle, connection }),
+                Err(e) => {
+                    warn!("Unable to start a transfer: {}", e);
+                    self.schedule(driver, Step::Wait(RESTART_DELAY));
+                }
+            },
+            Step::Wait(delay) => self.waiting.push(Waiting { until: Instant::now() + delay, driver }),
+            Step::Done => {}
+        }
+    }
+
+    // Aborts transfers no longer needed and resumes paused ones whose readers have freed buffer space,
+    // or whose files may receive data again.
+    fn check_active(&mut self) {
+        self.balance_shares();
+        for index in (0..self.active.len()).rev() {
+            let active = &self.active[index];
+            if active.driver.cancelled() {
+                debug!("Aborting a cancelled transfer");
+                let error = curl::Error::new(CURLE_ABORTED_BY_CALLBACK);
+                self.finish(index, Err(error));
+            } else if active.driver.resume() {
+                // the data held back by the pause may be delivered right away
+                if let Err(e) = active.handle.unpause_write() {
+                    warn!("Unable to resume a transfer: {}", e);
+                }
+            }
+        }
+    }
+
+    // Lets each file receive data in proportion to the weight of its share, while several files contend for it.
+    fn balance_shares(&mut self) {
+        let mut shares: Vec<&Arc<Share>> = vec![];
+        for share in self.active.iter().filter_map(|active| active.driver.contending_share()) {
+            if !shares.iter().any(|known| Arc::ptr_eq(known, share)) {
+                shares.push(share);
+            }
+        }
+        self.contending = balance(&shares, &self.contending);
+    }
+
+    fn collect_finished(&mut self) {
+        let mut finished = vec![];
+        self.multi.messages(|message| {
+            for (index, active) in self.active.iter().enumerate() {
+                if let Some(result) = message.result_for(&active.handle) {
+                    finished.push((index, result));
+                }
+            }
+        });
+        // removing from the end keeps the other indices valid
+        finished.sort_by_key(|(index, _)| Reverse(*index));
+        for (index, result) in finished {
+            self.finish(index, result);
+        }
+    }
+
+    fn finish(&mut self, index: usize, result: Result<(), curl::Error>) {
+        let Active { driver, handle, mut connection } = self.active.swap_remove(index);
+        match self.multi.remove(handle) {
+            Ok(easy) => {
+                connection.attach(easy);
+                let step = Arc::clone(&driver).finished(connection, result);
+                self.schedule(driver, step);
+            }
+            Err(e) => {
+                // the handle is gone, and dropping the connection counts it as closed
+                warn!("Unable to remove a finished transfer: {}", e);
+                self.schedule(driver, Step::Wait(RESTART_DELAY));
+            }
+        }
+    }
+
+    fn poll_time(&self) -> Duration {
+        let now = Instant::now();
+        let next_wait = self.waiting.iter().map(|waiting| waiting.until.saturating_duration_since(now)).min();
+        let curl_timeout = self.multi.get_timeout().ok().flatten();
+        [Some(MAX_POLL_TIME), next_wait, curl_timeout].into_iter().flatten().min().unwrap_or(MAX_POLL_TIME)
+    }
+}
+
+// Sets how much the transfers of each of `shares` may receive, given the shares which were contending for
+// the bandwidth `before`, and returns those contending now.
+pub(crate) fn balance(shares: &[&Arc<Share>], before: &[Arc<Share>]) -> Vec<Arc<Share>> {
+    let contending: Vec<Arc<Share>> = shares.iter()
+        .filter(|share| share.is_contending())
+        .map(|&share| Arc::clone(share))
+        .collect();
+    // a file starting to contend again continues from the file which has received the least
+    if let Some(floor) = lowest_progress(before) {
+        for share in &contending {
+            if !before.iter().any(|known| Arc::ptr_eq(known, share)) {
+                share.catch_up(floor);
+            }
+        }
+    }
+    let floor = lowest_progress(&contending).filter(|_| contending.len() > 1);
+    for share in shares {
+        match floor {
+            Some(floor) => share.allow(floor + FAIR_QUANTUM),
+            None => share.allowed.store(u64::MAX, Ordering::SeqCst),
+        }
+    }
+    contending
+}
+
+fn lowest_progress(shares: &[Arc<Share>]) -> Option<f64> {
+    shares.iter().map(|share| share.progress()).min_by(f64::total_cmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_receive_in_proportion_to_weights() {
+        let (first, second) = (Arc::new(Share::new(1)), Arc::new(Share::new(2)));
+        let contending = balance(&[&first, &second], &[]);
+        assert!(first.may_receive() && second.may_receive());
+
+        first.record(3 << 20);
+        second.record(3 << 20);
+        balance(&[&first, &second], &contending);
+        // 3 MiB per unit of weight against 1.5 MiB, more than the quantum ahead
+        assert!(!first.may_receive());
+        assert!(second.may_receive());
+
+        second.record(2 << 20);
+        balance(&[&first, &second], &contending);
+        assert!(first.may_receive());
+    }
+
+    #[test]
+    fn a_single_share_is_not_limited() {
+        let (first, second) = (Arc::new(Share::new(1)), Arc::new(Share::new(1)));
+        first.record(8 << 20);
+        let contending = balance(&[&first, &second], &[]);
+        assert!(!first.may_receive());
+
+        // the other file's buffer is full, so it doesn't contend anymore
+        balance(&[&first], &contending);
+        assert!(first.may_receive());
+    }
+
+    #[test]
+    fn returning_share_does_not_make_up_for_idle_time() {
+        let (first, second) = (Arc::new(Share::new(1)), Arc::new(Share::new(1)));
+        let contending = balance(&[&first], &[]);
+        first.record(8 << 20);
+        balance(&[&first, &second], &contending);
+        assert!(first.may_receive());
+
+        second.record(1);
+        assert!(second.progress() >= first.progress());
+    }
+}
@@ -0,0 +1,641 @@
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::mem::size_of;
+use std::os::raw::{c_int, c_void};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use curl::easy::{Auth, Easy, List};
+use curl_sys::{curl_socket_t, curlsocktype, CURLOPT_SOCKOPTDATA, CURLOPT_SOCKOPTFUNCTION, CURLE_OK};
+use libc::{setsockopt, socklen_t, EACCES, ENOENT, ERANGE, SOL_SOCKET, SO_RCVBUF};
+use log::{debug, warn};
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::connections::{self, Connection};
+use crate::credentials::{AddedHeaders, CredentialsProvider, StaticHeaders};
+use crate::download_budget::DownloadBudget;
+use crate::header_template::{expand_header, RequestContext};
+use crate::middleware::{Middleware, Request, Response};
+use crate::rate_limit::RateLimiter;
+use crate::throughput::Throughput;
+
+pub const HTTP_OK: u32 = 200;
+pub const HTTP_PARTIAL_CONTENT: u32 = 206;
+pub const HTTP_NOT_MODIFIED: u32 = 304;
+pub const HTTP_UNAUTHORIZED: u32 = 401;
+pub const HTTP_FORBIDDEN: u32 = 403;
+pub const HTTP_NOT_FOUND: u32 = 404;
+pub const HTTP_GONE: u32 = 410;
+pub const HTTP_RANGE_NOT_SATISFIABLE: u32 = 416;
+pub const HTTP_TOO_MANY_REQUESTS: u32 = 429;
+pub const HTTP_INTERNAL_SERVER_ERROR: u32 = 500;
+pub(crate) const MAX_REDIRECTS: u32 = 10;
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+// How many times a request is repeated with refreshed credentials after it was rejected
+pub const AUTH_RETRIES: u8 = 1;
+
+// Error of reads the server refuses with `status` for good, e.g. ENOENT for 404, so that applications fail
+// at once with a meaningful error. None if the status may be temporary, like server errors.
+pub fn refusal_error(status: u32) -> Option<io::Error> {
+    let errno = match status {
+        HTTP_UNAUTHORIZED | HTTP_FORBIDDEN => EACCES,
+        HTTP_NOT_FOUND | HTTP_GONE => ENOENT,
+        HTTP_RANGE_NOT_SATISFIABLE => ERANGE,
+        _ => return None,
+    };
+    Some(io::Error::from_raw_os_error(errno))
+}
+
+// Checks whether `error` is one of `refusal_error`, which retries can't help.
+pub fn is_refusal(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(EACCES | ENOENT | ERANGE))
+}
+
+// Value of the `Content-Range` header of a partial response, e.g. "bytes 0-1023/4096".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContentRange {
+    pub start: usize,
+    // inclusive
+    pub end: usize,
+    // None if the server does not know the size of the resource ("*")
+    pub total: Option<usize>,
+}
+
+// Settings shared by every HTTP request: the metadata probe and all range readers.
+#[derive(Clone)]
+pub struct Transport {
+    credentials: Arc<dyn CredentialsProvider>,
+    rate_limiter: Arc<RateLimiter>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    throughput: Arc<Throughput>,
+    download_budget: Arc<DownloadBudget>,
+    // counts the downloads of the file this transport reads, if set
+    file_downloads: Option<Arc<AtomicU64>>,
+    low_speed: Option<LowSpeedLimit>,
+    socket: SocketOptions,
+    tls: TlsOptions,
+    auth: Option<HttpAuth>,
+    // limit of open connections to a host, shared with all other transports of the process
+    max_connections_per_host: Option<usize>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    retry: RetryPolicy,
+    // how long a read waits for a reader to receive its data
+    read_timeout: Duration,
+    backend: Backend,
+}
+
+// HTTP implementation of the range fetches of readers and reads. Metadata requests, revalidations and
+// the warm-up of connections use curl with either.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backend {
+    // transfers driven by the IO thread with a curl multi handle
+    #[default]
+    Curl,
+    // tasks of a tokio runtime with hyper, see `async_backend`
+    #[cfg(feature = "async-backend")]
+    Hyper,
+}
+
+// Parses the value of `--backend`.
+pub fn parse_backend(value: &str) -> Result<Backend, String> {
+    match value {
+        "curl" => Ok(Backend::Curl),
+        #[cfg(feature = "async-backend")]
+        "hyper" => Ok(Backend::Hyper),
+        #[cfg(not(feature = "async-backend"))]
+        "hyper" => Err("httpfs is built without the async-backend feature".to_string()),
+        _ => Err(format!("Unknown backend {:?}, expected curl or hyper", value)),
+    }
+}
+
+// Authentication schemes negotiated by curl itself, as opposed to the headers of the credentials provider.
+#[derive(Clone)]
+pub enum HttpAuth {
+    // SPNEGO with the Kerberos ticket of the user, e.g. behind Active Directory SSO
+    Negotiate,
+    // legacy Windows authentication of IIS or SharePoint, the username may be given as DOMAIN\user
+    Ntlm { username: String, password: String },
+    // Basic or Digest, whichever the server asks for in its 401 response
+    Password { username: String, password: String },
+}
+
+impl HttpAuth {
+    // Checks that the libcurl in use was built with the scheme, which would otherwise fail every request.
+    pub fn check_supported(&self) -> Result<(), String> {
+        let version = curl::Version::get();
+        let supported = match self {
+            HttpAuth::Negotiate => version.feature_spnego(),
+            HttpAuth::Ntlm { .. } => version.feature_ntlm(),
+            HttpAuth::Password { .. } => true,
+        };
+        if !supported {
+            return Err(format!("libcurl {} was built without {} support", version.version(), self.name()));
+        }
+        Ok(())
+    }
+
+    // The name of the scheme, without the credentials.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HttpAuth::Negotiate => "SPNEGO",
+            HttpAuth::Ntlm { .. } => "NTLM",
+            HttpAuth::Password { .. } => "Basic/Digest",
+        }
+    }
+}
+
+// Parses USER:PASSWORD of options like `--ntlm`; the password may contain colons, the user may not.
+pub fn parse_credentials(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((user, password)) if !user.is_empty() => Ok((user.to_string(), password.to_string())),
+        _ => Err("Expected USER:PASSWORD".to_string()),
+    }
+}
+
+// Parses USER[:PASSWORD] of `--user`, the password is None if it is left out.
+pub fn parse_user(value: &str) -> Result<(String, Option<String>), String> {
+    match value.split_once(':') {
+        Some((user, password)) if !user.is_empty() => Ok((user.to_string(), Some(password.to_string()))),
+        None if !value.is_empty() => Ok((value.to_string(), None)),
+        _ => Err("Expected USER or USER:PASSWORD".to_string()),
+    }
+}
+
+// TLS settings of connections, e.g. for servers with certificates of a private CA or requiring client certificates.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    // PEM bundle of the CAs trusted instead of those of the system
+    pub ca_cert: Option<PathBuf>,
+    // PEM client certificate, and its private key unless the certificate file contains it
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    // accepts any certificate and host name, only for self-signed test servers
+    pub insecure: bool,
+}
+
+// TCP options of connections, e.g. for links whose bandwidth-delay product the system defaults don't cover.
+#[derive(Clone, Copy, Debug)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    // SO_RCVBUF in bytes, None keeps the system default
+    pub receive_buffer: Option<usize>,
+    // keepalive probes of idle connections, None disables them
+    pub keepalive: Option<Keepalive>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keepalive {
+    // how long a connection is idle before the first probe
+    pub idle: Duration,
+    pub interval: Duration,
+}
+
+impl Default for SocketOptions {
+    // The defaults of curl
+    fn default() -> Self {
+        SocketOptions { nodelay: true, receive_buffer: None, keepalive: None }
+    }
+}
+
+// Transfers slower than `bytes_per_sec` for `time` are aborted as stalled.
+#[derive(Clone, Copy, Debug)]
+pub struct LowSpeedLimit {
+    pub bytes_per_sec: u32,
+    pub time: Duration,
+}
+
+// How transfers interrupted by network errors, server errors or stalls are resumed: up to `attempts` times
+// in a row without progress, after a delay doubling from `base_delay` up to `max_delay`, of which a random
+// part up to a half is left out, so that readers cut off at once don't come back at once.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u8,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { attempts: 5, base_delay: Duration::from_millis(500), max_delay: Duration::from_secs(10) }
+    }
+}
+
+impl RetryPolicy {
+    // Delay before the retry following `failures` consecutive failed attempts, counted from 0.
+    pub fn delay(&self, failures: u8) -> Duration {
+        let delay = self.base_delay.saturating_mul(1 << failures.min(16)).min(self.max_delay);
+        let random = RandomState::new().hash_one(failures) % 1000;
+        delay.mul_f64(1.0 - random as f64 / 2000.0)
+    }
+}
+
+impl Transport {
+    pub fn new(credentials: Arc<dyn CredentialsProvider>) -> Self {
+        Transport {
+            credentials,
+            rate_limiter: Arc::new(RateLimiter::default()),
+            circuit_breaker: Arc::new(CircuitBreaker::default()),
+            throughput: Arc::new(Throughput::default()),
+            download_budget: Arc::new(DownloadBudget::default()),
+            file_downloads: None,
+            low_speed: None,
+            socket: SocketOptions::default(),
+            tls: TlsOptions::default(),
+            auth: None,
+            max_connections_per_host: None,
+            middlewares: vec![],
+            retry: RetryPolicy::default(),
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            backend: Backend::default(),
+        }
+    }
+
+    pub fn with_headers(headers: Vec<String>) -> Self {
+        Self::new(Arc::new(StaticHeaders::new(headers)))
+    }
+
+    // A transport sending `headers` on top of the credentials of this one, sharing everything else with it.
+    pub fn with_added_headers(&self, headers: Vec<String>) -> Self {
+        Transport { credentials: Arc::new(AddedHeaders::new(headers, Arc::clone(&self.credentials))), ..self.clone() }
+    }
+
+    pub fn with_low_speed_limit(mut self, limit: LowSpeedLimit) -> Self {
+        self.low_speed = Some(limit);
+        self
+    }
+
+    // Stops sending requests once the body bytes downloaded with this transport exceed `bytes`.
+    pub fn with_download_limit(mut self, bytes: u64) -> Self {
+        self.download_budget = Arc::new(DownloadBudget::new(Some(bytes)));
+        self
+    }
+
+    // Counts the downloads of this transport for the file at `path` too, see `DownloadBudget::file_counter`.
+    pub fn with_counted_file(mut self, path: &str) -> Self {
+        self.file_downloads = Some(self.download_budget.file_counter(path));
+        self
+    }
+
+    pub fn with_http_auth(mut self, auth: HttpAuth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket = options;
+        self
+    }
+
+    pub fn with_tls_options(mut self, options: TlsOptions) -> Self {
+        self.tls = options;
+        self
+    }
+
+    // Gives readers buffers of `buffer_size` bytes instead of sizing them by the measured throughput.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.throughput = Arc::new(Throughput::with_fixed_buffer_size(buffer_size));
+        self
+    }
+
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    pub fn with_max_connections_per_host(mut self, limit: usize) -> Self {
+        self.max_connections_per_host = Some(limit);
+        self
+    }
+
+    // Runs `middleware` on every request and response, after those added before it.
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    pub fn max_connections_per_host(&self) -> Option<usize> {
+        self.max_connections_per_host
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    #[cfg(feature = "async-backend")]
+    pub(crate) fn low_speed_limit(&self) -> Option<LowSpeedLimit> {
+        self.low_speed
+    }
+
+    #[cfg(feature = "async-backend")]
+    pub(crate) fn socket_options(&self) -> SocketOptions {
+        self.socket
+    }
+
+    // Checks that options only curl applies aren't set along with another backend, which would ignore them.
+    pub fn check_backend(&self) -> Result<(), String> {
+        if self.backend == Backend::Curl {
+            return Ok(());
+        }
+        if let Some(auth) = &self.auth {
+            return Err(format!("{} authentication needs the curl backend", auth.name()));
+        }
+        let tls = &self.tls;
+        if tls.ca_cert.is_some() || tls.cert.is_some() || tls.key.is_some() || tls.insecure {
+            return Err("--cacert, --cert, --key and --insecure need the curl backend".to_string());
+        }
+        Ok(())
+    }
+
+    // Creates a curl handle for `url` with `extra_headers` followed by the credentials headers,
+    // evaluating placeholders in their values. The handle of a finished transfer to the same host
+    // is taken if there is one, otherwise waits while the host has the maximum of connections open.
+    pub fn easy(&self, url: &str, extra_headers: &[String]) -> io::Result<Connection> {
+        self.configure(connections::acquire(url, self.max_connections_per_host), "GET", url, extra_headers)
+    }
+
+    // Like `easy`, for a HEAD request.
+    pub fn head(&self, url: &str, extra_headers: &[String]) -> io::Result<Connection> {
+        let easy = connections::acquire(url, self.max_connections_per_host);
+        let mut easy = self.configure(easy, "HEAD", url, extra_headers)?;
+        easy.nobody(true)?;
+        Ok(easy)
+    }
+
+    // Like `easy`, but returns None instead of waiting for a connection to the host.
+    pub fn try_easy(&self, url: &str, extra_headers: &[String]) -> io::Result<Option<Connection>> {
+        connections::try_acquire(url, self.max_connections_per_host)
+            .map(|easy| self.configure(easy, "GET", url, extra_headers))
+            .transpose()
+    }
+
+    fn configure(
+        &self,
+        mut easy: Connection,
+        method: &'static str,
+        url: &str,
+        extra_headers: &[String],
+    ) -> io::Result<Connection> {
+        self.download_budget.check()?;
+        easy.follow_location(true)?;
+        easy.max_redirections(MAX_REDIRECTS)?;
+        if let Some(limit) = self.low_speed {
+            easy.low_speed_limit(limit.bytes_per_sec)?;
+            easy.low_speed_time(limit.time)?;
+        }
+        self.apply_socket_options(&mut easy)?;
+        self.apply_tls_options(&mut easy)?;
+        if let Some(auth) = &self.auth {
+            apply_auth(&mut easy, auth)?;
+        }
+
+        let request = self.prepare_request(method, url, extra_headers)?;
+        easy.url(&request.url)?;
+        let mut headers = List::new();
+        for header in &request.headers {
+            headers.append(header)?;
+        }
+        debug!("CURL: Using headers {:?}", headers);
+        easy.http_headers(headers)?;
+        Ok(easy)
+    }
+
+    // The request to `url` with `extra_headers` followed by the credentials headers, with their placeholders
+    // evaluated, as the middlewares have changed it.
+    pub(crate) fn prepare_request(
+        &self,
+        method: &'static str,
+        url: &str,
+        extra_headers: &[String],
+    ) -> io::Result<Request> {
+        let range = extra_headers.iter()
+            .filter_map(|header| header.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("range"))
+            .map(|(_, value)| value.trim());
+        let context = RequestContext { range };
+        let headers = extra_headers.iter().chain(self.credentials.headers()?.iter())
+            .map(|header| expand_header(header, &context))
+            .collect();
+        let mut request = Request { method, url: url.to_string(), headers };
+        for middleware in &self.middlewares {
+            middleware.on_request(&mut request)?;
+        }
+        Ok(request)
+    }
+
+    // Keeps the handle after its transfer for later requests, so that they use its open connection.
+    pub fn release(&self, easy: Connection) {
+        connections::release(easy);
+    }
+
+    fn apply_socket_options(&self, easy: &mut Easy) -> io::Result<()> {
+        easy.tcp_nodelay(self.socket.nodelay)?;
+        if let Some(keepalive) = self.socket.keepalive {
+            easy.tcp_keepalive(true)?;
+            easy.tcp_keepidle(keepalive.idle)?;
+            easy.tcp_keepintvl(keepalive.interval)?;
+        }
+        if let Some(size) = self.socket.receive_buffer {
+            // curl has no option for it, the size is set by a callback on every new socket
+            let size = c_int::try_from(size).unwrap_or(c_int::MAX);
+            let callback: SockoptCallback = set_receive_buffer;
+            // SAFETY: the callback matches curl_sockopt_callback and its data is the size itself, not a pointer
+            let code = unsafe {
+                match curl_sys::curl_easy_setopt(easy.raw(), CURLOPT_SOCKOPTFUNCTION, callback) {
+                    CURLE_OK => curl_sys::curl_easy_setopt(easy.raw(), CURLOPT_SOCKOPTDATA, size as isize as *mut c_void),
+                    code => code,
+                }
+            };
+            if code != CURLE_OK {
+                return Err(curl::Error::new(code).into());
+            }
+        }
+        Ok(())
+    }
+
+    // Released handles are reset, so like all other options these are set on the handle of every request.
+    fn apply_tls_options(&self, easy: &mut Easy) -> io::Result<()> {
+        easy.ssl_verify_peer(!self.tls.insecure)?;
+        easy.ssl_verify_host(!self.tls.insecure)?;
+        if let Some(path) = &self.tls.ca_cert {
+            easy.cainfo(path)?;
+        }
+        if let Some(path) = &self.tls.cert {
+            easy.ssl_cert(path)?;
+        }
+        if let Some(path) = &self.tls.key {
+            easy.ssl_key(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+
+    pub fn throughput(&self) -> &Arc<Throughput> {
+        &self.throughput
+    }
+
+    pub fn download_budget(&self) -> &DownloadBudget {
+        &self.download_budget
+    }
+
+    // Counts received body bytes for the mount and the file, returns false once they exceed the download limit.
+    pub fn record_download(&self, bytes: usize) -> bool {
+        if let Some(file_downloads) = &self.file_downloads {
+            file_downloads.fetch_add(bytes as u64, Ordering::SeqCst);
+        }
+        self.download_budget.record(bytes)
+    }
+
+    // Passes the final response to a request for `url` to the middlewares.
+    pub fn on_response(&self, url: &str, status: u32, headers: &[(String, String)]) {
+        let response = Response { url, status, headers };
+        for middleware in &self.middlewares {
+            middleware.on_response(&response);
+        }
+    }
+
+    // Whether a response with `status` rejects the credentials, so that the request is repeated after a refresh.
+    pub fn rejects_credentials(&self, status: u32) -> bool {
+        status == HTTP_UNAUTHORIZED || (status == HTTP_FORBIDDEN && self.credentials.refreshes_forbidden())
+    }
+
+    // Asks the credentials provider for new credentials after the server rejected the current ones.
+    pub fn refresh_credentials(&self) -> io::Result<()> {
+        debug!("Refreshing credentials");
+        self.credentials.refresh()
+    }
+}
+
+// Returns the status code if the header line is a status line like "HTTP/1.1 206 Partial Content".
+pub fn parse_status_line(header: &[u8]) -> Option<u32> {
+    let line = std::str::from_utf8(header).ok()?;
+    if !line.starts_with("HTTP/") {
+        return None;
+    }
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// Splits a header line into the lowercase name and the value, e.g. ("etag", "\"abc\"").
+pub fn parse_header(header: &[u8]) -> Option<(String, String)> {
+    let line = std::str::from_utf8(header).ok()?;
+    let (name, value) = line.split_once(':')?;
+    Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+}
+
+// Informational (1xx) and redirect (3xx) responses precede the response with the actual data.
+pub fn is_interim_status(status: u32) -> bool {
+    (100..200).contains(&status) || (300..400).contains(&status)
+}
+
+pub fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    if end < start {
+        return None;
+    }
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some(ContentRange { start, end, total })
+}
+
+// Returns the size of the resource from the Content-Range of a 416 response, e.g. "bytes */1234".
+pub fn parse_unsatisfied_range(value: &str) -> Option<usize> {
+    value.trim().strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+fn apply_auth(easy: &mut Easy, auth: &HttpAuth) -> io::Result<()> {
+    match auth {
+        HttpAuth::Negotiate => {
+            easy.http_auth(Auth::new().gssnegotiate(true))?;
+            // the user comes from the ticket, but curl only authenticates when one is set, like `curl -u :`
+            easy.username("")?;
+            easy.password("")?;
+        }
+        HttpAuth::Ntlm { username, password } => {
+            easy.http_auth(Auth::new().ntlm(true))?;
+            easy.username(username)?;
+            easy.password(password)?;
+        }
+        HttpAuth::Password { username, password } => {
+            // with more than one scheme, curl learns the one of the server from its first 401 response
+            easy.http_auth(Auth::new().basic(true).digest(true))?;
+            easy.username(username)?;
+            easy.password(password)?;
+        }
+    }
+    Ok(())
+}
+
+type SockoptCallback = extern "C" fn(*mut c_void, curl_socket_t, curlsocktype) -> c_int;
+
+// Sets SO_RCVBUF of a new socket to the size passed as the callback data.
+extern "C" fn set_receive_buffer(size: *mut c_void, socket: curl_socket_t, _purpose: curlsocktype) -> c_int {
+    let size = size as isize as c_int;
+    // SAFETY: `socket` is a valid socket created by curl, and the option value is a c_int
+    let result = unsafe {
+        setsockopt(socket, SOL_SOCKET, SO_RCVBUF, &size as *const c_int as *const c_void, size_of::<c_int>() as socklen_t)
+    };
+    if result != 0 {
+        warn!("Unable to set the receive buffer size to {}: {}", size, io::Error::last_os_error());
+    }
+    // CURL_SOCKOPT_OK, the connection works with the default size as well
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delays_double_up_to_limit() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        for failures in 0..8 {
+            let full = Duration::from_millis(100 << failures).min(Duration::from_secs(1));
+            let delay = policy.delay(failures);
+            assert!(delay <= full && delay >= full / 2, "delay {:?} after {} failures", delay, failures);
+        }
+    }
+
+    #[test]
+    fn backends_are_parsed() {
+        assert_eq!(parse_backend("curl"), Ok(Backend::Curl));
+        #[cfg(feature = "async-backend")]
+        assert_eq!(parse_backend("hyper"), Ok(Backend::Hyper));
+        #[cfg(not(feature = "async-backend"))]
+        assert!(parse_backend("hyper").is_err());
+        assert!(parse_backend("reqwest").is_err());
+    }
+}
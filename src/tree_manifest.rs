@@ -0,0 +1,139 @@
+// Directory tree of remote files mounted together, e.g. a dataset catalog, described in a file in the
+// format of the remotes config:
+//
+//     [file "images/train/0001.jpg"]
+//     url = https://data.example.com/train/0001.jpg
+//     size = 48213
+//     header = X-Api-Key: ...
+//     weight = 4
+//
+// Directories are created for the components of the paths. A known size saves the metadata request
+// of the file at mount, and the headers are sent with the requests of that file only. Files share the
+// bandwidth in proportion to their weights, 1 by default, while several of them are being read.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::file_system::check_paths;
+use crate::header_template::validate_header;
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeEntry {
+    pub path: String,
+    pub url: String,
+    pub size: Option<usize>,
+    // validators known along with the size, e.g. from a bucket listing
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub headers: Vec<String>,
+    pub weight: Option<u32>,
+}
+
+impl TreeEntry {
+    // Applies the setting `key = value` of the file, e.g. `size = 48213`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "url" => self.url = value.to_string(),
+            "size" => self.size = Some(value.parse().map_err(|_| format!("invalid size {:?}", value))?),
+            "weight" => {
+                let weight = value.parse().ok().filter(|&weight| weight > 0)
+                    .ok_or_else(|| format!("invalid weight {:?}", value))?;
+                self.weight = Some(weight);
+            }
+            "header" => self.headers.push(validate_header(value)?),
+            key => return Err(format!("unknown setting {:?}", key)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeManifest {
+    pub entries: Vec<TreeEntry>,
+}
+
+impl TreeManifest {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut entries: Vec<TreeEntry> = vec![];
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let path = section.trim().strip_prefix("file")
+                    .map(|path| path.trim().trim_matches('"'))
+                    .filter(|path| !path.is_empty())
+                    .ok_or_else(|| format!("line {}: expected [file \"path\"], found {:?}", i + 1, line))?;
+                entries.push(TreeEntry { path: path.to_string(), ..TreeEntry::default() });
+                continue;
+            }
+
+            let Some(entry) = entries.last_mut() else {
+                return Err(format!("line {}: setting outside of a [file] section", i + 1));
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value, found {:?}", i + 1, line));
+            };
+            entry.set(key.trim(), value.trim()).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+
+        if let Some(entry) = entries.iter().find(|entry| entry.url.is_empty()) {
+            return Err(format!("file {:?} has no url", entry.path));
+        }
+        check_paths(entries.iter().map(|entry| entry.path.as_str()))?;
+        Ok(TreeManifest { entries })
+    }
+
+    // The entries of `newer` which are new or differ in their url, size or validators, and the paths which
+    // are gone from it, e.g. between two listings of a bucket.
+    pub fn changes(&self, newer: &TreeManifest) -> (Vec<TreeEntry>, Vec<String>) {
+        let same = |a: &TreeEntry, b: &TreeEntry| {
+            (&a.url, a.size, &a.etag, &a.last_modified) == (&b.url, b.size, &b.etag, &b.last_modified)
+        };
+        let changed = newer.entries.iter()
+            .filter(|entry| !self.entries.iter().any(|old| old.path == entry.path && same(old, entry)))
+            .cloned()
+            .collect();
+        let removed = self.entries.iter()
+            .filter(|old| !newer.entries.iter().any(|entry| entry.path == old.path))
+            .map(|old| old.path.clone())
+            .collect();
+        (changed, removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_between_listings() {
+        let entry = |path: &str, etag: &str| TreeEntry {
+            path: path.to_string(),
+            url: format!("https://example.com/{}", path),
+            size: Some(10),
+            etag: Some(etag.to_string()),
+            ..TreeEntry::default()
+        };
+        let older = TreeManifest { entries: vec![entry("a", "1"), entry("b", "1"), entry("c", "1")] };
+        let newer = TreeManifest { entries: vec![entry("a", "1"), entry("b", "2"), entry("d", "1")] };
+        let (changed, removed) = older.changes(&newer);
+        let changed: Vec<(&str, Option<&str>)> = changed.iter()
+            .map(|entry| (entry.path.as_str(), entry.etag.as_deref()))
+            .collect();
+        assert_eq!(changed, vec![("b", Some("2")), ("d", Some("1"))]);
+        assert_eq!(removed, vec!["c".to_string()]);
+        let (changed, removed) = newer.changes(&newer);
+        assert!(changed.is_empty() && removed.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+// Parsing of human-friendly sizes and durations used by command line options.
+
+use std::time::Duration;
+
+// Byte range given as `START-END` (END exclusive) or `START-` (till the end of the resource).
+#[derive(Clone, Copy, Debug)]
+pub struct ByteRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+// Parses sizes like `512`, `64K`, `100M` or `2G`. Suffixes are binary: 1K = 1024 bytes.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier: usize = match suffix.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(format!("Unknown size suffix in {:?}", value)),
+            };
+            (&value[..i], multiplier)
+        }
+        _ => (value, 1),
+    };
+    let number: usize = digits.trim().parse()
+        .map_err(|_| format!("Invalid size {:?}", value))?;
+    number.checked_mul(multiplier)
+        .ok_or_else(|| format!("Size {:?} is too large", value))
+}
+
+// Formats `bytes` with the suffixes of `parse_size`, e.g. `1.50G`.
+pub fn format_size(bytes: u64) -> String {
+    let suffixes = [(1u64 << 40, 'T'), (1 << 30, 'G'), (1 << 20, 'M'), (1 << 10, 'K')];
+    match suffixes.iter().find(|(multiplier, _)| bytes >= *multiplier) {
+        Some(&(multiplier, suffix)) => format!("{:.2}{}", bytes as f64 / multiplier as f64, suffix),
+        None => bytes.to_string(),
+    }
+}
+
+// Parses durations like `90`, `90s`, `30m`, `6h`, `2d` or `2ms`. A number without a suffix is in seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (digits, millis) = match value.char_indices().last() {
+        _ if value.ends_with("ms") => (&value[..value.len() - 2], 1),
+        Some((i, suffix)) if suffix.is_ascii_alphabetic() => {
+            let millis: u64 = match suffix.to_ascii_lowercase() {
+                's' => 1000,
+                'm' => 60 * 1000,
+                'h' => 60 * 60 * 1000,
+                'd' => 24 * 60 * 60 * 1000,
+                _ => return Err(format!("Unknown duration suffix in {:?}", value)),
+            };
+            (&value[..i], millis)
+        }
+        _ => (value, 1000),
+    };
+    let number: u64 = digits.trim().parse()
+        .map_err(|_| format!("Invalid duration {:?}", value))?;
+    number.checked_mul(millis)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("Duration {:?} is too long", value))
+}
+
+pub fn parse_byte_range(value: &str) -> Result<ByteRange, String> {
+    let Some((start, end)) = value.split_once('-') else {
+        return Err(format!("Invalid range {:?}, expected START-END or START-", value));
+    };
+    let start = parse_size(start)?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(parse_size(end)?),
+    };
+    if end.is_some_and(|end| end < start) {
+        return Err(format!("Invalid range {:?}, end is before start", value));
+    }
+    Ok(ByteRange { start, end })
+}
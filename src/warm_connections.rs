@@ -0,0 +1,67 @@
+// Connections to the origin opened when a mount starts, so that the first seeks find connections
+// with finished TCP and TLS handshakes instead of paying for them before any data flows.
+// Every connection is opened by a one-byte request on the IO thread and stays in its cache,
+// where the transfers of readers take it. Over HTTP/2 the requests share a single connection anyway.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::connections::Connection;
+use crate::transfers::{self, Driver, Step};
+use crate::transport::Transport;
+
+// How often a warm-up checks whether a connection to the host has been released
+const CONNECTION_RECHECK: Duration = Duration::from_millis(50);
+
+struct WarmUp {
+    transport: Transport,
+    url: String,
+    ordinal_number: usize, // just for logging
+}
+
+// Opens `count` connections to the origin of `url` in the background.
+pub fn warm_up(transport: &Transport, url: &str, count: usize) {
+    debug!("Opening {} connections to {} ahead of reads", count, url);
+    for ordinal_number in 0..count {
+        let warm_up = WarmUp { transport: transport.clone(), url: url.to_string(), ordinal_number };
+        transfers::spawn(Arc::new(warm_up));
+    }
+}
+
+impl Driver for WarmUp {
+    fn step(self: Arc<Self>) -> Step {
+        match self.transport.try_easy(&self.url, &["Range: bytes=0-0".to_string()]) {
+            Ok(Some(mut easy)) => match easy.write_function(|buf| Ok(buf.len())) {
+                Ok(()) => Step::Transfer(easy),
+                Err(e) => {
+                    warn!("Unable to set up connection {}: {}", self.ordinal_number, e);
+                    Step::Done
+                }
+            },
+            Ok(None) => Step::Wait(CONNECTION_RECHECK),
+            Err(e) => {
+                warn!("Unable to set up connection {}: {}", self.ordinal_number, e);
+                Step::Done
+            }
+        }
+    }
+
+    fn finished(self: Arc<Self>, easy: Connection, result: Result<(), curl::Error>) -> Step {
+        match result {
+            Ok(()) => debug!("Connection {} to {} is open", self.ordinal_number, self.url),
+            Err(e) => warn!("Unable to open connection {} to {}: {}", self.ordinal_number, self.url, e),
+        }
+        self.transport.release(easy);
+        Step::Done
+    }
+
+    fn resume(&self) -> bool {
+        false
+    }
+
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
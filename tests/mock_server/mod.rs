@@ -0,0 +1,247 @@
+// A range-capable HTTP server running in-process on localhost, serving one resource.
+// It can answer slowly, rate limit, fail or cut off responses, to check how readers cope with
+// an imperfect origin. Shared by the integration tests and the benchmarks.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct MockServer {
+    data: Arc<Vec<u8>>,
+    // delay before every response
+    latency: Duration,
+    // how many first GET requests are answered with 429
+    throttled: usize,
+    // every n-th GET request is answered with 503
+    failing_every: Option<usize>,
+    // every n-th GET request is cut off after this many bytes of the body
+    truncating_every: Option<(usize, usize)>,
+    // whether Range headers are honored
+    ranges: bool,
+    // requests without this Authorization value are answered with 403
+    authorization: Option<String>,
+    // requests without this Cookie value are answered with 403
+    cookie: Option<String>,
+    // sent as Last-Modified, ranges of requests with another If-Range are ignored
+    last_modified: Option<String>,
+    requests: Arc<AtomicUsize>,
+}
+
+struct Request {
+    method: String,
+    // the first byte and the last one if given
+    range: Option<(usize, Option<usize>)>,
+    authorization: Option<String>,
+    cookie: Option<String>,
+    if_range: Option<String>,
+}
+
+pub struct RunningServer {
+    url: String,
+    requests: Arc<AtomicUsize>,
+}
+
+// Contents of a test resource, different at every offset of a block.
+pub fn test_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+impl MockServer {
+    pub fn new(data: Vec<u8>) -> Self {
+        MockServer {
+            data: Arc::new(data),
+            latency: Duration::ZERO,
+            throttled: 0,
+            failing_every: None,
+            truncating_every: None,
+            ranges: true,
+            authorization: None,
+            cookie: None,
+            last_modified: None,
+            requests: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_throttling(mut self, requests: usize) -> Self {
+        self.throttled = requests;
+        self
+    }
+
+    pub fn with_failures(mut self, every: usize) -> Self {
+        self.failing_every = Some(every);
+        self
+    }
+
+    pub fn with_truncation(mut self, every: usize, after: usize) -> Self {
+        self.truncating_every = Some((every, after));
+        self
+    }
+
+    pub fn without_ranges(mut self) -> Self {
+        self.ranges = false;
+        self
+    }
+
+    pub fn with_authorization(mut self, value: &str) -> Self {
+        self.authorization = Some(value.to_string());
+        self
+    }
+
+    pub fn with_cookie(mut self, value: &str) -> Self {
+        self.cookie = Some(value.to_string());
+        self
+    }
+
+    pub fn with_last_modified(mut self, value: &str) -> Self {
+        self.last_modified = Some(value.to_string());
+        self
+    }
+
+    pub fn start(self) -> RunningServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/resource.bin", listener.local_addr().unwrap());
+        let requests = Arc::clone(&self.requests);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = self.clone();
+                thread::spawn(move || server.serve_connection(stream));
+            }
+        });
+        RunningServer { url, requests }
+    }
+
+    // Answers requests of a keep-alive connection until the client closes it.
+    fn serve_connection(&self, stream: TcpStream) {
+        // the head and the body are written separately, which must not wait for the ACK of the head
+        stream.set_nodelay(true).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let Some(Request { method, range, authorization, cookie, if_range }) = read_request(&mut reader) else {
+                return;
+            };
+            sleep(self.latency);
+            let number = match method.as_str() {
+                "GET" => self.requests.fetch_add(1, Ordering::SeqCst) + 1,
+                _ => 0,
+            };
+            if number > 0 && number <= self.throttled {
+                respond(&mut stream, "429 Too Many Requests", "Retry-After: 0\r\n", b"slow down");
+                continue;
+            }
+            if self.authorization.is_some() && authorization != self.authorization {
+                respond(&mut stream, "403 Forbidden", "", b"expired token");
+                continue;
+            }
+            if self.cookie.is_some() && cookie != self.cookie {
+                respond(&mut stream, "403 Forbidden", "", b"no session");
+                continue;
+            }
+            if self.failing_every.is_some_and(|every| number > 0 && number % every == 0) {
+                respond(&mut stream, "503 Service Unavailable", "", b"unavailable");
+                continue;
+            }
+
+            let size = self.data.len();
+            let unchanged = if_range.is_none() || if_range == self.last_modified;
+            let (status, mut extra, start, end) = match range.filter(|_| self.ranges && unchanged) {
+                Some((start, _)) if start >= size => {
+                    let extra = format!("Content-Range: bytes */{}\r\n", size);
+                    respond(&mut stream, "416 Range Not Satisfiable", &extra, b"");
+                    continue;
+                }
+                Some((start, end)) => {
+                    let end = end.map_or(size, |end| (end + 1).min(size));
+                    let extra = format!("Content-Range: bytes {}-{}/{}\r\n", start, end - 1, size);
+                    ("206 Partial Content", extra, start, end)
+                }
+                None => ("200 OK", String::new(), 0, size),
+            };
+            if let Some(last_modified) = &self.last_modified {
+                extra.push_str(&format!("Last-Modified: {}\r\n", last_modified));
+            }
+            let body = if method == "HEAD" { &[][..] } else { &self.data[start..end] };
+            let head = format!("HTTP/1.1 {}\r\n{}Accept-Ranges: bytes\r\nContent-Length: {}\r\n\r\n",
+                status, extra, end - start);
+            if stream.write_all(head.as_bytes()).is_err() {
+                return;
+            }
+            match self.truncating_every {
+                Some((every, after)) if number > 0 && number % every == 0 && after < body.len() => {
+                    let _ = stream.write_all(&body[..after]);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    return;
+                }
+                _ => {
+                    if stream.write_all(body).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl RunningServer {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // How many GET requests the server has received.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+// Reads the method and the byte range of a request, None when the connection is closed.
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).ok()? == 0 {
+        return None;
+    }
+    let method = request_line.split(' ').next()?.to_string();
+    let mut range = None;
+    let mut authorization = None;
+    let mut cookie = None;
+    let mut if_range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        if line == "\r\n" {
+            return Some(Request { method, range, authorization, cookie, if_range });
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+            if name.eq_ignore_ascii_case("cookie") {
+                cookie = Some(value.trim().to_string());
+            }
+            if name.eq_ignore_ascii_case("if-range") {
+                if_range = Some(value.trim().to_string());
+            }
+        }
+        if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+            let (start, end) = value.trim().split_once('-')?;
+            range = Some((start.parse().ok()?, end.parse().ok()));
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, extra: &str, body: &[u8]) {
+    let head = format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\n\r\n", status, extra, body.len());
+    let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body));
+}
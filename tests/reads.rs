@@ -0,0 +1,641 @@
+// Reads through a reader pool from the mock server, checking the served data byte by byte.
+
+mod mock_server;
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use httpfs::cache::{cached_version, DiskCache};
+use httpfs::checksum::{ChecksumManifest, Verifier};
+use httpfs::credentials::CommandCredentials;
+use httpfs::decrypt::{Decryption, NONCE_PREFIX_LEN};
+use httpfs::file_system::HttpFs;
+use httpfs::http_meta_reader::HttpMetaReader;
+use httpfs::middleware::{Middleware, Request, Response};
+use httpfs::mount::{mount_options, Mount};
+use httpfs::profile::ReadProfile;
+use httpfs::range_request::fetch_range;
+use httpfs::reader_pool::ReaderPool;
+use httpfs::resource_version::{EtagPolicy, ResourceVersion};
+use httpfs::span::Span;
+use httpfs::transport::Transport;
+use httpfs::warm_connections::warm_up;
+use sha2::{Digest, Sha256};
+
+use mock_server::{test_data, MockServer, RunningServer};
+
+const SIZE: usize = 4 * 1024 * 1024 + 123;
+const READ_SIZE: usize = 64 * 1024;
+
+fn pool(server: &RunningServer) -> ReaderPool {
+    ReaderPool::new(server.url(), SIZE, Transport::with_headers(vec![]))
+}
+
+fn read_all(pool: &ReaderPool, read_size: usize) -> Vec<u8> {
+    let mut data = vec![];
+    for offset in (0..SIZE).step_by(read_size) {
+        data.extend(pool.read(offset, read_size).unwrap());
+    }
+    data
+}
+
+// Encrypts `data` in the chunked AES-GCM format served with --decrypt_key.
+fn encrypt(key: &[u8; 32], chunk_size: usize, data: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let prefix = [7u8; NONCE_PREFIX_LEN];
+    let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+    let mut encrypted = prefix.to_vec();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let mut nonce = [0; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&(index as u32).to_be_bytes());
+        nonce[11] = (index == chunks.len() - 1) as u8;
+        encrypted.extend(cipher.encrypt(Nonce::from_slice(&nonce), *chunk).unwrap());
+    }
+    encrypted
+}
+
+// Checksum manifest of `data` in blocks of `block_size`, as read with --checksum_manifest.
+fn checksum_manifest(data: &[u8], block_size: usize) -> String {
+    let mut manifest = format!("block_size {}\n", block_size);
+    for block in data.chunks(block_size) {
+        manifest.push_str(&format!("{}\n", hex::encode(Sha256::digest(block))));
+    }
+    manifest
+}
+
+// Offsets spread over the resource in a fixed pseudo-random order.
+fn random_offsets(count: usize) -> Vec<usize> {
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    (0..count)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize % SIZE
+        })
+        .collect()
+}
+
+#[test]
+fn sequential_reads_return_resource() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    assert!(read_all(&pool(&server), READ_SIZE) == test_data(SIZE));
+    // a single reader streams the whole resource
+    assert_eq!(server.requests(), 1);
+}
+
+#[test]
+fn random_reads_return_requested_ranges() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let pool = pool(&server);
+    let expected = test_data(SIZE);
+    for offset in random_offsets(50) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+}
+
+#[test]
+fn reads_beyond_end_are_empty() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let pool = pool(&server);
+    assert_eq!(pool.read(SIZE - 10, READ_SIZE).unwrap().len(), 10);
+    assert!(pool.read(SIZE, READ_SIZE).unwrap().is_empty());
+}
+
+#[test]
+fn reads_with_latency() {
+    let server = MockServer::new(test_data(SIZE)).with_latency(Duration::from_millis(50)).start();
+    assert!(read_all(&pool(&server), READ_SIZE) == test_data(SIZE));
+}
+
+#[test]
+fn reads_recover_from_throttling() {
+    let server = MockServer::new(test_data(SIZE)).with_throttling(3).start();
+    assert!(read_all(&pool(&server), READ_SIZE) == test_data(SIZE));
+    assert_eq!(server.requests(), 4);
+}
+
+#[test]
+fn reads_recover_from_server_errors() {
+    let server = MockServer::new(test_data(SIZE)).with_failures(2).start();
+    let pool = pool(&server);
+    let expected = test_data(SIZE);
+    for offset in random_offsets(10) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+}
+
+#[test]
+fn truncated_transfers_are_resumed() {
+    let server = MockServer::new(test_data(SIZE)).with_truncation(1, 1024 * 1024).start();
+    assert!(read_all(&pool(&server), READ_SIZE) == test_data(SIZE));
+    assert!(server.requests() > 1);
+}
+
+#[test]
+fn server_ignoring_ranges() {
+    let server = MockServer::new(test_data(SIZE)).without_ranges().start();
+    let pool = pool(&server);
+    let expected = test_data(SIZE);
+    let offset = SIZE / 2;
+    assert!(pool.read(offset, READ_SIZE).unwrap()[..] == expected[offset..offset + READ_SIZE]);
+}
+
+#[test]
+fn server_ignoring_ranges_is_spooled_once() {
+    let server = MockServer::new(test_data(SIZE)).without_ranges().start();
+    let pool = pool(&server).with_spool(&env::temp_dir(), None).unwrap();
+    assert_eq!(pool.file_size(), SIZE);
+    let expected = test_data(SIZE);
+    for offset in random_offsets(20) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+    assert_eq!(server.requests(), 1);
+}
+
+#[test]
+fn one_shot_reads_request_only_read_data() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let pool = pool(&server).with_profile(ReadProfile::columnar());
+    let expected = test_data(SIZE);
+    for offset in random_offsets(5) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+    assert_eq!(server.requests(), 5);
+}
+
+#[test]
+fn concurrent_small_reads_are_batched() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let pool = pool(&server).with_profile(ReadProfile::columnar()).with_read_batching(Duration::from_millis(50));
+    let expected = test_data(SIZE);
+    // two clusters of nearby reads
+    let offsets: Vec<usize> = (0..8).map(|i| i * 8192).chain((0..8).map(|i| SIZE / 2 + i * 8192)).collect();
+    thread::scope(|scope| {
+        for &offset in &offsets {
+            let (pool, expected) = (&pool, &expected);
+            scope.spawn(move || assert!(pool.read(offset, 4096).unwrap()[..] == expected[offset..offset + 4096]));
+        }
+    });
+    assert!(server.requests() < offsets.len(), "{} requests for {} reads", server.requests(), offsets.len());
+}
+
+#[test]
+fn reads_within_connection_limit() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let transport = Transport::with_headers(vec![]).with_max_connections_per_host(1);
+    let pool = ReaderPool::new(server.url(), SIZE, transport);
+    let expected = test_data(SIZE);
+    // every new reader has to take the only connection of the previous one
+    for offset in random_offsets(20) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+}
+
+#[cfg(feature = "async-backend")]
+fn hyper_pool(server: &RunningServer) -> ReaderPool {
+    let transport = Transport::with_headers(vec![]).with_backend(httpfs::transport::Backend::Hyper);
+    ReaderPool::new(server.url(), SIZE, transport)
+}
+
+#[cfg(feature = "async-backend")]
+#[test]
+fn reads_with_hyper_backend() {
+    let server = MockServer::new(test_data(SIZE)).with_truncation(1, 1024 * 1024).start();
+    assert!(read_all(&hyper_pool(&server), READ_SIZE) == test_data(SIZE));
+    // the truncated transfers are resumed
+    assert!(server.requests() > 1);
+}
+
+#[cfg(feature = "async-backend")]
+#[test]
+fn one_shot_reads_with_hyper_backend() {
+    let server = MockServer::new(test_data(SIZE)).with_throttling(1).start();
+    let pool = hyper_pool(&server).with_profile(ReadProfile::columnar());
+    let expected = test_data(SIZE);
+    for offset in random_offsets(5) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+}
+
+#[test]
+fn idle_readers_are_stopped() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let pool = pool(&server).with_reader_idle_timeout(Duration::from_millis(100));
+    let expected = test_data(SIZE);
+    assert!(pool.read(0, READ_SIZE).unwrap()[..] == expected[..READ_SIZE]);
+    thread::sleep(Duration::from_millis(1500));
+    // the stopped reader doesn't serve the next read, which needs a new request
+    assert!(pool.read(READ_SIZE, READ_SIZE).unwrap()[..] == expected[READ_SIZE..2 * READ_SIZE]);
+    assert_eq!(server.requests(), 2);
+}
+
+#[test]
+fn hinted_ranges_are_read_without_requests() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let pool = pool(&server).with_prefetch_hints(8 * 1024 * 1024);
+    let hinted = Span::new(1024 * 1024, 3 * 1024 * 1024);
+    pool.hint(hinted);
+    for _ in 0..100 {
+        if server.requests() == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    // one request per hinted block
+    assert_eq!(server.requests(), 2);
+    let expected = test_data(SIZE);
+    for offset in (hinted.start()..hinted.end()).step_by(READ_SIZE) {
+        assert!(pool.read(offset, READ_SIZE).unwrap()[..] == expected[offset..offset + READ_SIZE]);
+    }
+    assert_eq!(server.requests(), 2);
+}
+
+#[test]
+fn cached_data_is_read_offline_by_next_pool() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let dir = env::temp_dir().join(format!("httpfs-cache-{}", std::process::id()));
+    let cache = DiskCache::open(&dir, server.url(), SIZE, None, None).unwrap();
+    let expected = test_data(SIZE);
+    // a pool of a previous mount, which drops its cache when it ends
+    assert!(read_all(&pool(&server).with_cache(cache, false), READ_SIZE) == expected);
+    let requests = server.requests();
+
+    let cache = DiskCache::open(&dir, server.url(), SIZE, None, None).unwrap();
+    let pool = pool(&server).with_cache(cache, true);
+    assert!(read_all(&pool, READ_SIZE) == expected);
+    assert_eq!(server.requests(), requests);
+    drop(pool);
+    // the cache of another version of the resource is emptied, and offline there is nothing to read
+    let cache = DiskCache::open(&dir, server.url(), SIZE, Some("\"v2\""), None).unwrap();
+    let pool = ReaderPool::new(server.url(), SIZE, Transport::with_headers(vec![])).with_cache(cache, true);
+    assert!(pool.read(0, READ_SIZE).is_err());
+    assert_eq!(server.requests(), requests);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupted_block_fails_with_eio() {
+    const BLOCK: usize = 1024 * 1024;
+    let expected = test_data(SIZE);
+    let mut corrupted = expected.clone();
+    corrupted[2 * BLOCK + 100] ^= 0x01;
+    let server = MockServer::new(corrupted).start();
+    let manifest = ChecksumManifest::parse(&checksum_manifest(&expected, BLOCK)).unwrap();
+    let pool = pool(&server).with_verifier(Verifier::new(SIZE, None, Some(manifest)).unwrap());
+    assert!(pool.read(BLOCK, READ_SIZE).unwrap() == expected[BLOCK..BLOCK + READ_SIZE]);
+    // whichever part of the block is read
+    for offset in [2 * BLOCK, 3 * BLOCK - READ_SIZE] {
+        assert_eq!(pool.read(offset, READ_SIZE).unwrap_err().raw_os_error(), Some(libc::EIO));
+    }
+    // other blocks are still served
+    assert!(pool.read(3 * BLOCK, READ_SIZE).unwrap() == expected[3 * BLOCK..3 * BLOCK + READ_SIZE]);
+}
+
+#[test]
+fn corrupted_file_fails_with_eio_at_end_of_sequential_read() {
+    let expected = test_data(SIZE);
+    let checksum: [u8; 32] = Sha256::digest(&expected).into();
+    let intact = MockServer::new(expected.clone()).start();
+    let verified = pool(&intact).with_verifier(Verifier::new(SIZE, Some(checksum), None).unwrap());
+    assert!(read_all(&verified, READ_SIZE) == expected);
+
+    let mut corrupted = expected.clone();
+    corrupted[SIZE / 2] ^= 0x01;
+    let server = MockServer::new(corrupted).start();
+    let pool = pool(&server).with_verifier(Verifier::new(SIZE, Some(checksum), None).unwrap());
+    let last = (SIZE - 1) / READ_SIZE * READ_SIZE;
+    for offset in (0..last).step_by(READ_SIZE) {
+        pool.read(offset, READ_SIZE).unwrap();
+    }
+    // the read completing the file fails, and so does every read after it
+    assert_eq!(pool.read(last, READ_SIZE).unwrap_err().raw_os_error(), Some(libc::EIO));
+    assert_eq!(pool.read(0, READ_SIZE).unwrap_err().raw_os_error(), Some(libc::EIO));
+}
+
+#[test]
+fn cached_data_is_dropped_when_last_modified_changes() {
+    const MODIFIED: &str = "Mon, 02 Oct 2023 10:00:00 GMT";
+    let server = MockServer::new(test_data(SIZE)).start();
+    let dir = env::temp_dir().join(format!("httpfs-cache-modified-{}", std::process::id()));
+    let cache = DiskCache::open(&dir, server.url(), SIZE, None, Some(MODIFIED)).unwrap();
+    assert!(read_all(&pool(&server).with_cache(cache, false), READ_SIZE) == test_data(SIZE));
+    let (size, etag, last_modified) = cached_version(&dir, server.url()).unwrap();
+    assert_eq!((size, etag, last_modified.as_deref()), (SIZE, None, Some(MODIFIED)));
+
+    // a resource without ETag changed in place to content of the same size
+    let cache = DiskCache::open(&dir, server.url(), SIZE, None, Some("Tue, 03 Oct 2023 10:00:00 GMT")).unwrap();
+    let requests = server.requests();
+    assert!(pool(&server).with_cache(cache, true).read(0, READ_SIZE).is_err());
+    assert_eq!(server.requests(), requests);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cached_data_is_read_from_the_mapping() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let dir = env::temp_dir().join(format!("httpfs-cache-mapped-{}", std::process::id()));
+    let cache = DiskCache::open(&dir, server.url(), SIZE, None, None).unwrap();
+    let pool = pool(&server).with_cache(cache, false);
+    assert!(pool.read_mapped(0, READ_SIZE).is_none());
+    assert!(read_all(&pool, READ_SIZE) == test_data(SIZE));
+
+    let requests = server.requests();
+    assert!(*pool.read_mapped(1000, READ_SIZE).unwrap() == test_data(SIZE)[1000..1000 + READ_SIZE]);
+    // clamped to the end of the resource
+    assert!(*pool.read_mapped(SIZE - 10, READ_SIZE).unwrap() == test_data(SIZE)[SIZE - 10..]);
+    assert!(pool.read_mapped(SIZE, READ_SIZE).is_none());
+    assert_eq!(server.requests(), requests);
+    drop(pool);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn blocks_failing_checksum_are_evicted_from_cache() {
+    const BLOCK: usize = 1024 * 1024;
+    let expected = test_data(SIZE);
+    let mut corrupted = expected.clone();
+    corrupted[BLOCK + 10] ^= 0xff;
+    let verifier = || {
+        let manifest = ChecksumManifest::parse(&checksum_manifest(&expected, BLOCK)).unwrap();
+        Verifier::new(SIZE, None, Some(manifest)).unwrap()
+    };
+    let good = MockServer::new(expected.clone()).start();
+    let bad = MockServer::new(corrupted.clone()).start();
+    let dir = env::temp_dir().join(format!("httpfs-cache-corrupted-{}", std::process::id()));
+    // the cache of `good` filled by a mount without checksums while the resource was corrupted
+    let cache = DiskCache::open(&dir, good.url(), SIZE, None, None).unwrap();
+    let unverified = ReaderPool::new(bad.url(), SIZE, Transport::with_headers(vec![])).with_cache(cache, false);
+    assert!(read_all(&unverified, READ_SIZE) == corrupted);
+    drop(unverified);
+
+    // the corrupted block fails once, then it is downloaded again
+    let cache = DiskCache::open(&dir, good.url(), SIZE, None, None).unwrap();
+    let pool = pool(&good).with_verifier(verifier()).with_cache(cache, false);
+    assert_eq!(pool.read(BLOCK, READ_SIZE).unwrap_err().raw_os_error(), Some(libc::EIO));
+    assert!(pool.read(BLOCK, READ_SIZE).unwrap() == expected[BLOCK..BLOCK + READ_SIZE]);
+    drop(pool);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // and data failing verification is never written to the cache
+    let cache = DiskCache::open(&dir, bad.url(), SIZE, None, None).unwrap();
+    let pool = ReaderPool::new(bad.url(), SIZE, Transport::with_headers(vec![]))
+        .with_verifier(verifier())
+        .with_cache(cache, false);
+    assert!(pool.read(0, BLOCK).unwrap() == expected[..BLOCK]);
+    assert!(pool.read(BLOCK, READ_SIZE).is_err());
+    drop(pool);
+    let cache = DiskCache::open(&dir, bad.url(), SIZE, None, None).unwrap();
+    let offline = ReaderPool::new(bad.url(), SIZE, Transport::with_headers(vec![])).with_cache(cache, true);
+    assert!(offline.read(0, READ_SIZE).unwrap() == expected[..READ_SIZE]);
+    assert!(offline.read(BLOCK, READ_SIZE).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn slow_requests_are_hedged_to_mirror() {
+    let origin = MockServer::new(test_data(SIZE)).with_latency(Duration::from_secs(2)).start();
+    let mirror = MockServer::new(test_data(SIZE)).start();
+    let pool = ReaderPool::new(origin.url(), SIZE, Transport::with_headers(vec![]))
+        .with_profile(ReadProfile::columnar())
+        .with_mirrors(vec![mirror.url().to_string()], Duration::from_millis(50));
+    let started = Instant::now();
+    let offset = SIZE / 3;
+    assert!(pool.read(offset, READ_SIZE).unwrap()[..] == test_data(SIZE)[offset..offset + READ_SIZE]);
+    assert!(started.elapsed() < Duration::from_secs(1), "the read took {:?}", started.elapsed());
+    assert_eq!(mirror.requests(), 1);
+}
+
+#[test]
+fn sequential_reads_are_striped_across_mirrors() {
+    let origin = MockServer::new(test_data(SIZE)).start();
+    let mirrors = [MockServer::new(test_data(SIZE)).start(), MockServer::new(test_data(SIZE)).start()];
+    let pool = ReaderPool::new(origin.url(), SIZE, Transport::with_headers(vec![]))
+        .with_mirrors(mirrors.iter().map(|mirror| mirror.url().to_string()).collect(), Duration::from_millis(50))
+        .with_striping(256 * 1024);
+    assert!(read_all(&pool, READ_SIZE) == test_data(SIZE));
+    // the 17 stripes are spread evenly over the three sources
+    assert!(origin.requests() >= 5);
+    assert!(mirrors.iter().all(|mirror| mirror.requests() >= 5));
+}
+
+#[test]
+fn warm_up_requests_in_background() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let transport = Transport::with_headers(vec![]);
+    warm_up(&transport, server.url(), 3);
+    for _ in 0..100 {
+        if server.requests() == 3 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.requests(), 3);
+    let pool = ReaderPool::new(server.url(), SIZE, transport);
+    assert!(pool.read(SIZE / 2, READ_SIZE).unwrap()[..] == test_data(SIZE)[SIZE / 2..SIZE / 2 + READ_SIZE]);
+}
+
+#[test]
+fn refused_reads_fail_at_once_with_errno() {
+    let server = MockServer::new(test_data(SIZE)).with_authorization("Bearer secret").start();
+    for pool in [pool(&server), pool(&server).with_profile(ReadProfile::columnar())] {
+        let started = Instant::now();
+        let error = pool.read(0, READ_SIZE).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EACCES));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+    // nothing is retried
+    assert_eq!(server.requests(), 2);
+}
+
+#[test]
+fn range_request_returns_span() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let version = ResourceVersion::new(None, EtagPolicy::Ignore);
+    let transport = Transport::with_headers(vec![]);
+    let data = fetch_range(&transport, server.url(), Span::new(1000, 2000), &version).unwrap();
+    assert!(data[..] == test_data(SIZE)[1000..2000]);
+}
+
+#[test]
+fn changed_resource_without_etag_is_detected_by_last_modified() {
+    let server = MockServer::new(test_data(SIZE)).with_last_modified("Wed, 14 Oct 2026 08:00:00 GMT").start();
+    let expected = test_data(SIZE);
+    let version = ResourceVersion::new(None, EtagPolicy::Fail)
+        .with_last_modified(Some("Wed, 14 Oct 2026 08:00:00 GMT".to_string()));
+    let span = Span::new(1000, 2000);
+    let data = fetch_range(&Transport::with_headers(vec![]), server.url(), span, &version).unwrap();
+    assert!(data == expected[1000..2000]);
+
+    // the server sends the whole resource, whose date doesn't match
+    let last_modified = Some("Tue, 13 Oct 2026 08:00:00 GMT".to_string());
+    let pool = pool(&server).with_etag_policy(None, last_modified, EtagPolicy::Fail);
+    assert!(pool.read(READ_SIZE, READ_SIZE).is_err());
+}
+
+#[test]
+fn modification_time_is_last_modified_of_responses() {
+    let server = MockServer::new(test_data(SIZE)).with_last_modified("Wed, 14 Oct 2026 08:00:00 GMT").start();
+    let pool = pool(&server);
+    assert_eq!(pool.modified(), None);
+    pool.read(0, READ_SIZE).unwrap();
+    assert_eq!(pool.modified(), Some(UNIX_EPOCH + Duration::from_secs(1791964800)));
+}
+
+#[test]
+fn meta_reports_size() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let meta = HttpMetaReader::new(server.url(), Transport::with_headers(vec![])).fetch_meta().unwrap();
+    assert_eq!(meta.size, SIZE);
+}
+
+#[test]
+fn rejected_credentials_are_refreshed_by_command() {
+    let server = MockServer::new(test_data(SIZE)).with_authorization("Bearer fresh").start();
+    let credentials = CommandCredentials::new("echo 'Authorization: Bearer fresh'", vec![
+        "Authorization: Bearer expired".to_string(),
+    ]);
+    let pool = ReaderPool::new(server.url(), SIZE, Transport::new(Arc::new(credentials)));
+    assert!(read_all(&pool, READ_SIZE) == test_data(SIZE));
+}
+
+#[test]
+fn session_cookies_of_login_command_are_sent() {
+    let server = MockServer::new(test_data(SIZE)).with_cookie("session=abc; csrf=def").start();
+    let credentials = CommandCredentials::new("printf 'Set-Cookie: session=abc; Path=/; HttpOnly\\nSet-Cookie: csrf=def\\n'", vec![]);
+    credentials.login().unwrap();
+    let pool = ReaderPool::new(server.url(), SIZE, Transport::new(Arc::new(credentials)));
+    assert!(read_all(&pool, READ_SIZE) == test_data(SIZE));
+}
+
+// Signs requests with a fixed token and records the statuses of responses.
+#[derive(Default)]
+struct SigningMiddleware {
+    statuses: Mutex<Vec<u32>>,
+}
+
+impl Middleware for SigningMiddleware {
+    fn on_request(&self, request: &mut Request) -> io::Result<()> {
+        request.headers.push("Authorization: Signed secret".to_string());
+        Ok(())
+    }
+
+    fn on_response(&self, response: &Response) {
+        self.statuses.lock().unwrap().push(response.status);
+    }
+}
+
+#[test]
+fn middleware_sees_requests_and_responses() {
+    let server = MockServer::new(test_data(SIZE)).with_authorization("Signed secret").start();
+    let middleware = Arc::new(SigningMiddleware::default());
+    let transport = Transport::with_headers(vec![]).with_middleware(middleware.clone());
+    let size = HttpMetaReader::new(server.url(), transport.clone()).fetch_file_size().unwrap();
+    let pool = ReaderPool::new(server.url(), size, transport);
+    assert!(read_all(&pool, READ_SIZE) == test_data(SIZE));
+    let statuses = middleware.statuses.lock().unwrap();
+    assert!(statuses.len() >= 2);
+    assert!(statuses.iter().all(|&status| status == 200 || status == 206), "{:?}", statuses);
+}
+
+#[test]
+fn added_headers_replace_those_of_the_transport() {
+    let server = MockServer::new(test_data(SIZE)).with_authorization("Bearer file").start();
+    let transport = Transport::with_headers(vec!["Authorization: Bearer mount".to_string()])
+        .with_added_headers(vec!["Authorization: Bearer file".to_string()]);
+    let pool = ReaderPool::new(server.url(), SIZE, transport);
+    assert!(read_all(&pool, READ_SIZE) == test_data(SIZE));
+}
+
+#[test]
+fn downloads_stop_at_limit() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let transport = Transport::with_headers(vec![]).with_download_limit(1024 * 1024);
+    let pool = ReaderPool::new(server.url(), SIZE, transport);
+    let expected = test_data(SIZE);
+    assert!(pool.read(0, READ_SIZE).unwrap()[..] == expected[..READ_SIZE]);
+    assert!((0..SIZE).step_by(READ_SIZE).any(|offset| pool.read(offset, READ_SIZE).is_err()));
+    // no more requests are sent
+    let requests = server.requests();
+    assert!(pool.read(SIZE - READ_SIZE, READ_SIZE).is_err());
+    assert_eq!(server.requests(), requests);
+}
+
+#[test]
+fn encrypted_resource_is_decrypted() {
+    let key = [42; 32];
+    let mut encrypted = encrypt(&key, READ_SIZE, &test_data(SIZE));
+    // corrupts the second to last chunk
+    let corrupted = encrypted.len() - 200;
+    encrypted[corrupted] ^= 1;
+    let server = MockServer::new(encrypted.clone()).start();
+    let decryption = Decryption::new(&key, &encrypted[..NONCE_PREFIX_LEN], READ_SIZE).unwrap();
+    let pool = ReaderPool::new(server.url(), encrypted.len(), Transport::with_headers(vec![]))
+        .with_decryption(decryption);
+    assert_eq!(pool.file_size(), SIZE);
+    let expected = test_data(SIZE);
+    for offset in random_offsets(20).into_iter().filter(|&offset| offset < SIZE - 2 * READ_SIZE) {
+        let data = pool.read(offset, 1000).unwrap();
+        assert!(data[..] == expected[offset..offset + 1000], "read at offset {}", offset);
+    }
+    assert!(pool.read(SIZE - 200, 100).is_err());
+}
+
+#[test]
+fn gzip_resource_is_decompressed() {
+    let mut encoder = GzEncoder::new(vec![], Compression::fast());
+    encoder.write_all(&test_data(SIZE)).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let server = MockServer::new(compressed.clone()).with_failures(3).start();
+    let pool = ReaderPool::new(server.url(), compressed.len(), Transport::with_headers(vec![]))
+        .with_gunzip(&env::temp_dir(), None)
+        .unwrap();
+    assert_eq!(pool.file_size(), SIZE);
+    let expected = test_data(SIZE);
+    for offset in random_offsets(20) {
+        let data = pool.read(offset, READ_SIZE).unwrap();
+        assert!(data[..] == expected[offset..(offset + READ_SIZE).min(SIZE)], "read at offset {}", offset);
+    }
+    assert!(read_all(&pool, READ_SIZE) == expected);
+}
+
+// Mounts need FUSE, without it, e.g. in a container without /dev/fuse, the test passes without mounting.
+#[test]
+fn joined_mount_serves_reads_until_unmounted() {
+    let server = MockServer::new(test_data(SIZE)).start();
+    let dir = env::temp_dir().join(format!("httpfs-join-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let handle = match Mount::spawn(HttpFs::new(pool(&server), "file"), &dir, &mount_options(false, false)) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Unable to mount, skipping: {}", e);
+            return;
+        }
+    };
+    let joined = thread::spawn(move || handle.join());
+    let path = dir.join("file");
+    let reader = thread::spawn(move || fs::read(path));
+    assert!(reader.join().unwrap().unwrap() == test_data(SIZE));
+    assert!(!joined.is_finished());
+
+    let unmount = |command: &str, flag: &str| Command::new(command).arg(flag).arg(&dir).status();
+    let unmounted = [("fusermount", "-u"), ("umount", "-l")].iter()
+        .any(|(command, flag)| unmount(command, flag).is_ok_and(|status| status.success()));
+    assert!(unmounted);
+    joined.join().unwrap().unwrap();
+    fs::remove_dir(&dir).unwrap();
+}